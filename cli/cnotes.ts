@@ -0,0 +1,160 @@
+// A small client for handing notes to third parties:
+//
+//   cnotes link <note-id> <leaf-hash>   prints a verification link, also the QR payload
+//   cnotes verify-link <link>           checks the linked leaf against the tree on chain
+//
+// Runs against ANCHOR_PROVIDER_URL, or a local validator without it.
+import {
+  ConcurrentMerkleTreeAccount,
+  SPL_NOOP_PROGRAM_ID,
+  deserializeChangeLogEventV1,
+} from "@solana/spl-account-compression"
+import { Connection, PublicKey } from "@solana/web3.js"
+import { bs58 } from "@coral-xyz/anchor/dist/cjs/utils/bytes"
+import { keccak256 } from "js-sha3"
+import { resolveNoteId } from "../tests/utils"
+
+const LINK_PREFIX = "cnotes:verify?"
+
+// Builds the link for the note `noteId` whose leaf hashes to `leafHash`, hex encoded
+export function encodeNoteLink(noteId: string, leafHash: string) {
+  resolveNoteId(noteId)
+  if (!/^[0-9a-f]{64}$/.test(leafHash)) {
+    throw new Error("The leaf hash must be 32 bytes of lowercase hex")
+  }
+  return `${LINK_PREFIX}note=${noteId}&leaf=${leafHash}`
+}
+
+// Reads the note id and leaf hash back from a link
+export function decodeNoteLink(link: string) {
+  if (!link.startsWith(LINK_PREFIX)) {
+    throw new Error("Not a cnotes verification link")
+  }
+  const params = new URLSearchParams(link.slice(LINK_PREFIX.length))
+  const noteId = params.get("note")
+  const leafHash = params.get("leaf")
+  if (noteId === null || leafHash === null) {
+    throw new Error("The link misses the note id or leaf hash")
+  }
+  encodeNoteLink(noteId, leafHash)
+  return { noteId, leafHash }
+}
+
+// Replays every change log of `merkleTree` in sequence order, returning the current leaves
+async function fetchLeaves(connection: Connection, merkleTree: PublicKey) {
+  const changes = []
+  let before: string | undefined
+  for (;;) {
+    const signatures = await connection.getSignaturesForAddress(merkleTree, {
+      before,
+    })
+    if (signatures.length === 0) {
+      break
+    }
+    before = signatures[signatures.length - 1].signature
+    for (const { signature, err } of signatures) {
+      if (err !== null) {
+        continue
+      }
+      const txInfo = await connection.getTransaction(signature, {
+        maxSupportedTransactionVersion: 0,
+      })
+      const accountKeys = txInfo.transaction.message.staticAccountKeys
+      for (const inner of txInfo.meta?.innerInstructions ?? []) {
+        for (const instruction of inner.instructions) {
+          const programId = accountKeys[instruction.programIdIndex]
+          if (!programId.equals(SPL_NOOP_PROGRAM_ID)) {
+            continue
+          }
+          try {
+            const changeLog = deserializeChangeLogEventV1(
+              Buffer.from(bs58.decode(instruction.data))
+            )
+            if (changeLog.treeId.equals(merkleTree)) {
+              changes.push(changeLog)
+            }
+          } catch (__) {
+            // Application data logged by the notes program, not a change log
+          }
+        }
+      }
+    }
+  }
+
+  changes.sort((a, b) => a.seq.cmp(b.seq))
+  const leaves = new Map<number, Buffer>()
+  for (const change of changes) {
+    leaves.set(change.index, change.path[0].node.toBuffer())
+  }
+  return leaves
+}
+
+// The proof of the leaf at `index` in a tree of `depth` holding `leaves`, all other leaves
+// empty, ordered from the leaf's sibling up
+function getProof(leaves: Map<number, Buffer>, depth: number, index: number) {
+  const hash = (left: Buffer, right: Buffer) =>
+    Buffer.from(keccak256.arrayBuffer(Buffer.concat([left, right])))
+  let empty = Buffer.alloc(32)
+  let level = leaves
+  const proof: Buffer[] = []
+  for (let height = 0; height < depth; height++) {
+    proof.push(level.get((index >> height) ^ 1) ?? empty)
+    const parents = new Map<number, Buffer>()
+    for (const position of level.keys()) {
+      const parent = position >> 1
+      if (!parents.has(parent)) {
+        const left = level.get(parent * 2) ?? empty
+        const right = level.get(parent * 2 + 1) ?? empty
+        parents.set(parent, hash(left, right))
+      }
+    }
+    empty = hash(empty, empty)
+    level = parents
+  }
+  return proof
+}
+
+// Checks the leaf of a link is the current leaf of its note, proven against the tree's root
+export async function verifyNoteLink(connection: Connection, link: string) {
+  const { noteId, leafHash } = decodeNoteLink(link)
+  const { merkleTree, leafIndex } = resolveNoteId(noteId)
+  const leaves = await fetchLeaves(connection, merkleTree)
+  const treeAccount = await ConcurrentMerkleTreeAccount.fromAccountAddress(
+    connection,
+    merkleTree
+  )
+  const proof = getProof(leaves, treeAccount.getMaxDepth(), leafIndex)
+
+  let node = Buffer.from(leafHash, "hex")
+  proof.forEach((sibling, height) => {
+    const pair = (leafIndex >> height) & 1 ? [sibling, node] : [node, sibling]
+    node = Buffer.from(keccak256.arrayBuffer(Buffer.concat(pair)))
+  })
+  return node.equals(treeAccount.getCurrentRoot())
+}
+
+async function main([command, ...args]: string[]) {
+  const connection = new Connection(
+    process.env.ANCHOR_PROVIDER_URL ?? "http://127.0.0.1:8899",
+    "confirmed"
+  )
+  if (command === "link" && args.length === 2) {
+    console.log(encodeNoteLink(args[0], args[1]))
+  } else if (command === "verify-link" && args.length === 1) {
+    // A root moving on while the leaves are replayed fails too, retrying settles it
+    const verified = await verifyNoteLink(connection, args[0])
+    console.log(verified ? "verified" : "not verified")
+    process.exitCode = verified ? 0 : 1
+  } else {
+    console.error("usage: cnotes link <note-id> <leaf-hash>")
+    console.error("       cnotes verify-link <link>")
+    process.exitCode = 2
+  }
+}
+
+if (require.main === module) {
+  main(process.argv.slice(2)).catch((error) => {
+    console.error(error.message)
+    process.exitCode = 1
+  })
+}
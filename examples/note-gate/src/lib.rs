@@ -0,0 +1,195 @@
+//! An example program integrating with compressed-notes through CPI, gating a pass on note
+//! ownership and posting notes as its own pda.
+
+#![allow(clippy::result_large_err)]
+
+use anchor_lang::prelude::*;
+use compressed_notes::{
+    cpi::accounts::NoteAccounts, hash_note_leaf_v2, program::CompressedNotes, CompressedNotesError,
+    GlobalConfig, NoteStatus, TreeState,
+};
+use spl_account_compression::{
+    cpi::{accounts::VerifyLeaf, verify_leaf},
+    program::SplAccountCompression,
+    Noop,
+};
+
+declare_id!("68VGBEwdGXAqQqksPivEpJjALQ1zeajeNv4jTPPz7vpx");
+
+#[program]
+pub mod note_gate {
+    use super::*;
+
+    // Instruction for claiming a pass by proving ownership of a published note, which may have
+    // been edited or locked since, but not one still in draft, deleted or redacted.
+    pub fn claim_pass<'info>(
+        ctx: Context<'_, '_, '_, 'info, ClaimPass<'info>>,
+        root: [u8; 32],     // Root the note leaf is proven against
+        note: String,       // The note message
+        status: NoteStatus, // The status the note leaf commits to
+        index: u32,         // Index of the note leaf in the merkle tree
+    ) -> Result<()> {
+        require!(
+            matches!(
+                status,
+                NoteStatus::Published | NoteStatus::Edited | NoteStatus::Locked
+            ),
+            NoteGateError::NoteNotPublished
+        );
+        // The leaf only verifies if the signer owns a note with this message and status
+        let leaf = hash_note_leaf_v2(&note, &ctx.accounts.owner.key(), status);
+
+        // CPI to verify the leaf, the proof is passed as remaining accounts
+        let cpi_ctx = CpiContext::new(
+            ctx.accounts.compression_program.to_account_info(),
+            VerifyLeaf {
+                merkle_tree: ctx.accounts.merkle_tree.to_account_info(),
+            },
+        )
+        .with_remaining_accounts(ctx.remaining_accounts.to_vec());
+        verify_leaf(cpi_ctx, root, leaf, index)?;
+
+        let pass = &mut ctx.accounts.pass;
+        pass.owner = ctx.accounts.owner.key();
+        pass.merkle_tree = ctx.accounts.merkle_tree.key();
+        pass.index = index;
+        pass.bump = *ctx.bumps.get("pass").unwrap();
+        Ok(())
+    }
+
+    // Instruction for appending a note owned by the poster pda.
+    pub fn post_note(ctx: Context<PostNote>, note: String) -> Result<()> {
+        let bump = *ctx.bumps.get("poster").unwrap();
+        let signer_seeds: &[&[&[u8]]] = &[&[Poster::SEED, &[bump]]];
+
+        // CPI to compressed-notes, signing as the poster pda
+        let cpi_ctx = CpiContext::new_with_signer(
+            ctx.accounts.notes_program.to_account_info(),
+            NoteAccounts {
+                owner: ctx.accounts.poster.to_account_info(),
+                payer: None,
+                session: None,
+                did: None,
+                role: None,
+                identity_link: None,
+                instructions: None,
+                rate_state: None,
+                recent_leaves: None,
+                hold: None,
+                tree_treasury: None,
+                payer_token_account: None,
+                fee_vault: None,
+                token_program: None,
+                gate_token_account: None,
+                gate_metadata: None,
+                stake: None,
+                subscription: None,
+                tree_config: ctx.accounts.tree_config.to_account_info(),
+                tree_authority: ctx.accounts.tree_authority.to_account_info(),
+                merkle_tree: ctx.accounts.merkle_tree.to_account_info(),
+                log_wrapper: ctx.accounts.log_wrapper.to_account_info(),
+                compression_program: ctx.accounts.compression_program.to_account_info(),
+                system_program: None,
+                global_config: ctx.accounts.global_config.to_account_info(),
+            },
+            signer_seeds,
+        );
+        compressed_notes::cpi::append_note(cpi_ctx, note)
+    }
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct Pass {
+    owner: Pubkey,       // The owner of the note the pass was claimed with
+    merkle_tree: Pubkey, // The tree holding the note
+    index: u32,          // Index of the note leaf in the merkle tree
+    bump: u8,            // The bump seed for the pda
+}
+
+impl Pass {
+    pub const SEED: &'static [u8] = b"pass";
+}
+
+// The pda posting notes for this program, it owns no data and only signs
+pub struct Poster;
+
+impl Poster {
+    pub const SEED: &'static [u8] = b"poster";
+}
+
+#[derive(Accounts)]
+pub struct ClaimPass<'info> {
+    // The owner of the note, also pays for the pass account
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    // The pass pda, one per tree and owner
+    #[account(
+        init,
+        payer = owner,
+        space = 8 + Pass::INIT_SPACE,
+        seeds = [Pass::SEED, merkle_tree.key().as_ref(), owner.key().as_ref()],
+        bump,
+    )]
+    pub pass: Account<'info, Pass>,
+
+    // The pda authority for the merkle tree, derived by compressed-notes
+    /// CHECK: Only its address is used, to check the tree belongs to compressed-notes
+    #[account(seeds = [merkle_tree.key().as_ref()], bump, seeds::program = CompressedNotes::id())]
+    pub tree_authority: UncheckedAccount<'info>,
+
+    // The merkle tree holding the note, a compressed-notes tree so the leaf is a note
+    /// CHECK: This account is validated by the spl account compression program
+    #[account(
+        constraint = TreeState::load(&merkle_tree)?.authority == tree_authority.key()
+            @ CompressedNotesError::InvalidTreeAuthority
+    )]
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    // The spl account compression program
+    pub compression_program: Program<'info, SplAccountCompression>,
+
+    // The system program, used to create the pass account
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct PostNote<'info> {
+    // The poster pda, owns the notes this program posts
+    /// CHECK: Only signs for the notes, never holds data
+    #[account(mut, seeds = [Poster::SEED], bump)]
+    pub poster: UncheckedAccount<'info>,
+
+    // The pda authority for the merkle tree, derived by compressed-notes
+    /// CHECK: Validated by the compressed-notes program
+    pub tree_authority: UncheckedAccount<'info>,
+
+    // The tree config pda, derived by compressed-notes
+    /// CHECK: Validated by the compressed-notes program
+    #[account(mut)]
+    pub tree_config: UncheckedAccount<'info>,
+
+    // The merkle tree account
+    /// CHECK: This account is validated by the spl account compression program
+    #[account(mut)]
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    // The noop program to log data
+    pub log_wrapper: Program<'info, Noop>,
+
+    // The spl account compression program
+    pub compression_program: Program<'info, SplAccountCompression>,
+
+    // The global config of compressed-notes
+    pub global_config: Account<'info, GlobalConfig>,
+
+    // The compressed-notes program
+    pub notes_program: Program<'info, CompressedNotes>,
+}
+
+#[error_code]
+pub enum NoteGateError {
+    #[msg("Only published notes, edited or locked since, can claim a pass")]
+    NoteNotPublished,
+}
@@ -0,0 +1,315 @@
+// Program tests running note-gate against compressed-notes and the spl account compression
+// program, all loaded natively
+
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::{keccak, system_program};
+use anchor_lang::{Discriminator, InstructionData};
+use compressed_notes::{
+    hash_note_leaf_v2, instruction_flags, Forest, GlobalConfig, NoteStatus, TreeConfig,
+    TREASURY_SEED,
+};
+use note_gate::{NoteGateError, Pass, Poster};
+use solana_program_test::{processor, BanksClientError, ProgramTest, ProgramTestContext};
+use solana_sdk::account::Account;
+use solana_sdk::instruction::{Instruction, InstructionError};
+use solana_sdk::signature::{Keypair, Signer};
+use solana_sdk::system_instruction;
+use solana_sdk::transaction::{Transaction, TransactionError};
+use spl_account_compression::state::CONCURRENT_MERKLE_TREE_HEADER_SIZE_V1;
+use spl_account_compression::ConcurrentMerkleTree;
+
+const MAX_DEPTH: usize = 3;
+const MAX_BUFFER_SIZE: usize = 8;
+const POSTER_NOTE: &str = "posted by a program";
+const MEMBER_NOTE: &str = "proof of membership";
+
+// A tree of compressed-notes with the poster's note at index 0 and the payer's at index 1
+struct NoteTree {
+    context: ProgramTestContext,
+    merkle_tree: Pubkey,
+    tree_authority: Pubkey,
+}
+
+fn global_config_address() -> Pubkey {
+    Pubkey::find_program_address(&[GlobalConfig::SEED], &compressed_notes::id()).0
+}
+
+fn poster_address() -> Pubkey {
+    Pubkey::find_program_address(&[Poster::SEED], &note_gate::id()).0
+}
+
+fn pass_address(merkle_tree: &Pubkey, owner: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[Pass::SEED, merkle_tree.as_ref(), owner.as_ref()],
+        &note_gate::id(),
+    )
+    .0
+}
+
+// A global config enabling every instruction, since natively loaded programs have no program
+// data account for `initialize_global_config` to check the upgrade authority against
+fn global_config_account(admin: &Pubkey) -> Account {
+    let bump = Pubkey::find_program_address(&[GlobalConfig::SEED], &compressed_notes::id()).1;
+    let mut data = GlobalConfig::DISCRIMINATOR.to_vec();
+    data.extend_from_slice(admin.as_ref());
+    data.extend_from_slice(&instruction_flags::ALL.to_le_bytes());
+    data.extend_from_slice(&0u64.to_le_bytes()); // No tree creation fee
+    data.push(0); // Not paused
+    data.push(bump);
+    data.resize(8 + GlobalConfig::INIT_SPACE, 0);
+    Account {
+        lamports: Rent::default().minimum_balance(data.len()),
+        data,
+        owner: compressed_notes::id(),
+        ..Account::default()
+    }
+}
+
+async fn process(
+    context: &mut ProgramTestContext,
+    instructions: &[Instruction],
+    signers: &[&Keypair],
+) -> std::result::Result<(), BanksClientError> {
+    let blockhash = context.banks_client.get_latest_blockhash().await?;
+    let mut all_signers = vec![&context.payer];
+    all_signers.extend_from_slice(signers);
+    let tx = Transaction::new_signed_with_payer(
+        instructions,
+        Some(&context.payer.pubkey()),
+        &all_signers,
+        blockhash,
+    );
+    context.banks_client.process_transaction(tx).await
+}
+
+// Starts the programs, creates a note tree and appends the poster's note through note-gate
+// and the payer's note directly
+async fn setup() -> NoteTree {
+    let mut program_test =
+        ProgramTest::new("note_gate", note_gate::id(), processor!(note_gate::entry));
+    program_test.add_program(
+        "compressed_notes",
+        compressed_notes::id(),
+        processor!(compressed_notes::entry),
+    );
+    program_test.add_program(
+        "spl_account_compression",
+        spl_account_compression::id(),
+        processor!(spl_account_compression::entry),
+    );
+    program_test.add_program("spl_noop", spl_noop::id(), processor!(spl_noop::noop));
+    let admin = Pubkey::new_unique();
+    program_test.add_account(global_config_address(), global_config_account(&admin));
+    let mut context = program_test.start_with_context().await;
+    let payer = context.payer.pubkey();
+
+    // Allocate the tree account for the compression program, then create the note tree
+    let merkle_tree = Keypair::new();
+    let tree_authority =
+        Pubkey::find_program_address(&[merkle_tree.pubkey().as_ref()], &compressed_notes::id()).0;
+    let tree_config = Pubkey::find_program_address(
+        &[TreeConfig::SEED, merkle_tree.pubkey().as_ref()],
+        &compressed_notes::id(),
+    )
+    .0;
+    let space = CONCURRENT_MERKLE_TREE_HEADER_SIZE_V1
+        + std::mem::size_of::<ConcurrentMerkleTree<MAX_DEPTH, MAX_BUFFER_SIZE>>();
+    let alloc_tree = system_instruction::create_account(
+        &payer,
+        &merkle_tree.pubkey(),
+        Rent::default().minimum_balance(space),
+        space as u64,
+        &spl_account_compression::id(),
+    );
+    let create_tree = Instruction {
+        program_id: compressed_notes::id(),
+        accounts: compressed_notes::accounts::CreateNoteTree {
+            creator: payer,
+            tree_config,
+            forest: Pubkey::find_program_address(
+                &[Forest::SEED, payer.as_ref()],
+                &compressed_notes::id(),
+            )
+            .0,
+            tree_authority,
+            merkle_tree: merkle_tree.pubkey(),
+            log_wrapper: spl_noop::id(),
+            compression_program: spl_account_compression::id(),
+            treasury: Pubkey::find_program_address(&[TREASURY_SEED], &compressed_notes::id()).0,
+            system_program: system_program::ID,
+            global_config: global_config_address(),
+        }
+        .to_account_metas(None),
+        data: compressed_notes::instruction::CreateNoteTree {
+            max_depth: MAX_DEPTH as u32,
+            max_buffer_size: MAX_BUFFER_SIZE as u32,
+            canopy_depth: 0,
+        }
+        .data(),
+    };
+    process(&mut context, &[alloc_tree, create_tree], &[&merkle_tree])
+        .await
+        .unwrap();
+
+    // The poster pda signs for its note through the cpi of note-gate
+    let post_note = Instruction {
+        program_id: note_gate::id(),
+        accounts: note_gate::accounts::PostNote {
+            poster: poster_address(),
+            tree_authority,
+            tree_config,
+            merkle_tree: merkle_tree.pubkey(),
+            log_wrapper: spl_noop::id(),
+            compression_program: spl_account_compression::id(),
+            global_config: global_config_address(),
+            notes_program: compressed_notes::id(),
+        }
+        .to_account_metas(None),
+        data: note_gate::instruction::PostNote {
+            note: POSTER_NOTE.to_string(),
+        }
+        .data(),
+    };
+    process(&mut context, &[post_note], &[]).await.unwrap();
+
+    let append_note = Instruction {
+        program_id: compressed_notes::id(),
+        accounts: compressed_notes::accounts::NoteAccounts {
+            owner: payer,
+            payer: None,
+            session: None,
+            did: None,
+            role: None,
+            identity_link: None,
+            instructions: None,
+            rate_state: None,
+            recent_leaves: None,
+            hold: None,
+            tree_treasury: None,
+            payer_token_account: None,
+            fee_vault: None,
+            token_program: None,
+            gate_token_account: None,
+            gate_metadata: None,
+            stake: None,
+            subscription: None,
+            tree_config,
+            tree_authority,
+            merkle_tree: merkle_tree.pubkey(),
+            log_wrapper: spl_noop::id(),
+            compression_program: spl_account_compression::id(),
+            system_program: None,
+            global_config: global_config_address(),
+        }
+        .to_account_metas(None),
+        data: compressed_notes::instruction::AppendNote {
+            note: MEMBER_NOTE.to_string(),
+        }
+        .data(),
+    };
+    process(&mut context, &[append_note], &[]).await.unwrap();
+
+    NoteTree {
+        context,
+        merkle_tree: merkle_tree.pubkey(),
+        tree_authority,
+    }
+}
+
+// The root of the tree and the proof of the member note at index 1, rebuilt from both leaves
+fn member_proof(member: &Pubkey) -> ([u8; 32], Vec<[u8; 32]>) {
+    let poster_leaf = hash_note_leaf_v2(POSTER_NOTE, &poster_address(), NoteStatus::Published);
+    let member_leaf = hash_note_leaf_v2(MEMBER_NOTE, member, NoteStatus::Published);
+    let empty_level_1 = keccak::hashv(&[&[0; 32], &[0; 32]]).to_bytes();
+    let empty_level_2 = keccak::hashv(&[&empty_level_1, &empty_level_1]).to_bytes();
+    let mut root = keccak::hashv(&[&poster_leaf, &member_leaf]).to_bytes();
+    for empty in [empty_level_1, empty_level_2] {
+        root = keccak::hashv(&[&root, &empty]).to_bytes();
+    }
+    (root, vec![poster_leaf, empty_level_1, empty_level_2])
+}
+
+fn claim_pass(tree: &NoteTree, owner: &Pubkey, status: NoteStatus) -> Instruction {
+    let (root, proof) = member_proof(&tree.context.payer.pubkey());
+    let mut accounts = note_gate::accounts::ClaimPass {
+        owner: *owner,
+        pass: pass_address(&tree.merkle_tree, owner),
+        tree_authority: tree.tree_authority,
+        merkle_tree: tree.merkle_tree,
+        compression_program: spl_account_compression::id(),
+        system_program: system_program::ID,
+    }
+    .to_account_metas(None);
+    accounts.extend(
+        proof
+            .iter()
+            .map(|node| AccountMeta::new_readonly(Pubkey::new_from_array(*node), false)),
+    );
+    Instruction {
+        program_id: note_gate::id(),
+        accounts,
+        data: note_gate::instruction::ClaimPass {
+            root,
+            note: MEMBER_NOTE.to_string(),
+            status,
+            index: 1,
+        }
+        .data(),
+    }
+}
+
+#[tokio::test]
+async fn owner_claims_pass() {
+    let mut tree = setup().await;
+    let owner = tree.context.payer.pubkey();
+
+    // The proof only verifies if the poster pda's note was appended as the first leaf
+    let claim = claim_pass(&tree, &owner, NoteStatus::Published);
+    process(&mut tree.context, &[claim], &[]).await.unwrap();
+
+    let pass = tree
+        .context
+        .banks_client
+        .get_account(pass_address(&tree.merkle_tree, &owner))
+        .await
+        .unwrap()
+        .expect("the pass is created");
+    assert_eq!(pass.owner, note_gate::id());
+}
+
+#[tokio::test]
+async fn non_owner_cannot_claim_pass() {
+    let mut tree = setup().await;
+    let stranger = Keypair::new();
+    let fund = system_instruction::transfer(
+        &tree.context.payer.pubkey(),
+        &stranger.pubkey(),
+        1_000_000_000,
+    );
+    process(&mut tree.context, &[fund], &[]).await.unwrap();
+
+    // The leaf is rehashed with the signer as owner, so it no longer verifies
+    let claim = claim_pass(&tree, &stranger.pubkey(), NoteStatus::Published);
+    assert!(process(&mut tree.context, &[claim], &[&stranger])
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn draft_cannot_claim_pass() {
+    let mut tree = setup().await;
+    let owner = tree.context.payer.pubkey();
+
+    let claim = claim_pass(&tree, &owner, NoteStatus::Draft);
+    let error = process(&mut tree.context, &[claim], &[])
+        .await
+        .unwrap_err()
+        .unwrap();
+    assert_eq!(
+        error,
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(NoteGateError::NoteNotPublished.into())
+        )
+    );
+}
@@ -0,0 +1,200 @@
+//! Watch-only auditors, attesting to leaves of a tree they cannot change. Compiled in with the
+//! `audit` feature.
+
+use anchor_lang::prelude::*;
+use spl_account_compression::{
+    cpi::{accounts::VerifyLeaf, verify_leaf},
+    program::SplAccountCompression,
+    wrap_application_data_v1, Noop,
+};
+
+use crate::{instruction_flags, CompressedNotesError, GlobalConfig, TreeState};
+
+// Grants `auditor` watch-only access to a tree.
+pub fn process_grant_auditor(ctx: Context<GrantAuditor>, auditor: Pubkey) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::GRANT_AUDITOR)?;
+
+    let auditor_role = &mut ctx.accounts.auditor_role;
+    auditor_role.auditor = auditor;
+    auditor_role.merkle_tree = ctx.accounts.merkle_tree.key();
+    auditor_role.granted_slot = Clock::get()?.slot;
+    auditor_role.bump = *ctx.bumps.get("auditor_role").unwrap();
+    Ok(())
+}
+
+// Revokes an auditor's access to a tree, refunding the role rent to the admin.
+pub fn process_revoke_auditor(ctx: Context<RevokeAuditor>) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::REVOKE_AUDITOR)?;
+    // The role is closed by the account constraints
+    Ok(())
+}
+
+// Verifies a leaf of the tree and logs the auditor's attestation for it.
+pub fn process_audit_leaf<'info>(
+    ctx: Context<'_, '_, '_, 'info, AuditLeaf<'info>>,
+    root: [u8; 32],      // Root the leaf is proven against
+    leaf_node: [u8; 32], // The leaf node hash being audited
+    index: u32,          // Index of the leaf in the merkle tree
+) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::AUDIT_LEAF)?;
+
+    // Verify or Fails, the proof is passed as remaining accounts
+    TreeState::load(&ctx.accounts.merkle_tree)?.require_leaf(index)?;
+    let cpi_ctx = CpiContext::new(
+        ctx.accounts.compression_program.to_account_info(),
+        VerifyLeaf {
+            merkle_tree: ctx.accounts.merkle_tree.to_account_info(),
+        },
+    )
+    .with_remaining_accounts(ctx.remaining_accounts.to_vec());
+    verify_leaf(cpi_ctx, root, leaf_node, index)?;
+
+    // Create a new "audit log" for the verified leaf, signed by the auditor
+    let audit_log = AuditLog::new(
+        leaf_node,
+        ctx.accounts.merkle_tree.key(),
+        index,
+        ctx.accounts.auditor.key(),
+        Clock::get()?.slot,
+    );
+    // Log the "audit log" data using noop program
+    wrap_application_data_v1(audit_log.try_to_vec()?, &ctx.accounts.log_wrapper)?;
+    Ok(())
+}
+
+#[derive(AnchorSerialize)]
+pub struct AuditLog {
+    leaf_node: [u8; 32], // The leaf node hash that was verified
+    merkle_tree: Pubkey, // The tree holding the leaf
+    index: u32,          // Index of the leaf in the merkle tree
+    auditor: Pubkey,     // Pubkey of the auditor who verified the leaf
+    slot: u64,           // The slot the leaf was verified
+}
+
+impl AuditLog {
+    // Constructs a new audit log for the given leaf node and auditor
+    pub fn new(
+        leaf_node: [u8; 32],
+        merkle_tree: Pubkey,
+        index: u32,
+        auditor: Pubkey,
+        slot: u64,
+    ) -> Self {
+        Self {
+            leaf_node,
+            merkle_tree,
+            index,
+            auditor,
+            slot,
+        }
+    }
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct AuditorRole {
+    auditor: Pubkey,     // Pubkey of the auditor
+    merkle_tree: Pubkey, // The tree the auditor can read
+    granted_slot: u64,   // The slot the role was granted
+    bump: u8,            // The bump seed for the pda
+}
+
+#[derive(Accounts)]
+#[instruction(auditor: Pubkey)]
+pub struct GrantAuditor<'info> {
+    // The admin of the global config, pays for the role account
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    // The global config pda
+    #[account(
+        seeds = [GlobalConfig::SEED],
+        bump = global_config.bump,
+        has_one = admin @ CompressedNotesError::Unauthorized,
+    )]
+    pub global_config: Account<'info, GlobalConfig>,
+
+    // The tree the auditor is granted access to
+    /// CHECK: Only used as a seed
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    // The auditor role pda, one per tree and auditor
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + AuditorRole::INIT_SPACE,
+        seeds = [b"auditor", merkle_tree.key().as_ref(), auditor.as_ref()],
+        bump,
+    )]
+    pub auditor_role: Account<'info, AuditorRole>,
+
+    // The system program, used to create the role account
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RevokeAuditor<'info> {
+    // The admin of the global config, receives the role rent
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    // The global config pda
+    #[account(
+        seeds = [GlobalConfig::SEED],
+        bump = global_config.bump,
+        has_one = admin @ CompressedNotesError::Unauthorized,
+    )]
+    pub global_config: Account<'info, GlobalConfig>,
+
+    // The auditor role pda, closed by this instruction
+    #[account(
+        mut,
+        close = admin,
+        seeds = [
+            b"auditor",
+            auditor_role.merkle_tree.as_ref(),
+            auditor_role.auditor.as_ref(),
+        ],
+        bump = auditor_role.bump,
+    )]
+    pub auditor_role: Account<'info, AuditorRole>,
+}
+
+#[derive(Accounts)]
+pub struct AuditLeaf<'info> {
+    // The auditor, must hold a role for the tree
+    pub auditor: Signer<'info>,
+
+    // The auditor role pda for this tree and auditor
+    #[account(
+        seeds = [b"auditor", merkle_tree.key().as_ref(), auditor.key().as_ref()],
+        bump = auditor_role.bump,
+    )]
+    pub auditor_role: Account<'info, AuditorRole>,
+
+    // The merkle tree account, only read
+    /// CHECK: This account is validated by the spl account compression program
+    #[account(
+        owner = spl_account_compression::ID @ CompressedNotesError::InvalidMerkleTreeOwner,
+    )]
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    // The noop program to log data
+    pub log_wrapper: Program<'info, Noop>,
+
+    // The spl account compression program
+    pub compression_program: Program<'info, SplAccountCompression>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
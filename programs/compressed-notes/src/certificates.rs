@@ -0,0 +1,369 @@
+//! Certificate trees only their issuer appends to, doubling as the issuer's revocation list.
+//! Compiled in with the `certificates` feature.
+
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::keccak;
+use spl_account_compression::{
+    cpi::{accounts::VerifyLeaf, verify_leaf},
+    program::SplAccountCompression,
+    wrap_application_data_v1, Noop,
+};
+
+use crate::{
+    instruction_flags, leaf_domain, leaf_schema, CompressedNotesError, Forest, GlobalConfig,
+    NoteId, TreeCpi, TreePurpose, TreeState,
+};
+
+// Hashes a certificate's subject, claims hash and expiry into the leaf node
+pub fn hash_certificate_leaf(subject: &Pubkey, claims_hash: &[u8; 32], expiry: i64) -> [u8; 32] {
+    keccak::hashv(&[
+        &leaf_domain::tag(leaf_domain::CERTIFICATE),
+        subject.as_ref(),
+        claims_hash,
+        &expiry.to_le_bytes(),
+    ])
+    .to_bytes()
+}
+
+// Hashes the marker that replaces a revoked certificate's leaf node
+pub fn hash_revoked_certificate_leaf(certificate_leaf: &[u8; 32]) -> [u8; 32] {
+    keccak::hashv(&[
+        &leaf_domain::tag(leaf_domain::REVOKED_CERTIFICATE),
+        certificate_leaf,
+    ])
+    .to_bytes()
+}
+
+// Creates a new tree that only the issuer can issue certificates to.
+pub fn process_create_certificate_tree(
+    ctx: Context<CreateCertificateTree>,
+    max_depth: u32,       // Max depth of the merkle tree
+    max_buffer_size: u32, // Max buffer size of the merkle tree
+) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::CREATE_CERTIFICATE_TREE)?;
+
+    let issuer = &mut ctx.accounts.issuer;
+    issuer.authority = ctx.accounts.authority.key();
+    issuer.merkle_tree = ctx.accounts.merkle_tree.key();
+    issuer.bump = *ctx.bumps.get("issuer").unwrap();
+
+    // CPI to initialize an empty merkle tree, with the issuer pda as its authority
+    issuer_tree_cpi(
+        &ctx.accounts.compression_program,
+        issuer,
+        &ctx.accounts.merkle_tree,
+        &ctx.accounts.log_wrapper,
+    )
+    .init(max_depth, max_buffer_size)?;
+    ctx.accounts.forest.record(
+        ctx.accounts.authority.key(),
+        ctx.accounts.merkle_tree.key(),
+        TreePurpose::Certificates,
+        *ctx.bumps.get("forest").unwrap(),
+    )
+}
+
+// Appends a certificate for `subject` to the issuer's tree.
+pub fn process_issue_certificate(
+    ctx: Context<IssuerAccounts>,
+    subject: Pubkey,       // Pubkey the certificate is issued to
+    claims_hash: [u8; 32], // Hash of the claims made about the subject, kept off-chain
+    expiry: i64,           // Unix timestamp after which the certificate is no longer valid
+) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::ISSUE_CERTIFICATE)?;
+    require!(
+        expiry > Clock::get()?.unix_timestamp,
+        CompressedNotesError::InvalidExpiry
+    );
+
+    // Hash the certificate which will be stored as leaf node in the merkle tree
+    let leaf_node = hash_certificate_leaf(&subject, &claims_hash, expiry);
+    // Create a new "certificate log" using the leaf node hash, its id, issuer and claims.
+    let note_id = NoteId::next(&ctx.accounts.merkle_tree, leaf_schema::V3)?;
+    let certificate_log = CertificateLog::new(
+        leaf_node,
+        note_id,
+        ctx.accounts.issuer.key(),
+        subject,
+        claims_hash,
+        expiry,
+    );
+    // Log the "certificate log" data using noop program
+    wrap_application_data_v1(certificate_log.try_to_vec()?, &ctx.accounts.log_wrapper)?;
+
+    ctx.accounts.issuer.issued_count += 1;
+
+    // CPI to append the leaf node to the issuer's tree
+    issuer_tree_cpi(
+        &ctx.accounts.compression_program,
+        &ctx.accounts.issuer,
+        &ctx.accounts.merkle_tree,
+        &ctx.accounts.log_wrapper,
+    )
+    .append(leaf_node)?;
+    Ok(())
+}
+
+// Revokes a certificate by replacing its leaf with a revocation marker.
+pub fn process_revoke_certificate(
+    ctx: Context<IssuerAccounts>,
+    root: [u8; 32],        // Root the certificate leaf is proven against
+    subject: Pubkey,       // Pubkey the certificate was issued to
+    claims_hash: [u8; 32], // Hash of the claims committed into the certificate
+    expiry: i64,           // The expiry committed into the certificate
+    index: u32,            // Index of the certificate leaf in the merkle tree
+) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::REVOKE_CERTIFICATE)?;
+
+    let certificate_leaf = hash_certificate_leaf(&subject, &claims_hash, expiry);
+    let leaf_node = hash_revoked_certificate_leaf(&certificate_leaf);
+
+    // Log the revocation so indexers add the certificate to the revocation list
+    let note_id = NoteId::new(ctx.accounts.merkle_tree.key(), index, leaf_schema::V3);
+    let revocation_log =
+        RevocationLog::new(leaf_node, note_id, certificate_leaf, Clock::get()?.slot);
+    wrap_application_data_v1(revocation_log.try_to_vec()?, &ctx.accounts.log_wrapper)?;
+
+    ctx.accounts.issuer.revoked_count += 1;
+
+    // CPI to replace the certificate leaf with its revocation marker
+    issuer_tree_cpi(
+        &ctx.accounts.compression_program,
+        &ctx.accounts.issuer,
+        &ctx.accounts.merkle_tree,
+        &ctx.accounts.log_wrapper,
+    )
+    .replace(root, certificate_leaf, leaf_node, index)?;
+    Ok(())
+}
+
+// Fails unless the certificate is in the issuer's tree, unrevoked and unexpired.
+pub fn process_check_certificate(
+    ctx: Context<CheckCertificate>,
+    root: [u8; 32],        // Root the certificate leaf is proven against
+    subject: Pubkey,       // Pubkey the certificate was issued to
+    claims_hash: [u8; 32], // Hash of the claims committed into the certificate
+    expiry: i64,           // The expiry committed into the certificate
+    index: u32,            // Index of the certificate leaf in the merkle tree
+) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::CHECK_CERTIFICATE)?;
+    require!(
+        Clock::get()?.unix_timestamp < expiry,
+        CompressedNotesError::CertificateExpired
+    );
+
+    // Verify or Fails, a revoked certificate's leaf was replaced by its marker
+    let leaf_node = hash_certificate_leaf(&subject, &claims_hash, expiry);
+    TreeState::load(&ctx.accounts.merkle_tree)?.require_leaf(index)?;
+    let cpi_ctx = CpiContext::new(
+        ctx.accounts.compression_program.to_account_info(),
+        VerifyLeaf {
+            merkle_tree: ctx.accounts.merkle_tree.to_account_info(),
+        },
+    );
+    verify_leaf(cpi_ctx, root, leaf_node, index)
+}
+
+// Builds a TreeCpi signing as the issuer pda, the authority of certificate trees
+fn issuer_tree_cpi<'info>(
+    compression_program: &Program<'info, SplAccountCompression>,
+    issuer: &Account<'info, CertificateIssuer>,
+    merkle_tree: &UncheckedAccount<'info>,
+    log_wrapper: &Program<'info, Noop>,
+) -> TreeCpi<'info> {
+    TreeCpi::new(
+        compression_program,
+        issuer,
+        merkle_tree,
+        log_wrapper,
+        issuer.bump,
+    )
+    .with_seed_prefix(CertificateIssuer::SEED)
+}
+
+#[derive(AnchorSerialize)]
+pub struct CertificateLog {
+    leaf_node: [u8; 32],   // The leaf node hash
+    note_id: NoteId,       // The id of the leaf
+    issuer: Pubkey,        // The issuer pda of the certificate tree
+    subject: Pubkey,       // Pubkey the certificate is issued to
+    claims_hash: [u8; 32], // Hash of the claims made about the subject
+    expiry: i64,           // Unix timestamp after which the certificate is no longer valid
+}
+
+impl CertificateLog {
+    // Constructs a new certificate log from given leaf node, id, issuer, subject and claims
+    pub fn new(
+        leaf_node: [u8; 32],
+        note_id: NoteId,
+        issuer: Pubkey,
+        subject: Pubkey,
+        claims_hash: [u8; 32],
+        expiry: i64,
+    ) -> Self {
+        Self {
+            leaf_node,
+            note_id,
+            issuer,
+            subject,
+            claims_hash,
+            expiry,
+        }
+    }
+}
+
+#[derive(AnchorSerialize)]
+pub struct RevocationLog {
+    leaf_node: [u8; 32],        // The leaf node hash of the revocation marker
+    note_id: NoteId,            // The id of the revoked leaf
+    certificate_leaf: [u8; 32], // The leaf node hash of the revoked certificate
+    slot: u64,                  // The slot of the revocation
+}
+
+impl RevocationLog {
+    // Constructs a new revocation log for the given certificate leaf node
+    pub fn new(
+        leaf_node: [u8; 32],
+        note_id: NoteId,
+        certificate_leaf: [u8; 32],
+        slot: u64,
+    ) -> Self {
+        Self {
+            leaf_node,
+            note_id,
+            certificate_leaf,
+            slot,
+        }
+    }
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct CertificateIssuer {
+    authority: Pubkey,   // The only signer allowed to issue and revoke certificates
+    merkle_tree: Pubkey, // The certificate tree
+    issued_count: u64,   // The number of certificates issued
+    revoked_count: u64,  // The number of certificates revoked
+    bump: u8,            // The bump seed for the pda
+}
+
+impl CertificateIssuer {
+    pub const SEED: &'static [u8] = b"issuer";
+}
+
+#[derive(Accounts)]
+pub struct CreateCertificateTree<'info> {
+    // The issuer authority, also pays for the issuer account
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    // The issuer pda, which is also the authority of the merkle tree
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + CertificateIssuer::INIT_SPACE,
+        seeds = [CertificateIssuer::SEED, merkle_tree.key().as_ref()],
+        bump,
+    )]
+    pub issuer: Account<'info, CertificateIssuer>,
+
+    // The forest pda of the creator, listing their trees, created with their first tree
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = 8 + Forest::INIT_SPACE,
+        seeds = [Forest::SEED, authority.key().as_ref()],
+        bump,
+    )]
+    pub forest: Account<'info, Forest>,
+
+    // The merkle tree account
+    /// CHECK: This account is validated by the spl account compression program
+    #[account(
+        mut,
+        owner = spl_account_compression::ID @ CompressedNotesError::InvalidMerkleTreeOwner,
+    )]
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    // The noop program to log data
+    pub log_wrapper: Program<'info, Noop>,
+
+    // The spl account compression program
+    pub compression_program: Program<'info, SplAccountCompression>,
+
+    // The system program, used to create the issuer account
+    pub system_program: Program<'info, System>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[derive(Accounts)]
+pub struct IssuerAccounts<'info> {
+    // The issuer authority
+    pub authority: Signer<'info>,
+
+    // The issuer pda, which is also the authority of the merkle tree
+    #[account(
+        mut,
+        seeds = [CertificateIssuer::SEED, merkle_tree.key().as_ref()],
+        bump = issuer.bump,
+        has_one = authority @ CompressedNotesError::Unauthorized,
+    )]
+    pub issuer: Account<'info, CertificateIssuer>,
+
+    // The certificate tree
+    /// CHECK: This account is validated by the spl account compression program
+    #[account(
+        mut,
+        owner = spl_account_compression::ID @ CompressedNotesError::InvalidMerkleTreeOwner,
+    )]
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    // The noop program to log data
+    pub log_wrapper: Program<'info, Noop>,
+
+    // The spl account compression program
+    pub compression_program: Program<'info, SplAccountCompression>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[derive(Accounts)]
+pub struct CheckCertificate<'info> {
+    // The issuer pda, ties the tree to its issuer
+    #[account(
+        seeds = [CertificateIssuer::SEED, merkle_tree.key().as_ref()],
+        bump = issuer.bump,
+    )]
+    pub issuer: Account<'info, CertificateIssuer>,
+
+    // The certificate tree, only read
+    /// CHECK: This account is validated by the spl account compression program
+    #[account(
+        owner = spl_account_compression::ID @ CompressedNotesError::InvalidMerkleTreeOwner,
+    )]
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    // The spl account compression program
+    pub compression_program: Program<'info, SplAccountCompression>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
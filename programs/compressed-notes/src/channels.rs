@@ -0,0 +1,317 @@
+//! Broadcast channels, trees only the channel authority appends to, with subscriptions indexers
+//! fan messages out to. Compiled in with the `channels` feature.
+
+use anchor_lang::prelude::*;
+use spl_account_compression::{program::SplAccountCompression, wrap_application_data_v1, Noop};
+
+use crate::{
+    hash_note_leaf, instruction_flags, leaf_schema, require_clean_text, CompressedNotesError,
+    Forest, GlobalConfig, NoteId, TreeCpi, TreePurpose,
+};
+
+// Creates a new tree owned by a broadcast channel. The channel pda is its compression
+// authority, so note and message instructions cannot append to it.
+pub fn process_create_channel_tree(
+    ctx: Context<CreateChannelTree>,
+    max_depth: u32,       // Max depth of the merkle tree
+    max_buffer_size: u32, // Max buffer size of the merkle tree
+) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::CREATE_CHANNEL_TREE)?;
+
+    let channel = &mut ctx.accounts.channel;
+    channel.authority = ctx.accounts.authority.key();
+    channel.merkle_tree = ctx.accounts.merkle_tree.key();
+    channel.bump = *ctx.bumps.get("channel").unwrap();
+
+    // CPI to initialize an empty merkle tree, with the channel pda as its authority
+    channel_tree_cpi(
+        &ctx.accounts.compression_program,
+        channel,
+        &ctx.accounts.merkle_tree,
+        &ctx.accounts.log_wrapper,
+    )
+    .init(max_depth, max_buffer_size)?;
+    ctx.accounts.forest.record(
+        ctx.accounts.authority.key(),
+        ctx.accounts.merkle_tree.key(),
+        TreePurpose::Channel,
+        *ctx.bumps.get("forest").unwrap(),
+    )
+}
+
+// Subscribes the signer to a channel.
+pub fn process_subscribe(ctx: Context<Subscribe>) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::SUBSCRIBE)?;
+
+    let subscription = &mut ctx.accounts.subscription;
+    subscription.channel = ctx.accounts.channel.key();
+    subscription.subscriber = ctx.accounts.subscriber.key();
+    subscription.subscribed_slot = Clock::get()?.slot;
+    subscription.bump = *ctx.bumps.get("subscription").unwrap();
+
+    ctx.accounts.channel.subscriber_count += 1;
+    Ok(())
+}
+
+// Unsubscribes the signer from a channel, refunding the subscription rent.
+pub fn process_unsubscribe(ctx: Context<Unsubscribe>) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::UNSUBSCRIBE)?;
+
+    ctx.accounts.channel.subscriber_count -= 1;
+    Ok(())
+}
+
+// Appends a message from the channel authority to the channel tree.
+pub fn process_broadcast(ctx: Context<Broadcast>, message: String) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::BROADCAST)?;
+    // Refuse text that indexers would display deceptively
+    require_clean_text(&message)?;
+
+    let authority = ctx.accounts.authority.key();
+    // Hash the message which will be stored as leaf node in the merkle tree
+    let leaf_node = hash_note_leaf(&message, &authority);
+    // Create a new "broadcast log" using the leaf node hash, its id, channel and message.
+    let note_id = NoteId::next(&ctx.accounts.merkle_tree, leaf_schema::V1)?;
+    let broadcast_log = BroadcastLog::new(
+        leaf_node,
+        note_id,
+        ctx.accounts.channel.key(),
+        authority,
+        message,
+    );
+    // Log the "broadcast log" data using noop program
+    wrap_application_data_v1(broadcast_log.try_to_vec()?, &ctx.accounts.log_wrapper)?;
+
+    ctx.accounts.channel.message_count += 1;
+
+    // CPI to append the leaf node to the channel tree
+    channel_tree_cpi(
+        &ctx.accounts.compression_program,
+        &ctx.accounts.channel,
+        &ctx.accounts.merkle_tree,
+        &ctx.accounts.log_wrapper,
+    )
+    .append(leaf_node)?;
+    Ok(())
+}
+
+// Builds a TreeCpi signing as the channel pda, the authority of channel trees
+fn channel_tree_cpi<'info>(
+    compression_program: &Program<'info, SplAccountCompression>,
+    channel: &Account<'info, Channel>,
+    merkle_tree: &UncheckedAccount<'info>,
+    log_wrapper: &Program<'info, Noop>,
+) -> TreeCpi<'info> {
+    TreeCpi::new(
+        compression_program,
+        channel,
+        merkle_tree,
+        log_wrapper,
+        channel.bump,
+    )
+    .with_seed_prefix(Channel::SEED)
+}
+
+#[derive(AnchorSerialize)]
+pub struct BroadcastLog {
+    leaf_node: [u8; 32], // The leaf node hash
+    note_id: NoteId,     // The id of the leaf
+    channel: Pubkey,     // The channel the message was broadcast on
+    authority: Pubkey,   // Pubkey of the channel authority
+    message: String,     // The message
+}
+
+impl BroadcastLog {
+    // Constructs a new broadcast log from given leaf node, id, channel and message
+    pub fn new(
+        leaf_node: [u8; 32],
+        note_id: NoteId,
+        channel: Pubkey,
+        authority: Pubkey,
+        message: String,
+    ) -> Self {
+        Self {
+            leaf_node,
+            note_id,
+            channel,
+            authority,
+            message,
+        }
+    }
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct Channel {
+    authority: Pubkey,     // The only signer allowed to broadcast on the channel
+    merkle_tree: Pubkey,   // The channel tree
+    subscriber_count: u64, // The number of active subscriptions
+    message_count: u64,    // The number of messages broadcast
+    bump: u8,              // The bump seed for the pda
+}
+
+impl Channel {
+    pub const SEED: &'static [u8] = b"channel";
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct Subscription {
+    channel: Pubkey,      // The channel subscribed to
+    subscriber: Pubkey,   // Pubkey of the subscriber
+    subscribed_slot: u64, // The slot the subscription was created
+    bump: u8,             // The bump seed for the pda
+}
+
+#[derive(Accounts)]
+pub struct CreateChannelTree<'info> {
+    // The channel authority, also pays for the channel account
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    // The channel pda, which is also the authority of the merkle tree
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + Channel::INIT_SPACE,
+        seeds = [Channel::SEED, merkle_tree.key().as_ref()],
+        bump,
+    )]
+    pub channel: Account<'info, Channel>,
+
+    // The forest pda of the creator, listing their trees, created with their first tree
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = 8 + Forest::INIT_SPACE,
+        seeds = [Forest::SEED, authority.key().as_ref()],
+        bump,
+    )]
+    pub forest: Account<'info, Forest>,
+
+    // The merkle tree account
+    /// CHECK: This account is validated by the spl account compression program
+    #[account(
+        mut,
+        owner = spl_account_compression::ID @ CompressedNotesError::InvalidMerkleTreeOwner,
+    )]
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    // The noop program to log data
+    pub log_wrapper: Program<'info, Noop>,
+
+    // The spl account compression program
+    pub compression_program: Program<'info, SplAccountCompression>,
+
+    // The system program, used to create the channel account
+    pub system_program: Program<'info, System>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[derive(Accounts)]
+pub struct Subscribe<'info> {
+    // The subscriber, also pays for the subscription account
+    #[account(mut)]
+    pub subscriber: Signer<'info>,
+
+    // The channel to subscribe to
+    #[account(
+        mut,
+        seeds = [Channel::SEED, channel.merkle_tree.as_ref()],
+        bump = channel.bump,
+    )]
+    pub channel: Account<'info, Channel>,
+
+    // The subscription pda
+    #[account(
+        init,
+        payer = subscriber,
+        space = 8 + Subscription::INIT_SPACE,
+        seeds = [b"subscription", channel.key().as_ref(), subscriber.key().as_ref()],
+        bump,
+    )]
+    pub subscription: Account<'info, Subscription>,
+
+    // The system program, used to create the subscription account
+    pub system_program: Program<'info, System>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[derive(Accounts)]
+pub struct Unsubscribe<'info> {
+    // The subscriber, receives the subscription rent
+    #[account(mut)]
+    pub subscriber: Signer<'info>,
+
+    // The channel to unsubscribe from
+    #[account(
+        mut,
+        seeds = [Channel::SEED, channel.merkle_tree.as_ref()],
+        bump = channel.bump,
+    )]
+    pub channel: Account<'info, Channel>,
+
+    // The subscription pda, closed by this instruction
+    #[account(
+        mut,
+        close = subscriber,
+        seeds = [b"subscription", channel.key().as_ref(), subscriber.key().as_ref()],
+        bump = subscription.bump,
+    )]
+    pub subscription: Account<'info, Subscription>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[derive(Accounts)]
+pub struct Broadcast<'info> {
+    // The channel authority
+    pub authority: Signer<'info>,
+
+    // The channel pda, which is also the authority of the merkle tree
+    #[account(
+        mut,
+        seeds = [Channel::SEED, merkle_tree.key().as_ref()],
+        bump = channel.bump,
+        has_one = authority @ CompressedNotesError::Unauthorized,
+    )]
+    pub channel: Account<'info, Channel>,
+
+    // The channel tree
+    /// CHECK: This account is validated by the spl account compression program
+    #[account(
+        mut,
+        owner = spl_account_compression::ID @ CompressedNotesError::InvalidMerkleTreeOwner,
+    )]
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    // The noop program to log data
+    pub log_wrapper: Program<'info, Noop>,
+
+    // The spl account compression program
+    pub compression_program: Program<'info, SplAccountCompression>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
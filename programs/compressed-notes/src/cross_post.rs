@@ -0,0 +1,233 @@
+//! Cross posting the same note to several trees in one instruction, linking the copies by note
+//! id in a `CrossPostLog`.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use anchor_lang::prelude::*;
+use anchor_spl::metadata::MetadataAccount;
+use anchor_spl::token::{Token, TokenAccount};
+use spl_account_compression::{program::SplAccountCompression, wrap_application_data_v1, Noop};
+
+use crate::{
+    hash_note_leaf_v2, instruction_flags, leaf_schema, CompressedNotesError, FeeOperation,
+    GlobalConfig, NoteId, NoteLog, NoteStatus, RateState, RecentLeaves, Role, TreeConfig, TreeCpi,
+    TreeSubscription, TREE_TREASURY_SEED,
+};
+
+// The most trees a cross post can append to, bounded by transaction account limits
+pub const MAX_CROSS_POST_TREES: usize = 4;
+
+// Appends a published note to every tree in the remaining accounts, each passed as the accounts
+// of a `CrossPostTree`.
+pub fn process_cross_post<'info>(
+    ctx: Context<'_, '_, '_, 'info, CrossPost<'info>>,
+    note: String, // The note message
+) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::CROSS_POST)?;
+
+    let owner = ctx.accounts.owner.key();
+    let status = NoteStatus::Published;
+    // Hash the "note message" which will be stored as leaf node in every tree
+    let leaf_node = hash_note_leaf_v2(&note, &owner, status);
+
+    let mut accounts = ctx.remaining_accounts;
+    let mut note_ids: Vec<NoteId> = Vec::with_capacity(MAX_CROSS_POST_TREES);
+    while !accounts.is_empty() {
+        require!(
+            note_ids.len() < MAX_CROSS_POST_TREES,
+            CompressedNotesError::InvalidCrossPostTrees
+        );
+        // The accounts of the tree are checked like those of any instruction
+        let mut bumps = BTreeMap::new();
+        let mut tree = CrossPostTree::try_accounts(
+            ctx.program_id,
+            &mut accounts,
+            &owner.try_to_vec()?,
+            &mut bumps,
+            &mut BTreeSet::new(),
+        )?;
+        let merkle_tree = tree.merkle_tree.key();
+        require!(
+            !note_ids
+                .iter()
+                .any(|note_id| note_id.merkle_tree == merkle_tree),
+            CompressedNotesError::InvalidCrossPostTrees
+        );
+
+        // Log the copy like any other note for indexers
+        let note_id = NoteId::next(&tree.merkle_tree, leaf_schema::V2)?;
+        let note_log = NoteLog::new(leaf_node, note_id, owner, note.clone(), status);
+        wrap_application_data_v1(note_log.try_to_vec()?, &ctx.accounts.log_wrapper)?;
+
+        // Count the append on the tree config, failing if the tree is frozen or private
+        TreeConfig::record_append(
+            &tree.tree_config,
+            &tree.merkle_tree,
+            &owner,
+            tree.role.as_deref(),
+        )?;
+        // Count the append against the rate limits of this tree
+        TreeConfig::record_rate(&tree.tree_config, tree.rate_state.as_deref_mut())?;
+        // A tree refusing duplicate leaves checks the leaf against its recent leaves
+        TreeConfig::record_leaf(
+            &tree.tree_config,
+            tree.recent_leaves.as_deref_mut(),
+            &leaf_node,
+        )?;
+        // A tree charging an append fee takes it from the owner into its tree treasury
+        TreeConfig::record_fee(
+            &tree.tree_config,
+            &ctx.accounts.owner,
+            tree.tree_treasury.as_ref(),
+            ctx.accounts.system_program.as_ref(),
+            FeeOperation::Append(note.len()),
+        )?;
+        // A tree charging a token append fee takes it from the owner into its fee vault
+        TreeConfig::record_token_fee(
+            &tree.tree_config,
+            &ctx.accounts.owner,
+            tree.payer_token_account.as_ref(),
+            tree.fee_vault.as_ref(),
+            ctx.accounts.token_program.as_ref(),
+        )?;
+        // A token- or nft-gated tree only accepts owners holding its gate
+        TreeConfig::require_gate(
+            &tree.tree_config,
+            &owner,
+            tree.gate_token_account.as_ref(),
+            tree.gate_metadata.as_ref(),
+        )?;
+        // A tree selling access only accepts posters with an active subscription
+        TreeConfig::require_subscription(&tree.tree_config, &owner, tree.subscription.as_deref())?;
+        // CPI to append the leaf node to this tree
+        TreeCpi::new(
+            &ctx.accounts.compression_program,
+            &tree.tree_authority,
+            &tree.merkle_tree,
+            &ctx.accounts.log_wrapper,
+            bumps["tree_authority"],
+        )
+        .append(leaf_node)?;
+
+        tree.exit(ctx.program_id)?;
+        note_ids.push(note_id);
+    }
+    require!(
+        !note_ids.is_empty(),
+        CompressedNotesError::InvalidCrossPostTrees
+    );
+
+    // Link the copies together
+    let cross_post_log = CrossPostLog::new(leaf_node, owner, note_ids);
+    wrap_application_data_v1(cross_post_log.try_to_vec()?, &ctx.accounts.log_wrapper)?;
+    Ok(())
+}
+
+#[derive(AnchorSerialize)]
+pub struct CrossPostLog {
+    leaf_node: [u8; 32],   // The leaf node hash appended to every tree
+    owner: Pubkey,         // Pubkey of the note owner
+    note_ids: Vec<NoteId>, // The ids of the copies, in the order of the trees
+}
+
+impl CrossPostLog {
+    // Constructs a new cross post log from given leaf node, owner and trees
+    pub fn new(leaf_node: [u8; 32], owner: Pubkey, note_ids: Vec<NoteId>) -> Self {
+        Self {
+            leaf_node,
+            owner,
+            note_ids,
+        }
+    }
+}
+
+// The accounts of one tree a note is cross posted to by `owner`
+#[derive(Accounts)]
+#[instruction(owner: Pubkey)]
+pub struct CrossPostTree<'info> {
+    // The role of the owner on the tree, if any, letting them write to a private tree
+    pub role: Option<Account<'info, Role>>,
+
+    // The rate state of the owner on the tree, needed to append to a rate limited tree
+    #[account(
+        mut,
+        seeds = [RateState::SEED, merkle_tree.key().as_ref(), owner.as_ref()],
+        bump,
+    )]
+    pub rate_state: Option<Account<'info, RateState>>,
+
+    // The recent leaves pda of the tree, needed to append to a tree refusing duplicate leaves
+    #[account(
+        mut,
+        seeds = [RecentLeaves::SEED, merkle_tree.key().as_ref()],
+        bump,
+    )]
+    pub recent_leaves: Option<Account<'info, RecentLeaves>>,
+
+    // The tree treasury pda, needed to append to a tree charging an append fee
+    #[account(mut, seeds = [TREE_TREASURY_SEED, merkle_tree.key().as_ref()], bump)]
+    pub tree_treasury: Option<SystemAccount<'info>>,
+
+    // The signer's token account paying the fee, needed to append to a tree charging a token fee
+    #[account(mut)]
+    pub payer_token_account: Option<Account<'info, TokenAccount>>,
+
+    // The fee vault pda of the tree's fee mint, needed to append to a tree charging a token fee
+    #[account(mut)]
+    pub fee_vault: Option<Account<'info, TokenAccount>>,
+
+    // A token account of the owner, needed to append to a token- or nft-gated tree
+    pub gate_token_account: Option<Account<'info, TokenAccount>>,
+
+    // The metadata account of the owner's nft, needed to append to an nft-gated tree
+    pub gate_metadata: Option<Account<'info, MetadataAccount>>,
+
+    // The owner's subscription to the tree, needed to append to a tree selling access
+    pub subscription: Option<Account<'info, TreeSubscription>>,
+
+    // The tree config pda, counting appends, which may not have been created
+    /// CHECK: Only read and written if it exists, the seeds tie it to the merkle tree
+    #[account(mut, seeds = [TreeConfig::SEED, merkle_tree.key().as_ref()], bump)]
+    pub tree_config: UncheckedAccount<'info>,
+
+    // The pda authority for the merkle tree, only used for signing
+    #[account(
+        seeds = [merkle_tree.key().as_ref()],
+        bump,
+    )]
+    pub tree_authority: SystemAccount<'info>,
+
+    // The merkle tree account
+    /// CHECK: This account is validated by the spl account compression program
+    #[account(
+        mut,
+        owner = spl_account_compression::ID @ CompressedNotesError::InvalidMerkleTreeOwner,
+    )]
+    pub merkle_tree: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CrossPost<'info> {
+    // The owner of the note, also pays the append fees of trees charging one
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    // The noop program to log data
+    pub log_wrapper: Program<'info, Noop>,
+
+    // The spl account compression program
+    pub compression_program: Program<'info, SplAccountCompression>,
+
+    // The system program, needed to append to trees charging append fees
+    pub system_program: Option<Program<'info, System>>,
+
+    // The spl token program, needed to append to trees charging a token fee
+    pub token_program: Option<Program<'info, Token>>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
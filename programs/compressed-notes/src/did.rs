@@ -0,0 +1,130 @@
+//! DID-linked note ownership, letting any current controller of a DID document sign for the
+//! notes it owns, so notes survive a key rotation.
+
+use anchor_lang::prelude::*;
+
+use crate::{instruction_flags, CompressedNotesError, GlobalConfig};
+
+// Creates a DID document controlled by the given keys.
+pub fn process_create_did(
+    ctx: Context<CreateDid>,
+    did_id: u64,              // Id of the document, unique per creator
+    controllers: Vec<Pubkey>, // The keys allowed to act for the document
+) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::CREATE_DID)?;
+
+    let did = &mut ctx.accounts.did;
+    did.creator = ctx.accounts.creator.key();
+    did.did_id = did_id;
+    did.set_controllers(controllers)?;
+    did.bump = *ctx.bumps.get("did").unwrap();
+    Ok(())
+}
+
+// Replaces the controllers of a DID document, signed by a current controller.
+pub fn process_set_did_controllers(
+    ctx: Context<SetDidControllers>,
+    controllers: Vec<Pubkey>, // The keys allowed to act for the document from now on
+) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::SET_DID_CONTROLLERS)?;
+
+    let did = &mut ctx.accounts.did;
+    did.require_controller(&ctx.accounts.controller.key())?;
+    did.set_controllers(controllers)
+}
+
+// Resolves the owner a note leaf commits to: the DID document passed as the optional `did`
+// account, in which case the signer must control it, otherwise the signer itself
+pub fn resolve_note_owner(signer: &Pubkey, did: &Option<Account<DidDocument>>) -> Result<Pubkey> {
+    match did {
+        Some(did) => {
+            did.require_controller(signer)?;
+            Ok(did.key())
+        }
+        None => Ok(*signer),
+    }
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct DidDocument {
+    creator: Pubkey, // The key that created the document, only used as a seed
+    did_id: u64,     // Id of the document, unique per creator
+    #[max_len(4)]
+    controllers: Vec<Pubkey>, // The keys allowed to act for the document
+    bump: u8,        // The bump seed for the pda
+}
+
+impl DidDocument {
+    pub const SEED: &'static [u8] = b"did";
+    // Must match the `max_len` of `controllers`
+    pub const MAX_CONTROLLERS: usize = 4;
+
+    // Fails unless `key` is one of the document's controllers
+    pub fn require_controller(&self, key: &Pubkey) -> Result<()> {
+        require!(
+            self.controllers.contains(key),
+            CompressedNotesError::Unauthorized
+        );
+        Ok(())
+    }
+
+    // Replaces the controllers, a document always keeps at least one
+    fn set_controllers(&mut self, controllers: Vec<Pubkey>) -> Result<()> {
+        require!(
+            (1..=DidDocument::MAX_CONTROLLERS).contains(&controllers.len()),
+            CompressedNotesError::InvalidDidControllers
+        );
+        self.controllers = controllers;
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+#[instruction(did_id: u64)]
+pub struct CreateDid<'info> {
+    // The creator of the document, also pays for the document account
+    #[account(mut)]
+    pub creator: Signer<'info>,
+
+    // The DID document pda
+    #[account(
+        init,
+        payer = creator,
+        space = 8 + DidDocument::INIT_SPACE,
+        seeds = [DidDocument::SEED, creator.key().as_ref(), &did_id.to_le_bytes()],
+        bump,
+    )]
+    pub did: Account<'info, DidDocument>,
+
+    // The system program, used to create the document account
+    pub system_program: Program<'info, System>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[derive(Accounts)]
+pub struct SetDidControllers<'info> {
+    // A current controller of the document
+    pub controller: Signer<'info>,
+
+    // The DID document pda
+    #[account(
+        mut,
+        seeds = [DidDocument::SEED, did.creator.as_ref(), &did.did_id.to_le_bytes()],
+        bump = did.bump,
+    )]
+    pub did: Account<'info, DidDocument>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
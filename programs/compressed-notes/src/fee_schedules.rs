@@ -0,0 +1,73 @@
+//! Per-tree fee schedules pricing appends, updates and deletes separately, charged through
+//! `TreeConfig::record_fee` into the tree treasury.
+
+use anchor_lang::prelude::*;
+
+use crate::{instruction_flags, top_up_tree_treasury, SetAppendFee};
+
+// Sets the lamports appends, updates and deletes on the tree pay into its tree treasury, where
+// `set_append_fee` only sets the base fee and `quote_append` prices appends the same way.
+pub fn process_set_fee_schedule(
+    ctx: Context<SetAppendFee>,
+    base_fee: u64,     // Lamports each append pays
+    per_byte_fee: u64, // Lamports each byte of content appended or updated pays
+    update_fee: u64,   // Lamports each update pays
+    delete_fee: u64,   // Lamports each delete pays
+) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::SET_FEE_SCHEDULE)?;
+
+    let fees = FeeSchedule {
+        base_fee,
+        per_byte_fee,
+        update_fee,
+        delete_fee,
+    };
+    if fees.charges() {
+        top_up_tree_treasury(
+            &ctx.accounts.system_program,
+            &ctx.accounts.authority,
+            &ctx.accounts.tree_treasury,
+        )?;
+    }
+    ctx.accounts.tree_config.set_fee_schedule(fees);
+    Ok(())
+}
+
+// The lamports each operation on a tree pays into its tree treasury
+#[derive(
+    AnchorSerialize, AnchorDeserialize, InitSpace, Clone, Copy, Debug, Default, PartialEq, Eq,
+)]
+pub struct FeeSchedule {
+    pub base_fee: u64,     // Lamports each append pays
+    pub per_byte_fee: u64, // Lamports each byte of content appended or updated pays
+    pub update_fee: u64,   // Lamports each update pays
+    pub delete_fee: u64,   // Lamports each delete pays
+}
+
+// An operation on a tree priced by its fee schedule
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FeeOperation {
+    Append(usize), // Appending a leaf with content of the given bytes
+    Update(usize), // Replacing a leaf with new content of the given bytes
+    Delete,        // Deleting a note
+}
+
+impl FeeSchedule {
+    // Whether any operation costs lamports
+    pub fn charges(&self) -> bool {
+        *self != FeeSchedule::default()
+    }
+
+    // The lamports `operation` pays, saturating since no payer could cover the excess
+    pub fn fee_for(&self, operation: FeeOperation) -> u64 {
+        let per_byte = |bytes: usize| self.per_byte_fee.saturating_mul(bytes as u64);
+        match operation {
+            FeeOperation::Append(bytes) => self.base_fee.saturating_add(per_byte(bytes)),
+            FeeOperation::Update(bytes) => self.update_fee.saturating_add(per_byte(bytes)),
+            FeeOperation::Delete => self.delete_fee,
+        }
+    }
+}
@@ -0,0 +1,60 @@
+//! Per-creator tree registry in a forest pda at `[b"forest", creator]`, listing every tree the
+//! creator made so clients find them from one account.
+
+use anchor_lang::prelude::*;
+
+use crate::CompressedNotesError;
+
+// What a tree in a forest holds
+#[derive(AnchorSerialize, AnchorDeserialize, InitSpace, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TreePurpose {
+    Notes,        // create_note_tree
+    Conversation, // create_conversation_tree
+    Channel,      // create_channel_tree
+    Certificates, // create_certificate_tree
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, InitSpace, Clone, Copy, Debug)]
+pub struct ForestEntry {
+    pub merkle_tree: Pubkey,  // The tree created
+    pub purpose: TreePurpose, // What the tree holds
+    pub created_at: i64,      // The unix timestamp the tree was created
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct Forest {
+    creator: Pubkey, // The creator whose trees are listed
+    #[max_len(32)]
+    trees: Vec<ForestEntry>, // The trees created, oldest first
+    bump: u8,        // The bump seed for the pda
+}
+
+impl Forest {
+    pub const SEED: &'static [u8] = b"forest";
+    // Must match the `max_len` of `trees`
+    pub const MAX_TREES: usize = 32;
+
+    // Lists a new tree of `creator`, creating the forest on their first tree, failing with
+    // `ForestFull` past `MAX_TREES`
+    pub fn record(
+        &mut self,
+        creator: Pubkey,
+        merkle_tree: Pubkey,
+        purpose: TreePurpose,
+        bump: u8,
+    ) -> Result<()> {
+        require!(
+            self.trees.len() < Forest::MAX_TREES,
+            CompressedNotesError::ForestFull
+        );
+        self.creator = creator;
+        self.bump = bump;
+        self.trees.push(ForestEntry {
+            merkle_tree,
+            purpose,
+            created_at: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+}
@@ -0,0 +1,248 @@
+//! Ethereum identities linked to wallets in a pda at `[b"identity_link", wallet]`, so notes can
+//! be attributed to an EVM address.
+
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::instruction::Instruction;
+use anchor_lang::solana_program::sysvar::instructions::{
+    self as sysvar_instructions, load_current_index_checked, load_instruction_at_checked,
+};
+use anchor_lang::solana_program::{keccak, secp256k1_program};
+
+use crate::{
+    hash_note_leaf_v2, instruction_flags, leaf_domain, CompressedNotesError, GlobalConfig, NoteId,
+    NoteStatus,
+};
+
+// Prefix of every link message, so no other signed message doubles as a link
+pub const LINK_DOMAIN: &[u8] = b"compressed-notes:link_eth_identity";
+// The prefix `personal_sign` puts before a 32 byte message
+pub const ETH_SIGNED_MESSAGE_PREFIX: &[u8] = b"\x19Ethereum Signed Message:\n32";
+
+// Hashes the wallet into the message its ethereum address signs to be linked to it
+pub fn link_message(wallet: &Pubkey) -> [u8; 32] {
+    keccak::hashv(&[LINK_DOMAIN, crate::ID.as_ref(), wallet.as_ref()]).to_bytes()
+}
+
+// Hashes a note message, its owner, its status and the owner's linked ethereum address into
+// the leaf node of an attributed note. The message is hashed on its own first
+pub fn hash_attributed_note_leaf(
+    note: &str,
+    owner: &Pubkey,
+    status: NoteStatus,
+    eth_address: &[u8; 20],
+) -> [u8; 32] {
+    keccak::hashv(&[
+        &leaf_domain::tag(leaf_domain::ATTRIBUTED_NOTE),
+        &keccak::hash(note.as_bytes()).to_bytes(),
+        owner.as_ref(),
+        &[status as u8],
+        eth_address,
+    ])
+    .to_bytes()
+}
+
+// Hashes a note leaf, attributed to the owner's linked ethereum address if their identity
+// link is given, along with the leaf schema it uses. Updates need the same link to recompute
+// an attributed leaf, so it stays editable only while the address is linked
+pub fn hash_note_leaf_for(
+    note: &str,
+    owner: &Pubkey,
+    status: NoteStatus,
+    identity_link: &Option<Account<IdentityLink>>,
+) -> Result<([u8; 32], u16)> {
+    match identity_link {
+        Some(identity_link) => {
+            require_keys_eq!(
+                identity_link.wallet,
+                *owner,
+                CompressedNotesError::Unauthorized
+            );
+            let leaf = hash_attributed_note_leaf(note, owner, status, &identity_link.eth_address);
+            Ok((leaf, crate::leaf_schema::V8))
+        }
+        None => Ok((
+            hash_note_leaf_v2(note, owner, status),
+            crate::leaf_schema::V2,
+        )),
+    }
+}
+
+// Links `eth_address` to the signer, proven by its `personal_sign` signature of the link
+// message, verified by a secp256k1 instruction right before this one.
+pub fn process_link_eth_identity(
+    ctx: Context<LinkEthIdentity>,
+    eth_address: [u8; 20], // The ethereum address linked to the signer
+    signature: [u8; 65],   // The address's signature of the link message, with its recovery id
+) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::LINK_ETH_IDENTITY)?;
+
+    // The secp256k1 program verified the signature in the instruction before this one
+    let wallet = ctx.accounts.wallet.key();
+    let message = [ETH_SIGNED_MESSAGE_PREFIX, &link_message(&wallet)].concat();
+    let instructions = &ctx.accounts.instructions;
+    let current_index = load_current_index_checked(instructions)?;
+    require!(current_index > 0, CompressedNotesError::InvalidEthSignature);
+    let verify_index = current_index - 1;
+    let verify_ix = load_instruction_at_checked(verify_index.into(), instructions)?;
+    require_secp256k1_signature(&verify_ix, verify_index, &eth_address, &signature, &message)?;
+
+    let identity_link = &mut ctx.accounts.identity_link;
+    identity_link.wallet = wallet;
+    identity_link.eth_address = eth_address;
+    identity_link.linked_at = Clock::get()?.unix_timestamp;
+    identity_link.bump = *ctx.bumps.get("identity_link").unwrap();
+    Ok(())
+}
+
+// Removes the signer's identity link, refunding the rent to them.
+pub fn process_unlink_eth_identity(ctx: Context<UnlinkEthIdentity>) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::UNLINK_ETH_IDENTITY)?;
+    Ok(())
+}
+
+// Fails unless `ix`, at `ix_index` in the transaction, is a secp256k1 program instruction
+// verifying exactly one `signature` by `eth_address` over `message`, all held in its own data
+pub fn require_secp256k1_signature(
+    ix: &Instruction,
+    ix_index: u16,
+    eth_address: &[u8; 20],
+    signature: &[u8; 65],
+    message: &[u8],
+) -> Result<()> {
+    require_keys_eq!(
+        ix.program_id,
+        secp256k1_program::ID,
+        CompressedNotesError::InvalidEthSignature
+    );
+    // One signature count byte, then the offsets of the signature, address and message,
+    // each u16 offsets followed by the u8 index of the instruction holding them
+    let data = &ix.data;
+    require!(
+        data.len() >= 12 && data[0] == 1,
+        CompressedNotesError::InvalidEthSignature
+    );
+    let offset = |at: usize| u16::from_le_bytes([data[at], data[at + 1]]) as usize;
+    let (signature_offset, signature_ix) = (offset(1), data[3]);
+    let (eth_address_offset, eth_address_ix) = (offset(4), data[6]);
+    let (message_offset, message_size, message_ix) = (offset(7), offset(9), data[11]);
+    require!(
+        [signature_ix, eth_address_ix, message_ix]
+            .iter()
+            .all(|index| u16::from(*index) == ix_index),
+        CompressedNotesError::InvalidEthSignature
+    );
+
+    let field = |offset: usize, len: usize| data.get(offset..offset + len);
+    require!(
+        field(eth_address_offset, 20) == Some(&eth_address[..])
+            && field(signature_offset, 65) == Some(&signature[..])
+            && message_size == message.len()
+            && field(message_offset, message.len()) == Some(message),
+        CompressedNotesError::InvalidEthSignature
+    );
+    Ok(())
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct IdentityLink {
+    wallet: Pubkey,        // The wallet the address is linked to
+    eth_address: [u8; 20], // The linked ethereum address
+    linked_at: i64,        // The unix timestamp the address was linked
+    bump: u8,              // The bump seed for the pda
+}
+
+impl IdentityLink {
+    pub const SEED: &'static [u8] = b"identity_link";
+}
+
+#[derive(AnchorSerialize)]
+pub struct EthAttributionLog {
+    leaf_node: [u8; 32],   // The leaf node hash of the attributed note
+    note_id: NoteId,       // The id of the leaf
+    owner: Pubkey,         // Pubkey of the note owner
+    eth_address: [u8; 20], // The owner's linked ethereum address committed into the leaf
+}
+
+impl EthAttributionLog {
+    // Constructs a new eth attribution log from given leaf node, id, owner and address
+    pub fn new(leaf_node: [u8; 32], note_id: NoteId, owner: Pubkey, eth_address: [u8; 20]) -> Self {
+        Self {
+            leaf_node,
+            note_id,
+            owner,
+            eth_address,
+        }
+    }
+
+    // Constructs the attribution log of a leaf hashed with `identity_link`, if any
+    pub fn for_leaf(
+        leaf_node: [u8; 32],
+        note_id: NoteId,
+        identity_link: &Option<Account<IdentityLink>>,
+    ) -> Option<Self> {
+        identity_link.as_ref().map(|identity_link| {
+            Self::new(
+                leaf_node,
+                note_id,
+                identity_link.wallet,
+                identity_link.eth_address,
+            )
+        })
+    }
+}
+
+#[derive(Accounts)]
+pub struct LinkEthIdentity<'info> {
+    // The wallet the address is linked to, pays for the identity link account
+    #[account(mut)]
+    pub wallet: Signer<'info>,
+
+    // The identity link pda, one per wallet
+    #[account(
+        init,
+        payer = wallet,
+        space = 8 + IdentityLink::INIT_SPACE,
+        seeds = [IdentityLink::SEED, wallet.key().as_ref()],
+        bump,
+    )]
+    pub identity_link: Account<'info, IdentityLink>,
+
+    // The instructions sysvar, used to read the secp256k1 instruction verifying the signature
+    /// CHECK: The address constraint ties it to the instructions sysvar
+    #[account(address = sysvar_instructions::ID)]
+    pub instructions: UncheckedAccount<'info>,
+
+    // The system program, used to create the identity link account
+    pub system_program: Program<'info, System>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[derive(Accounts)]
+pub struct UnlinkEthIdentity<'info> {
+    // The wallet the address is linked to, refunded the identity link rent
+    #[account(mut)]
+    pub wallet: Signer<'info>,
+
+    // The identity link pda, closed by this instruction
+    #[account(
+        mut,
+        close = wallet,
+        seeds = [IdentityLink::SEED, wallet.key().as_ref()],
+        bump = identity_link.bump,
+    )]
+    pub identity_link: Account<'info, IdentityLink>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
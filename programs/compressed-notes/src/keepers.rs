@@ -0,0 +1,261 @@
+//! Keeper SLAs, bonding lamports against checkpointing a tree every `sla_slots` slots. Compiled
+//! in with the `keepers` feature.
+
+use anchor_lang::prelude::*;
+use anchor_lang::system_program::{transfer, Transfer};
+use spl_account_compression::{wrap_application_data_v1, Noop};
+
+use crate::{instruction_flags, CompressedNotesError, GlobalConfig, TreeState};
+
+// Registers the signer as a keeper of a tree, escrowing `bond` lamports.
+pub fn process_register_keeper(
+    ctx: Context<RegisterKeeper>,
+    bond: u64,      // Lamports slashed if the keeper misses its SLA
+    sla_slots: u64, // The most slots allowed between two checkpoints
+) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::REGISTER_KEEPER)?;
+
+    // A new tree account fails here, before anything is escrowed
+    let tree_state = TreeState::load(&ctx.accounts.merkle_tree)?;
+
+    let keeper_bond = &mut ctx.accounts.keeper_bond;
+    keeper_bond.keeper = ctx.accounts.keeper.key();
+    keeper_bond.merkle_tree = ctx.accounts.merkle_tree.key();
+    keeper_bond.bond = bond;
+    keeper_bond.sla_slots = sla_slots;
+    keeper_bond.record(&tree_state, Clock::get()?.slot);
+    keeper_bond.bump = *ctx.bumps.get("keeper_bond").unwrap();
+
+    // Move the bond into the keeper bond pda
+    let cpi_ctx = CpiContext::new(
+        ctx.accounts.system_program.to_account_info(),
+        Transfer {
+            from: ctx.accounts.keeper.to_account_info(),
+            to: ctx.accounts.keeper_bond.to_account_info(),
+        },
+    );
+    transfer(cpi_ctx, bond)
+}
+
+// Checkpoints the tree, proving the keeper is still maintaining it. Checkpoints run while the
+// deployment is paused, so a pause never makes a keeper miss its window.
+pub fn process_keeper_checkpoint(ctx: Context<KeeperCheckpoint>) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::KEEPER_CHECKPOINT)?;
+
+    let slot = Clock::get()?.slot;
+    let keeper_bond = &mut ctx.accounts.keeper_bond;
+    // A missed window cannot be made up for, the bond is forfeit
+    require!(
+        !keeper_bond.missed_sla(slot),
+        CompressedNotesError::SlaMissed
+    );
+
+    let tree_state = TreeState::load(&ctx.accounts.merkle_tree)?;
+    keeper_bond.record(&tree_state, slot);
+
+    // Log the checkpoint for indexers and tree operators
+    let checkpoint_log = KeeperCheckpointLog::new(
+        keeper_bond.keeper,
+        keeper_bond.merkle_tree,
+        tree_state.root,
+        tree_state.sequence_number,
+        slot,
+    );
+    wrap_application_data_v1(checkpoint_log.try_to_vec()?, &ctx.accounts.log_wrapper)?;
+    Ok(())
+}
+
+// Slashes a keeper that missed its SLA, the bond and rent go to the signer.
+pub fn process_slash_keeper(ctx: Context<SlashKeeper>) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::SLASH_KEEPER)?;
+
+    require!(
+        ctx.accounts.keeper_bond.missed_sla(Clock::get()?.slot),
+        CompressedNotesError::SlaNotMissed
+    );
+    Ok(())
+}
+
+// Withdraws a keeper in good standing, refunding the bond and rent.
+pub fn process_deregister_keeper(ctx: Context<DeregisterKeeper>) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::DEREGISTER_KEEPER)?;
+
+    require!(
+        !ctx.accounts.keeper_bond.missed_sla(Clock::get()?.slot),
+        CompressedNotesError::SlaMissed
+    );
+    Ok(())
+}
+
+#[derive(AnchorSerialize)]
+pub struct KeeperCheckpointLog {
+    keeper: Pubkey,       // Pubkey of the keeper
+    merkle_tree: Pubkey,  // The tree checkpointed
+    root: [u8; 32],       // The root of the tree at the checkpoint
+    sequence_number: u64, // The number of changes applied to the tree at the checkpoint
+    slot: u64,            // The slot of the checkpoint
+}
+
+impl KeeperCheckpointLog {
+    // Constructs a new checkpoint log from given keeper, tree, root, sequence number and slot
+    pub fn new(
+        keeper: Pubkey,
+        merkle_tree: Pubkey,
+        root: [u8; 32],
+        sequence_number: u64,
+        slot: u64,
+    ) -> Self {
+        Self {
+            keeper,
+            merkle_tree,
+            root,
+            sequence_number,
+            slot,
+        }
+    }
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct KeeperBond {
+    keeper: Pubkey,      // The keeper committed to maintaining the tree
+    merkle_tree: Pubkey, // The tree maintained
+    bond: u64,           // Lamports escrowed on top of the rent
+    sla_slots: u64,      // The most slots allowed between two checkpoints
+    last_slot: u64,      // The slot of the latest checkpoint
+    last_root: [u8; 32], // The root of the tree at the latest checkpoint
+    last_sequence: u64,  // The sequence number of the tree at the latest checkpoint
+    bump: u8,            // The bump seed for the pda
+}
+
+impl KeeperBond {
+    pub const SEED: &'static [u8] = b"keeper";
+
+    // Whether the keeper let more than `sla_slots` slots pass since its latest checkpoint
+    pub fn missed_sla(&self, slot: u64) -> bool {
+        slot > self.last_slot.saturating_add(self.sla_slots)
+    }
+
+    // Records a checkpoint of the tree at `slot`
+    pub fn record(&mut self, tree_state: &TreeState, slot: u64) {
+        self.last_slot = slot;
+        self.last_root = tree_state.root;
+        self.last_sequence = tree_state.sequence_number;
+    }
+}
+
+#[derive(Accounts)]
+pub struct RegisterKeeper<'info> {
+    // The keeper, pays the bond and the keeper bond account
+    #[account(mut)]
+    pub keeper: Signer<'info>,
+
+    // The merkle tree to maintain
+    /// CHECK: Parsed as a tree account owned by the spl account compression program
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    // The keeper bond pda, one per tree and keeper
+    #[account(
+        init,
+        payer = keeper,
+        space = 8 + KeeperBond::INIT_SPACE,
+        seeds = [KeeperBond::SEED, merkle_tree.key().as_ref(), keeper.key().as_ref()],
+        bump,
+    )]
+    pub keeper_bond: Account<'info, KeeperBond>,
+
+    // The system program, used to create the keeper bond account and escrow the bond
+    pub system_program: Program<'info, System>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[derive(Accounts)]
+pub struct KeeperCheckpoint<'info> {
+    // The keeper
+    pub keeper: Signer<'info>,
+
+    // The merkle tree maintained
+    /// CHECK: Parsed as a tree account owned by the spl account compression program
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    // The keeper bond pda
+    #[account(
+        mut,
+        seeds = [KeeperBond::SEED, merkle_tree.key().as_ref(), keeper.key().as_ref()],
+        bump = keeper_bond.bump,
+        has_one = keeper @ CompressedNotesError::Unauthorized,
+        has_one = merkle_tree,
+    )]
+    pub keeper_bond: Account<'info, KeeperBond>,
+
+    // The noop program to log data
+    pub log_wrapper: Program<'info, Noop>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[derive(Accounts)]
+pub struct SlashKeeper<'info> {
+    // Anyone, receives the bond and the keeper bond rent
+    #[account(mut)]
+    pub slasher: Signer<'info>,
+
+    // The keeper bond pda, closed by this instruction
+    #[account(
+        mut,
+        close = slasher,
+        seeds = [
+            KeeperBond::SEED,
+            keeper_bond.merkle_tree.as_ref(),
+            keeper_bond.keeper.as_ref(),
+        ],
+        bump = keeper_bond.bump,
+    )]
+    pub keeper_bond: Account<'info, KeeperBond>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[derive(Accounts)]
+pub struct DeregisterKeeper<'info> {
+    // The keeper, receives the bond and the keeper bond rent
+    #[account(mut)]
+    pub keeper: Signer<'info>,
+
+    // The keeper bond pda, closed by this instruction
+    #[account(
+        mut,
+        close = keeper,
+        seeds = [
+            KeeperBond::SEED,
+            keeper_bond.merkle_tree.as_ref(),
+            keeper.key().as_ref(),
+        ],
+        bump = keeper_bond.bump,
+        has_one = keeper @ CompressedNotesError::Unauthorized,
+    )]
+    pub keeper_bond: Account<'info, KeeperBond>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
@@ -0,0 +1,144 @@
+//! Legal holds on leaves in a pda at `[b"hold", merkle_tree, leaf_hash]`, refusing replaces of a
+//! held leaf through the tree config.
+
+use anchor_lang::prelude::*;
+use spl_account_compression::{wrap_application_data_v1, Noop};
+
+use crate::{instruction_flags, CompressedNotesError, GlobalConfig, Role, RoleKind, TreeConfig};
+
+// Puts the leaf `leaf_hash` on legal hold, or lifts its hold, refunding the hold rent. While the
+// tree holds any leaf, replaces take the hold pda of their leaf as the optional `hold` account.
+pub fn process_set_legal_hold(
+    ctx: Context<SetLegalHold>,
+    leaf_hash: [u8; 32], // The leaf node held or released
+    held: bool,          // Whether the leaf is put on hold or released
+) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::SET_LEGAL_HOLD)?;
+
+    let merkle_tree = ctx.accounts.merkle_tree.key();
+    let authority = ctx.accounts.authority.key();
+    // A hold pda created by this instruction is still zeroed
+    let hold = &mut ctx.accounts.hold;
+    let was_held = hold.merkle_tree != Pubkey::default();
+    ctx.accounts.tree_config.count_hold(was_held, held);
+    if held && !was_held {
+        hold.merkle_tree = merkle_tree;
+        hold.leaf_hash = leaf_hash;
+        hold.held_by = authority;
+        hold.held_at = Clock::get()?.unix_timestamp;
+        hold.bump = *ctx.bumps.get("hold").unwrap();
+    } else if !held {
+        hold.close(ctx.accounts.authority.to_account_info())?;
+    }
+
+    // Log the change so indexers can mark the record as held
+    let hold_log = LegalHoldLog::new(merkle_tree, leaf_hash, authority, held, Clock::get()?.slot);
+    wrap_application_data_v1(hold_log.try_to_vec()?, &ctx.accounts.log_wrapper)?;
+    Ok(())
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct Hold {
+    merkle_tree: Pubkey, // The tree holding the leaf
+    leaf_hash: [u8; 32], // The leaf node on hold
+    held_by: Pubkey,     // The compliance member who put the leaf on hold
+    held_at: i64,        // The unix timestamp the leaf was put on hold
+    bump: u8,            // The bump seed for the pda
+}
+
+impl Hold {
+    pub const SEED: &'static [u8] = b"hold";
+
+    // Fails unless `hold` is the never created hold pda of `leaf` in `merkle_tree`
+    pub fn require_unheld(
+        hold: Option<&AccountInfo>,
+        merkle_tree: &Pubkey,
+        leaf: &[u8; 32],
+    ) -> Result<()> {
+        let hold = hold.ok_or(CompressedNotesError::HoldMissing)?;
+        let (address, _) =
+            Pubkey::find_program_address(&[Hold::SEED, merkle_tree.as_ref(), leaf], &crate::ID);
+        require_keys_eq!(*hold.key, address, CompressedNotesError::HoldMissing);
+        require!(hold.data_is_empty(), CompressedNotesError::LeafOnHold);
+        Ok(())
+    }
+}
+
+#[derive(AnchorSerialize)]
+pub struct LegalHoldLog {
+    merkle_tree: Pubkey, // The tree holding the leaf
+    leaf_hash: [u8; 32], // The leaf node held or released
+    authority: Pubkey,   // Pubkey of the compliance member
+    held: bool,          // Whether the leaf was put on hold or released
+    slot: u64,           // The slot of the change
+}
+
+impl LegalHoldLog {
+    // Constructs a new legal hold log from given tree, leaf, authority, held flag and slot
+    pub fn new(
+        merkle_tree: Pubkey,
+        leaf_hash: [u8; 32],
+        authority: Pubkey,
+        held: bool,
+        slot: u64,
+    ) -> Self {
+        Self {
+            merkle_tree,
+            leaf_hash,
+            authority,
+            held,
+            slot,
+        }
+    }
+}
+
+#[derive(Accounts)]
+#[instruction(leaf_hash: [u8; 32])]
+pub struct SetLegalHold<'info> {
+    // The compliance member, pays for or is refunded the hold account
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    // The role of the signer, which must include the compliance role
+    #[account(
+        constraint = tree_config.grants(Some(&role), &authority.key(), RoleKind::Compliance)
+            @ CompressedNotesError::Unauthorized,
+    )]
+    pub role: Account<'info, Role>,
+
+    // The hold pda of the leaf, created when the leaf is first held
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = 8 + Hold::INIT_SPACE,
+        seeds = [Hold::SEED, merkle_tree.key().as_ref(), leaf_hash.as_ref()],
+        bump,
+    )]
+    pub hold: Account<'info, Hold>,
+
+    // The tree config pda, counting the leaves on hold
+    #[account(
+        mut,
+        seeds = [TreeConfig::SEED, merkle_tree.key().as_ref()],
+        bump,
+    )]
+    pub tree_config: Account<'info, TreeConfig>,
+
+    // The merkle tree account
+    /// CHECK: Only used to derive the hold and tree config pdas
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    // The noop program to log data
+    pub log_wrapper: Program<'info, Noop>,
+
+    // The system program, used to create the hold account
+    pub system_program: Program<'info, System>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
@@ -1,170 +1,2354 @@
+#![allow(clippy::result_large_err)]
+// The generated cpi module mirrors the instruction arguments, update_message has nine
+#![allow(clippy::too_many_arguments)]
+
 use anchor_lang::{
-    prelude::*, 
-    solana_program::keccak
+    prelude::*,
+    solana_program::{keccak, sysvar::instructions as sysvar_instructions},
+    system_program::{create_account, CreateAccount},
 };
+use anchor_spl::metadata::MetadataAccount;
+use anchor_spl::token::{Token, TokenAccount};
 use spl_account_compression::{
-    Noop,
-    program::SplAccountCompression,
     cpi::{
-        accounts::{Initialize, Modify, VerifyLeaf},
-        init_empty_merkle_tree, verify_leaf, replace_leaf, append, 
+        accounts::{CloseTree as CloseEmptyTree, Initialize, Modify, VerifyLeaf},
+        append, close_empty_tree, init_empty_merkle_tree, replace_leaf, verify_leaf,
     },
-    wrap_application_data_v1, 
+    program::SplAccountCompression,
+    wrap_application_data_v1, Noop,
 };
 
-declare_id!("2CCvZS82NbYtLDuQgegGJB9pspjMizama2tQQy8Vu6Ps");
+// Builds a TreeCpi from an instruction context holding the standard tree accounts
+macro_rules! tree_cpi {
+    ($ctx:expr) => {
+        $crate::TreeCpi::new(
+            &$ctx.accounts.compression_program,
+            &$ctx.accounts.tree_authority,
+            &$ctx.accounts.merkle_tree,
+            &$ctx.accounts.log_wrapper,
+            *$ctx.bumps.get("tree_authority").unwrap(),
+        )
+    };
+}
+
+// Runs the handler of an optional subsystem, or fails with SubsystemNotCompiled
+// when the subsystem's cargo feature was left out of this build
+macro_rules! subsystem {
+    ($feature:literal, $handler:path, $($arg:ident),*) => {{
+        #[cfg(feature = $feature)]
+        return $handler($($arg),*);
+        #[cfg(not(feature = $feature))]
+        {
+            let _ = ($($arg),*);
+            return err!(CompressedNotesError::SubsystemNotCompiled);
+        }
+    }};
+}
+
+// Declares empty stand-ins for the accounts contexts of an optional subsystem when its cargo
+// feature was left out of this build. `#[program]` does not see cfg attributes, so the
+// entrypoints of the subsystem stay and need a context, but take no accounts and only fail
+// with SubsystemNotCompiled. The instruction argument types of the subsystem, listed in
+// `args`, stand in as unit structs
+macro_rules! stand_ins {
+    ($feature:literal, args: [$($arg:ident),*], $($context:ident),* $(,)?) => {
+        $(
+            #[cfg(not(feature = $feature))]
+            #[derive(AnchorSerialize, AnchorDeserialize)]
+            pub struct $arg;
+        )*
+        stand_ins!($feature, $($context),*);
+    };
+    ($feature:literal, $($context:ident),* $(,)?) => {$(
+        #[cfg(not(feature = $feature))]
+        pub struct $context<'info>(std::marker::PhantomData<&'info ()>);
+
+        #[cfg(not(feature = $feature))]
+        impl<'info> Accounts<'info> for $context<'info> {
+            fn try_accounts(
+                _program_id: &Pubkey,
+                _accounts: &mut &[AccountInfo<'info>],
+                _ix_data: &[u8],
+                _bumps: &mut std::collections::BTreeMap<String, u8>,
+                _reallocs: &mut std::collections::BTreeSet<Pubkey>,
+            ) -> Result<Self> {
+                Ok(Self(std::marker::PhantomData))
+            }
+        }
+
+        #[cfg(not(feature = $feature))]
+        impl<'info> AccountsExit<'info> for $context<'info> {}
+
+        #[cfg(not(feature = $feature))]
+        impl<'info> ToAccountInfos<'info> for $context<'info> {
+            fn to_account_infos(&self) -> Vec<AccountInfo<'info>> {
+                Vec::new()
+            }
+        }
+
+        #[cfg(not(feature = $feature))]
+        impl ToAccountMetas for $context<'_> {
+            fn to_account_metas(&self, _is_signer: Option<bool>) -> Vec<AccountMeta> {
+                Vec::new()
+            }
+        }
+
+        // The client and cpi client structs `#[program]` re-exports for every context
+        paste::paste! {
+            #[cfg(not(feature = $feature))]
+            pub mod [<__client_accounts_ $context:snake>] {
+                use anchor_lang::prelude::*;
+
+                pub struct $context;
+
+                impl ToAccountMetas for $context {
+                    fn to_account_metas(&self, _is_signer: Option<bool>) -> Vec<AccountMeta> {
+                        Vec::new()
+                    }
+                }
+            }
+
+            #[cfg(not(feature = $feature))]
+            pub mod [<__cpi_client_accounts_ $context:snake>] {
+                pub use crate::$context;
+            }
+        }
+    )*};
+}
+
+#[cfg(feature = "audit")]
+pub mod audit;
+#[cfg(feature = "certificates")]
+pub mod certificates;
+#[cfg(feature = "channels")]
+pub mod channels;
+pub mod cross_post;
+pub mod did;
+pub mod fee_schedules;
+pub mod forest;
+pub mod identity;
+#[cfg(feature = "keepers")]
+pub mod keepers;
+pub mod legal_holds;
+pub mod lifecycle;
+#[cfg(feature = "messaging")]
+pub mod message_escrows;
+#[cfg(feature = "messaging")]
+pub mod messaging;
+pub mod migration;
+pub mod moderation;
+pub mod multisig;
+pub mod nft_gates;
+pub mod note_id;
+#[cfg(feature = "offers")]
+pub mod offers;
+#[cfg(feature = "orgs")]
+pub mod orgs;
+pub mod permits;
+pub mod quote;
+pub mod rate_limits;
+#[cfg(feature = "reactions")]
+pub mod reactions;
+#[cfg(feature = "receipts")]
+pub mod receipts;
+pub mod recent_leaves;
+pub mod roles;
+pub mod sessions;
+pub mod shared_notes;
+pub mod stakes;
+pub mod subscriptions;
+#[cfg(test)]
+mod test_vectors;
+pub mod text;
+pub mod tips;
+pub mod token_fees;
+pub mod token_gates;
+pub mod treasury;
+pub mod tree_config;
+pub mod tree_state;
+
+#[cfg(feature = "audit")]
+pub use audit::*;
+#[cfg(feature = "certificates")]
+pub use certificates::*;
+#[cfg(feature = "channels")]
+pub use channels::*;
+pub use cross_post::*;
+pub use did::*;
+pub use fee_schedules::*;
+pub use forest::*;
+pub use identity::*;
+#[cfg(feature = "keepers")]
+pub use keepers::*;
+pub use legal_holds::*;
+pub use lifecycle::*;
+#[cfg(feature = "messaging")]
+pub use message_escrows::*;
+#[cfg(feature = "messaging")]
+pub use messaging::*;
+pub use migration::*;
+pub use moderation::*;
+pub use multisig::*;
+pub use nft_gates::*;
+pub use note_id::*;
+#[cfg(feature = "offers")]
+pub use offers::*;
+#[cfg(feature = "orgs")]
+pub use orgs::*;
+pub use permits::*;
+pub use quote::*;
+pub use rate_limits::*;
+#[cfg(feature = "reactions")]
+pub use reactions::*;
+#[cfg(feature = "receipts")]
+pub use receipts::*;
+pub use recent_leaves::*;
+pub use roles::*;
+pub use sessions::*;
+pub use shared_notes::*;
+pub use stakes::*;
+pub use subscriptions::*;
+pub use text::*;
+pub use tips::*;
+pub use token_fees::*;
+pub use token_gates::*;
+pub use treasury::*;
+pub use tree_config::*;
+pub use tree_state::*;
+
+stand_ins!("audit", GrantAuditor, RevokeAuditor, AuditLeaf);
+stand_ins!(
+    "certificates",
+    CreateCertificateTree,
+    IssuerAccounts,
+    CheckCertificate,
+);
+stand_ins!(
+    "channels",
+    CreateChannelTree,
+    Subscribe,
+    Unsubscribe,
+    Broadcast,
+);
+stand_ins!(
+    "keepers",
+    RegisterKeeper,
+    KeeperCheckpoint,
+    SlashKeeper,
+    DeregisterKeeper,
+);
+stand_ins!(
+    "messaging",
+    args: [PresenceState],
+    AppendMessage,
+    UpdateMessage,
+    CreateConversationTree,
+    RequestConversation,
+    CreateSenderNonce,
+    AcceptConversation,
+    SetAllowlist,
+    BlockSender,
+    UnblockSender,
+    SignalAccounts,
+    InboxTreeAccounts,
+    CreateMessageEscrow,
+    UnlockMessage,
+    ReleaseMessageKey,
+    RefundMessageEscrow,
+    AcknowledgeMessage,
+    SetConversationFlags,
+    BurnExpiredMessage,
+    CreateGroup,
+    GroupAdmin,
+    CreateConversationMeta,
+    UpdateConversationMeta,
+    GroupMessageAccounts,
+);
+stand_ins!(
+    "offers",
+    MakeOffer,
+    AcceptOffer,
+    CancelOffer,
+    OfferNoteTransfer,
+    AcceptNoteTransfer,
+    CancelNoteTransfer,
+);
+stand_ins!(
+    "orgs",
+    args: [RetentionTier],
+    CreateOrganization,
+    PurchaseSeats,
+    AddSeat,
+    RemoveSeat,
+    OrgNoteAccounts,
+    SetRetentionTier,
+    PruneOrgNote,
+    OpenUsagePeriod,
+    FinalizeUsagePeriod,
+);
+stand_ins!("reactions", ReactionAccounts);
+stand_ins!("receipts", ReceiptAccounts, ConfirmDelivery);
+
+declare_id!("2CCvZS82NbYtLDuQgegGJB9pspjMizama2tQQy8Vu6Ps");
+
+// The longest note in bytes, more than fits in a transaction, so it bounds notes sent
+// through CPI
+pub const MAX_NOTE_LEN: usize = 1024;
+
+// Hashes a note message and its owner into the leaf node stored in the merkle tree
+pub fn hash_note_leaf(note: &str, owner: &Pubkey) -> [u8; 32] {
+    keccak::hashv(&[note.as_bytes(), owner.as_ref()]).to_bytes()
+}
+
+// Hashes a note message, its owner and its status into the leaf node stored in a note tree
+pub fn hash_note_leaf_v2(note: &str, owner: &Pubkey, status: NoteStatus) -> [u8; 32] {
+    keccak::hashv(&[note.as_bytes(), owner.as_ref(), &[status as u8]]).to_bytes()
+}
+
+#[program]
+pub mod compressed_notes {
+    use super::*;
+
+    // Instruction for the program upgrade authority to create the global config.
+    pub fn initialize_global_config(
+        ctx: Context<InitializeGlobalConfig>,
+        admin: Pubkey, // The admin allowed to change the global config
+    ) -> Result<()> {
+        let global_config = &mut ctx.accounts.global_config;
+        global_config.admin = admin;
+        // Every instruction is enabled until the admin says otherwise
+        global_config.enabled_instructions = instruction_flags::ALL;
+        // Trees are free to create until the admin sets a fee
+        global_config.tree_creation_fee = 0;
+        global_config.paused = false;
+        global_config.bump = *ctx.bumps.get("global_config").unwrap();
+        Ok(())
+    }
+
+    // Instruction for the admin to choose which instructions are enabled on this deployment.
+    pub fn set_enabled_instructions(
+        ctx: Context<GlobalConfigAdmin>,
+        enabled_instructions: u128, // Bitmask of `instruction_flags`
+    ) -> Result<()> {
+        ctx.accounts.global_config.enabled_instructions = enabled_instructions;
+        Ok(())
+    }
+
+    // Instruction for the admin to set the fee paid to the protocol treasury for each note tree.
+    pub fn set_tree_creation_fee(
+        ctx: Context<GlobalConfigAdmin>,
+        tree_creation_fee: u64, // Lamports paid by the creator of a note tree
+    ) -> Result<()> {
+        ctx.accounts.global_config.tree_creation_fee = tree_creation_fee;
+        Ok(())
+    }
+
+    // Instruction for the admin to pause every instruction changing state on this deployment.
+    pub fn pause(ctx: Context<GlobalConfigAdmin>) -> Result<()> {
+        ctx.accounts.global_config.paused = true;
+        Ok(())
+    }
+
+    // Instruction for the admin to lift a pause of this deployment.
+    pub fn unpause(ctx: Context<GlobalConfigAdmin>) -> Result<()> {
+        ctx.accounts.global_config.paused = false;
+        Ok(())
+    }
+
+    // Instruction for creating a new note tree.
+    pub fn create_note_tree(
+        ctx: Context<CreateNoteTree>,
+        max_depth: u32,       // Max depth of the merkle tree
+        max_buffer_size: u32, // Max buffer size of the merkle tree
+        canopy_depth: u32,    // Levels of the tree cached on chain, shortening every proof
+    ) -> Result<()> {
+        // Check the instruction is enabled on this deployment
+        ctx.accounts
+            .global_config
+            .require_enabled(instruction_flags::CREATE_NOTE_TREE)?;
+
+        init_note_tree(ctx, max_depth, max_buffer_size, canopy_depth)
+    }
+
+    // Instruction for creating a new note tree in an account this program allocates, funded
+    // by the creator. The merkle tree keypair still signs for its account.
+    pub fn create_allocated_note_tree(
+        ctx: Context<CreateNoteTree>,
+        max_depth: u32,       // Max depth of the merkle tree
+        max_buffer_size: u32, // Max buffer size of the merkle tree
+        canopy_depth: u32,    // Levels of the tree cached on chain, shortening every proof
+    ) -> Result<()> {
+        // Check the instruction is enabled on this deployment
+        ctx.accounts
+            .global_config
+            .require_enabled(instruction_flags::CREATE_ALLOCATED_NOTE_TREE)?;
+
+        create_tree_account(ctx.accounts, max_depth, max_buffer_size, canopy_depth, &[])?;
+        init_note_tree(ctx, max_depth, max_buffer_size, canopy_depth)
+    }
+
+    // Instruction for creating a new note tree at a pda of the creator and a namespace, so
+    // apps can derive its address instead of storing it.
+    pub fn create_named_tree(
+        ctx: Context<CreateNoteTree>,
+        namespace: String,    // Names the tree among the creator's named trees
+        max_depth: u32,       // Max depth of the merkle tree
+        max_buffer_size: u32, // Max buffer size of the merkle tree
+        canopy_depth: u32,    // Levels of the tree cached on chain, shortening every proof
+    ) -> Result<()> {
+        // Check the instruction is enabled on this deployment
+        ctx.accounts
+            .global_config
+            .require_enabled(instruction_flags::CREATE_NAMED_TREE)?;
+        require!(
+            namespace.len() <= TreeConfig::MAX_NAMESPACE_LEN,
+            CompressedNotesError::NamespaceTooLong
+        );
+
+        let creator = ctx.accounts.creator.key();
+        let (named_tree, bump) = Pubkey::find_program_address(
+            &[
+                TreeConfig::NAMED_TREE_SEED,
+                creator.as_ref(),
+                namespace.as_bytes(),
+            ],
+            ctx.program_id,
+        );
+        require_keys_eq!(
+            ctx.accounts.merkle_tree.key(),
+            named_tree,
+            CompressedNotesError::InvalidNamedTree
+        );
+
+        // The tree account is the pda, so it signs for its creation with its seeds
+        let signer_seeds: &[&[&[u8]]] = &[&[
+            TreeConfig::NAMED_TREE_SEED,
+            creator.as_ref(),
+            namespace.as_bytes(),
+            &[bump],
+        ]];
+        create_tree_account(
+            ctx.accounts,
+            max_depth,
+            max_buffer_size,
+            canopy_depth,
+            signer_seeds,
+        )?;
+        init_note_tree(ctx, max_depth, max_buffer_size, canopy_depth)
+    }
+
+    // Instruction for the authority of an empty note tree to close it and reclaim the rent.
+    pub fn close_tree(ctx: Context<CloseTree>) -> Result<()> {
+        tree_config::process_close_tree(ctx)
+    }
+
+    // Instruction for the authority of an empty note tree to close it, keeping its final
+    // state in a tombstone.
+    pub fn decommission_tree(ctx: Context<DecommissionTree>) -> Result<()> {
+        tree_config::process_decommission_tree(ctx)
+    }
+
+    // Instruction for the authority of a note tree to hand it to another wallet.
+    pub fn transfer_tree_authority(
+        ctx: Context<TreeConfigAuthority>,
+        new_authority: Pubkey,
+    ) -> Result<()> {
+        tree_config::process_transfer_tree_authority(ctx, new_authority)
+    }
+
+    // Instruction for the authority of a note tree to pick the tree appends continue in once full.
+    pub fn set_next_tree(ctx: Context<SetNextTree>) -> Result<()> {
+        tree_config::process_set_next_tree(ctx)
+    }
+
+    // Instruction for the authority of a note tree to label it for explorers and apps.
+    pub fn set_tree_metadata(
+        ctx: Context<SetTreeMetadata>,
+        name: String,
+        description: String,
+        image_uri: String,
+    ) -> Result<()> {
+        tree_config::process_set_tree_metadata(ctx, name, description, image_uri)
+    }
+
+    // Instruction for the authority of a note tree to hand it to an m-of-n multisig.
+    pub fn create_tree_multisig(
+        ctx: Context<CreateTreeMultisig>,
+        members: Vec<Pubkey>,
+        threshold: u8,
+    ) -> Result<()> {
+        multisig::process_create_tree_multisig(ctx, members, threshold)
+    }
+
+    // Instruction for a member of a tree multisig to propose a change to the tree.
+    pub fn propose_tree_action(ctx: Context<ProposeTreeAction>, action: TreeAction) -> Result<()> {
+        multisig::process_propose_tree_action(ctx, action)
+    }
+
+    // Instruction for a member of a tree multisig to approve a proposed change.
+    pub fn approve_tree_action(ctx: Context<ApproveTreeAction>) -> Result<()> {
+        multisig::process_approve_tree_action(ctx)
+    }
+
+    // Instruction for applying a change approved by enough members of a tree multisig.
+    pub fn execute_tree_action(ctx: Context<ExecuteTreeAction>) -> Result<()> {
+        multisig::process_execute_tree_action(ctx)
+    }
+
+    // Instruction for the authority or an admin of a note tree to grant a member a role.
+    pub fn grant_role(ctx: Context<GrantRole>, member: Pubkey, kind: RoleKind) -> Result<()> {
+        roles::process_grant_role(ctx, member, kind)
+    }
+
+    // Instruction for the authority or an admin of a note tree to revoke a member's role.
+    pub fn revoke_role(ctx: Context<RevokeRole>) -> Result<()> {
+        roles::process_revoke_role(ctx)
+    }
+
+    // Instruction for the authority of two note trees to start copying one into the other.
+    pub fn begin_migration(ctx: Context<BeginMigration>, compact: bool) -> Result<()> {
+        migration::process_begin_migration(ctx, compact)
+    }
+
+    // Instruction for the authority of a migration to copy a proven leaf into the new tree.
+    pub fn migrate_leaf<'info>(
+        ctx: Context<'_, '_, '_, 'info, MigrateLeaf<'info>>,
+        index: u32,
+        root: [u8; 32],
+        payload: LeafPayload,
+    ) -> Result<()> {
+        migration::process_migrate_leaf(ctx, index, root, payload)
+    }
+
+    // Instruction for the authority of a migration to retire the old tree once it is copied.
+    pub fn finish_migration(ctx: Context<FinishMigration>) -> Result<()> {
+        migration::process_finish_migration(ctx)
+    }
+
+    // Instruction for the authority of a note tree to make it public or private.
+    pub fn set_tree_visibility(
+        ctx: Context<TreeConfigAuthority>,
+        visibility: Visibility,
+    ) -> Result<()> {
+        tree_config::process_set_tree_visibility(ctx, visibility)
+    }
+
+    // Instruction for the authority of a note tree to let a writer append to it while private.
+    pub fn add_writer(ctx: Context<AddWriter>, writer: Pubkey) -> Result<()> {
+        tree_config::process_add_writer(ctx, writer)
+    }
+
+    // Instruction for the authority of a note tree to revoke a writer.
+    pub fn remove_writer(ctx: Context<RemoveWriter>, writer: Pubkey) -> Result<()> {
+        tree_config::process_remove_writer(ctx, writer)
+    }
+
+    // Instruction for the authority of a note tree to limit how often each sender appends.
+    pub fn set_rate_limits(
+        ctx: Context<TreeConfigAuthority>,
+        min_append_slots: u64,
+        max_appends_per_epoch: u32,
+    ) -> Result<()> {
+        tree_config::process_set_rate_limits(ctx, min_append_slots, max_appends_per_epoch)
+    }
+
+    // Instruction for the authority of a note tree to charge a fee for each append.
+    pub fn set_append_fee(ctx: Context<SetAppendFee>, fee_lamports: u64) -> Result<()> {
+        treasury::process_set_append_fee(ctx, fee_lamports)
+    }
+
+    // Instruction for the authority of a note tree to price appends, updates and deletes separately.
+    pub fn set_fee_schedule(
+        ctx: Context<SetAppendFee>,
+        base_fee: u64,
+        per_byte_fee: u64,
+        update_fee: u64,
+        delete_fee: u64,
+    ) -> Result<()> {
+        fee_schedules::process_set_fee_schedule(ctx, base_fee, per_byte_fee, update_fee, delete_fee)
+    }
+
+    // Instruction for the authority of a note tree to withdraw the append fees it collected.
+    pub fn withdraw_tree_treasury(ctx: Context<WithdrawTreeTreasury>, amount: u64) -> Result<()> {
+        treasury::process_withdraw_tree_treasury(ctx, amount)
+    }
+
+    // Instruction for the authority of a note tree to charge a fee in an spl token for each append.
+    pub fn set_token_append_fee(ctx: Context<SetTokenAppendFee>, amount: u64) -> Result<()> {
+        token_fees::process_set_token_append_fee(ctx, amount)
+    }
+
+    // Instruction for the authority of a note tree to withdraw the token fees it collected.
+    pub fn withdraw_token_fees(ctx: Context<WithdrawTokenFees>, amount: u64) -> Result<()> {
+        token_fees::process_withdraw_token_fees(ctx, amount)
+    }
+
+    // Instruction for the authority of a note tree to restrict posting to holders of a token.
+    pub fn set_token_gate(
+        ctx: Context<TreeConfigAuthority>,
+        token_gate: Option<TokenGate>, // The balance posters must hold, none for no gate
+    ) -> Result<()> {
+        token_gates::process_set_token_gate(ctx, token_gate)
+    }
+
+    // Instruction for the authority of a note tree to restrict posting to holders of a collection.
+    pub fn set_nft_gate(
+        ctx: Context<TreeConfigAuthority>,
+        collection: Option<Pubkey>, // The collection posters must hold an nft of, none for no gate
+    ) -> Result<()> {
+        nft_gates::process_set_nft_gate(ctx, collection)
+    }
+
+    // Instruction for the authority of a note tree to choose which programs can call in via cpi.
+    pub fn set_cpi_policy(ctx: Context<TreeConfigAuthority>, cpi_policy: CpiPolicy) -> Result<()> {
+        tree_config::process_set_cpi_policy(ctx, cpi_policy)
+    }
+
+    // Instruction for the authority of a note tree to refuse duplicate leaves within a window.
+    pub fn create_recent_leaves(
+        ctx: Context<CreateRecentLeaves>,
+        window_slots: u64, // The slots each generation of the filter covers
+    ) -> Result<()> {
+        recent_leaves::process_create_recent_leaves(ctx, window_slots)
+    }
+
+    // Instruction for the authority of a note tree to stop refusing duplicate leaves.
+    pub fn close_recent_leaves(ctx: Context<CloseRecentLeaves>) -> Result<()> {
+        recent_leaves::process_close_recent_leaves(ctx)
+    }
+
+    // Instruction for a compliance member of a note tree to put a leaf on legal hold or lift it.
+    pub fn set_legal_hold(
+        ctx: Context<SetLegalHold>,
+        leaf_hash: [u8; 32], // The leaf node held or released
+        held: bool,          // Whether the leaf is put on hold or released
+    ) -> Result<()> {
+        legal_holds::process_set_legal_hold(ctx, leaf_hash, held)
+    }
+
+    // Instruction for anyone to report a leaf of a tree to its moderators.
+    pub fn report_leaf(
+        ctx: Context<ReportLeaf>,
+        merkle_tree: Pubkey, // The tree holding the leaf
+        leaf_hash: [u8; 32], // The leaf node reported
+        reason: u16,         // Why the leaf is reported, as defined by the app
+    ) -> Result<()> {
+        moderation::process_report_leaf(ctx, merkle_tree, leaf_hash, reason)
+    }
+
+    // Instruction for a moderator of a note tree to take a note down, logging the reason.
+    pub fn takedown_note<'info>(
+        ctx: Context<'_, '_, '_, 'info, TakedownNote<'info>>,
+        index: u32,         // Index of the note leaf in the merkle tree
+        root: [u8; 32],     // Root the note leaf is proven against
+        payload: String,    // The note message
+        owner: Pubkey,      // The owner committed into the leaf
+        status: NoteStatus, // The current status of the note
+        reason_code: u16,   // Why the note is taken down, as defined by the app
+    ) -> Result<()> {
+        moderation::process_takedown_note(ctx, index, root, payload, owner, status, reason_code)
+    }
+
+    // Instruction for the authority of a note tree to ask posters to stake lamports.
+    pub fn set_min_stake(ctx: Context<TreeConfigAuthority>, min_stake: u64) -> Result<()> {
+        stakes::process_set_min_stake(ctx, min_stake)
+    }
+
+    // Instruction for a poster to stake lamports on a note tree so they can append to it.
+    pub fn stake_for_posting(ctx: Context<StakeForPosting>, amount: u64) -> Result<()> {
+        stakes::process_stake_for_posting(ctx, amount)
+    }
+
+    // Instruction for a poster to take back their unlocked stake on a note tree.
+    pub fn withdraw_stake(ctx: Context<WithdrawStake>, amount: u64) -> Result<()> {
+        stakes::process_withdraw_stake(ctx, amount)
+    }
+
+    // Instruction for a moderator of a note tree to slash the stake of a poster taken down.
+    pub fn slash_stake<'info>(
+        ctx: Context<'_, '_, '_, 'info, SlashStake<'info>>,
+        index: u32,              // Index of the takedown leaf in the merkle tree
+        root: [u8; 32],          // Root the takedown leaf is proven against
+        original_leaf: [u8; 32], // The leaf node of the note taken down
+        amount: u64,             // The staked lamports slashed
+    ) -> Result<()> {
+        stakes::process_slash_stake(ctx, index, root, original_leaf, amount)
+    }
+
+    // Instruction for the authority of a note tree to sell access to it by the day.
+    pub fn set_subscription_price(
+        ctx: Context<SetSubscriptionPrice>,
+        price_per_day: u64,
+    ) -> Result<()> {
+        subscriptions::process_set_subscription_price(ctx, price_per_day)
+    }
+
+    // Instruction for a member to buy days of access to a premium note tree.
+    pub fn subscribe_to_tree(
+        ctx: Context<SubscribeToTree>,
+        merkle_tree: Pubkey, // The premium tree subscribed to
+        duration: u32,       // The days of access bought
+    ) -> Result<()> {
+        subscriptions::process_subscribe_to_tree(ctx, merkle_tree, duration)
+    }
+
+    // Instruction for checking a member has an active subscription to a tree, fails otherwise.
+    pub fn check_subscription(
+        ctx: Context<CheckSubscription>,
+        merkle_tree: Pubkey, // The premium tree
+        subscriber: Pubkey,  // Pubkey of the member whose access is checked
+    ) -> Result<()> {
+        subscriptions::process_check_subscription(ctx, merkle_tree, subscriber)
+    }
+
+    // Instruction for anyone to tip the owner of a note in lamports or an spl token.
+    pub fn tip_note<'info>(
+        ctx: Context<'_, '_, '_, 'info, TipNote<'info>>,
+        index: u32,         // Index of the note leaf in the merkle tree
+        root: [u8; 32],     // Root the note leaf is proven against
+        note: String,       // The note message
+        status: NoteStatus, // The current status of the note
+        amount: u64,        // Lamports, or tokens of the tipper's token account, paid to the owner
+    ) -> Result<()> {
+        tips::process_tip_note(ctx, index, root, note, status, amount)
+    }
+
+    // Instruction for a sender to start counting their appends to a rate limited note tree.
+    pub fn create_rate_state(ctx: Context<CreateRateState>) -> Result<()> {
+        rate_limits::process_create_rate_state(ctx)
+    }
+
+    // Instruction for an owner to let a session key sign appends on their behalf until it expires.
+    pub fn create_session(
+        ctx: Context<CreateSession>,
+        session_key: Pubkey, // The key allowed to sign for the owner
+        expires_at: i64,     // Unix timestamp after which the session key can no longer sign
+        scope: u128,         // Bitmask of the `instruction_flags` the session key can sign
+    ) -> Result<()> {
+        sessions::process_create_session(ctx, session_key, expires_at, scope)
+    }
+
+    // Instruction for an owner to end a session before it expires.
+    pub fn revoke_session(ctx: Context<RevokeSession>) -> Result<()> {
+        sessions::process_revoke_session(ctx)
+    }
+
+    // Instruction for the authority of a note tree to freeze it, refusing appends and replaces.
+    pub fn freeze_tree(ctx: Context<TreeConfigAuthority>) -> Result<()> {
+        tree_config::process_freeze_tree(ctx)
+    }
+
+    // Instruction for the authority of a frozen note tree to thaw it.
+    pub fn thaw_tree(ctx: Context<TreeConfigAuthority>) -> Result<()> {
+        tree_config::process_thaw_tree(ctx)
+    }
+
+    // Instruction for the global config admin to freeze a note tree, logging the reason.
+    pub fn emergency_freeze_tree(
+        ctx: Context<EmergencyFreezeTree>,
+        reason_code: u16, // Why the tree is frozen, as defined by the protocol, never zero
+    ) -> Result<()> {
+        tree_config::process_emergency_freeze_tree(ctx, reason_code)
+    }
+
+    // Instruction for the global config admin to thaw a note tree it froze.
+    pub fn emergency_thaw_tree(
+        ctx: Context<EmergencyFreezeTree>,
+        reason_code: u16, // Why the tree is thawed, as defined by the protocol
+    ) -> Result<()> {
+        tree_config::process_emergency_thaw_tree(ctx, reason_code)
+    }
+
+    // Instruction for appending a published note to a tree.
+    pub fn append_note<'info>(
+        ctx: Context<'_, '_, '_, 'info, NoteAccounts<'info>>,
+        note: String,
+    ) -> Result<()> {
+        // Check the instruction is enabled on this deployment
+        ctx.accounts
+            .global_config
+            .require_enabled(instruction_flags::APPEND_NOTE)?;
+        append_note_leaf(
+            ctx,
+            note,
+            NoteStatus::Published,
+            instruction_flags::APPEND_NOTE,
+        )
+    }
+
+    // Instruction for appending a draft note to a tree, published later with set_note_status.
+    pub fn append_draft<'info>(
+        ctx: Context<'_, '_, '_, 'info, NoteAccounts<'info>>,
+        note: String,
+    ) -> Result<()> {
+        // Check the instruction is enabled on this deployment
+        ctx.accounts
+            .global_config
+            .require_enabled(instruction_flags::APPEND_DRAFT)?;
+        append_note_leaf(
+            ctx,
+            note,
+            NoteStatus::Draft,
+            instruction_flags::APPEND_DRAFT,
+        )
+    }
+
+    pub fn update_note<'info>(
+        ctx: Context<'_, '_, '_, 'info, NoteAccounts<'info>>,
+        index: u32,
+        root: Option<[u8; 32]>, // Root the old leaf is proven against, or none to derive it
+        old_note: String,
+        new_note: String,
+        status: NoteStatus, // The current status of the note
+    ) -> Result<()> {
+        // Check the instruction is enabled on this deployment
+        ctx.accounts
+            .global_config
+            .require_enabled(instruction_flags::UPDATE_NOTE)?;
+
+        let owner = resolve_note_owner(&ctx.accounts.owner.key(), &ctx.accounts.did)?;
+        let (old_leaf, schema_version) =
+            hash_note_leaf_for(&old_note, &owner, status, &ctx.accounts.identity_link)?;
+
+        // An edit that changes nothing fails, so clients do not mistake it for an update
+        require!(old_note != new_note, CompressedNotesError::NoChange);
+        require!(
+            new_note.len() <= MAX_NOTE_LEN,
+            CompressedNotesError::NoteTooLong
+        );
+        // Refuse text that indexers would display deceptively
+        require_clean_text(&new_note)?;
+
+        // Without a root, the proof decides it, the tree config then checks it is recent
+        let root = match root {
+            Some(root) => root,
+            None => TreeState::proven_root(
+                &ctx.accounts.merkle_tree,
+                old_leaf,
+                index,
+                ctx.remaining_accounts,
+            )?,
+        };
+
+        // A tree restricting cpi callers only accepts the programs it allows
+        TreeConfig::require_caller(
+            &ctx.accounts.tree_config,
+            ctx.accounts.instructions.as_deref(),
+        )?;
+
+        // A private tree only lets those who can write to it edit their notes
+        TreeConfig::require_writable(
+            &ctx.accounts.tree_config,
+            &ctx.accounts.owner.key(),
+            ctx.accounts.role.as_deref(),
+        )?;
+
+        // A tree charging an update fee takes it from the signer into the tree treasury
+        TreeConfig::record_fee(
+            &ctx.accounts.tree_config,
+            ctx.accounts.fee_payer(),
+            ctx.accounts.tree_treasury.as_ref(),
+            ctx.accounts.system_program.as_ref(),
+            FeeOperation::Update(new_note.len()),
+        )?;
+
+        // Count the replace on the tree config, failing if the tree is frozen or the root is
+        // older than its recent roots
+        TreeConfig::record_replace(
+            &ctx.accounts.tree_config,
+            &ctx.accounts.merkle_tree,
+            &root,
+            &old_leaf,
+            ctx.accounts.hold.as_deref(),
+        )?;
+
+        // Verify Leaf
+        {
+            // Verify or Fails
+            tree_cpi!(ctx)
+                .with_proof(ctx.remaining_accounts)
+                .verify(root, old_leaf, index)?;
+        }
+
+        // Editing a draft keeps it a draft, editing anything else marks it edited
+        let new_status = status.transition(status.edited())?;
+        let (new_leaf, _) =
+            hash_note_leaf_for(&new_note, &owner, new_status, &ctx.accounts.identity_link)?;
+
+        // Log out for indexers
+        let note_id = NoteId::new(ctx.accounts.merkle_tree.key(), index, schema_version);
+        let note_log = NoteLog::new(new_leaf, note_id, owner, new_note, new_status);
+        // Log the "note log" data using noop program
+        wrap_application_data_v1(note_log.try_to_vec()?, &ctx.accounts.log_wrapper)?;
+        // An attributed note also logs the ethereum address its leaf commits to
+        if let Some(attribution_log) =
+            EthAttributionLog::for_leaf(new_leaf, note_id, &ctx.accounts.identity_link)
+        {
+            wrap_application_data_v1(attribution_log.try_to_vec()?, &ctx.accounts.log_wrapper)?;
+        }
+
+        // CPI to replace the old leaf node with the new one
+        tree_cpi!(ctx)
+            .with_proof(ctx.remaining_accounts)
+            .replace(root, old_leaf, new_leaf, index)?;
+
+        Ok(())
+    }
+
+    // Instruction for moving a note to a new status, e.g. publishing, locking or deleting it.
+    pub fn set_note_status<'info>(
+        ctx: Context<'_, '_, '_, 'info, NoteAccounts<'info>>,
+        index: u32,             // Index of the note leaf in the merkle tree
+        root: [u8; 32],         // Root the old leaf is proven against
+        note: String,           // The current note message
+        status: NoteStatus,     // The current status of the note
+        new_status: NoteStatus, // The status to move the note to
+    ) -> Result<()> {
+        // Check the instruction is enabled on this deployment
+        ctx.accounts
+            .global_config
+            .require_enabled(instruction_flags::SET_NOTE_STATUS)?;
+
+        let owner = resolve_note_owner(&ctx.accounts.owner.key(), &ctx.accounts.did)?;
+        let (old_leaf, schema_version) =
+            hash_note_leaf_for(&note, &owner, status, &ctx.accounts.identity_link)?;
+        let new_status = status.transition(new_status)?;
+
+        // Deleted and redacted leaves drop the note content
+        let new_note = if new_status.retains_content() {
+            note
+        } else {
+            String::new()
+        };
+        let (new_leaf, _) =
+            hash_note_leaf_for(&new_note, &owner, new_status, &ctx.accounts.identity_link)?;
+
+        // Log out for indexers
+        let note_id = NoteId::new(ctx.accounts.merkle_tree.key(), index, schema_version);
+        let note_log = NoteLog::new(new_leaf, note_id, owner, new_note, new_status);
+        // Log the "note log" data using noop program
+        wrap_application_data_v1(note_log.try_to_vec()?, &ctx.accounts.log_wrapper)?;
+        // An attributed note also logs the ethereum address its leaf commits to
+        if let Some(attribution_log) =
+            EthAttributionLog::for_leaf(new_leaf, note_id, &ctx.accounts.identity_link)
+        {
+            wrap_application_data_v1(attribution_log.try_to_vec()?, &ctx.accounts.log_wrapper)?;
+        }
+
+        // A tree restricting cpi callers only accepts the programs it allows
+        TreeConfig::require_caller(
+            &ctx.accounts.tree_config,
+            ctx.accounts.instructions.as_deref(),
+        )?;
+
+        // A private tree only lets those who can write to it change their notes
+        TreeConfig::require_writable(
+            &ctx.accounts.tree_config,
+            &ctx.accounts.owner.key(),
+            ctx.accounts.role.as_deref(),
+        )?;
+
+        // A tree charging a delete fee takes it from the signer into the tree treasury
+        if matches!(new_status, NoteStatus::Deleted | NoteStatus::Redacted) {
+            TreeConfig::record_fee(
+                &ctx.accounts.tree_config,
+                ctx.accounts.fee_payer(),
+                ctx.accounts.tree_treasury.as_ref(),
+                ctx.accounts.system_program.as_ref(),
+                FeeOperation::Delete,
+            )?;
+        }
+
+        // Count the replace on the tree config, failing if the tree is frozen
+        TreeConfig::record_replace(
+            &ctx.accounts.tree_config,
+            &ctx.accounts.merkle_tree,
+            &root,
+            &old_leaf,
+            ctx.accounts.hold.as_deref(),
+        )?;
+
+        // CPI to replace the old leaf node with the new one, the proof is passed as remaining accounts
+        tree_cpi!(ctx)
+            .with_proof(ctx.remaining_accounts)
+            .replace(root, old_leaf, new_leaf, index)?;
+        Ok(())
+    }
+
+    // Instruction for linking an ethereum address to the signer, proven by its secp256k1 signature.
+    pub fn link_eth_identity(
+        ctx: Context<LinkEthIdentity>,
+        eth_address: [u8; 20], // The ethereum address linked to the signer
+        signature: [u8; 65],   // The address's signature of the link message, with its recovery id
+    ) -> Result<()> {
+        identity::process_link_eth_identity(ctx, eth_address, signature)
+    }
+
+    // Instruction for removing the ethereum address linked to the signer.
+    pub fn unlink_eth_identity(ctx: Context<UnlinkEthIdentity>) -> Result<()> {
+        identity::process_unlink_eth_identity(ctx)
+    }
+
+    // Instruction for a relayer to append a note its owner permitted with an ed25519 signature.
+    pub fn append_note_with_permit(
+        ctx: Context<AppendNoteWithPermit>,
+        note: String,
+        owner: Pubkey,       // The owner who signed the permit, committed into the leaf
+        signature: [u8; 64], // The owner's ed25519 signature of the permit message
+        expiry: i64,         // Unix timestamp after which the permit can no longer be submitted
+    ) -> Result<()> {
+        permits::process_append_note_with_permit(ctx, note, owner, signature, expiry)
+    }
+
+    // Instruction for closing an expired permit, refunding the relayer who submitted it.
+    pub fn close_expired_permit(ctx: Context<CloseExpiredPermit>) -> Result<()> {
+        permits::process_close_expired_permit(ctx)
+    }
+
+    // Instruction for appending a note owned jointly by the signer and co-owners signing with them.
+    pub fn append_shared_note<'info>(
+        ctx: Context<'_, '_, '_, 'info, NoteAccounts<'info>>,
+        note: String,
+        co_owners: Vec<Pubkey>, // The owners besides the signer, each signing in the remaining accounts
+    ) -> Result<()> {
+        shared_notes::process_append_shared_note(ctx, note, co_owners)
+    }
+
+    // Instruction for every owner of a shared note to replace it with a new message.
+    pub fn update_shared_note<'info>(
+        ctx: Context<'_, '_, '_, 'info, NoteAccounts<'info>>,
+        index: u32,
+        root: [u8; 32],
+        old_note: String,
+        new_note: String,
+        status: NoteStatus,     // The current status of the note
+        co_owners: Vec<Pubkey>, // The owners besides the signer, each signing in the remaining accounts
+    ) -> Result<()> {
+        shared_notes::process_update_shared_note(
+            ctx, index, root, old_note, new_note, status, co_owners,
+        )
+    }
+
+    // Instruction for moving a note to another tree, deleting it in the one it leaves.
+    pub fn move_note(
+        ctx: Context<MoveNote>,
+        src_index: u32,
+        root: [u8; 32],
+        note: String,
+        status: NoteStatus,
+    ) -> Result<()> {
+        migration::process_move_note(ctx, src_index, root, note, status)
+    }
+
+    // Instruction for appending the same published note to several trees at once.
+    pub fn cross_post<'info>(
+        ctx: Context<'_, '_, '_, 'info, CrossPost<'info>>,
+        note: String,
+    ) -> Result<()> {
+        cross_post::process_cross_post(ctx, note)
+    }
+
+    // Instruction for creating a DID document that can own notes.
+    pub fn create_did(
+        ctx: Context<CreateDid>,
+        did_id: u64,              // Id of the document, unique per creator
+        controllers: Vec<Pubkey>, // The keys allowed to act for the document
+    ) -> Result<()> {
+        did::process_create_did(ctx, did_id, controllers)
+    }
+
+    // Instruction for a controller to rotate the controllers of a DID document.
+    pub fn set_did_controllers(
+        ctx: Context<SetDidControllers>,
+        controllers: Vec<Pubkey>, // The keys allowed to act for the document from now on
+    ) -> Result<()> {
+        did::process_set_did_controllers(ctx, controllers)
+    }
+
+    // Instruction for creating an organization that members can post notes on behalf of.
+    pub fn create_organization(ctx: Context<CreateOrganization>, org_id: u64) -> Result<()> {
+        subsystem!("orgs", orgs::process_create_organization, ctx, org_id)
+    }
+
+    // Instruction for the organization owner to buy seats from the protocol treasury.
+    pub fn purchase_seats(ctx: Context<PurchaseSeats>, count: u32) -> Result<()> {
+        subsystem!("orgs", orgs::process_purchase_seats, ctx, count)
+    }
+
+    // Instruction for the organization owner to assign a free seat to a member.
+    pub fn add_seat(ctx: Context<AddSeat>, member: Pubkey) -> Result<()> {
+        subsystem!("orgs", orgs::process_add_seat, ctx, member)
+    }
+
+    // Instruction for the organization owner to take a seat back from a member.
+    pub fn remove_seat(ctx: Context<RemoveSeat>) -> Result<()> {
+        subsystem!("orgs", orgs::process_remove_seat, ctx)
+    }
+
+    // Instruction for a seated member to append a note owned by the organization.
+    pub fn append_org_note(ctx: Context<OrgNoteAccounts>, note: String) -> Result<()> {
+        subsystem!("orgs", orgs::process_append_org_note, ctx, note)
+    }
+
+    // Instruction for changing how long an organization keeps its notes.
+    pub fn set_retention_tier(
+        ctx: Context<SetRetentionTier>,
+        retention_tier: RetentionTier,
+    ) -> Result<()> {
+        subsystem!(
+            "orgs",
+            orgs::process_set_retention_tier,
+            ctx,
+            retention_tier
+        )
+    }
+
+    // Instruction for pruning an org note older than the organization's retention tier.
+    pub fn prune_org_note<'info>(
+        ctx: Context<'_, '_, '_, 'info, PruneOrgNote<'info>>,
+        root: [u8; 32], // Root the note leaf is proven against
+        note: String,   // The note
+        posted_at: i64, // The posting time committed into the leaf
+        index: u32,     // Index of the note leaf in the merkle tree
+    ) -> Result<()> {
+        subsystem!(
+            "orgs",
+            orgs::process_prune_org_note,
+            ctx,
+            root,
+            note,
+            posted_at,
+            index
+        )
+    }
+
+    // Instruction for opening an organization's usage for the current billing period.
+    pub fn open_usage_period(ctx: Context<OpenUsagePeriod>, period: u64) -> Result<()> {
+        subsystem!("orgs", orgs::process_open_usage_period, ctx, period)
+    }
+
+    // Instruction for finalizing an organization's usage once the period has ended.
+    pub fn finalize_usage_period(ctx: Context<FinalizeUsagePeriod>) -> Result<()> {
+        subsystem!("orgs", orgs::process_finalize_usage_period, ctx)
+    }
+
+    // Instruction for the global config admin to withdraw from the protocol treasury.
+    pub fn withdraw_treasury(ctx: Context<WithdrawTreasury>, amount: u64) -> Result<()> {
+        treasury::process_withdraw_treasury(ctx, amount)
+    }
+
+    // Instruction for bonding a keeper to checkpoint a tree within an SLA.
+    pub fn register_keeper(
+        ctx: Context<RegisterKeeper>,
+        bond: u64,      // Lamports slashed if the keeper misses its SLA
+        sla_slots: u64, // The most slots allowed between two checkpoints
+    ) -> Result<()> {
+        subsystem!(
+            "keepers",
+            keepers::process_register_keeper,
+            ctx,
+            bond,
+            sla_slots
+        )
+    }
+
+    // Instruction for a keeper to checkpoint the tree it maintains.
+    pub fn keeper_checkpoint(ctx: Context<KeeperCheckpoint>) -> Result<()> {
+        subsystem!("keepers", keepers::process_keeper_checkpoint, ctx)
+    }
+
+    // Instruction for slashing a keeper that missed its SLA, callable by anyone.
+    pub fn slash_keeper(ctx: Context<SlashKeeper>) -> Result<()> {
+        subsystem!("keepers", keepers::process_slash_keeper, ctx)
+    }
+
+    // Instruction for a keeper in good standing to withdraw its bond.
+    pub fn deregister_keeper(ctx: Context<DeregisterKeeper>) -> Result<()> {
+        subsystem!("keepers", keepers::process_deregister_keeper, ctx)
+    }
+
+    // Instruction for reading how many leaves a tree has left, returned as return data.
+    pub fn get_tree_capacity(ctx: Context<ReadTree>) -> Result<u64> {
+        tree_state::process_get_tree_capacity(ctx)
+    }
+
+    // Instruction for reading the current root of a tree, returned as return data.
+    pub fn get_tree_root(ctx: Context<ReadTree>) -> Result<TreeRoot> {
+        tree_state::process_get_tree_root(ctx)
+    }
+
+    // Instruction for pricing an append before sending it, the quote is returned as return data.
+    pub fn quote_append(
+        ctx: Context<QuoteAppend>,
+        content_len: u32,
+        options: QuoteOptions,
+    ) -> Result<Quote> {
+        quote::process_quote_append(ctx, content_len, options)
+    }
+
+    // Instruction for appending a message addressed to a recipient to a tree.
+    pub fn append_message<'info>(
+        ctx: Context<'_, '_, '_, 'info, AppendMessage<'info>>,
+        recipient: Pubkey,             // Pubkey of the message recipient
+        message: String,               // The message to send
+        parent_leaf: Option<[u8; 32]>, // Leaf node of the message this replies to, if any
+        priority: u8,                  // The priority of the message, see `message_priority`
+        nonce: u64, // Chosen by the sender, distinct per message to the recipient
+    ) -> Result<()> {
+        subsystem!(
+            "messaging",
+            messaging::process_append_message,
+            ctx,
+            recipient,
+            message,
+            parent_leaf,
+            priority,
+            nonce
+        )
+    }
+
+    // Instruction for the sender of a direct message sent before V5 leaves to replace it
+    // with a new version.
+    #[allow(clippy::too_many_arguments)]
+    pub fn update_message<'info>(
+        ctx: Context<'_, '_, '_, 'info, UpdateMessage<'info>>,
+        index: u32,
+        root: Option<[u8; 32]>,
+        recipient: Pubkey,
+        old_message: String,
+        new_message: String,
+        parent_leaf: Option<[u8; 32]>,
+        edit_count: u32,
+        priority: u8,
+    ) -> Result<()> {
+        subsystem!(
+            "messaging",
+            messaging::process_update_message,
+            ctx,
+            index,
+            root,
+            recipient,
+            old_message,
+            new_message,
+            parent_leaf,
+            edit_count,
+            priority
+        )
+    }
+
+    // Instruction for the sender of a direct message with a V5 leaf, which commits to the
+    // recipient, to replace it with a new version.
+    #[allow(clippy::too_many_arguments)]
+    pub fn update_message_v2<'info>(
+        ctx: Context<'_, '_, '_, 'info, UpdateMessage<'info>>,
+        index: u32,
+        root: Option<[u8; 32]>,
+        recipient: Pubkey,
+        old_message: String,
+        new_message: String,
+        parent_leaf: Option<[u8; 32]>,
+        edit_count: u32,
+        priority: u8,
+        nonce: u64,
+    ) -> Result<()> {
+        subsystem!(
+            "messaging",
+            messaging::process_update_message_v2,
+            ctx,
+            index,
+            root,
+            recipient,
+            old_message,
+            new_message,
+            parent_leaf,
+            edit_count,
+            priority,
+            nonce
+        )
+    }
+
+    // Instruction for initializing a tree dedicated to one conversation.
+    pub fn create_conversation_tree(
+        ctx: Context<CreateConversationTree>,
+        recipient: Pubkey,    // Pubkey of the other participant
+        max_depth: u32,       // Max depth of the merkle tree
+        max_buffer_size: u32, // Max buffer size of the merkle tree
+        canopy_depth: u32,    // Levels of the tree cached on chain, shortening every proof
+    ) -> Result<()> {
+        subsystem!(
+            "messaging",
+            messaging::process_create_conversation_tree,
+            ctx,
+            recipient,
+            max_depth,
+            max_buffer_size,
+            canopy_depth
+        )
+    }
+
+    // Instruction for asking a recipient to accept direct messages.
+    pub fn request_conversation(
+        ctx: Context<RequestConversation>,
+        recipient: Pubkey, // Pubkey of the recipient asked to accept
+    ) -> Result<()> {
+        subsystem!(
+            "messaging",
+            messaging::process_request_conversation,
+            ctx,
+            recipient
+        )
+    }
+
+    // Instruction for a sender to keep a nonce pda, so each of their messages is only sent once.
+    pub fn create_sender_nonce(ctx: Context<CreateSenderNonce>) -> Result<()> {
+        subsystem!("messaging", messaging::process_create_sender_nonce, ctx)
+    }
+
+    // Instruction for accepting a conversation request.
+    pub fn accept_conversation(ctx: Context<AcceptConversation>) -> Result<()> {
+        subsystem!("messaging", messaging::process_accept_conversation, ctx)
+    }
+
+    // Instruction for allowing senders to message the signer without a request.
+    pub fn set_allowlist(ctx: Context<SetAllowlist>, senders: Vec<Pubkey>) -> Result<()> {
+        subsystem!("messaging", messaging::process_set_allowlist, ctx, senders)
+    }
+
+    // Instruction for blocking a sender from messaging the signer.
+    pub fn block_sender(ctx: Context<BlockSender>, sender: Pubkey) -> Result<()> {
+        subsystem!("messaging", messaging::process_block_sender, ctx, sender)
+    }
+
+    // Instruction for unblocking a previously blocked sender.
+    pub fn unblock_sender(ctx: Context<UnblockSender>, sender: Pubkey) -> Result<()> {
+        subsystem!("messaging", messaging::process_unblock_sender, ctx, sender)
+    }
+
+    // Instruction for logging the signer's presence, without writing any state.
+    pub fn emit_presence(ctx: Context<SignalAccounts>, state: PresenceState) -> Result<()> {
+        subsystem!("messaging", messaging::process_emit_presence, ctx, state)
+    }
+
+    // Instruction for logging that the signer is typing in a conversation.
+    pub fn emit_typing(ctx: Context<SignalAccounts>, conversation: Pubkey) -> Result<()> {
+        subsystem!(
+            "messaging",
+            messaging::process_emit_typing,
+            ctx,
+            conversation
+        )
+    }
+
+    // Instruction for provisioning a recipient's inbox tree, one 10KiB step per call.
+    pub fn get_or_create_inbox_tree(
+        ctx: Context<InboxTreeAccounts>,
+        recipient: Pubkey, // Pubkey of the recipient owning the inbox tree
+    ) -> Result<()> {
+        subsystem!(
+            "messaging",
+            messaging::process_get_or_create_inbox_tree,
+            ctx,
+            recipient
+        )
+    }
+
+    // Instruction for appending a message that anyone can burn once it expires.
+    pub fn append_message_with_ttl<'info>(
+        ctx: Context<'_, '_, '_, 'info, AppendMessage<'info>>,
+        recipient: Pubkey, // Pubkey of the message recipient
+        message: String,   // The message to send
+        expires_at: i64,   // Unix timestamp after which the message can be burned
+    ) -> Result<()> {
+        subsystem!(
+            "messaging",
+            messaging::process_append_message_with_ttl,
+            ctx,
+            recipient,
+            message,
+            expires_at
+        )
+    }
+
+    // Instruction for appending an end-to-end encrypted message.
+    pub fn append_encrypted_message<'info>(
+        ctx: Context<'_, '_, '_, 'info, AppendMessage<'info>>,
+        recipient: Pubkey,                 // Pubkey of the message recipient
+        ciphertext: Vec<u8>,               // The encrypted message
+        nonce: [u8; 24],                   // The nonce the message was encrypted with
+        sender_ephemeral_pubkey: [u8; 32], // The sender's ephemeral x25519 key for the key exchange
+    ) -> Result<()> {
+        subsystem!(
+            "messaging",
+            messaging::process_append_encrypted_message,
+            ctx,
+            recipient,
+            ciphertext,
+            nonce,
+            sender_ephemeral_pubkey
+        )
+    }
+
+    // Instruction for forwarding a message, committing to where it came from.
+    pub fn forward_message<'info>(
+        ctx: Context<'_, '_, '_, 'info, AppendMessage<'info>>,
+        recipient: Pubkey,       // Pubkey of the message recipient
+        original_leaf: [u8; 32], // Leaf node of the message being forwarded
+        original_sender: Pubkey, // Pubkey of the sender of the forwarded message
+        message: String,         // The message being forwarded
+    ) -> Result<()> {
+        subsystem!(
+            "messaging",
+            messaging::process_forward_message,
+            ctx,
+            recipient,
+            original_leaf,
+            original_sender,
+            message
+        )
+    }
+
+    // Instruction for the sender of an encrypted message to price it for its recipient.
+    pub fn create_message_escrow(
+        ctx: Context<CreateMessageEscrow>,
+        leaf_node: [u8; 32],       // The leaf node of the encrypted message
+        ciphertext_hash: [u8; 32], // The keccak hash of the encrypted message
+        nonce: [u8; 24],           // The nonce the message was encrypted with
+        sender_ephemeral_pubkey: [u8; 32], // The sender's ephemeral x25519 key for the key exchange
+        recipient: Pubkey,         // Pubkey of the recipient who can unlock the message
+        price: u64,                // Lamports the recipient pays to unlock the message
+    ) -> Result<()> {
+        subsystem!(
+            "messaging",
+            message_escrows::process_create_message_escrow,
+            ctx,
+            leaf_node,
+            ciphertext_hash,
+            nonce,
+            sender_ephemeral_pubkey,
+            recipient,
+            price
+        )
+    }
+
+    // Instruction for the recipient of an escrowed message to pay its price into the escrow.
+    pub fn unlock_message(ctx: Context<UnlockMessage>) -> Result<()> {
+        subsystem!("messaging", message_escrows::process_unlock_message, ctx)
+    }
+
+    // Instruction for the sender of an unlocked message to release its key and take the price.
+    pub fn release_message_key(
+        ctx: Context<ReleaseMessageKey>,
+        sealed_key: [u8; 32],
+    ) -> Result<()> {
+        subsystem!(
+            "messaging",
+            message_escrows::process_release_message_key,
+            ctx,
+            sealed_key
+        )
+    }
+
+    // Instruction for the recipient of an unlocked message to take its price back once overdue.
+    pub fn refund_message_escrow(ctx: Context<RefundMessageEscrow>) -> Result<()> {
+        subsystem!(
+            "messaging",
+            message_escrows::process_refund_message_escrow,
+            ctx
+        )
+    }
+
+    // Instruction for the recipient of a message to append an acknowledgement of it.
+    pub fn acknowledge_message(
+        ctx: Context<AcknowledgeMessage>,
+        original_leaf: [u8; 32],
+    ) -> Result<()> {
+        subsystem!(
+            "messaging",
+            messaging::process_acknowledge_message,
+            ctx,
+            original_leaf
+        )
+    }
+
+    // Instruction for a participant to archive or mute a conversation.
+    pub fn set_conversation_flags(
+        ctx: Context<SetConversationFlags>,
+        archived: bool,
+        muted: bool,
+    ) -> Result<()> {
+        subsystem!(
+            "messaging",
+            messaging::process_set_conversation_flags,
+            ctx,
+            archived,
+            muted
+        )
+    }
+
+    // Instruction for anyone to tombstone an expired message.
+    pub fn burn_expired_message<'info>(
+        ctx: Context<'_, '_, '_, 'info, BurnExpiredMessage<'info>>,
+        root: [u8; 32],
+        message: String,
+        sender: Pubkey,
+        expires_at: i64,
+        index: u32,
+    ) -> Result<()> {
+        subsystem!(
+            "messaging",
+            messaging::process_burn_expired_message,
+            ctx,
+            root,
+            message,
+            sender,
+            expires_at,
+            index
+        )
+    }
+
+    // Instruction for creating a group that messages can be sent to.
+    pub fn create_group(
+        ctx: Context<CreateGroup>,
+        group_id: u64,        // Id of the group, unique per admin
+        members: Vec<Pubkey>, // The members who can post to and receive from the group
+    ) -> Result<()> {
+        subsystem!(
+            "messaging",
+            messaging::process_create_group,
+            ctx,
+            group_id,
+            members
+        )
+    }
 
-// STRUCTS GO HERE
+    // Instruction for the group admin to replace the member list.
+    pub fn set_group_members(ctx: Context<GroupAdmin>, members: Vec<Pubkey>) -> Result<()> {
+        subsystem!(
+            "messaging",
+            messaging::process_set_group_members,
+            ctx,
+            members
+        )
+    }
 
-#[program]
-pub mod compressed_notes {
-    use super::*;
+    // Instruction for the group admin to create the group chat's metadata.
+    pub fn create_conversation_meta(
+        ctx: Context<CreateConversationMeta>,
+        title: Option<String>,     // The title of the chat, if any
+        participants: Vec<Pubkey>, // The participants shown for the chat
+    ) -> Result<()> {
+        subsystem!(
+            "messaging",
+            messaging::process_create_conversation_meta,
+            ctx,
+            title,
+            participants
+        )
+    }
 
-    // Instruction for creating a new note tree.
-    pub fn create_note_tree(
-        ctx: Context<NoteAccounts>,
+    // Instruction for the metadata admin to update a group chat's metadata.
+    pub fn update_conversation_meta(
+        ctx: Context<UpdateConversationMeta>,
+        title: Option<String>,     // The title of the chat, if any
+        participants: Vec<Pubkey>, // The participants shown for the chat
+        muted: u16,                // Bit i mutes participants[i]
+        admin: Pubkey,             // The admin allowed to update the metadata from now on
+    ) -> Result<()> {
+        subsystem!(
+            "messaging",
+            messaging::process_update_conversation_meta,
+            ctx,
+            title,
+            participants,
+            muted,
+            admin
+        )
+    }
+
+    // Instruction for appending a message addressed to every member of a group to a tree.
+    pub fn append_group_message(ctx: Context<GroupMessageAccounts>, message: String) -> Result<()> {
+        subsystem!(
+            "messaging",
+            messaging::process_append_group_message,
+            ctx,
+            message
+        )
+    }
+
+    // Instruction for creating a new tree that only the channel authority can broadcast to.
+    pub fn create_channel_tree(
+        ctx: Context<CreateChannelTree>,
         max_depth: u32,       // Max depth of the merkle tree
         max_buffer_size: u32, // Max buffer size of the merkle tree
     ) -> Result<()> {
-        // Get the address for the merkle tree account
-        let merkle_tree = ctx.accounts.merkle_tree.key();
+        subsystem!(
+            "channels",
+            channels::process_create_channel_tree,
+            ctx,
+            max_depth,
+            max_buffer_size
+        )
+    }
 
-        // Define the seeds for pda signing
-        let signer_seeds: &[&[&[u8]]] = &[&[
-            merkle_tree.as_ref(), // The address of the merkle tree account as a seed
-            &[*ctx.bumps.get("tree_authority").unwrap()], // The bump seed for the pda
-        ]];
+    // Instruction for subscribing to a channel.
+    pub fn subscribe(ctx: Context<Subscribe>) -> Result<()> {
+        subsystem!("channels", channels::process_subscribe, ctx)
+    }
 
-        // Create cpi context for init_empty_merkle_tree instruction.
-        let cpi_ctx = CpiContext::new_with_signer(
-            ctx.accounts.compression_program.to_account_info(), // The spl account compression program
-            Initialize {
-                authority: ctx.accounts.tree_authority.to_account_info(), // The authority for the merkle tree, using a PDA
-                merkle_tree: ctx.accounts.merkle_tree.to_account_info(), // The merkle tree account to be initialized
-                noop: ctx.accounts.log_wrapper.to_account_info(), // The noop program to log data
-            },
-            signer_seeds, // The seeds for pda signing
-        );
+    // Instruction for unsubscribing from a channel.
+    pub fn unsubscribe(ctx: Context<Unsubscribe>) -> Result<()> {
+        subsystem!("channels", channels::process_unsubscribe, ctx)
+    }
 
-        // CPI to initialize an empty merkle tree with given max depth and buffer size
-        init_empty_merkle_tree(cpi_ctx, max_depth, max_buffer_size)?;
-        Ok(())
+    // Instruction for the channel authority to broadcast a message to its subscribers.
+    pub fn broadcast(ctx: Context<Broadcast>, message: String) -> Result<()> {
+        subsystem!("channels", channels::process_broadcast, ctx, message)
     }
 
-    // Instruction for appending a note to a tree.
-    pub fn append_note(ctx: Context<NoteAccounts>, note: String) -> Result<()> {
-        // Hash the "note message" which will be stored as leaf node in the merkle tree
-        let leaf_node =
-            keccak::hashv(&[note.as_bytes(), ctx.accounts.owner.key().as_ref()]).to_bytes();
-        // Create a new "note log" using the leaf node hash and note.
-        let note_log = NoteLog::new(leaf_node.clone(), ctx.accounts.owner.key().clone(), note);
-        // Log the "note log" data using noop program
-        wrap_application_data_v1(note_log.try_to_vec()?, &ctx.accounts.log_wrapper)?;
-        // Get the address for the merkle tree account
-        let merkle_tree = ctx.accounts.merkle_tree.key();
-        // Define the seeds for pda signing
-        let signer_seeds: &[&[&[u8]]] = &[&[
-            merkle_tree.as_ref(), // The address of the merkle tree account as a seed
-            &[*ctx.bumps.get("tree_authority").unwrap()], // The bump seed for the pda
-        ]];
-        // Create a new cpi context and append the leaf node to the merkle tree.
-        let cpi_ctx = CpiContext::new_with_signer(
-            ctx.accounts.compression_program.to_account_info(), // The spl account compression program
-            Modify {
-                authority: ctx.accounts.tree_authority.to_account_info(), // The authority for the merkle tree, using a PDA
-                merkle_tree: ctx.accounts.merkle_tree.to_account_info(), // The merkle tree account to be modified
-                noop: ctx.accounts.log_wrapper.to_account_info(), // The noop program to log data
-            },
-            signer_seeds, // The seeds for pda signing
-        );
-        // CPI to append the leaf node to the merkle tree
-        append(cpi_ctx, leaf_node)?;
-        Ok(())
+    // Instruction for a buyer to escrow an offer on a note until a deadline.
+    pub fn make_offer(
+        ctx: Context<MakeOffer>,
+        index: u32,  // Index of the note leaf in the merkle tree
+        amount: u64, // Lamports offered for the note
+        expiry: i64, // Unix timestamp after which the offer can no longer be accepted
+    ) -> Result<()> {
+        subsystem!(
+            "offers",
+            offers::process_make_offer,
+            ctx,
+            index,
+            amount,
+            expiry
+        )
+    }
+
+    // Instruction for the note owner to accept an offer, handing the note to the buyer.
+    pub fn accept_offer<'info>(
+        ctx: Context<'_, '_, '_, 'info, AcceptOffer<'info>>,
+        root: [u8; 32],
+        note: String,
+        status: NoteStatus,
+    ) -> Result<()> {
+        subsystem!(
+            "offers",
+            offers::process_accept_offer,
+            ctx,
+            root,
+            note,
+            status
+        )
+    }
+
+    // Instruction for the buyer to withdraw an offer.
+    pub fn cancel_offer(ctx: Context<CancelOffer>) -> Result<()> {
+        subsystem!("offers", offers::process_cancel_offer, ctx)
+    }
+
+    // Instruction for the note owner to offer a note to a new owner, who must accept it.
+    pub fn offer_note_transfer(
+        ctx: Context<OfferNoteTransfer>,
+        index: u32,         // Index of the note leaf in the merkle tree
+        new_owner: Pubkey,  // The owner the note is offered to
+        note_owner: Pubkey, // The owner the note leaf commits to, the signer or its DID document
+    ) -> Result<()> {
+        subsystem!(
+            "offers",
+            offers::process_offer_note_transfer,
+            ctx,
+            index,
+            new_owner,
+            note_owner
+        )
+    }
+
+    // Instruction for the new owner to accept a transfer offer, taking over the note.
+    pub fn accept_note_transfer<'info>(
+        ctx: Context<'_, '_, '_, 'info, AcceptNoteTransfer<'info>>,
+        root: [u8; 32],
+        note: String,
+        status: NoteStatus,
+    ) -> Result<()> {
+        subsystem!(
+            "offers",
+            offers::process_accept_note_transfer,
+            ctx,
+            root,
+            note,
+            status
+        )
+    }
+
+    // Instruction for the note owner to withdraw a transfer offer.
+    pub fn cancel_note_transfer(ctx: Context<CancelNoteTransfer>) -> Result<()> {
+        subsystem!("offers", offers::process_cancel_note_transfer, ctx)
+    }
+
+    // Instruction for the recipient of a message to mark it as read.
+    pub fn mark_read(ctx: Context<ReceiptAccounts>, leaf_hash: [u8; 32]) -> Result<()> {
+        subsystem!("receipts", receipts::process_mark_read, ctx, leaf_hash)
+    }
+
+    // Instruction for a message recipient to confirm a message was delivered.
+    pub fn confirm_delivery(ctx: Context<ConfirmDelivery>, leaf_hash: [u8; 32]) -> Result<()> {
+        subsystem!(
+            "receipts",
+            receipts::process_confirm_delivery,
+            ctx,
+            leaf_hash
+        )
+    }
+
+    // Instruction for the global config admin to grant an auditor watch-only access to a tree.
+    pub fn grant_auditor(ctx: Context<GrantAuditor>, auditor: Pubkey) -> Result<()> {
+        subsystem!("audit", audit::process_grant_auditor, ctx, auditor)
+    }
+
+    // Instruction for the global config admin to revoke an auditor's access to a tree.
+    pub fn revoke_auditor(ctx: Context<RevokeAuditor>) -> Result<()> {
+        subsystem!("audit", audit::process_revoke_auditor, ctx)
+    }
+
+    // Instruction for an auditor to verify a leaf and log an attestation for it.
+    pub fn audit_leaf<'info>(
+        ctx: Context<'_, '_, '_, 'info, AuditLeaf<'info>>,
+        root: [u8; 32],
+        leaf_node: [u8; 32],
+        index: u32,
+    ) -> Result<()> {
+        subsystem!(
+            "audit",
+            audit::process_audit_leaf,
+            ctx,
+            root,
+            leaf_node,
+            index
+        )
+    }
+
+    // Instruction for creating a new tree that only the issuer can issue certificates to.
+    pub fn create_certificate_tree(
+        ctx: Context<CreateCertificateTree>,
+        max_depth: u32,       // Max depth of the merkle tree
+        max_buffer_size: u32, // Max buffer size of the merkle tree
+    ) -> Result<()> {
+        subsystem!(
+            "certificates",
+            certificates::process_create_certificate_tree,
+            ctx,
+            max_depth,
+            max_buffer_size
+        )
+    }
+
+    // Instruction for issuing a certificate to a subject.
+    pub fn issue_certificate(
+        ctx: Context<IssuerAccounts>,
+        subject: Pubkey,       // Pubkey the certificate is issued to
+        claims_hash: [u8; 32], // Hash of the claims made about the subject, kept off-chain
+        expiry: i64,           // Unix timestamp after which the certificate is no longer valid
+    ) -> Result<()> {
+        subsystem!(
+            "certificates",
+            certificates::process_issue_certificate,
+            ctx,
+            subject,
+            claims_hash,
+            expiry
+        )
     }
 
-    pub fn update_note(
-        ctx: Context<NoteAccounts>,
+    // Instruction for revoking a certificate, adding it to the issuer's revocation list.
+    pub fn revoke_certificate(
+        ctx: Context<IssuerAccounts>,
+        root: [u8; 32],
+        subject: Pubkey,
+        claims_hash: [u8; 32],
+        expiry: i64,
         index: u32,
+    ) -> Result<()> {
+        subsystem!(
+            "certificates",
+            certificates::process_revoke_certificate,
+            ctx,
+            root,
+            subject,
+            claims_hash,
+            expiry,
+            index
+        )
+    }
+
+    // Instruction for checking a certificate is issued, unrevoked and unexpired, fails otherwise.
+    pub fn check_certificate(
+        ctx: Context<CheckCertificate>,
         root: [u8; 32],
-        old_note: String,
-        new_note: String,
+        subject: Pubkey,
+        claims_hash: [u8; 32],
+        expiry: i64,
+        index: u32,
     ) -> Result<()> {
-        let old_leaf =
-            keccak::hashv(&[old_note.as_bytes(), ctx.accounts.owner.key().as_ref()]).to_bytes();
+        subsystem!(
+            "certificates",
+            certificates::process_check_certificate,
+            ctx,
+            root,
+            subject,
+            claims_hash,
+            expiry,
+            index
+        )
+    }
 
-        let merkle_tree = ctx.accounts.merkle_tree.key();
+    // Instruction for reacting to a message or note with an emoji.
+    pub fn react(ctx: Context<ReactionAccounts>, leaf_hash: [u8; 32], emoji: String) -> Result<()> {
+        subsystem!("reactions", reactions::process_react, ctx, leaf_hash, emoji)
+    }
+}
 
-        // Define the seeds for pda signing
-        let signer_seeds: &[&[&[u8]]] = &[&[
-            merkle_tree.as_ref(), // The address of the merkle tree account as a seed
-            &[*ctx.bumps.get("tree_authority").unwrap()], // The bump seed for the pda
-        ]];
+// Creates the tree account with exactly the size of the given parameters, funded by the
+// creator. `signer_seeds` sign for a tree account that is a pda, and are empty otherwise.
+fn create_tree_account(
+    accounts: &CreateNoteTree,
+    max_depth: u32,
+    max_buffer_size: u32,
+    canopy_depth: u32,
+    signer_seeds: &[&[&[u8]]],
+) -> Result<()> {
+    let space = TreeState::account_size(max_depth, max_buffer_size, canopy_depth)?;
+    let cpi_ctx = CpiContext::new_with_signer(
+        accounts.system_program.to_account_info(),
+        CreateAccount {
+            from: accounts.creator.to_account_info(),
+            to: accounts.merkle_tree.to_account_info(),
+        },
+        signer_seeds,
+    );
+    create_account(
+        cpi_ctx,
+        Rent::get()?.minimum_balance(space),
+        space as u64,
+        &spl_account_compression::ID,
+    )
+}
 
-        // Verify Leaf
-        {
-            if old_note == new_note {
-                msg!("Notes are the same!");
-                return Ok(());
-            }
+// Initializes a note tree in its allocated account, recording its config and forest entry
+fn init_note_tree(
+    ctx: Context<CreateNoteTree>,
+    max_depth: u32,
+    max_buffer_size: u32,
+    canopy_depth: u32,
+) -> Result<()> {
+    // Anyone can create a tree, paying the protocol fee if one is set
+    let fee = ctx.accounts.global_config.tree_creation_fee;
+    if fee > 0 {
+        pay_treasury(
+            &ctx.accounts.system_program,
+            &ctx.accounts.creator,
+            &ctx.accounts.treasury,
+            fee,
+        )?;
+    }
 
-            let cpi_ctx = CpiContext::new_with_signer(
-                ctx.accounts.compression_program.to_account_info(), // The spl account compression program
-                VerifyLeaf {
-                    merkle_tree: ctx.accounts.merkle_tree.to_account_info(), // The merkle tree account to be modified
-                },
-                signer_seeds, // The seeds for pda signing
-            );
-            // Verify or Fails
-            verify_leaf(cpi_ctx, root, old_leaf, index)?;
-        }
+    // CPI to initialize an empty merkle tree with given max depth, buffer size and canopy
+    tree_cpi!(ctx).init_with_canopy(max_depth, max_buffer_size, canopy_depth)?;
 
-        let new_leaf =
-            keccak::hashv(&[new_note.as_bytes(), ctx.accounts.owner.key().as_ref()]).to_bytes();
+    // Record the creator as the first tree authority, and the tree parameters
+    let tree_state = TreeState::load(&ctx.accounts.merkle_tree)?;
+    ctx.accounts.tree_config.init(
+        ctx.accounts.creator.key(),
+        ctx.accounts.merkle_tree.key(),
+        &tree_state,
+        Clock::get()?.unix_timestamp,
+        *ctx.bumps.get("tree_config").unwrap(),
+    );
+    ctx.accounts.forest.record(
+        ctx.accounts.creator.key(),
+        ctx.accounts.merkle_tree.key(),
+        TreePurpose::Notes,
+        *ctx.bumps.get("forest").unwrap(),
+    )
+}
 
-        // Log out for indexers
-        let note_log = NoteLog::new(new_leaf.clone(), ctx.accounts.owner.key().clone(), new_note);
-        // Log the "note log" data using noop program
-        wrap_application_data_v1(note_log.try_to_vec()?, &ctx.accounts.log_wrapper)?;
+// Appends a new note leaf in the given status, logging it for indexers
+fn append_note_leaf<'info>(
+    ctx: Context<'_, '_, '_, 'info, NoteAccounts<'info>>,
+    note: String,
+    status: NoteStatus,
+    instruction: u128, // The instruction appending the note, checked against a session's scope
+) -> Result<()> {
+    require!(
+        note.len() <= MAX_NOTE_LEN,
+        CompressedNotesError::NoteTooLong
+    );
+    // Refuse text that indexers would display deceptively
+    require_clean_text(&note)?;
+    // A session key signs for the session's owner
+    let signer = Session::resolve(
+        &ctx.accounts.owner.key(),
+        &ctx.accounts.session,
+        instruction,
+    )?;
+    let owner = resolve_note_owner(&signer, &ctx.accounts.did)?;
+    // Hash the "note message" which will be stored as leaf node in the merkle tree
+    let (leaf_node, schema_version) =
+        hash_note_leaf_for(&note, &owner, status, &ctx.accounts.identity_link)?;
+    let note_len = note.len();
 
-        // replace leaf
+    // A full tree continues in the successor its authority linked with `set_next_tree`, passed
+    // as a (tree_config, tree_authority, merkle_tree) triple in the remaining accounts. The
+    // successor has the same policy, so the checks below run against the full tree's accounts
+    let tree_config: &AccountInfo<'info> = &ctx.accounts.tree_config;
+    let (tree_config, merkle_tree, tree_cpi) = match ctx.remaining_accounts {
+        [next_config, tree_authority, next_tree]
+            if TreeState::load(&ctx.accounts.merkle_tree)?.is_full() =>
         {
-            let cpi_ctx = CpiContext::new_with_signer(
-                ctx.accounts.compression_program.to_account_info(), // The spl account compression program
-                Modify {
-                    authority: ctx.accounts.tree_authority.to_account_info(), // The authority for the merkle tree, using a PDA
-                    merkle_tree: ctx.accounts.merkle_tree.to_account_info(), // The merkle tree account to be modified
-                    noop: ctx.accounts.log_wrapper.to_account_info(), // The noop program to log data
-                },
-                signer_seeds, // The seeds for pda signing
+            TreeConfig::require_successor(tree_config, next_config, next_tree.key)?;
+            // The tree authority must be the pda for the successor tree
+            let (expected_authority, bump) =
+                Pubkey::find_program_address(&[next_tree.key.as_ref()], ctx.program_id);
+            require_keys_eq!(
+                tree_authority.key(),
+                expected_authority,
+                CompressedNotesError::InvalidSuccessorTree
+            );
+            let tree_cpi = TreeCpi::new(
+                &ctx.accounts.compression_program,
+                tree_authority,
+                next_tree,
+                &ctx.accounts.log_wrapper,
+                bump,
             );
-            // CPI to append the leaf node to the merkle tree
-            replace_leaf(cpi_ctx, root, old_leaf, new_leaf, index)?;
+            (next_config, next_tree, tree_cpi)
+        }
+        _ => (
+            tree_config,
+            ctx.accounts.merkle_tree.as_ref(),
+            tree_cpi!(ctx),
+        ),
+    };
+
+    // Create a new "note log" using the leaf node hash, the id the leaf will get and note.
+    let note_id = NoteId::next(merkle_tree, schema_version)?;
+    let note_log = NoteLog::new(leaf_node, note_id, owner, note, status);
+    // Log the "note log" data using noop program
+    wrap_application_data_v1(note_log.try_to_vec()?, &ctx.accounts.log_wrapper)?;
+    // An attributed note also logs the ethereum address its leaf commits to
+    if let Some(attribution_log) =
+        EthAttributionLog::for_leaf(leaf_node, note_id, &ctx.accounts.identity_link)
+    {
+        wrap_application_data_v1(attribution_log.try_to_vec()?, &ctx.accounts.log_wrapper)?;
+    }
+    // Count the append against the rate limits of the tree the owner appends to
+    TreeConfig::record_rate(
+        &ctx.accounts.tree_config,
+        ctx.accounts.rate_state.as_deref_mut(),
+    )?;
+    // A tree refusing duplicate leaves checks the leaf against its recent leaves
+    TreeConfig::record_leaf(
+        &ctx.accounts.tree_config,
+        ctx.accounts.recent_leaves.as_deref_mut(),
+        &leaf_node,
+    )?;
+    // A tree charging an append fee takes it from the signer into the tree treasury
+    TreeConfig::record_fee(
+        &ctx.accounts.tree_config,
+        ctx.accounts.fee_payer(),
+        ctx.accounts.tree_treasury.as_ref(),
+        ctx.accounts.system_program.as_ref(),
+        FeeOperation::Append(note_len),
+    )?;
+    // A tree charging a token append fee takes it from the signer into its fee vault
+    TreeConfig::record_token_fee(
+        &ctx.accounts.tree_config,
+        ctx.accounts.fee_payer(),
+        ctx.accounts.payer_token_account.as_ref(),
+        ctx.accounts.fee_vault.as_ref(),
+        ctx.accounts.token_program.as_ref(),
+    )?;
+    // A token- or nft-gated tree only accepts posters holding its gate
+    TreeConfig::require_gate(
+        &ctx.accounts.tree_config,
+        &signer,
+        ctx.accounts.gate_token_account.as_ref(),
+        ctx.accounts.gate_metadata.as_ref(),
+    )?;
+    // A tree asking posters to stake only accepts posters with enough staked
+    TreeConfig::record_stake(&ctx.accounts.tree_config, ctx.accounts.stake.as_deref_mut())?;
+    // A tree selling access only accepts posters with an active subscription
+    TreeConfig::require_subscription(
+        &ctx.accounts.tree_config,
+        &signer,
+        ctx.accounts.subscription.as_deref(),
+    )?;
+    // A tree restricting cpi callers only accepts the programs it allows
+    TreeConfig::require_caller(tree_config, ctx.accounts.instructions.as_deref())?;
+    // Count the append on the tree config, failing if the tree is frozen or private
+    TreeConfig::record_append(
+        tree_config,
+        merkle_tree,
+        &signer,
+        ctx.accounts.role.as_deref(),
+    )?;
+    // CPI to append the leaf node to the merkle tree
+    tree_cpi.append(leaf_node)?;
+    Ok(())
+}
+
+// The accounts needed to cpi into the spl account compression program for one tree,
+// signing as the tree authority pda
+pub struct TreeCpi<'info> {
+    compression_program: AccountInfo<'info>, // The spl account compression program
+    tree_authority: AccountInfo<'info>,      // The pda authority for the merkle tree
+    merkle_tree: AccountInfo<'info>,         // The merkle tree account
+    log_wrapper: AccountInfo<'info>,         // The noop program to log data
+    seed_prefix: &'static [u8], // Seed preceding the merkle tree in the authority pda seeds
+    bump: u8,                   // The bump seed for the tree authority pda
+    proof: Vec<AccountInfo<'info>>, // The proof nodes passed on with every cpi, empty by default
+}
+
+impl<'info> TreeCpi<'info> {
+    pub fn new(
+        compression_program: &impl ToAccountInfo<'info>,
+        tree_authority: &impl ToAccountInfo<'info>,
+        merkle_tree: &impl ToAccountInfo<'info>,
+        log_wrapper: &impl ToAccountInfo<'info>,
+        bump: u8,
+    ) -> Self {
+        Self {
+            compression_program: compression_program.to_account_info(),
+            tree_authority: tree_authority.to_account_info(),
+            merkle_tree: merkle_tree.to_account_info(),
+            log_wrapper: log_wrapper.to_account_info(),
+            // An empty seed does not change the derived address, so this signs as [merkle_tree]
+            seed_prefix: b"",
+            bump,
+            proof: Vec::new(),
         }
+    }
+
+    // Signs as a tree authority pda derived from [seed_prefix, merkle_tree] instead
+    pub fn with_seed_prefix(mut self, seed_prefix: &'static [u8]) -> Self {
+        self.seed_prefix = seed_prefix;
+        self
+    }
+
+    // Passes `proof` on as the proof of the leaf verified or replaced, the compression
+    // program completes the levels it leaves out from the canopy and empty subtrees
+    pub fn with_proof(mut self, proof: &[AccountInfo<'info>]) -> Self {
+        self.proof = proof.to_vec();
+        self
+    }
+
+    // Initializes an empty merkle tree with the given max depth and buffer size
+    pub fn init(&self, max_depth: u32, max_buffer_size: u32) -> Result<()> {
+        TreeState::require_valid_parameters(max_depth, max_buffer_size)?;
+        let merkle_tree = self.merkle_tree.key();
+        // Define the seeds for pda signing
+        let signer_seeds: &[&[&[u8]]] = &[&[self.seed_prefix, merkle_tree.as_ref(), &[self.bump]]];
+        let cpi_ctx = CpiContext::new_with_signer(
+            self.compression_program.clone(),
+            Initialize {
+                authority: self.tree_authority.clone(),
+                merkle_tree: self.merkle_tree.clone(),
+                noop: self.log_wrapper.clone(),
+            },
+            signer_seeds,
+        );
+        init_empty_merkle_tree(cpi_ctx, max_depth, max_buffer_size)
+    }
+
+    // Initializes an empty merkle tree, failing unless the account was allocated
+    // with exactly the size of the tree and a canopy of `canopy_depth` levels
+    pub fn init_with_canopy(
+        &self,
+        max_depth: u32,
+        max_buffer_size: u32,
+        canopy_depth: u32,
+    ) -> Result<()> {
+        TreeState::require_account_size(
+            &self.merkle_tree,
+            max_depth,
+            max_buffer_size,
+            canopy_depth,
+        )?;
+        self.init(max_depth, max_buffer_size)
+    }
+
+    // Appends a leaf node to the merkle tree, failing with TreeFull if it has no leaves left
+    pub fn append(&self, leaf_node: [u8; 32]) -> Result<()> {
+        require!(
+            !TreeState::load(&self.merkle_tree)?.is_full(),
+            CompressedNotesError::TreeFull
+        );
+        let merkle_tree = self.merkle_tree.key();
+        // Define the seeds for pda signing
+        let signer_seeds: &[&[&[u8]]] = &[&[self.seed_prefix, merkle_tree.as_ref(), &[self.bump]]];
+        append(self.modify_ctx(signer_seeds), leaf_node)
+    }
 
+    // Replaces the leaf node at `index`, failing if `old_leaf` is not at that index under `root`
+    pub fn replace(
+        &self,
+        root: [u8; 32],
+        old_leaf: [u8; 32],
+        new_leaf: [u8; 32],
+        index: u32,
+    ) -> Result<()> {
+        self.require_leaf(root, old_leaf, index)?;
+        let merkle_tree = self.merkle_tree.key();
+        // Define the seeds for pda signing
+        let signer_seeds: &[&[&[u8]]] = &[&[self.seed_prefix, merkle_tree.as_ref(), &[self.bump]]];
+        replace_leaf(
+            self.modify_ctx(signer_seeds),
+            root,
+            old_leaf,
+            new_leaf,
+            index,
+        )
+    }
+
+    // Closes the merkle tree, failing unless it is empty, and pays its rent to `recipient`
+    pub fn close(&self, recipient: &impl ToAccountInfo<'info>) -> Result<()> {
+        let merkle_tree = self.merkle_tree.key();
+        // Define the seeds for pda signing
+        let signer_seeds: &[&[&[u8]]] = &[&[self.seed_prefix, merkle_tree.as_ref(), &[self.bump]]];
+        let cpi_ctx = CpiContext::new_with_signer(
+            self.compression_program.clone(),
+            CloseEmptyTree {
+                merkle_tree: self.merkle_tree.clone(),
+                authority: self.tree_authority.clone(),
+                recipient: recipient.to_account_info(),
+            },
+            signer_seeds,
+        );
+        close_empty_tree(cpi_ctx)
+    }
+
+    // Fails unless `leaf` is at `index` in the merkle tree under `root`
+    pub fn verify(&self, root: [u8; 32], leaf: [u8; 32], index: u32) -> Result<()> {
+        self.require_leaf(root, leaf, index)?;
+        let cpi_ctx = CpiContext::new(
+            self.compression_program.clone(),
+            VerifyLeaf {
+                merkle_tree: self.merkle_tree.clone(),
+            },
+        )
+        .with_remaining_accounts(self.proof.clone());
+        verify_leaf(cpi_ctx, root, leaf, index)
+    }
+
+    // Fails unless the tree was initialized with this authority pda and `leaf` is at `index`.
+    // Only proofs of the current root are checked here, the compression program fast-forwards
+    // proofs of older roots through its change log and checks those itself.
+    fn require_leaf(&self, root: [u8; 32], leaf: [u8; 32], index: u32) -> Result<()> {
+        let state = TreeState::load(&self.merkle_tree)?;
+        require_keys_eq!(
+            state.authority,
+            self.tree_authority.key(),
+            CompressedNotesError::InvalidTreeAuthority
+        );
+        state.require_leaf(index)?;
+        if root == state.root {
+            let proven_root = TreeState::proven_root(&self.merkle_tree, leaf, index, &self.proof)?;
+            require!(proven_root == root, CompressedNotesError::LeafMismatch);
+        }
         Ok(())
     }
+
+    fn modify_ctx<'a, 'b, 'c>(
+        &self,
+        signer_seeds: &'a [&'b [&'c [u8]]],
+    ) -> CpiContext<'a, 'b, 'c, 'info, Modify<'info>> {
+        CpiContext::new_with_signer(
+            self.compression_program.clone(),
+            Modify {
+                authority: self.tree_authority.clone(),
+                merkle_tree: self.merkle_tree.clone(),
+                noop: self.log_wrapper.clone(),
+            },
+            signer_seeds,
+        )
+        .with_remaining_accounts(self.proof.clone())
+    }
 }
 
 #[derive(AnchorSerialize)]
 pub struct NoteLog {
-    leaf_node: [u8; 32],  // The leaf node hash
-    owner: Pubkey,        // Pubkey of the note owner
-    note: String,         // The note message
+    leaf_node: [u8; 32], // The leaf node hash
+    note_id: NoteId,     // The id of the leaf
+    owner: Pubkey,       // Pubkey of the note owner
+    note: String,        // The note message
+    status: NoteStatus,  // The status committed into the leaf node
 }
 
 impl NoteLog {
-    // Constructs a new note from given leaf node and message
-    pub fn new(leaf_node: [u8; 32], owner: Pubkey, note: String) -> Self {
-        Self { leaf_node, owner, note }
+    // Constructs a new note from given leaf node, id, message and status
+    pub fn new(
+        leaf_node: [u8; 32],
+        note_id: NoteId,
+        owner: Pubkey,
+        note: String,
+        status: NoteStatus,
+    ) -> Self {
+        Self {
+            leaf_node,
+            note_id,
+            owner,
+            note,
+            status,
+        }
     }
 }
 
+#[derive(Accounts)]
+pub struct CreateNoteTree<'info> {
+    // The creator of the tree, pays for the tree config account and the protocol fee
+    #[account(mut)]
+    pub creator: Signer<'info>,
+
+    // The tree config pda, recording the creator and tree parameters
+    #[account(
+        init,
+        payer = creator,
+        space = 8 + TreeConfig::INIT_SPACE,
+        seeds = [TreeConfig::SEED, merkle_tree.key().as_ref()],
+        bump,
+    )]
+    pub tree_config: Account<'info, TreeConfig>,
+
+    // The forest pda of the creator, listing their trees, created with their first tree
+    #[account(
+        init_if_needed,
+        payer = creator,
+        space = 8 + Forest::INIT_SPACE,
+        seeds = [Forest::SEED, creator.key().as_ref()],
+        bump,
+    )]
+    pub forest: Account<'info, Forest>,
+
+    // The pda authority for the merkle tree, only used for signing
+    #[account(
+        seeds = [merkle_tree.key().as_ref()],
+        bump,
+    )]
+    pub tree_authority: SystemAccount<'info>,
+
+    // The merkle tree account, allocated by the client, by `create_allocated_note_tree`, or
+    // at a pda by `create_named_tree`
+    /// CHECK: This account is validated by the spl account compression program
+    #[account(mut)]
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    // The noop program to log data
+    pub log_wrapper: Program<'info, Noop>,
+
+    // The spl account compression program
+    pub compression_program: Program<'info, SplAccountCompression>,
+
+    // The protocol treasury pda, receives the tree creation fee
+    #[account(mut, seeds = [TREASURY_SEED], bump)]
+    pub treasury: SystemAccount<'info>,
+
+    // The system program, used to create the tree config and tree accounts and pay the fee
+    pub system_program: Program<'info, System>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
 #[derive(Accounts)]
 pub struct NoteAccounts<'info> {
-    // The payer for the transaction
+    // The author of the note, who pays the fees unless a separate payer signs
     #[account(mut)]
     pub owner: Signer<'info>,
 
+    // A separate payer for the fees, if any, so an app can sponsor its users' notes
+    #[account(mut)]
+    pub payer: Option<Signer<'info>>,
+
+    // The session of the owner's session key signing appends, if any
+    pub session: Option<Account<'info, Session>>,
+
+    // The DID document owning the note, if any, the owner must be one of its controllers
+    pub did: Option<Account<'info, DidDocument>>,
+
+    // The role of the owner on the tree, if any, letting them write to a private tree
+    pub role: Option<Account<'info, Role>>,
+
+    // The identity link of the owner, if any, committing their ethereum address into the leaf
+    pub identity_link: Option<Account<'info, IdentityLink>>,
+
+    // The instructions sysvar, needed on trees restricting which programs can call in
+    /// CHECK: The address constraint ties it to the instructions sysvar
+    #[account(address = sysvar_instructions::ID)]
+    pub instructions: Option<UncheckedAccount<'info>>,
+
+    // The rate state of the owner on the tree, needed to append to a rate limited tree
+    #[account(
+        mut,
+        seeds = [
+            RateState::SEED,
+            merkle_tree.key().as_ref(),
+            Session::acting_for(owner.key, &session).as_ref(),
+        ],
+        bump,
+    )]
+    pub rate_state: Option<Account<'info, RateState>>,
+
+    // The recent leaves pda of the tree, needed to append to a tree refusing duplicate leaves
+    #[account(
+        mut,
+        seeds = [RecentLeaves::SEED, merkle_tree.key().as_ref()],
+        bump,
+    )]
+    pub recent_leaves: Option<Account<'info, RecentLeaves>>,
+
+    // The hold pda of the leaf replaced, needed to replace leaves in a tree with leaves on hold
+    /// CHECK: Checked against the leaf replaced, which must not be on hold
+    pub hold: Option<UncheckedAccount<'info>>,
+
+    // The tree treasury pda, needed on a tree charging a fee for the operation
+    #[account(mut, seeds = [TREE_TREASURY_SEED, merkle_tree.key().as_ref()], bump)]
+    pub tree_treasury: Option<SystemAccount<'info>>,
+
+    // The signer's token account paying the fee, needed to append to a tree charging a token fee
+    #[account(mut)]
+    pub payer_token_account: Option<Account<'info, TokenAccount>>,
+
+    // The fee vault pda of the tree's fee mint, needed to append to a tree charging a token fee
+    #[account(mut)]
+    pub fee_vault: Option<Account<'info, TokenAccount>>,
+
+    // The spl token program, needed to append to a tree charging a token fee
+    pub token_program: Option<Program<'info, Token>>,
+
+    // A token account of the poster, needed to append to a token- or nft-gated tree
+    pub gate_token_account: Option<Account<'info, TokenAccount>>,
+
+    // The metadata account of the poster's nft, needed to append to an nft-gated tree
+    pub gate_metadata: Option<Account<'info, MetadataAccount>>,
+
+    // The stake pda of the owner on the tree, needed to append to a tree asking posters to stake
+    #[account(
+        mut,
+        seeds = [
+            Stake::SEED,
+            merkle_tree.key().as_ref(),
+            Session::acting_for(owner.key, &session).as_ref(),
+        ],
+        bump,
+    )]
+    pub stake: Option<Account<'info, Stake>>,
+
+    // The poster's subscription to the tree, needed to append to a tree selling access
+    pub subscription: Option<Account<'info, TreeSubscription>>,
+
+    // The tree config pda, counting appends and replaces, which may not have been created
+    /// CHECK: Only read and written if it exists, the seeds tie it to the merkle tree
+    #[account(mut, seeds = [TreeConfig::SEED, merkle_tree.key().as_ref()], bump)]
+    pub tree_config: UncheckedAccount<'info>,
+
     // The pda authority for the merkle tree, only used for signing
     #[account(
         seeds = [merkle_tree.key().as_ref()],
@@ -174,7 +2358,10 @@ pub struct NoteAccounts<'info> {
 
     // The merkle tree account
     /// CHECK: This account is validated by the spl account compression program
-    #[account(mut)]
+    #[account(
+        mut,
+        owner = spl_account_compression::ID @ CompressedNotesError::InvalidMerkleTreeOwner,
+    )]
     pub merkle_tree: UncheckedAccount<'info>,
 
     // The noop program to log data
@@ -182,4 +2369,450 @@ pub struct NoteAccounts<'info> {
 
     // The spl account compression program
     pub compression_program: Program<'info, SplAccountCompression>,
-}
\ No newline at end of file
+
+    // The system program, needed on a tree charging a fee for the operation
+    pub system_program: Option<Program<'info, System>>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+impl<'info> NoteAccounts<'info> {
+    // The signer paying fees, the separate payer if one signs, otherwise the owner
+    pub fn fee_payer(&self) -> &Signer<'info> {
+        self.payer.as_ref().unwrap_or(&self.owner)
+    }
+}
+
+// Bit flags for the instructions that can be switched off per deployment
+pub mod instruction_flags {
+    pub const CREATE_NOTE_TREE: u128 = 1 << 0;
+    pub const APPEND_NOTE: u128 = 1 << 1;
+    pub const UPDATE_NOTE: u128 = 1 << 2;
+    pub const APPEND_MESSAGE: u128 = 1 << 3;
+    pub const MARK_READ: u128 = 1 << 4;
+    pub const CREATE_GROUP: u128 = 1 << 5;
+    pub const SET_GROUP_MEMBERS: u128 = 1 << 6;
+    pub const APPEND_GROUP_MESSAGE: u128 = 1 << 7;
+    pub const CREATE_CHANNEL_TREE: u128 = 1 << 8;
+    pub const SUBSCRIBE: u128 = 1 << 9;
+    pub const UNSUBSCRIBE: u128 = 1 << 10;
+    pub const BROADCAST: u128 = 1 << 11;
+    pub const APPEND_DRAFT: u128 = 1 << 12;
+    pub const SET_NOTE_STATUS: u128 = 1 << 13;
+    pub const MAKE_OFFER: u128 = 1 << 14;
+    pub const ACCEPT_OFFER: u128 = 1 << 15;
+    pub const CANCEL_OFFER: u128 = 1 << 16;
+    pub const REACT: u128 = 1 << 17;
+    pub const AUDIT_LEAF: u128 = 1 << 18;
+    pub const QUOTE_APPEND: u128 = 1 << 19;
+    pub const APPEND_MESSAGE_WITH_TTL: u128 = 1 << 20;
+    pub const BURN_EXPIRED_MESSAGE: u128 = 1 << 21;
+    pub const UPDATE_MESSAGE: u128 = 1 << 22;
+    pub const CROSS_POST: u128 = 1 << 23;
+    pub const APPEND_ENCRYPTED_MESSAGE: u128 = 1 << 24;
+    pub const FORWARD_MESSAGE: u128 = 1 << 25;
+    pub const CREATE_CERTIFICATE_TREE: u128 = 1 << 26;
+    pub const ISSUE_CERTIFICATE: u128 = 1 << 27;
+    pub const REVOKE_CERTIFICATE: u128 = 1 << 28;
+    pub const CHECK_CERTIFICATE: u128 = 1 << 29;
+    pub const CREATE_CONVERSATION_META: u128 = 1 << 30;
+    pub const UPDATE_CONVERSATION_META: u128 = 1 << 31;
+    pub const CREATE_DID: u128 = 1 << 32;
+    pub const SET_DID_CONTROLLERS: u128 = 1 << 33;
+    pub const CREATE_ORGANIZATION: u128 = 1 << 34;
+    pub const PURCHASE_SEATS: u128 = 1 << 35;
+    pub const ADD_SEAT: u128 = 1 << 36;
+    pub const REMOVE_SEAT: u128 = 1 << 37;
+    pub const APPEND_ORG_NOTE: u128 = 1 << 38;
+    pub const CREATE_CONVERSATION_TREE: u128 = 1 << 39;
+    pub const REQUEST_CONVERSATION: u128 = 1 << 40;
+    pub const ACCEPT_CONVERSATION: u128 = 1 << 41;
+    pub const SET_ALLOWLIST: u128 = 1 << 42;
+    pub const OPEN_USAGE_PERIOD: u128 = 1 << 43;
+    pub const FINALIZE_USAGE_PERIOD: u128 = 1 << 44;
+    pub const SET_RETENTION_TIER: u128 = 1 << 45;
+    pub const PRUNE_ORG_NOTE: u128 = 1 << 46;
+    pub const BLOCK_SENDER: u128 = 1 << 47;
+    pub const UNBLOCK_SENDER: u128 = 1 << 48;
+    pub const EMIT_PRESENCE: u128 = 1 << 49;
+    pub const EMIT_TYPING: u128 = 1 << 50;
+    pub const GET_OR_CREATE_INBOX_TREE: u128 = 1 << 51;
+    pub const REGISTER_KEEPER: u128 = 1 << 52;
+    pub const KEEPER_CHECKPOINT: u128 = 1 << 53;
+    pub const SLASH_KEEPER: u128 = 1 << 54;
+    pub const DEREGISTER_KEEPER: u128 = 1 << 55;
+    pub const CONFIRM_DELIVERY: u128 = 1 << 56;
+    pub const ACKNOWLEDGE_MESSAGE: u128 = 1 << 57;
+    pub const SET_CONVERSATION_FLAGS: u128 = 1 << 58;
+    pub const CLOSE_TREE: u128 = 1 << 59;
+    pub const TRANSFER_TREE_AUTHORITY: u128 = 1 << 60;
+    pub const FREEZE_TREE: u128 = 1 << 61;
+    pub const THAW_TREE: u128 = 1 << 62;
+    pub const GET_TREE_CAPACITY: u128 = 1 << 63;
+    pub const SET_TREE_METADATA: u128 = 1 << 64;
+    pub const SET_TREE_VISIBILITY: u128 = 1 << 65;
+    pub const ADD_WRITER: u128 = 1 << 66;
+    pub const REMOVE_WRITER: u128 = 1 << 67;
+    pub const CREATE_ALLOCATED_NOTE_TREE: u128 = 1 << 68;
+    pub const BEGIN_MIGRATION: u128 = 1 << 69;
+    pub const MIGRATE_LEAF: u128 = 1 << 70;
+    pub const FINISH_MIGRATION: u128 = 1 << 71;
+    pub const MOVE_NOTE: u128 = 1 << 72;
+    pub const GET_TREE_ROOT: u128 = 1 << 73;
+    pub const CREATE_NAMED_TREE: u128 = 1 << 74;
+    pub const DECOMMISSION_TREE: u128 = 1 << 75;
+    pub const CREATE_TREE_MULTISIG: u128 = 1 << 76;
+    pub const PROPOSE_TREE_ACTION: u128 = 1 << 77;
+    pub const APPROVE_TREE_ACTION: u128 = 1 << 78;
+    pub const EXECUTE_TREE_ACTION: u128 = 1 << 79;
+    pub const GRANT_ROLE: u128 = 1 << 80;
+    pub const REVOKE_ROLE: u128 = 1 << 81;
+    pub const CREATE_SENDER_NONCE: u128 = 1 << 82;
+    pub const SET_RATE_LIMITS: u128 = 1 << 83;
+    pub const CREATE_RATE_STATE: u128 = 1 << 84;
+    pub const CREATE_SESSION: u128 = 1 << 85;
+    pub const REVOKE_SESSION: u128 = 1 << 86;
+    pub const APPEND_SHARED_NOTE: u128 = 1 << 87;
+    pub const UPDATE_SHARED_NOTE: u128 = 1 << 88;
+    pub const OFFER_NOTE_TRANSFER: u128 = 1 << 89;
+    pub const ACCEPT_NOTE_TRANSFER: u128 = 1 << 90;
+    pub const CANCEL_NOTE_TRANSFER: u128 = 1 << 91;
+    pub const SET_CPI_POLICY: u128 = 1 << 92;
+    pub const APPEND_NOTE_WITH_PERMIT: u128 = 1 << 93;
+    pub const CLOSE_EXPIRED_PERMIT: u128 = 1 << 94;
+    pub const LINK_ETH_IDENTITY: u128 = 1 << 95;
+    pub const UNLINK_ETH_IDENTITY: u128 = 1 << 96;
+    pub const CREATE_RECENT_LEAVES: u128 = 1 << 97;
+    pub const CLOSE_RECENT_LEAVES: u128 = 1 << 98;
+    pub const SET_LEGAL_HOLD: u128 = 1 << 99;
+    pub const TAKEDOWN_NOTE: u128 = 1 << 100;
+    pub const REPORT_LEAF: u128 = 1 << 101;
+    pub const EMERGENCY_FREEZE_TREE: u128 = 1 << 102;
+    pub const EMERGENCY_THAW_TREE: u128 = 1 << 103;
+    pub const SET_APPEND_FEE: u128 = 1 << 104;
+    pub const WITHDRAW_TREE_TREASURY: u128 = 1 << 105;
+    pub const SET_TOKEN_APPEND_FEE: u128 = 1 << 106;
+    pub const WITHDRAW_TOKEN_FEES: u128 = 1 << 107;
+    pub const TIP_NOTE: u128 = 1 << 108;
+    pub const SET_TOKEN_GATE: u128 = 1 << 109;
+    pub const SET_NFT_GATE: u128 = 1 << 110;
+    pub const SET_MIN_STAKE: u128 = 1 << 111;
+    pub const STAKE_FOR_POSTING: u128 = 1 << 112;
+    pub const WITHDRAW_STAKE: u128 = 1 << 113;
+    pub const SLASH_STAKE: u128 = 1 << 114;
+    pub const CREATE_MESSAGE_ESCROW: u128 = 1 << 115;
+    pub const UNLOCK_MESSAGE: u128 = 1 << 116;
+    pub const SET_SUBSCRIPTION_PRICE: u128 = 1 << 117;
+    pub const SUBSCRIBE_TO_TREE: u128 = 1 << 118;
+    pub const CHECK_SUBSCRIPTION: u128 = 1 << 119;
+    pub const SET_FEE_SCHEDULE: u128 = 1 << 120;
+    pub const GRANT_AUDITOR: u128 = 1 << 121;
+    pub const REVOKE_AUDITOR: u128 = 1 << 122;
+    pub const WITHDRAW_TREASURY: u128 = 1 << 123;
+    pub const RELEASE_MESSAGE_KEY: u128 = 1 << 124;
+    pub const REFUND_MESSAGE_ESCROW: u128 = 1 << 125;
+    pub const SET_NEXT_TREE: u128 = 1 << 126;
+    pub const ALL: u128 = u128::MAX;
+    // The instructions only reading a tree, still allowed while the deployment is paused
+    pub const READ_ONLY: u128 =
+        AUDIT_LEAF | CHECK_CERTIFICATE | CHECK_SUBSCRIPTION | GET_TREE_CAPACITY | GET_TREE_ROOT;
+    // The instructions allowed while the deployment is paused, keepers keep checkpointing
+    // so a pause cannot make them miss their SLA
+    pub const PAUSE_EXEMPT: u128 = READ_ONLY | KEEPER_CHECKPOINT;
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct GlobalConfig {
+    admin: Pubkey,              // The admin allowed to change the global config
+    enabled_instructions: u128, // Bitmask of `instruction_flags` enabled on this deployment
+    tree_creation_fee: u64,     // Lamports paid to the protocol treasury for each note tree
+    paused: bool,               // Whether instructions changing state are paused
+    bump: u8,                   // The bump seed for the pda
+}
+
+impl GlobalConfig {
+    pub const SEED: &'static [u8] = b"global_config";
+
+    // Fails unless every instruction in `flags` is enabled on this deployment and, for
+    // instructions changing state other than keeper checkpoints, the deployment is not paused
+    pub fn require_enabled(&self, flags: u128) -> Result<()> {
+        require!(
+            !self.paused || flags & !instruction_flags::PAUSE_EXEMPT == 0,
+            CompressedNotesError::ProgramPaused
+        );
+        require!(
+            self.enabled_instructions & flags == flags,
+            CompressedNotesError::InstructionDisabled
+        );
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+pub struct InitializeGlobalConfig<'info> {
+    // The upgrade authority of this program, pays for the global config
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    // The global config pda, one per deployment
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + GlobalConfig::INIT_SPACE,
+        seeds = [GlobalConfig::SEED],
+        bump,
+    )]
+    pub global_config: Account<'info, GlobalConfig>,
+
+    // This program, used to find its program data account
+    #[account(constraint = program.programdata_address()? == Some(program_data.key()))]
+    pub program: Program<'info, crate::program::CompressedNotes>,
+
+    // The program data account, which records the upgrade authority
+    #[account(
+        constraint = program_data.upgrade_authority_address == Some(authority.key())
+            @ CompressedNotesError::Unauthorized
+    )]
+    pub program_data: Account<'info, ProgramData>,
+
+    // The system program, used to create the global config account
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct GlobalConfigAdmin<'info> {
+    // The admin of the global config
+    pub admin: Signer<'info>,
+
+    // The global config pda
+    #[account(
+        mut,
+        seeds = [GlobalConfig::SEED],
+        bump = global_config.bump,
+        has_one = admin @ CompressedNotesError::Unauthorized,
+    )]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[error_code]
+pub enum CompressedNotesError {
+    #[msg("This instruction is disabled on this deployment")]
+    InstructionDisabled,
+    #[msg("The signer is not allowed to perform this action")]
+    Unauthorized,
+    #[msg("This instruction's subsystem is not compiled into this deployment")]
+    SubsystemNotCompiled,
+    #[msg("A group can have at most 16 members")]
+    GroupTooLarge,
+    #[msg("The sender is not a member of the group")]
+    NotGroupMember,
+    #[msg("The note cannot move from its current status to the requested one")]
+    InvalidNoteTransition,
+    #[msg("The offer has expired")]
+    OfferExpired,
+    #[msg("A reaction must be between 1 and 32 bytes")]
+    InvalidReaction,
+    #[msg("The expiry must be in the future")]
+    InvalidExpiry,
+    #[msg("The message has not expired yet")]
+    MessageNotExpired,
+    #[msg("A cross post needs 1 to 4 distinct (tree authority, merkle tree) account pairs")]
+    InvalidCrossPostTrees,
+    #[msg("The account is not an initialized spl account compression tree")]
+    InvalidTreeAccount,
+    #[msg("The certificate has expired")]
+    CertificateExpired,
+    #[msg("A title can be at most 64 bytes")]
+    TitleTooLong,
+    #[msg("A DID document needs 1 to 4 controllers")]
+    InvalidDidControllers,
+    #[msg("The organization has no free seats")]
+    NoSeatsAvailable,
+    #[msg("The recipient has not accepted messages from the sender")]
+    ConversationNotAccepted,
+    #[msg("An allowlist can have at most 8 senders")]
+    AllowlistTooLarge,
+    #[msg("The usage period is not the current one or was finalized")]
+    UsagePeriodClosed,
+    #[msg("The usage period has not ended yet")]
+    UsagePeriodNotEnded,
+    #[msg("The note is still within the organization's retention period")]
+    RetentionNotElapsed,
+    #[msg("The recipient has blocked the sender")]
+    SenderBlocked,
+    #[msg("A block list can have at most 32 senders")]
+    BlockListFull,
+    #[msg("The keeper missed its SLA and can only be slashed")]
+    SlaMissed,
+    #[msg("The keeper has not missed its SLA")]
+    SlaNotMissed,
+    #[msg(
+        "An outbox is one (tree authority, merkle tree) pair for a tree other than the recipient's"
+    )]
+    InvalidOutboxTree,
+    #[msg("The canopy depth is deeper than the tree")]
+    InvalidCanopyDepth,
+    #[msg("The tree is frozen by its authority")]
+    TreeFrozen,
+    #[msg("The forest lists the most trees it can hold")]
+    ForestFull,
+    #[msg("The successor tree is not the one linked to the full tree, or not its pda authority")]
+    InvalidSuccessorTree,
+    #[msg("The tree has no leaves left, append to its successor")]
+    TreeFull,
+    #[msg("The tree name, description or image uri is too long")]
+    TreeMetadataTooLong,
+    #[msg("The tree is private, only its authority and writers can append to it")]
+    TreeNotWritable,
+    #[msg("The tree already lists its maximum number of writers")]
+    TooManyWriters,
+    #[msg("The writer is already listed when adding, or not listed when removing")]
+    InvalidWriter,
+    #[msg("The tree account size does not match its depth, buffer size and canopy depth")]
+    InvalidTreeAccountSize,
+    #[msg("Leaves migrate in index order, this one was already migrated or skipped")]
+    LeafAlreadyMigrated,
+    #[msg("The leaf is deleted or redacted, a compacting migration leaves it behind")]
+    LeafTombstoned,
+    #[msg("The root is older than the tree's recent roots, fetch a fresh proof")]
+    RootTooOld,
+    #[msg("No leaf was appended at the index")]
+    LeafIndexOutOfBounds,
+    #[msg("The tree was not initialized with this program's authority pda")]
+    InvalidTreeAuthority,
+    #[msg("A tree namespace can be at most 32 bytes")]
+    NamespaceTooLong,
+    #[msg("The merkle tree is not the pda of the creator and namespace")]
+    InvalidNamedTree,
+    #[msg("The compression program does not support the tree's depth and buffer size")]
+    UnsupportedTreeParameters,
+    #[msg("A note can be at most 1024 bytes")]
+    NoteTooLong,
+    #[msg("The leaf at the index does not match the given content under the root")]
+    LeafMismatch,
+    #[msg("The merkle tree is not owned by the spl account compression program")]
+    InvalidMerkleTreeOwner,
+    #[msg("A multisig needs 1 to 10 distinct members and a threshold between 1 and their count")]
+    InvalidMultisig,
+    #[msg("The signer is not a member of the multisig")]
+    NotMultisigMember,
+    #[msg("The member already approved the proposal")]
+    AlreadyApproved,
+    #[msg("The proposal does not have enough approvals yet")]
+    NotEnoughApprovals,
+    #[msg("The proposal was already executed")]
+    ProposalAlreadyExecuted,
+    #[msg("The nonce is not the next nonce of the sender")]
+    InvalidNonce,
+    #[msg("The sender appends to the tree faster than its rate limits allow")]
+    RateLimited,
+    #[msg("The tree is rate limited, the sender's rate state must be passed")]
+    RateStateMissing,
+    #[msg("The program is paused by the global config admin")]
+    ProgramPaused,
+    #[msg("The signer is not the session key of the session")]
+    InvalidSession,
+    #[msg("The session has expired")]
+    SessionExpired,
+    #[msg("The session does not cover this instruction")]
+    OutsideSessionScope,
+    #[msg("A shared note needs 1 to 8 co-owners, distinct from each other and the signer")]
+    InvalidCoOwners,
+    #[msg("Every co-owner must sign, in the order given, in the remaining accounts")]
+    MissingCoOwnerSignature,
+    #[msg("A tree can allowlist at most 4 cpi callers")]
+    TooManyCpiCallers,
+    #[msg("The tree restricts cpi callers, the instructions sysvar must be passed")]
+    InstructionsSysvarMissing,
+    #[msg("The tree does not allow this program to call in through a cpi")]
+    CpiCallerNotAllowed,
+    #[msg("The previous instruction does not verify the owner's ed25519 signature of the permit")]
+    InvalidPermit,
+    #[msg("The permit has expired")]
+    PermitExpired,
+    #[msg("The permit has not expired yet")]
+    PermitNotExpired,
+    #[msg(
+        "The previous instruction does not verify the address's secp256k1 signature of the link"
+    )]
+    InvalidEthSignature,
+    #[msg("Text contains a control character other than a newline or tab")]
+    ControlCharacter,
+    #[msg("Text contains an invisible or bidirectional formatting character")]
+    InvisibleCharacter,
+    #[msg("Text is not in Unicode NFC form")]
+    TextNotNormalized,
+    #[msg("The dedupe window must span at least one slot")]
+    InvalidDedupeWindow,
+    #[msg("The leaf was already appended to the tree recently")]
+    DuplicateLeaf,
+    #[msg("The tree refuses duplicate leaves, pass its recent leaves pda")]
+    RecentLeavesMissing,
+    #[msg("The new message is the same as the current one")]
+    NoChange,
+    #[msg("The leaf is on legal hold")]
+    LeafOnHold,
+    #[msg("The tree has leaves on legal hold, pass the hold pda of the leaf replaced")]
+    HoldMissing,
+    #[msg("The tree is frozen by the global config admin")]
+    TreeEmergencyFrozen,
+    #[msg("The reason code must not be zero")]
+    MissingReasonCode,
+    #[msg("The tree charges an append fee, the tree treasury and system program must be passed")]
+    TreeTreasuryMissing,
+    #[msg("The treasury must keep its rent exempt minimum")]
+    InsufficientTreasury,
+    #[msg("The tree charges a token append fee, the payer's token account, fee vault and token program must be passed")]
+    FeeVaultMissing,
+    #[msg("The fee vault is not the tree's vault for its fee mint")]
+    InvalidFeeVault,
+    #[msg("The tip must be more than zero")]
+    ZeroTip,
+    #[msg("Tips in an spl token need both token accounts and the token program, the owner's token account held by the owner")]
+    InvalidTipAccount,
+    #[msg("The tree is token-gated, a token account of the poster must be passed")]
+    GateTokenAccountMissing,
+    #[msg("The gate token account is not the poster's account of the gate mint")]
+    InvalidGateTokenAccount,
+    #[msg("The poster holds fewer tokens of the gate mint than the tree requires")]
+    BelowGateBalance,
+    #[msg("The tree is nft-gated, the poster's token account and nft metadata must be passed")]
+    GateMetadataMissing,
+    #[msg("The nft is not a verified member of the tree's gate collection")]
+    NotInGateCollection,
+    #[msg("The tree asks posters to stake, the poster's stake account must be passed")]
+    StakeMissing,
+    #[msg("The poster has staked fewer lamports than the tree requires")]
+    BelowMinStake,
+    #[msg("The stake is locked until enough slots have passed since the poster's last append")]
+    StakeLocked,
+    #[msg("Fewer lamports are staked than asked for")]
+    InsufficientStake,
+    #[msg("An escrowed message must have a price")]
+    ZeroEscrowPrice,
+    #[msg(
+        "The escrowed leaf is not the signer's encrypted message with the given ciphertext hash"
+    )]
+    InvalidEscrowLeaf,
+    #[msg("The tree does not sell subscriptions")]
+    NotPremiumTree,
+    #[msg("The subscription costs more lamports than fit in a u64")]
+    SubscriptionTooLong,
+    #[msg("The tree sells access, the poster's subscription to it must be passed")]
+    SubscriptionMissing,
+    #[msg("The subscription has expired")]
+    SubscriptionExpired,
+    #[msg("Role actions need the role pda of their member")]
+    RoleAccountMissing,
+    #[msg("The escrowed message is already unlocked")]
+    EscrowAlreadyUnlocked,
+    #[msg("The escrowed message has not been unlocked")]
+    EscrowNotUnlocked,
+    #[msg("The sender can still release the key of the escrowed message")]
+    KeyReleaseNotOverdue,
+    #[msg("The successor tree checks appends differently from the full tree")]
+    SuccessorPolicyMismatch,
+}
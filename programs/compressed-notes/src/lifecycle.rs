@@ -0,0 +1,54 @@
+//! The lifecycle of a note, whose status every note leaf commits to.
+
+use anchor_lang::prelude::*;
+
+use crate::CompressedNotesError;
+
+// The status of a note, committed into its leaf node
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NoteStatus {
+    Draft,     // Appended but not yet published, can be edited freely
+    Published, // Visible to readers
+    Edited,    // Published and edited at least once since
+    Locked,    // Can no longer be edited or deleted by its owner
+    Deleted,   // Removed by its owner
+    Redacted,  // Content removed, including from a locked note
+}
+
+impl NoteStatus {
+    // Fails unless a note may move from this status to `to`, otherwise returns `to`. Deleted
+    // and Redacted are final
+    pub fn transition(self, to: NoteStatus) -> Result<NoteStatus> {
+        use NoteStatus::*;
+        let allowed = matches!(
+            (self, to),
+            (Draft, Draft | Published | Deleted)
+                | (Published | Edited, Edited | Locked | Deleted | Redacted)
+                | (Locked, Redacted)
+        );
+        require!(allowed, CompressedNotesError::InvalidNoteTransition);
+        Ok(to)
+    }
+
+    // Fails unless a note in this status may change owner, otherwise returns the unchanged status
+    pub fn transfer(self) -> Result<NoteStatus> {
+        require!(
+            self.retains_content(),
+            CompressedNotesError::InvalidNoteTransition
+        );
+        Ok(self)
+    }
+
+    // The status a note moves to when its content is edited
+    pub fn edited(self) -> NoteStatus {
+        match self {
+            NoteStatus::Draft => NoteStatus::Draft,
+            _ => NoteStatus::Edited,
+        }
+    }
+
+    // Whether a leaf in this status still commits to the note content
+    pub fn retains_content(self) -> bool {
+        !matches!(self, NoteStatus::Deleted | NoteStatus::Redacted)
+    }
+}
@@ -0,0 +1,291 @@
+//! Pay-to-unlock messages, escrowing the price of an encrypted message in a pda at
+//! `[b"escrow", leaf_node]` until its sender releases the key. Compiled in with `messaging`.
+
+use anchor_lang::prelude::*;
+use anchor_lang::system_program::{transfer, Transfer};
+use spl_account_compression::{wrap_application_data_v1, Noop};
+
+use crate::{
+    hash_encrypted_message_leaf_from_digest, instruction_flags, CompressedNotesError, GlobalConfig,
+};
+
+// Prices the encrypted message `leaf_node` of the signer at `price` lamports for `recipient`.
+// The ciphertext hash and key exchange metadata must hash to the leaf with the signer as
+// sender, so nobody can sell a message they did not send.
+pub fn process_create_message_escrow(
+    ctx: Context<CreateMessageEscrow>,
+    leaf_node: [u8; 32],               // The leaf node of the encrypted message
+    ciphertext_hash: [u8; 32],         // The keccak hash of the encrypted message
+    nonce: [u8; 24],                   // The nonce the message was encrypted with
+    sender_ephemeral_pubkey: [u8; 32], // The sender's ephemeral x25519 key for the key exchange
+    recipient: Pubkey,                 // Pubkey of the recipient who can unlock the message
+    price: u64,                        // Lamports the recipient pays to unlock the message
+) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::CREATE_MESSAGE_ESCROW)?;
+    require!(price > 0, CompressedNotesError::ZeroEscrowPrice);
+
+    // Only the sender committed into the leaf can price it
+    let sender = ctx.accounts.sender.key();
+    let expected_leaf = hash_encrypted_message_leaf_from_digest(
+        &ciphertext_hash,
+        &sender,
+        &nonce,
+        &sender_ephemeral_pubkey,
+    );
+    require!(
+        leaf_node == expected_leaf,
+        CompressedNotesError::InvalidEscrowLeaf
+    );
+
+    let escrow = &mut ctx.accounts.escrow;
+    escrow.merkle_tree = ctx.accounts.merkle_tree.key();
+    escrow.leaf_node = leaf_node;
+    escrow.sender = sender;
+    escrow.recipient = recipient;
+    escrow.price = price;
+    escrow.bump = *ctx.bumps.get("escrow").unwrap();
+    Ok(())
+}
+
+// Pays the price of an escrowed message into its escrow, until the sender releases the key.
+pub fn process_unlock_message(ctx: Context<UnlockMessage>) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::UNLOCK_MESSAGE)?;
+    require!(
+        ctx.accounts.escrow.release_deadline == 0,
+        CompressedNotesError::EscrowAlreadyUnlocked
+    );
+
+    let cpi_ctx = CpiContext::new(
+        ctx.accounts.system_program.to_account_info(),
+        Transfer {
+            from: ctx.accounts.recipient.to_account_info(),
+            to: ctx.accounts.escrow.to_account_info(),
+        },
+    );
+    transfer(cpi_ctx, ctx.accounts.escrow.price)?;
+
+    let escrow = &mut ctx.accounts.escrow;
+    escrow.release_deadline = Clock::get()?
+        .unix_timestamp
+        .saturating_add(Escrow::RELEASE_WINDOW);
+    Ok(())
+}
+
+// Logs the decryption key of an unlocked message and pays its price to the sender.
+pub fn process_release_message_key(
+    ctx: Context<ReleaseMessageKey>,
+    sealed_key: [u8; 32], // The decryption key of the message, encrypted to the recipient
+) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::RELEASE_MESSAGE_KEY)?;
+
+    let escrow = &ctx.accounts.escrow;
+    require!(
+        escrow.release_deadline != 0,
+        CompressedNotesError::EscrowNotUnlocked
+    );
+
+    // Log the key for the recipient, the price and rent go to the sender when the escrow closes
+    let key_release_log = KeyReleaseLog::new(
+        escrow.merkle_tree,
+        escrow.leaf_node,
+        escrow.sender,
+        escrow.recipient,
+        escrow.price,
+        sealed_key,
+        Clock::get()?.slot,
+    );
+    wrap_application_data_v1(key_release_log.try_to_vec()?, &ctx.accounts.log_wrapper)?;
+    Ok(())
+}
+
+// Refunds the price of an unlocked message whose sender missed the release deadline.
+pub fn process_refund_message_escrow(ctx: Context<RefundMessageEscrow>) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::REFUND_MESSAGE_ESCROW)?;
+
+    let escrow = &ctx.accounts.escrow;
+    require!(
+        escrow.release_deadline != 0,
+        CompressedNotesError::EscrowNotUnlocked
+    );
+    require!(
+        Clock::get()?.unix_timestamp >= escrow.release_deadline,
+        CompressedNotesError::KeyReleaseNotOverdue
+    );
+
+    // Pay the price back to the recipient, the rent goes back to the sender when the escrow closes
+    let price = escrow.price;
+    **ctx
+        .accounts
+        .escrow
+        .to_account_info()
+        .try_borrow_mut_lamports()? -= price;
+    **ctx.accounts.recipient.try_borrow_mut_lamports()? += price;
+    Ok(())
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct Escrow {
+    merkle_tree: Pubkey,   // The tree holding the message
+    leaf_node: [u8; 32],   // The leaf node of the encrypted message
+    sender: Pubkey,        // The sender paid to unlock the message
+    recipient: Pubkey,     // The recipient who can unlock the message
+    price: u64,            // Lamports the recipient pays to unlock the message
+    release_deadline: i64, // Unix timestamp the sender must release the key by, zero until unlocked
+    bump: u8,              // The bump seed for the pda
+}
+
+impl Escrow {
+    pub const SEED: &'static [u8] = b"escrow";
+    // Seconds the sender has to release the key after the unlock before the recipient can refund
+    pub const RELEASE_WINDOW: i64 = 3 * 24 * 60 * 60;
+}
+
+#[derive(AnchorSerialize)]
+pub struct KeyReleaseLog {
+    merkle_tree: Pubkey,  // The tree holding the message
+    leaf_node: [u8; 32],  // The leaf node of the unlocked message
+    sender: Pubkey,       // Pubkey of the sender, who releases the decryption key
+    recipient: Pubkey,    // Pubkey of the recipient, who paid
+    price: u64,           // Lamports paid to the sender
+    sealed_key: [u8; 32], // The decryption key of the message, encrypted to the recipient
+    slot: u64,            // The slot of the key release
+}
+
+impl KeyReleaseLog {
+    // Constructs a new key release log from given tree, leaf, participants, price, key and slot
+    pub fn new(
+        merkle_tree: Pubkey,
+        leaf_node: [u8; 32],
+        sender: Pubkey,
+        recipient: Pubkey,
+        price: u64,
+        sealed_key: [u8; 32],
+        slot: u64,
+    ) -> Self {
+        Self {
+            merkle_tree,
+            leaf_node,
+            sender,
+            recipient,
+            price,
+            sealed_key,
+            slot,
+        }
+    }
+}
+
+#[derive(Accounts)]
+#[instruction(leaf_node: [u8; 32])]
+pub struct CreateMessageEscrow<'info> {
+    // The sender of the message, pays for the escrow account
+    #[account(mut)]
+    pub sender: Signer<'info>,
+
+    // The escrow pda of the message, one per leaf
+    #[account(
+        init,
+        payer = sender,
+        space = 8 + Escrow::INIT_SPACE,
+        seeds = [Escrow::SEED, leaf_node.as_ref()],
+        bump,
+    )]
+    pub escrow: Account<'info, Escrow>,
+
+    // The merkle tree holding the message
+    /// CHECK: Only recorded on the escrow for clients to find the message
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    // The system program, used to create the escrow account
+    pub system_program: Program<'info, System>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[derive(Accounts)]
+pub struct UnlockMessage<'info> {
+    // The recipient of the message, pays its price into the escrow
+    #[account(mut)]
+    pub recipient: Signer<'info>,
+
+    // The escrow pda of the message, holds the price until the key is released or refunded
+    #[account(
+        mut,
+        seeds = [Escrow::SEED, escrow.leaf_node.as_ref()],
+        bump = escrow.bump,
+        has_one = recipient @ CompressedNotesError::Unauthorized,
+    )]
+    pub escrow: Account<'info, Escrow>,
+
+    // The system program, used to pay the price
+    pub system_program: Program<'info, System>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[derive(Accounts)]
+pub struct ReleaseMessageKey<'info> {
+    // The sender of the message, paid the price and refunded the escrow rent
+    #[account(mut)]
+    pub sender: Signer<'info>,
+
+    // The escrow pda of the message, closed once the key is released
+    #[account(
+        mut,
+        seeds = [Escrow::SEED, escrow.leaf_node.as_ref()],
+        bump = escrow.bump,
+        has_one = sender @ CompressedNotesError::Unauthorized,
+        close = sender,
+    )]
+    pub escrow: Account<'info, Escrow>,
+
+    // The noop program to log data
+    pub log_wrapper: Program<'info, Noop>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[derive(Accounts)]
+pub struct RefundMessageEscrow<'info> {
+    // The recipient of the message, refunded the price
+    #[account(mut)]
+    pub recipient: Signer<'info>,
+
+    // The sender of the message, refunded the escrow rent
+    /// CHECK: The escrow names the sender, only credited
+    #[account(mut)]
+    pub sender: UncheckedAccount<'info>,
+
+    // The escrow pda of the message, closed once refunded
+    #[account(
+        mut,
+        seeds = [Escrow::SEED, escrow.leaf_node.as_ref()],
+        bump = escrow.bump,
+        has_one = sender @ CompressedNotesError::Unauthorized,
+        has_one = recipient @ CompressedNotesError::Unauthorized,
+        close = sender,
+    )]
+    pub escrow: Account<'info, Escrow>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
@@ -0,0 +1,2416 @@
+//! Direct messages between a sender and a recipient, appended as leaves in the same trees as
+//! notes. Compiled in with the `messaging` feature.
+
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::sysvar::instructions as sysvar_instructions;
+use anchor_lang::solana_program::{entrypoint::MAX_PERMITTED_DATA_INCREASE, keccak};
+use anchor_lang::system_program::{create_account, CreateAccount};
+use anchor_spl::metadata::MetadataAccount;
+use anchor_spl::token::{Token, TokenAccount};
+use spl_account_compression::state::CONCURRENT_MERKLE_TREE_HEADER_SIZE_V1;
+use spl_account_compression::{
+    program::SplAccountCompression, wrap_application_data_v1, ConcurrentMerkleTree, Noop,
+};
+
+use crate::{
+    instruction_flags, leaf_domain, leaf_schema, require_clean_text, CompressedNotesError,
+    FeeOperation, Forest, GlobalConfig, NoteId, RateState, RecentLeaves, Role, Session, Stake,
+    TreeConfig, TreeCpi, TreePurpose, TreeState, TreeSubscription, TREE_TREASURY_SEED,
+};
+
+// Message priorities, a higher priority is more important
+pub mod message_priority {
+    pub const NORMAL: u8 = 0;
+    // Messages at this priority or above are urgent
+    pub const HIGH: u8 = 1;
+}
+
+// Hashes a message, its sender, the leaf it replies to, if any, and its priority into the leaf node
+pub fn hash_message_leaf(
+    message: &str,
+    sender: &Pubkey,
+    parent_leaf: Option<&[u8; 32]>,
+    priority: u8,
+) -> [u8; 32] {
+    let mut data: Vec<&[u8]> = vec![message.as_bytes(), sender.as_ref()];
+    // A message that is not a reply hashes the same as before replies existed
+    if let Some(parent_leaf) = parent_leaf {
+        data.push(parent_leaf);
+    }
+    // Likewise a normal priority message hashes the same as before priorities existed
+    let priority = [priority];
+    if priority[0] != message_priority::NORMAL {
+        data.push(&priority);
+    }
+    keccak::hashv(&data).to_bytes()
+}
+
+// Hashes a direct message with both participants into the leaf node, so the recipient can
+// prove the message was addressed to them. The sender picks a distinct nonce per message so
+// repeating a message to the same recipient still gets a distinct leaf. Only the parent, if any, is optional, and it
+// changes the length of the hashed data, so every layout gets a distinct leaf.
+pub fn hash_message_leaf_v2(
+    message: &str,
+    sender: &Pubkey,
+    recipient: &Pubkey,
+    nonce: u64,
+    parent_leaf: Option<&[u8; 32]>,
+    edit_count: u32,
+    priority: u8,
+) -> [u8; 32] {
+    let message_hash = keccak::hash(message.as_bytes());
+    let nonce = nonce.to_le_bytes();
+    let edit_count = edit_count.to_le_bytes();
+    let priority = [priority];
+    let tag = leaf_domain::tag(leaf_domain::DIRECT_MESSAGE);
+    let mut data: Vec<&[u8]> = vec![
+        &tag,
+        message_hash.as_ref(),
+        sender.as_ref(),
+        recipient.as_ref(),
+        &nonce,
+        &edit_count,
+        &priority,
+    ];
+    if let Some(parent_leaf) = parent_leaf {
+        data.push(parent_leaf);
+    }
+    keccak::hashv(&data).to_bytes()
+}
+
+// Hashes an acknowledgement of the message at `original_leaf` by `acknowledger`
+pub fn hash_acknowledgement_leaf(original_leaf: &[u8; 32], acknowledger: &Pubkey) -> [u8; 32] {
+    keccak::hashv(&[
+        &leaf_domain::tag(leaf_domain::ACKNOWLEDGEMENT),
+        original_leaf,
+        acknowledger.as_ref(),
+    ])
+    .to_bytes()
+}
+
+// Hashes a self-destructing message, its sender and its expiry into the leaf node.
+// The ttl message domain keeps the leaf from passing for another layout, since anyone
+// can burn an expired message.
+pub fn hash_ttl_message_leaf(message: &str, sender: &Pubkey, expires_at: i64) -> [u8; 32] {
+    let message_hash = keccak::hash(message.as_bytes());
+    keccak::hashv(&[
+        &leaf_domain::tag(leaf_domain::TTL_MESSAGE),
+        message_hash.as_ref(),
+        sender.as_ref(),
+        &expires_at.to_le_bytes(),
+    ])
+    .to_bytes()
+}
+
+// Hashes an edited message, its sender, its edit count and the leaf it replies to, if any.
+// Only fixed-size fields follow the message hash, so each edit count gets a distinct leaf.
+pub fn hash_edited_message_leaf(
+    message: &str,
+    sender: &Pubkey,
+    parent_leaf: Option<&[u8; 32]>,
+    edit_count: u32,
+    priority: u8,
+) -> [u8; 32] {
+    let message_hash = keccak::hash(message.as_bytes());
+    let edit_count = edit_count.to_le_bytes();
+    let tag = leaf_domain::tag(leaf_domain::EDITED_MESSAGE);
+    let mut data: Vec<&[u8]> = vec![&tag, message_hash.as_ref(), sender.as_ref(), &edit_count];
+    if let Some(parent_leaf) = parent_leaf {
+        data.push(parent_leaf);
+    }
+    let priority = [priority];
+    if priority[0] != message_priority::NORMAL {
+        data.push(&priority);
+    }
+    keccak::hashv(&data).to_bytes()
+}
+
+// Hashes an encrypted message, its sender and its key exchange metadata into the leaf node.
+// The ciphertext is hashed on its own first so only fixed-size fields follow it.
+pub fn hash_encrypted_message_leaf(
+    ciphertext: &[u8],
+    sender: &Pubkey,
+    nonce: &[u8; 24],
+    sender_ephemeral_pubkey: &[u8; 32],
+) -> [u8; 32] {
+    let ciphertext_hash = keccak::hash(ciphertext).to_bytes();
+    hash_encrypted_message_leaf_from_digest(
+        &ciphertext_hash,
+        sender,
+        nonce,
+        sender_ephemeral_pubkey,
+    )
+}
+
+// Hashes an encrypted message from the keccak hash of its ciphertext, as
+// `hash_encrypted_message_leaf` does, for instructions not taking the ciphertext
+pub fn hash_encrypted_message_leaf_from_digest(
+    ciphertext_hash: &[u8; 32],
+    sender: &Pubkey,
+    nonce: &[u8; 24],
+    sender_ephemeral_pubkey: &[u8; 32],
+) -> [u8; 32] {
+    keccak::hashv(&[
+        &leaf_domain::tag(leaf_domain::ENCRYPTED_MESSAGE),
+        ciphertext_hash,
+        sender.as_ref(),
+        nonce,
+        sender_ephemeral_pubkey,
+    ])
+    .to_bytes()
+}
+
+// Hashes a forwarded message, its sender and the leaf and sender it was forwarded from
+pub fn hash_forwarded_message_leaf(
+    message: &str,
+    sender: &Pubkey,
+    original_leaf: &[u8; 32],
+    original_sender: &Pubkey,
+) -> [u8; 32] {
+    let message_hash = keccak::hash(message.as_bytes());
+    keccak::hashv(&[
+        &leaf_domain::tag(leaf_domain::FORWARDED_MESSAGE),
+        message_hash.as_ref(),
+        sender.as_ref(),
+        original_leaf,
+        original_sender.as_ref(),
+    ])
+    .to_bytes()
+}
+
+// Hashes a group message, its sender and every recipient into the leaf node. The message
+// is hashed on its own first, so a crafted message cannot shift where the recipients sit
+pub fn hash_group_message_leaf(message: &str, sender: &Pubkey, recipients: &[Pubkey]) -> [u8; 32] {
+    let tag = leaf_domain::tag(leaf_domain::GROUP_MESSAGE);
+    let message_hash = keccak::hash(message.as_bytes());
+    let mut data: Vec<&[u8]> = vec![&tag, message_hash.as_ref(), sender.as_ref()];
+    data.extend(recipients.iter().map(|recipient| recipient.as_ref()));
+    keccak::hashv(&data).to_bytes()
+}
+
+// Appends a message addressed to a recipient to a tree, once the recipient accepted a
+// conversation request from the sender or allowlisted them, unless they blocked the sender.
+pub fn process_append_message<'info>(
+    ctx: Context<'_, '_, '_, 'info, AppendMessage<'info>>,
+    recipient: Pubkey,             // Pubkey of the message recipient
+    message: String,               // The message to send
+    parent_leaf: Option<[u8; 32]>, // Leaf node of the message this replies to, if any
+    priority: u8,                  // The priority of the message, see `message_priority`
+    nonce: u64,                    // Chosen by the sender, distinct per message to the recipient
+) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::APPEND_MESSAGE)?;
+    // Refuse text that indexers would display deceptively
+    require_clean_text(&message)?;
+
+    // A session key sends for the session's owner
+    let sender = Session::resolve(
+        &ctx.accounts.sender.key(),
+        &ctx.accounts.session,
+        instruction_flags::APPEND_MESSAGE,
+    )?;
+    // A sender keeping a nonce pda must send its next nonce, consuming it
+    if let Some(sender_nonce) = &mut ctx.accounts.sender_nonce {
+        sender_nonce.consume(nonce)?;
+    }
+
+    // Hash the message which will be stored as leaf node in the merkle tree
+    let leaf_node = hash_message_leaf_v2(
+        &message,
+        &sender,
+        &recipient,
+        nonce,
+        parent_leaf.as_ref(),
+        0,
+        priority,
+    );
+    let content_len = message.len();
+    // Create a new "message log" using the leaf node hash, its id, participants and message.
+    let note_id = NoteId::next(&ctx.accounts.merkle_tree, leaf_schema::V5)?;
+    let message_log = MessageLog::new(
+        leaf_node,
+        note_id,
+        sender,
+        recipient,
+        message,
+        parent_leaf,
+        None,
+        priority,
+        Some(nonce),
+    );
+
+    // Urgent messages are also logged on their own and counted, for priority inboxes
+    if priority >= message_priority::HIGH {
+        let urgent_log = UrgentMessageLog::new(leaf_node, note_id, sender, recipient, priority);
+        wrap_application_data_v1(urgent_log.try_to_vec()?, &ctx.accounts.log_wrapper)?;
+        ctx.accounts.inbox.urgent_count += 1;
+    }
+    append_message_leaf(ctx, recipient, note_id, leaf_node, content_len, message_log)
+}
+
+// Initializes a tree dedicated to the conversation between the signer and `recipient`.
+// The tree account is allocated by the caller in the same transaction, since a cpi
+// cannot allocate more than 10KiB, and the conversation tree pda records it.
+pub fn process_create_conversation_tree(
+    ctx: Context<CreateConversationTree>,
+    recipient: Pubkey,    // Pubkey of the other participant
+    max_depth: u32,       // Max depth of the merkle tree
+    max_buffer_size: u32, // Max buffer size of the merkle tree
+    canopy_depth: u32,    // Levels of the tree cached on chain, shortening every proof
+) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::CREATE_CONVERSATION_TREE)?;
+
+    let conversation_tree = &mut ctx.accounts.conversation_tree;
+    conversation_tree.participants =
+        Conversation::participants(ctx.accounts.sender.key(), recipient);
+    conversation_tree.merkle_tree = ctx.accounts.merkle_tree.key();
+    conversation_tree.bump = *ctx.bumps.get("conversation_tree").unwrap();
+
+    // CPI to initialize an empty merkle tree with given max depth, buffer size and canopy
+    tree_cpi!(ctx).init_with_canopy(max_depth, max_buffer_size, canopy_depth)?;
+    ctx.accounts.forest.record(
+        ctx.accounts.sender.key(),
+        ctx.accounts.merkle_tree.key(),
+        TreePurpose::Conversation,
+        *ctx.bumps.get("forest").unwrap(),
+    )
+}
+
+// Asks `recipient` to accept direct messages from the signer.
+pub fn process_request_conversation(
+    ctx: Context<RequestConversation>,
+    recipient: Pubkey, // Pubkey of the recipient asked to accept
+) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::REQUEST_CONVERSATION)?;
+
+    let conversation_state = &mut ctx.accounts.conversation_state;
+    conversation_state.requester = ctx.accounts.requester.key();
+    conversation_state.recipient = recipient;
+    conversation_state.bump = *ctx.bumps.get("conversation_state").unwrap();
+    Ok(())
+}
+
+// Creates the nonce pda of the signer, starting their message nonces at zero. From then on
+// `append_message` only accepts the next nonce, so a relayer cannot submit a message twice.
+pub fn process_create_sender_nonce(ctx: Context<CreateSenderNonce>) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::CREATE_SENDER_NONCE)?;
+
+    let sender_nonce = &mut ctx.accounts.sender_nonce;
+    sender_nonce.sender = ctx.accounts.sender.key();
+    sender_nonce.next_nonce = 0;
+    sender_nonce.bump = *ctx.bumps.get("sender_nonce").unwrap();
+    Ok(())
+}
+
+// Accepts a conversation request, after which both participants can message each other.
+pub fn process_accept_conversation(ctx: Context<AcceptConversation>) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::ACCEPT_CONVERSATION)?;
+
+    ctx.accounts.conversation_state.accepted = true;
+    Ok(())
+}
+
+// Replaces the senders allowed to message the signer without a conversation request.
+pub fn process_set_allowlist(ctx: Context<SetAllowlist>, senders: Vec<Pubkey>) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::SET_ALLOWLIST)?;
+
+    let inbox = &mut ctx.accounts.inbox;
+    inbox.init(
+        ctx.accounts.recipient.key(),
+        *ctx.bumps.get("inbox").unwrap(),
+    );
+    inbox.set_allowlist(senders)
+}
+
+// Adds `sender` to the signer's block list.
+pub fn process_block_sender(ctx: Context<BlockSender>, sender: Pubkey) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::BLOCK_SENDER)?;
+
+    let block_list = &mut ctx.accounts.block_list;
+    block_list.owner = ctx.accounts.owner.key();
+    block_list.bump = *ctx.bumps.get("block_list").unwrap();
+    block_list.block(sender)
+}
+
+// Removes `sender` from the signer's block list.
+pub fn process_unblock_sender(ctx: Context<UnblockSender>, sender: Pubkey) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::UNBLOCK_SENDER)?;
+
+    ctx.accounts.block_list.unblock(&sender);
+    Ok(())
+}
+
+// Logs the signer's presence for chat clients, without touching any tree or account.
+pub fn process_emit_presence(ctx: Context<SignalAccounts>, state: PresenceState) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::EMIT_PRESENCE)?;
+
+    let presence_log = PresenceLog::new(ctx.accounts.user.key(), state, Clock::get()?.slot);
+    wrap_application_data_v1(presence_log.try_to_vec()?, &ctx.accounts.log_wrapper)?;
+    Ok(())
+}
+
+// Logs that the signer is typing in a conversation, without touching any tree or account.
+pub fn process_emit_typing(ctx: Context<SignalAccounts>, conversation: Pubkey) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::EMIT_TYPING)?;
+
+    let typing_log = TypingLog::new(ctx.accounts.user.key(), conversation, Clock::get()?.slot);
+    wrap_application_data_v1(typing_log.try_to_vec()?, &ctx.accounts.log_wrapper)?;
+    Ok(())
+}
+
+// Provisions the recipient's inbox tree, a tree at a pda derived from the recipient that
+// clients can subscribe to. A cpi can only allocate 10KiB per instruction, so the account
+// is created owned by this program and grown by 10KiB per call. Once it has its full size
+// it is handed to the compression program and initialized. Calling it again after that
+// is a no-op, so clients can prepend enough calls to the first message to a recipient.
+pub fn process_get_or_create_inbox_tree(
+    ctx: Context<InboxTreeAccounts>,
+    recipient: Pubkey, // Pubkey of the recipient owning the inbox tree
+) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::GET_OR_CREATE_INBOX_TREE)?;
+
+    let merkle_tree = ctx.accounts.merkle_tree.to_account_info();
+    // The tree is already initialized
+    if merkle_tree.owner == &spl_account_compression::ID {
+        return Ok(());
+    }
+
+    if merkle_tree.data_is_empty() {
+        // Fund the rent of the full size up front, so the account stays rent exempt as it grows
+        let bump = *ctx.bumps.get("merkle_tree").unwrap();
+        let signer_seeds: &[&[&[u8]]] = &[&[Inbox::TREE_SEED, recipient.as_ref(), &[bump]]];
+        let cpi_ctx = CpiContext::new_with_signer(
+            ctx.accounts.system_program.to_account_info(),
+            CreateAccount {
+                from: ctx.accounts.payer.to_account_info(),
+                to: merkle_tree.clone(),
+            },
+            signer_seeds,
+        );
+        create_account(
+            cpi_ctx,
+            Rent::get()?.minimum_balance(Inbox::TREE_ACCOUNT_SIZE),
+            MAX_PERMITTED_DATA_INCREASE.min(Inbox::TREE_ACCOUNT_SIZE) as u64,
+            ctx.program_id,
+        )?;
+    } else {
+        let len =
+            (merkle_tree.data_len() + MAX_PERMITTED_DATA_INCREASE).min(Inbox::TREE_ACCOUNT_SIZE);
+        merkle_tree.realloc(len, true)?;
+    }
+
+    if merkle_tree.data_len() == Inbox::TREE_ACCOUNT_SIZE {
+        // The data is still all zeroes, so this program can hand the account over
+        merkle_tree.assign(&spl_account_compression::ID);
+        // CPI to initialize an empty merkle tree with the inbox tree shape
+        tree_cpi!(ctx).init(Inbox::TREE_MAX_DEPTH, Inbox::TREE_MAX_BUFFER_SIZE)?;
+    }
+    Ok(())
+}
+
+// Appends a message that anyone can burn once `expires_at` has passed.
+pub fn process_append_message_with_ttl<'info>(
+    ctx: Context<'_, '_, '_, 'info, AppendMessage<'info>>,
+    recipient: Pubkey, // Pubkey of the message recipient
+    message: String,   // The message to send
+    expires_at: i64,   // Unix timestamp after which the message can be burned
+) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::APPEND_MESSAGE_WITH_TTL)?;
+    // Refuse text that indexers would display deceptively
+    require_clean_text(&message)?;
+    require!(
+        expires_at > Clock::get()?.unix_timestamp,
+        CompressedNotesError::InvalidExpiry
+    );
+
+    // A session key sends for the session's owner
+    let sender = Session::resolve(
+        &ctx.accounts.sender.key(),
+        &ctx.accounts.session,
+        instruction_flags::APPEND_MESSAGE_WITH_TTL,
+    )?;
+    // Hash the message, committing to its expiry
+    let leaf_node = hash_ttl_message_leaf(&message, &sender, expires_at);
+    let content_len = message.len();
+    // Create a new "message log" using the leaf node hash, its id, participants, message and expiry.
+    let note_id = NoteId::next(&ctx.accounts.merkle_tree, leaf_schema::V9)?;
+    let message_log = MessageLog::new(
+        leaf_node,
+        note_id,
+        sender,
+        recipient,
+        message,
+        None,
+        Some(expires_at),
+        message_priority::NORMAL,
+        None,
+    );
+    append_message_leaf(ctx, recipient, note_id, leaf_node, content_len, message_log)
+}
+
+// Appends a message encrypted off-chain for a recipient. The program never sees the
+// plaintext, the leaf commits to the ciphertext and the key exchange metadata.
+pub fn process_append_encrypted_message<'info>(
+    ctx: Context<'_, '_, '_, 'info, AppendMessage<'info>>,
+    recipient: Pubkey,                 // Pubkey of the message recipient
+    ciphertext: Vec<u8>,               // The encrypted message
+    nonce: [u8; 24],                   // The nonce the message was encrypted with
+    sender_ephemeral_pubkey: [u8; 32], // The sender's ephemeral x25519 key for the key exchange
+) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::APPEND_ENCRYPTED_MESSAGE)?;
+
+    // A session key sends for the session's owner
+    let sender = Session::resolve(
+        &ctx.accounts.sender.key(),
+        &ctx.accounts.session,
+        instruction_flags::APPEND_ENCRYPTED_MESSAGE,
+    )?;
+    // Hash the ciphertext, committing to the key exchange metadata
+    let leaf_node =
+        hash_encrypted_message_leaf(&ciphertext, &sender, &nonce, &sender_ephemeral_pubkey);
+    let content_len = ciphertext.len();
+    // Create a new "encrypted message log" using the leaf node hash, its id, participants and ciphertext.
+    let note_id = NoteId::next(&ctx.accounts.merkle_tree, leaf_schema::V11)?;
+    let message_log = EncryptedMessageLog::new(
+        leaf_node,
+        note_id,
+        sender,
+        recipient,
+        ciphertext,
+        nonce,
+        sender_ephemeral_pubkey,
+    );
+    append_message_leaf(ctx, recipient, note_id, leaf_node, content_len, message_log)
+}
+
+// Appends a forwarded message to the recipient's conversation. The leaf commits to the
+// original leaf and sender, which clients check against the tree to show provenance.
+pub fn process_forward_message<'info>(
+    ctx: Context<'_, '_, '_, 'info, AppendMessage<'info>>,
+    recipient: Pubkey,       // Pubkey of the message recipient
+    original_leaf: [u8; 32], // Leaf node of the message being forwarded
+    original_sender: Pubkey, // Pubkey of the sender of the forwarded message
+    message: String,         // The message being forwarded
+) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::FORWARD_MESSAGE)?;
+    // Refuse text that indexers would display deceptively
+    require_clean_text(&message)?;
+
+    // A session key sends for the session's owner
+    let sender = Session::resolve(
+        &ctx.accounts.sender.key(),
+        &ctx.accounts.session,
+        instruction_flags::FORWARD_MESSAGE,
+    )?;
+    // Hash the message, committing to where it was forwarded from
+    let leaf_node =
+        hash_forwarded_message_leaf(&message, &sender, &original_leaf, &original_sender);
+    let content_len = message.len();
+    // Create a new "forwarded message log" using the leaf node hash, its id, participants, message and origin.
+    let note_id = NoteId::next(&ctx.accounts.merkle_tree, leaf_schema::V12)?;
+    let message_log = ForwardedMessageLog::new(
+        leaf_node,
+        note_id,
+        sender,
+        recipient,
+        message,
+        original_leaf,
+        original_sender,
+    );
+    append_message_leaf(ctx, recipient, note_id, leaf_node, content_len, message_log)
+}
+
+// Logs a direct message, records it on the conversation and appends its leaf to the tree,
+// mirroring it into the sender's outbox tree if passed as a (tree_authority, merkle_tree) pair
+// in the remaining accounts
+fn append_message_leaf<'info>(
+    ctx: Context<'_, '_, '_, 'info, AppendMessage<'info>>,
+    recipient: Pubkey,
+    note_id: NoteId,
+    leaf_node: [u8; 32],
+    content_len: usize, // The bytes of the message or ciphertext, priced by the fee schedule
+    message_log: impl AnchorSerialize,
+) -> Result<()> {
+    // The caller resolved any session already
+    let sender = Session::acting_for(ctx.accounts.sender.key, &ctx.accounts.session);
+    require!(
+        !BlockList::blocks(&ctx.accounts.block_list, &sender)?,
+        CompressedNotesError::SenderBlocked
+    );
+    // Messaging someone else needs their consent, either an accepted conversation or the allowlist
+    let accepted = ctx
+        .accounts
+        .conversation_state
+        .as_ref()
+        .is_some_and(|state| state.accepted);
+    require!(
+        sender == recipient || accepted || ctx.accounts.inbox.allows(&sender),
+        CompressedNotesError::ConversationNotAccepted
+    );
+
+    // Log the "message log" data using noop program
+    wrap_application_data_v1(message_log.try_to_vec()?, &ctx.accounts.log_wrapper)?;
+
+    // Record the activity on the conversation between sender and recipient
+    let conversation = &mut ctx.accounts.conversation;
+    if conversation.message_count == 0 {
+        conversation.participants = Conversation::participants(sender, recipient);
+        conversation.bump = *ctx.bumps.get("conversation").unwrap();
+    }
+    conversation.message_count += 1;
+    conversation.last_activity_slot = Clock::get()?.slot;
+
+    // Count the message as unread until the recipient marks it read
+    let inbox = &mut ctx.accounts.inbox;
+    inbox.init(recipient, *ctx.bumps.get("inbox").unwrap());
+    inbox.unread_count += 1;
+
+    // Count the message against the rate limits of the tree the sender appends to
+    TreeConfig::record_rate(
+        &ctx.accounts.tree_config,
+        ctx.accounts.rate_state.as_deref_mut(),
+    )?;
+    // A tree refusing duplicate leaves checks the leaf against its recent leaves
+    TreeConfig::record_leaf(
+        &ctx.accounts.tree_config,
+        ctx.accounts.recent_leaves.as_deref_mut(),
+        &leaf_node,
+    )?;
+    // A tree charging an append fee takes it from the signer into the tree treasury
+    TreeConfig::record_fee(
+        &ctx.accounts.tree_config,
+        ctx.accounts.fee_payer(),
+        ctx.accounts.tree_treasury.as_ref(),
+        Some(&ctx.accounts.system_program),
+        FeeOperation::Append(content_len),
+    )?;
+    // A tree charging a token append fee takes it from the signer into its fee vault
+    TreeConfig::record_token_fee(
+        &ctx.accounts.tree_config,
+        ctx.accounts.fee_payer(),
+        ctx.accounts.payer_token_account.as_ref(),
+        ctx.accounts.fee_vault.as_ref(),
+        ctx.accounts.token_program.as_ref(),
+    )?;
+    // A token- or nft-gated tree only accepts posters holding its gate
+    TreeConfig::require_gate(
+        &ctx.accounts.tree_config,
+        &sender,
+        ctx.accounts.gate_token_account.as_ref(),
+        ctx.accounts.gate_metadata.as_ref(),
+    )?;
+    // A tree asking posters to stake only accepts senders with enough staked
+    TreeConfig::record_stake(&ctx.accounts.tree_config, ctx.accounts.stake.as_deref_mut())?;
+    // A tree selling access only accepts posters with an active subscription
+    TreeConfig::require_subscription(
+        &ctx.accounts.tree_config,
+        &sender,
+        ctx.accounts.subscription.as_deref(),
+    )?;
+    // A tree restricting cpi callers only accepts the programs it allows
+    TreeConfig::require_caller(
+        &ctx.accounts.tree_config,
+        ctx.accounts.instructions.as_deref(),
+    )?;
+    // Count the append on the tree config, failing if the tree is frozen or private
+    TreeConfig::record_append(
+        &ctx.accounts.tree_config,
+        &ctx.accounts.merkle_tree,
+        &sender,
+        ctx.accounts.role.as_deref(),
+    )?;
+
+    // CPI to append the leaf node to the merkle tree
+    tree_cpi!(ctx).append(leaf_node)?;
+
+    // Mirror the leaf into the sender's outbox tree, when one is passed as a
+    // (tree_authority, merkle_tree) pair in the remaining accounts
+    match ctx.remaining_accounts {
+        [] => Ok(()),
+        [tree_authority, outbox_tree] => {
+            require_keys_neq!(
+                outbox_tree.key(),
+                ctx.accounts.merkle_tree.key(),
+                CompressedNotesError::InvalidOutboxTree
+            );
+            // The tree authority must be the pda for the outbox tree
+            let (expected_authority, bump) =
+                Pubkey::find_program_address(&[outbox_tree.key.as_ref()], ctx.program_id);
+            require_keys_eq!(
+                tree_authority.key(),
+                expected_authority,
+                CompressedNotesError::InvalidOutboxTree
+            );
+
+            // Link the copy to the delivered message for the sender's indexer
+            let outbox_id = NoteId::next(outbox_tree, note_id.schema_version)?;
+            let outbox_log = OutboxLog::new(leaf_node, outbox_id, note_id, sender, recipient);
+            wrap_application_data_v1(outbox_log.try_to_vec()?, &ctx.accounts.log_wrapper)?;
+
+            // CPI to append the same leaf node to the outbox tree
+            TreeCpi::new(
+                &ctx.accounts.compression_program,
+                tree_authority,
+                outbox_tree,
+                &ctx.accounts.log_wrapper,
+                bump,
+            )
+            .append(leaf_node)
+        }
+        _ => err!(CompressedNotesError::InvalidOutboxTree),
+    }
+}
+
+// Replaces a direct message sent before V5 leaves with a new version, logging the edit. The
+// old leaf is recomputed with the signer as sender, so only the sender can edit a message.
+#[allow(clippy::too_many_arguments)]
+pub fn process_update_message<'info>(
+    ctx: Context<'_, '_, '_, 'info, UpdateMessage<'info>>,
+    index: u32,                    // Index of the message leaf in the merkle tree
+    root: Option<[u8; 32]>,        // Root the old leaf is proven against, or none to derive it
+    recipient: Pubkey,             // Pubkey of the message recipient
+    old_message: String,           // The current message
+    new_message: String,           // The message to replace it with
+    parent_leaf: Option<[u8; 32]>, // Leaf node of the message this replies to, if any
+    edit_count: u32,               // The number of times the message was edited so far
+    priority: u8,                  // The priority of the message, kept across edits
+) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::UPDATE_MESSAGE)?;
+    // Refuse text that indexers would display deceptively
+    require_clean_text(&new_message)?;
+
+    let sender = ctx.accounts.sender.key();
+    // Messages that were never edited keep their original leaf hashing
+    let old_leaf = if edit_count == 0 {
+        hash_message_leaf(&old_message, &sender, parent_leaf.as_ref(), priority)
+    } else {
+        hash_edited_message_leaf(
+            &old_message,
+            &sender,
+            parent_leaf.as_ref(),
+            edit_count,
+            priority,
+        )
+    };
+
+    // An edit that changes nothing fails, so clients do not mistake it for an update
+    require!(old_message != new_message, CompressedNotesError::NoChange);
+
+    let edit_count = edit_count + 1;
+    let new_leaf = hash_edited_message_leaf(
+        &new_message,
+        &sender,
+        parent_leaf.as_ref(),
+        edit_count,
+        priority,
+    );
+
+    // Log the new version of the message for indexers
+    let note_id = NoteId::new(ctx.accounts.merkle_tree.key(), index, leaf_schema::V10);
+    let message_log = MessageLog::new(
+        new_leaf,
+        note_id,
+        sender,
+        recipient,
+        new_message,
+        parent_leaf,
+        None,
+        priority,
+        None,
+    );
+    replace_message_leaf(ctx, root, old_leaf, message_log, edit_count)
+}
+
+// Replaces a direct message with a V5 leaf with a new version, logging the edit.
+#[allow(clippy::too_many_arguments)]
+pub fn process_update_message_v2<'info>(
+    ctx: Context<'_, '_, '_, 'info, UpdateMessage<'info>>,
+    index: u32,                    // Index of the message leaf in the merkle tree
+    root: Option<[u8; 32]>,        // Root the old leaf is proven against, or none to derive it
+    recipient: Pubkey,             // Pubkey of the message recipient
+    old_message: String,           // The current message
+    new_message: String,           // The message to replace it with
+    parent_leaf: Option<[u8; 32]>, // Leaf node of the message this replies to, if any
+    edit_count: u32,               // The number of times the message was edited so far
+    priority: u8,                  // The priority of the message, kept across edits
+    nonce: u64,                    // The nonce the message was sent with, kept across edits
+) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::UPDATE_MESSAGE)?;
+    // Refuse text that indexers would display deceptively
+    require_clean_text(&new_message)?;
+
+    let sender = ctx.accounts.sender.key();
+    let old_leaf = hash_message_leaf_v2(
+        &old_message,
+        &sender,
+        &recipient,
+        nonce,
+        parent_leaf.as_ref(),
+        edit_count,
+        priority,
+    );
+
+    // An edit that changes nothing fails, so clients do not mistake it for an update
+    require!(old_message != new_message, CompressedNotesError::NoChange);
+
+    let edit_count = edit_count + 1;
+    let new_leaf = hash_message_leaf_v2(
+        &new_message,
+        &sender,
+        &recipient,
+        nonce,
+        parent_leaf.as_ref(),
+        edit_count,
+        priority,
+    );
+
+    // Log the new version of the message for indexers
+    let note_id = NoteId::new(ctx.accounts.merkle_tree.key(), index, leaf_schema::V5);
+    let message_log = MessageLog::new(
+        new_leaf,
+        note_id,
+        sender,
+        recipient,
+        new_message,
+        parent_leaf,
+        None,
+        priority,
+        Some(nonce),
+    );
+    replace_message_leaf(ctx, root, old_leaf, message_log, edit_count)
+}
+
+// Logs the new version of a message and the edit, then replaces the old leaf with it
+fn replace_message_leaf<'info>(
+    ctx: Context<'_, '_, '_, 'info, UpdateMessage<'info>>,
+    root: Option<[u8; 32]>,
+    old_leaf: [u8; 32],
+    message_log: MessageLog,
+    edit_count: u32,
+) -> Result<()> {
+    let (new_leaf, note_id) = (message_log.leaf_node, message_log.note_id);
+    // Without a root, the proof decides it, the tree config then checks it is recent
+    let root = match root {
+        Some(root) => root,
+        None => TreeState::proven_root(
+            &ctx.accounts.merkle_tree,
+            old_leaf,
+            note_id.leaf_index,
+            ctx.remaining_accounts,
+        )?,
+    };
+    wrap_application_data_v1(message_log.try_to_vec()?, &ctx.accounts.log_wrapper)?;
+    // Log the edit itself so indexers can link the versions
+    let edit_log = EditLog::new(
+        old_leaf,
+        new_leaf,
+        note_id,
+        ctx.accounts.sender.key(),
+        edit_count,
+        Clock::get()?.slot,
+    );
+    wrap_application_data_v1(edit_log.try_to_vec()?, &ctx.accounts.log_wrapper)?;
+
+    // A tree charging an update fee takes it from the sender into the tree treasury
+    TreeConfig::record_fee(
+        &ctx.accounts.tree_config,
+        &ctx.accounts.sender,
+        ctx.accounts.tree_treasury.as_ref(),
+        ctx.accounts.system_program.as_ref(),
+        FeeOperation::Update(message_log.message.len()),
+    )?;
+    // Count the replace on the tree config, failing if the tree is frozen
+    TreeConfig::record_replace(
+        &ctx.accounts.tree_config,
+        &ctx.accounts.merkle_tree,
+        &root,
+        &old_leaf,
+        ctx.accounts.hold.as_deref(),
+    )?;
+
+    // CPI to replace the old leaf node with the new one, failing unless the old leaf is at index
+    tree_cpi!(ctx).with_proof(ctx.remaining_accounts).replace(
+        root,
+        old_leaf,
+        new_leaf,
+        note_id.leaf_index,
+    )?;
+    Ok(())
+}
+
+// Persists the signer's archive and mute preferences for a conversation.
+pub fn process_set_conversation_flags(
+    ctx: Context<SetConversationFlags>,
+    archived: bool, // Whether the signer archived the conversation
+    muted: bool,    // Whether the signer muted the conversation
+) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::SET_CONVERSATION_FLAGS)?;
+
+    ctx.accounts
+        .conversation
+        .set_flags(&ctx.accounts.participant.key(), archived, muted)
+}
+
+// Appends a leaf acknowledging the message at `original_leaf`, signed by its recipient.
+// The original leaf is not checked against the tree, clients prove both leaves together.
+pub fn process_acknowledge_message(
+    ctx: Context<AcknowledgeMessage>,
+    original_leaf: [u8; 32], // Leaf node of the message being acknowledged
+) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::ACKNOWLEDGE_MESSAGE)?;
+
+    let recipient = ctx.accounts.recipient.key();
+    let leaf_node = hash_acknowledgement_leaf(&original_leaf, &recipient);
+
+    // Log the acknowledgement for indexers
+    let note_id = NoteId::next(&ctx.accounts.merkle_tree, leaf_schema::V6)?;
+    let acknowledgement_log = AcknowledgementLog::new(
+        leaf_node,
+        note_id,
+        original_leaf,
+        recipient,
+        Clock::get()?.slot,
+    );
+    wrap_application_data_v1(acknowledgement_log.try_to_vec()?, &ctx.accounts.log_wrapper)?;
+
+    // Count the append on the tree config, failing if the tree is frozen or private
+    TreeConfig::record_append(
+        &ctx.accounts.tree_config,
+        &ctx.accounts.merkle_tree,
+        &recipient,
+        ctx.accounts.role.as_deref(),
+    )?;
+    // CPI to append the acknowledgement leaf to the merkle tree
+    tree_cpi!(ctx).append(leaf_node)?;
+    Ok(())
+}
+
+// Tombstones an expired self-destructing message, callable by anyone.
+pub fn process_burn_expired_message<'info>(
+    ctx: Context<'_, '_, '_, 'info, BurnExpiredMessage<'info>>,
+    root: [u8; 32],  // Root the message leaf is proven against
+    message: String, // The message
+    sender: Pubkey,  // Pubkey of the message sender
+    expires_at: i64, // The expiry committed into the leaf
+    index: u32,      // Index of the message leaf in the merkle tree
+) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::BURN_EXPIRED_MESSAGE)?;
+    require!(
+        Clock::get()?.unix_timestamp >= expires_at,
+        CompressedNotesError::MessageNotExpired
+    );
+
+    let leaf_node = hash_ttl_message_leaf(&message, &sender, expires_at);
+
+    // Log the burn so indexers drop the message content
+    let note_id = NoteId::new(ctx.accounts.merkle_tree.key(), index, leaf_schema::V9);
+    let burn_log = MessageBurnLog::new(leaf_node, note_id);
+    wrap_application_data_v1(burn_log.try_to_vec()?, &ctx.accounts.log_wrapper)?;
+
+    // Count the replace on the tree config, failing if the tree is frozen, the root is older
+    // than its recent roots or the leaf is on hold
+    TreeConfig::record_replace(
+        &ctx.accounts.tree_config,
+        &ctx.accounts.merkle_tree,
+        &root,
+        &leaf_node,
+        ctx.accounts.hold.as_deref(),
+    )?;
+    // CPI to replace the message leaf with an empty leaf
+    tree_cpi!(ctx)
+        .with_proof(ctx.remaining_accounts)
+        .replace(root, leaf_node, [0; 32], index)?;
+    Ok(())
+}
+
+// Creates a group administered by the signer.
+pub fn process_create_group(
+    ctx: Context<CreateGroup>,
+    group_id: u64,        // Id of the group, unique per admin
+    members: Vec<Pubkey>, // The members who can post to and receive from the group
+) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::CREATE_GROUP)?;
+    require!(
+        members.len() <= Group::MAX_MEMBERS,
+        CompressedNotesError::GroupTooLarge
+    );
+
+    let group = &mut ctx.accounts.group;
+    group.admin = ctx.accounts.admin.key();
+    group.group_id = group_id;
+    group.members = members;
+    group.bump = *ctx.bumps.get("group").unwrap();
+    Ok(())
+}
+
+// Replaces the member list of a group.
+pub fn process_set_group_members(ctx: Context<GroupAdmin>, members: Vec<Pubkey>) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::SET_GROUP_MEMBERS)?;
+    require!(
+        members.len() <= Group::MAX_MEMBERS,
+        CompressedNotesError::GroupTooLarge
+    );
+
+    ctx.accounts.group.members = members;
+    Ok(())
+}
+
+// Creates the mutable metadata of a group chat, administered by the group admin.
+pub fn process_create_conversation_meta(
+    ctx: Context<CreateConversationMeta>,
+    title: Option<String>,     // The title of the chat, if any
+    participants: Vec<Pubkey>, // The participants shown for the chat
+) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::CREATE_CONVERSATION_META)?;
+
+    let meta = &mut ctx.accounts.conversation_meta;
+    meta.group = ctx.accounts.group.key();
+    meta.admin = ctx.accounts.admin.key();
+    meta.set(title, participants, 0)?;
+    meta.bump = *ctx.bumps.get("conversation_meta").unwrap();
+    Ok(())
+}
+
+// Replaces the metadata of a group chat, optionally handing it to a new admin.
+pub fn process_update_conversation_meta(
+    ctx: Context<UpdateConversationMeta>,
+    title: Option<String>,     // The title of the chat, if any
+    participants: Vec<Pubkey>, // The participants shown for the chat
+    muted: u16,                // Bit i mutes participants[i]
+    admin: Pubkey,             // The admin allowed to update the metadata from now on
+) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::UPDATE_CONVERSATION_META)?;
+
+    let meta = &mut ctx.accounts.conversation_meta;
+    meta.set(title, participants, muted)?;
+    meta.admin = admin;
+    Ok(())
+}
+
+// Appends a message from a group member, addressed to every other member, to a tree.
+pub fn process_append_group_message(
+    ctx: Context<GroupMessageAccounts>,
+    message: String, // The message to send
+) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::APPEND_GROUP_MESSAGE)?;
+    // Refuse text that indexers would display deceptively
+    require_clean_text(&message)?;
+
+    let sender = ctx.accounts.sender.key();
+    let group = &ctx.accounts.group;
+    require!(
+        group.members.contains(&sender),
+        CompressedNotesError::NotGroupMember
+    );
+
+    // A tree charging an append fee takes it from the sender into the tree treasury
+    TreeConfig::record_fee(
+        &ctx.accounts.tree_config,
+        &ctx.accounts.sender,
+        ctx.accounts.tree_treasury.as_ref(),
+        ctx.accounts.system_program.as_ref(),
+        FeeOperation::Append(message.len()),
+    )?;
+    // A tree charging a token append fee takes it from the sender into its fee vault
+    TreeConfig::record_token_fee(
+        &ctx.accounts.tree_config,
+        &ctx.accounts.sender,
+        ctx.accounts.payer_token_account.as_ref(),
+        ctx.accounts.fee_vault.as_ref(),
+        ctx.accounts.token_program.as_ref(),
+    )?;
+    // A token- or nft-gated tree only accepts senders holding its gate
+    TreeConfig::require_gate(
+        &ctx.accounts.tree_config,
+        &sender,
+        ctx.accounts.gate_token_account.as_ref(),
+        ctx.accounts.gate_metadata.as_ref(),
+    )?;
+    // A tree selling access only accepts posters with an active subscription
+    TreeConfig::require_subscription(
+        &ctx.accounts.tree_config,
+        &sender,
+        ctx.accounts.subscription.as_deref(),
+    )?;
+
+    // Every member except the sender receives the message
+    let recipients: Vec<Pubkey> = group
+        .members
+        .iter()
+        .filter(|member| **member != sender)
+        .copied()
+        .collect();
+    // Hash the message, committing to the sender and all recipients
+    let leaf_node = hash_group_message_leaf(&message, &sender, &recipients);
+    // Create a new "group message log" using the leaf node hash, its id, group, recipients and message.
+    let note_id = NoteId::next(&ctx.accounts.merkle_tree, leaf_schema::V13)?;
+    let message_log =
+        GroupMessageLog::new(leaf_node, note_id, sender, group.key(), recipients, message);
+    // Log the "group message log" data using noop program
+    wrap_application_data_v1(message_log.try_to_vec()?, &ctx.accounts.log_wrapper)?;
+
+    // Count the append on the tree config, failing if the tree is frozen or private
+    TreeConfig::record_append(
+        &ctx.accounts.tree_config,
+        &ctx.accounts.merkle_tree,
+        &sender,
+        ctx.accounts.role.as_deref(),
+    )?;
+    // CPI to append the leaf node to the merkle tree
+    tree_cpi!(ctx).append(leaf_node)?;
+    Ok(())
+}
+
+#[derive(AnchorSerialize)]
+pub struct MessageLog {
+    leaf_node: [u8; 32],           // The leaf node hash
+    note_id: NoteId,               // The id of the leaf
+    sender: Pubkey,                // Pubkey of the message sender
+    recipient: Pubkey,             // Pubkey of the message recipient
+    message: String,               // The message
+    parent_leaf: Option<[u8; 32]>, // Leaf node of the message this replies to, if any
+    expires_at: Option<i64>,       // Unix timestamp after which the message can be burned, if any
+    priority: u8,                  // The priority of the message
+    nonce: Option<u64>,            // The nonce committed into the leaf, if its schema has one
+}
+
+impl MessageLog {
+    // Constructs a new message log from given leaf node, id, participants, message, parent, expiry,
+    // priority and nonce
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        leaf_node: [u8; 32],
+        note_id: NoteId,
+        sender: Pubkey,
+        recipient: Pubkey,
+        message: String,
+        parent_leaf: Option<[u8; 32]>,
+        expires_at: Option<i64>,
+        priority: u8,
+        nonce: Option<u64>,
+    ) -> Self {
+        Self {
+            leaf_node,
+            note_id,
+            sender,
+            recipient,
+            message,
+            parent_leaf,
+            expires_at,
+            priority,
+            nonce,
+        }
+    }
+}
+
+#[derive(AnchorSerialize)]
+pub struct UrgentMessageLog {
+    leaf_node: [u8; 32], // The leaf node hash of the urgent message
+    note_id: NoteId,     // The id of the leaf
+    sender: Pubkey,      // Pubkey of the message sender
+    recipient: Pubkey,   // Pubkey of the message recipient
+    priority: u8,        // The priority of the message, at least `message_priority::HIGH`
+}
+
+impl UrgentMessageLog {
+    // Constructs a new urgent message log from given leaf node, id, participants and priority
+    pub fn new(
+        leaf_node: [u8; 32],
+        note_id: NoteId,
+        sender: Pubkey,
+        recipient: Pubkey,
+        priority: u8,
+    ) -> Self {
+        Self {
+            leaf_node,
+            note_id,
+            sender,
+            recipient,
+            priority,
+        }
+    }
+}
+
+#[derive(AnchorSerialize)]
+pub struct OutboxLog {
+    leaf_node: [u8; 32],  // The leaf node hash appended to both trees
+    note_id: NoteId,      // The id of the copy in the sender's outbox tree
+    delivered_id: NoteId, // The id of the message in the recipient's tree
+    sender: Pubkey,       // Pubkey of the message sender
+    recipient: Pubkey,    // Pubkey of the message recipient
+}
+
+impl OutboxLog {
+    // Constructs a new outbox log from given leaf node, ids and participants
+    pub fn new(
+        leaf_node: [u8; 32],
+        note_id: NoteId,
+        delivered_id: NoteId,
+        sender: Pubkey,
+        recipient: Pubkey,
+    ) -> Self {
+        Self {
+            leaf_node,
+            note_id,
+            delivered_id,
+            sender,
+            recipient,
+        }
+    }
+}
+
+#[derive(AnchorSerialize)]
+pub struct EncryptedMessageLog {
+    leaf_node: [u8; 32],               // The leaf node hash
+    note_id: NoteId,                   // The id of the leaf
+    sender: Pubkey,                    // Pubkey of the message sender
+    recipient: Pubkey,                 // Pubkey of the message recipient
+    ciphertext: Vec<u8>,               // The encrypted message
+    nonce: [u8; 24],                   // The nonce the message was encrypted with
+    sender_ephemeral_pubkey: [u8; 32], // The sender's ephemeral x25519 key for the key exchange
+}
+
+impl EncryptedMessageLog {
+    // Constructs a new encrypted message log from given leaf node, id, participants and payload
+    pub fn new(
+        leaf_node: [u8; 32],
+        note_id: NoteId,
+        sender: Pubkey,
+        recipient: Pubkey,
+        ciphertext: Vec<u8>,
+        nonce: [u8; 24],
+        sender_ephemeral_pubkey: [u8; 32],
+    ) -> Self {
+        Self {
+            leaf_node,
+            note_id,
+            sender,
+            recipient,
+            ciphertext,
+            nonce,
+            sender_ephemeral_pubkey,
+        }
+    }
+}
+
+#[derive(AnchorSerialize)]
+pub struct ForwardedMessageLog {
+    leaf_node: [u8; 32],     // The leaf node hash
+    note_id: NoteId,         // The id of the leaf
+    sender: Pubkey,          // Pubkey of the sender who forwarded the message
+    recipient: Pubkey,       // Pubkey of the message recipient
+    message: String,         // The message
+    original_leaf: [u8; 32], // Leaf node of the message that was forwarded
+    original_sender: Pubkey, // Pubkey of the sender of the message that was forwarded
+}
+
+impl ForwardedMessageLog {
+    // Constructs a new forwarded message log from given leaf node, id, participants, message and origin
+    pub fn new(
+        leaf_node: [u8; 32],
+        note_id: NoteId,
+        sender: Pubkey,
+        recipient: Pubkey,
+        message: String,
+        original_leaf: [u8; 32],
+        original_sender: Pubkey,
+    ) -> Self {
+        Self {
+            leaf_node,
+            note_id,
+            sender,
+            recipient,
+            message,
+            original_leaf,
+            original_sender,
+        }
+    }
+}
+
+#[derive(AnchorSerialize)]
+pub struct AcknowledgementLog {
+    leaf_node: [u8; 32],     // The leaf node hash of the acknowledgement
+    note_id: NoteId,         // The id of the leaf
+    original_leaf: [u8; 32], // Leaf node of the message acknowledged
+    recipient: Pubkey,       // Pubkey of the recipient who acknowledged the message
+    slot: u64,               // The slot the message was acknowledged
+}
+
+impl AcknowledgementLog {
+    // Constructs a new acknowledgement log from given leaf node, id, original leaf, recipient
+    // and slot
+    pub fn new(
+        leaf_node: [u8; 32],
+        note_id: NoteId,
+        original_leaf: [u8; 32],
+        recipient: Pubkey,
+        slot: u64,
+    ) -> Self {
+        Self {
+            leaf_node,
+            note_id,
+            original_leaf,
+            recipient,
+            slot,
+        }
+    }
+}
+
+#[derive(AnchorSerialize)]
+pub struct EditLog {
+    old_leaf: [u8; 32], // The leaf node hash of the previous version
+    new_leaf: [u8; 32], // The leaf node hash of the new version
+    note_id: NoteId,    // The id of the edited leaf
+    editor: Pubkey,     // Pubkey of the sender who edited the message
+    edit_count: u32,    // The number of edits including this one, committed into the new leaf
+    slot: u64,          // The slot of the edit
+}
+
+impl EditLog {
+    // Constructs a new edit log linking the old and new leaf nodes
+    pub fn new(
+        old_leaf: [u8; 32],
+        new_leaf: [u8; 32],
+        note_id: NoteId,
+        editor: Pubkey,
+        edit_count: u32,
+        slot: u64,
+    ) -> Self {
+        Self {
+            old_leaf,
+            new_leaf,
+            note_id,
+            editor,
+            edit_count,
+            slot,
+        }
+    }
+}
+
+#[derive(AnchorSerialize)]
+pub struct MessageBurnLog {
+    leaf_node: [u8; 32], // The leaf node hash of the burned message
+    note_id: NoteId,     // The id of the now empty leaf
+}
+
+impl MessageBurnLog {
+    // Constructs a new burn log for the given leaf node
+    pub fn new(leaf_node: [u8; 32], note_id: NoteId) -> Self {
+        Self { leaf_node, note_id }
+    }
+}
+
+#[derive(AnchorSerialize)]
+pub struct GroupMessageLog {
+    leaf_node: [u8; 32],     // The leaf node hash
+    note_id: NoteId,         // The id of the leaf
+    sender: Pubkey,          // Pubkey of the message sender
+    group: Pubkey,           // The group the message was sent to
+    recipients: Vec<Pubkey>, // Pubkeys of every recipient, committed into the leaf
+    message: String,         // The message
+}
+
+impl GroupMessageLog {
+    // Constructs a new group message log from given leaf node, id, participants and message
+    pub fn new(
+        leaf_node: [u8; 32],
+        note_id: NoteId,
+        sender: Pubkey,
+        group: Pubkey,
+        recipients: Vec<Pubkey>,
+        message: String,
+    ) -> Self {
+        Self {
+            leaf_node,
+            note_id,
+            sender,
+            group,
+            recipients,
+            message,
+        }
+    }
+}
+
+// The presence a user shows to chat clients
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PresenceState {
+    Online,
+    Away,
+    Offline,
+}
+
+#[derive(AnchorSerialize)]
+pub struct PresenceLog {
+    user: Pubkey,         // Pubkey of the user
+    state: PresenceState, // The presence the user shows
+    slot: u64,            // The slot the presence was logged
+}
+
+impl PresenceLog {
+    // Constructs a new presence log from given user, state and slot
+    pub fn new(user: Pubkey, state: PresenceState, slot: u64) -> Self {
+        Self { user, state, slot }
+    }
+}
+
+#[derive(AnchorSerialize)]
+pub struct TypingLog {
+    user: Pubkey,         // Pubkey of the user typing
+    conversation: Pubkey, // The conversation or group the user is typing in
+    slot: u64,            // The slot the typing was logged
+}
+
+impl TypingLog {
+    // Constructs a new typing log from given user, conversation and slot
+    pub fn new(user: Pubkey, conversation: Pubkey, slot: u64) -> Self {
+        Self {
+            user,
+            conversation,
+            slot,
+        }
+    }
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct Conversation {
+    participants: [Pubkey; 2], // The two participants, in ascending order
+    message_count: u64,        // The number of messages appended between the participants
+    last_activity_slot: u64,   // The slot of the latest message
+    delivered_count: u64,      // The number of messages confirmed delivered by a recipient
+    archived: [bool; 2],       // Whether each participant archived the conversation
+    muted: [bool; 2],          // Whether each participant muted the conversation
+    bump: u8,                  // The bump seed for the pda
+}
+
+impl Conversation {
+    pub const SEED: &'static [u8] = b"conv";
+
+    // Orders a sender/recipient pair the same way as the conversation pda seeds
+    pub fn participants(a: Pubkey, b: Pubkey) -> [Pubkey; 2] {
+        [a.min(b), a.max(b)]
+    }
+
+    // The position of `participant` in the participants, failing if they are not one
+    fn position(&self, participant: &Pubkey) -> Result<usize> {
+        self.participants
+            .iter()
+            .position(|key| key == participant)
+            .ok_or_else(|| error!(CompressedNotesError::Unauthorized))
+    }
+
+    // Counts a delivery confirmed by `recipient`, who must be one of the participants
+    pub fn record_delivery(&mut self, recipient: &Pubkey) -> Result<()> {
+        self.position(recipient)?;
+        self.delivered_count += 1;
+        Ok(())
+    }
+
+    // Sets the archive and mute preferences of `participant`, leaving the other one's as is
+    pub fn set_flags(&mut self, participant: &Pubkey, archived: bool, muted: bool) -> Result<()> {
+        let position = self.position(participant)?;
+        self.archived[position] = archived;
+        self.muted[position] = muted;
+        Ok(())
+    }
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct ConversationState {
+    requester: Pubkey, // The participant who asked for the conversation
+    recipient: Pubkey, // The participant who has to accept it
+    accepted: bool,    // Whether the recipient accepted the request
+    bump: u8,          // The bump seed for the pda
+}
+
+impl ConversationState {
+    pub const SEED: &'static [u8] = b"conv_state";
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct SenderNonce {
+    sender: Pubkey,  // The sender the nonces are counted for
+    next_nonce: u64, // The nonce the sender's next message must commit to
+    bump: u8,        // The bump seed for the pda
+}
+
+impl SenderNonce {
+    pub const SEED: &'static [u8] = b"sender_nonce";
+
+    // Consumes `nonce`, failing unless it is the next nonce
+    pub fn consume(&mut self, nonce: u64) -> Result<()> {
+        require!(nonce == self.next_nonce, CompressedNotesError::InvalidNonce);
+        self.next_nonce += 1;
+        Ok(())
+    }
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct BlockList {
+    owner: Pubkey, // The recipient who blocked the senders
+    #[max_len(32)]
+    blocked: Vec<Pubkey>, // Senders not allowed to message the owner
+    bump: u8,      // The bump seed for the pda
+}
+
+impl BlockList {
+    pub const SEED: &'static [u8] = b"block_list";
+    // Must match the `max_len` of `blocked`
+    pub const MAX_BLOCKED: usize = 32;
+
+    // Whether `block_list` blocks `sender`, a list that was never created blocks no one
+    pub fn blocks(block_list: &AccountInfo, sender: &Pubkey) -> Result<bool> {
+        if block_list.data_is_empty() {
+            return Ok(false);
+        }
+        let block_list = Account::<BlockList>::try_from(block_list)?;
+        Ok(block_list.blocked.contains(sender))
+    }
+
+    // Blocks `sender`, blocking a sender twice is a no-op
+    pub fn block(&mut self, sender: Pubkey) -> Result<()> {
+        if self.blocked.contains(&sender) {
+            return Ok(());
+        }
+        require!(
+            self.blocked.len() < BlockList::MAX_BLOCKED,
+            CompressedNotesError::BlockListFull
+        );
+        self.blocked.push(sender);
+        Ok(())
+    }
+
+    // Unblocks `sender`, unblocking a sender that is not blocked is a no-op
+    pub fn unblock(&mut self, sender: &Pubkey) {
+        self.blocked.retain(|blocked| blocked != sender);
+    }
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct ConversationTree {
+    participants: [Pubkey; 2], // The two participants, in ascending order
+    merkle_tree: Pubkey,       // The tree dedicated to the conversation
+    bump: u8,                  // The bump seed for the pda
+}
+
+impl ConversationTree {
+    pub const SEED: &'static [u8] = b"conv_tree";
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct Inbox {
+    recipient: Pubkey, // The recipient whose messages are counted
+    unread_count: u64, // The number of direct messages not yet marked read
+    urgent_count: u64, // The number of urgent direct messages received
+    #[max_len(8)]
+    allowlist: Vec<Pubkey>, // Senders allowed to message the recipient without a request
+    bump: u8,          // The bump seed for the pda
+}
+
+impl Inbox {
+    pub const SEED: &'static [u8] = b"inbox";
+    // The seed of the recipient's inbox tree, which the inbox itself does not record
+    pub const TREE_SEED: &'static [u8] = b"inbox_tree";
+    // The shape of inbox trees, 16384 messages
+    pub const TREE_MAX_DEPTH: u32 = 14;
+    pub const TREE_MAX_BUFFER_SIZE: u32 = 64;
+    // The size of an inbox tree account, with no canopy
+    pub const TREE_ACCOUNT_SIZE: usize =
+        CONCURRENT_MERKLE_TREE_HEADER_SIZE_V1 + std::mem::size_of::<ConcurrentMerkleTree<14, 64>>();
+    // Must match the `max_len` of `allowlist`
+    pub const MAX_ALLOWLIST: usize = 8;
+
+    // Sets the recipient and bump, the same on every call for a given inbox
+    pub fn init(&mut self, recipient: Pubkey, bump: u8) {
+        self.recipient = recipient;
+        self.bump = bump;
+    }
+
+    // Counts one message as read, a recipient can mark more leaves read than they received
+    pub fn mark_read(&mut self) {
+        self.unread_count = self.unread_count.saturating_sub(1);
+    }
+
+    // Whether the recipient allowlisted `sender`
+    pub fn allows(&self, sender: &Pubkey) -> bool {
+        self.allowlist.contains(sender)
+    }
+
+    // Replaces the allowlist, up to `MAX_ALLOWLIST` senders
+    pub fn set_allowlist(&mut self, senders: Vec<Pubkey>) -> Result<()> {
+        require!(
+            senders.len() <= Inbox::MAX_ALLOWLIST,
+            CompressedNotesError::AllowlistTooLarge
+        );
+        self.allowlist = senders;
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+#[instruction(recipient: Pubkey)]
+pub struct AppendMessage<'info> {
+    // The sender of the message, pays for the conversation and inbox accounts without a payer
+    #[account(mut)]
+    pub sender: Signer<'info>,
+
+    // A separate payer for the fees and new accounts, if any, so an app can sponsor its users'
+    // messages
+    #[account(mut)]
+    pub payer: Option<Signer<'info>>,
+
+    // The session of the sender's session key signing the message, if any
+    pub session: Option<Account<'info, Session>>,
+
+    // The role of the sender on the tree, if any, letting them write to a private tree
+    pub role: Option<Account<'info, Role>>,
+
+    // The pda authority for the merkle tree, only used for signing
+    #[account(
+        seeds = [merkle_tree.key().as_ref()],
+        bump,
+    )]
+    pub tree_authority: SystemAccount<'info>,
+
+    // The merkle tree account
+    /// CHECK: This account is validated by the spl account compression program
+    #[account(
+        mut,
+        owner = spl_account_compression::ID @ CompressedNotesError::InvalidMerkleTreeOwner,
+    )]
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    // The tree config pda, counting appends and replaces, which may not have been created
+    /// CHECK: Only read and written if it exists, the seeds tie it to the merkle tree
+    #[account(mut, seeds = [TreeConfig::SEED, merkle_tree.key().as_ref()], bump)]
+    pub tree_config: UncheckedAccount<'info>,
+
+    // The conversation between sender and recipient, created on the first message
+    #[account(
+        init_if_needed,
+        payer = payer.as_ref().unwrap_or(&sender),
+        space = 8 + Conversation::INIT_SPACE,
+        seeds = [
+            Conversation::SEED,
+            Session::acting_for(sender.key, &session).min(recipient).as_ref(),
+            Session::acting_for(sender.key, &session).max(recipient).as_ref(),
+        ],
+        bump,
+    )]
+    pub conversation: Account<'info, Conversation>,
+
+    // The recipient's inbox, created on the first message they receive
+    #[account(
+        init_if_needed,
+        payer = payer.as_ref().unwrap_or(&sender),
+        space = 8 + Inbox::INIT_SPACE,
+        seeds = [Inbox::SEED, recipient.as_ref()],
+        bump,
+    )]
+    pub inbox: Account<'info, Inbox>,
+
+    // The recipient's consent to the conversation, not needed if the sender is allowlisted
+    #[account(
+        seeds = [
+            ConversationState::SEED,
+            Session::acting_for(sender.key, &session).min(recipient).as_ref(),
+            Session::acting_for(sender.key, &session).max(recipient).as_ref(),
+        ],
+        bump = conversation_state.bump,
+    )]
+    pub conversation_state: Option<Account<'info, ConversationState>>,
+
+    // The recipient's block list, which may not have been created
+    /// CHECK: Only read if it exists, the seeds tie it to the recipient
+    #[account(seeds = [BlockList::SEED, recipient.as_ref()], bump)]
+    pub block_list: UncheckedAccount<'info>,
+
+    // The instructions sysvar, needed on trees restricting which programs can call in
+    /// CHECK: The address constraint ties it to the instructions sysvar
+    #[account(address = sysvar_instructions::ID)]
+    pub instructions: Option<UncheckedAccount<'info>>,
+
+    // The sender's nonce pda, if they keep one, only checked by append_message
+    #[account(
+        mut,
+        seeds = [SenderNonce::SEED, Session::acting_for(sender.key, &session).as_ref()],
+        bump = sender_nonce.bump,
+    )]
+    pub sender_nonce: Option<Account<'info, SenderNonce>>,
+
+    // The rate state of the sender on the tree, needed to append to a rate limited tree
+    #[account(
+        mut,
+        seeds = [
+            RateState::SEED,
+            merkle_tree.key().as_ref(),
+            Session::acting_for(sender.key, &session).as_ref(),
+        ],
+        bump,
+    )]
+    pub rate_state: Option<Account<'info, RateState>>,
+
+    // The recent leaves pda of the tree, needed to append to a tree refusing duplicate leaves
+    #[account(
+        mut,
+        seeds = [RecentLeaves::SEED, merkle_tree.key().as_ref()],
+        bump,
+    )]
+    pub recent_leaves: Option<Account<'info, RecentLeaves>>,
+
+    // The tree treasury pda, needed to append to a tree charging an append fee
+    #[account(mut, seeds = [TREE_TREASURY_SEED, merkle_tree.key().as_ref()], bump)]
+    pub tree_treasury: Option<SystemAccount<'info>>,
+
+    // The signer's token account paying the fee, needed to append to a tree charging a token fee
+    #[account(mut)]
+    pub payer_token_account: Option<Account<'info, TokenAccount>>,
+
+    // The fee vault pda of the tree's fee mint, needed to append to a tree charging a token fee
+    #[account(mut)]
+    pub fee_vault: Option<Account<'info, TokenAccount>>,
+
+    // The spl token program, needed to append to a tree charging a token fee
+    pub token_program: Option<Program<'info, Token>>,
+
+    // A token account of the poster, needed to append to a token- or nft-gated tree
+    pub gate_token_account: Option<Account<'info, TokenAccount>>,
+
+    // The metadata account of the poster's nft, needed to append to an nft-gated tree
+    pub gate_metadata: Option<Account<'info, MetadataAccount>>,
+
+    // The stake pda of the sender on the tree, needed to append to a tree asking posters to stake
+    #[account(
+        mut,
+        seeds = [
+            Stake::SEED,
+            merkle_tree.key().as_ref(),
+            Session::acting_for(sender.key, &session).as_ref(),
+        ],
+        bump,
+    )]
+    pub stake: Option<Account<'info, Stake>>,
+
+    // The poster's subscription to the tree, needed to append to a tree selling access
+    pub subscription: Option<Account<'info, TreeSubscription>>,
+
+    // The noop program to log data
+    pub log_wrapper: Program<'info, Noop>,
+
+    // The spl account compression program
+    pub compression_program: Program<'info, SplAccountCompression>,
+
+    // The system program, used to create the conversation and inbox accounts
+    pub system_program: Program<'info, System>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+impl<'info> AppendMessage<'info> {
+    // The signer paying fees, the separate payer if one signs, otherwise the sender
+    pub fn fee_payer(&self) -> &Signer<'info> {
+        self.payer.as_ref().unwrap_or(&self.sender)
+    }
+}
+
+#[derive(Accounts)]
+#[instruction(recipient: Pubkey)]
+pub struct RequestConversation<'info> {
+    // The participant asking for the conversation, pays for the conversation state account
+    #[account(mut)]
+    pub requester: Signer<'info>,
+
+    // The conversation state pda, one per pair of participants
+    #[account(
+        init,
+        payer = requester,
+        space = 8 + ConversationState::INIT_SPACE,
+        seeds = [
+            ConversationState::SEED,
+            requester.key().min(recipient).as_ref(),
+            requester.key().max(recipient).as_ref(),
+        ],
+        bump,
+    )]
+    pub conversation_state: Account<'info, ConversationState>,
+
+    // The system program, used to create the conversation state account
+    pub system_program: Program<'info, System>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[derive(Accounts)]
+pub struct CreateSenderNonce<'info> {
+    // The sender, pays for the nonce account
+    #[account(mut)]
+    pub sender: Signer<'info>,
+
+    // The nonce pda, one per sender
+    #[account(
+        init,
+        payer = sender,
+        space = 8 + SenderNonce::INIT_SPACE,
+        seeds = [SenderNonce::SEED, sender.key().as_ref()],
+        bump,
+    )]
+    pub sender_nonce: Account<'info, SenderNonce>,
+
+    // The system program, used to create the nonce account
+    pub system_program: Program<'info, System>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptConversation<'info> {
+    // The participant the request was sent to
+    pub recipient: Signer<'info>,
+
+    // The conversation state pda
+    #[account(
+        mut,
+        seeds = [
+            ConversationState::SEED,
+            conversation_state.requester.min(recipient.key()).as_ref(),
+            conversation_state.requester.max(recipient.key()).as_ref(),
+        ],
+        bump = conversation_state.bump,
+        has_one = recipient @ CompressedNotesError::Unauthorized,
+    )]
+    pub conversation_state: Account<'info, ConversationState>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[derive(Accounts)]
+#[instruction(recipient: Pubkey)]
+pub struct InboxTreeAccounts<'info> {
+    // Pays for the inbox tree account
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    // The pda authority for the merkle tree, only used for signing
+    #[account(
+        seeds = [merkle_tree.key().as_ref()],
+        bump,
+    )]
+    pub tree_authority: SystemAccount<'info>,
+
+    // The recipient's inbox tree, created over one or more calls
+    /// CHECK: Created by this instruction, then validated by the spl account compression program
+    #[account(
+        mut,
+        seeds = [Inbox::TREE_SEED, recipient.as_ref()],
+        bump,
+    )]
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    // The noop program to log data
+    pub log_wrapper: Program<'info, Noop>,
+
+    // The spl account compression program
+    pub compression_program: Program<'info, SplAccountCompression>,
+
+    // The system program, used to create the inbox tree account
+    pub system_program: Program<'info, System>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[derive(Accounts)]
+pub struct SignalAccounts<'info> {
+    // The user the signal is about
+    pub user: Signer<'info>,
+
+    // The noop program to log data
+    pub log_wrapper: Program<'info, Noop>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[derive(Accounts)]
+pub struct BlockSender<'info> {
+    // The owner of the block list, pays for it if it does not exist yet
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    // The owner's block list
+    #[account(
+        init_if_needed,
+        payer = owner,
+        space = 8 + BlockList::INIT_SPACE,
+        seeds = [BlockList::SEED, owner.key().as_ref()],
+        bump,
+    )]
+    pub block_list: Account<'info, BlockList>,
+
+    // The system program, used to create the block list account
+    pub system_program: Program<'info, System>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[derive(Accounts)]
+pub struct UnblockSender<'info> {
+    // The owner of the block list
+    pub owner: Signer<'info>,
+
+    // The owner's block list
+    #[account(
+        mut,
+        seeds = [BlockList::SEED, owner.key().as_ref()],
+        bump = block_list.bump,
+        has_one = owner @ CompressedNotesError::Unauthorized,
+    )]
+    pub block_list: Account<'info, BlockList>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[derive(Accounts)]
+pub struct SetAllowlist<'info> {
+    // The owner of the inbox, pays for it if it does not exist yet
+    #[account(mut)]
+    pub recipient: Signer<'info>,
+
+    // The recipient's inbox
+    #[account(
+        init_if_needed,
+        payer = recipient,
+        space = 8 + Inbox::INIT_SPACE,
+        seeds = [Inbox::SEED, recipient.key().as_ref()],
+        bump,
+    )]
+    pub inbox: Account<'info, Inbox>,
+
+    // The system program, used to create the inbox account
+    pub system_program: Program<'info, System>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[derive(Accounts)]
+#[instruction(recipient: Pubkey)]
+pub struct CreateConversationTree<'info> {
+    // One of the participants, pays for the conversation tree account
+    #[account(mut)]
+    pub sender: Signer<'info>,
+
+    // The conversation tree pda, one per pair of participants
+    #[account(
+        init,
+        payer = sender,
+        space = 8 + ConversationTree::INIT_SPACE,
+        seeds = [
+            ConversationTree::SEED,
+            sender.key().min(recipient).as_ref(),
+            sender.key().max(recipient).as_ref(),
+        ],
+        bump,
+    )]
+    pub conversation_tree: Account<'info, ConversationTree>,
+
+    // The forest pda of the creator, listing their trees, created with their first tree
+    #[account(
+        init_if_needed,
+        payer = sender,
+        space = 8 + Forest::INIT_SPACE,
+        seeds = [Forest::SEED, sender.key().as_ref()],
+        bump,
+    )]
+    pub forest: Account<'info, Forest>,
+
+    // The pda authority for the merkle tree, only used for signing
+    #[account(
+        seeds = [merkle_tree.key().as_ref()],
+        bump,
+    )]
+    pub tree_authority: SystemAccount<'info>,
+
+    // The merkle tree account, allocated but not yet initialized
+    /// CHECK: This account is validated by the spl account compression program
+    #[account(
+        mut,
+        owner = spl_account_compression::ID @ CompressedNotesError::InvalidMerkleTreeOwner,
+    )]
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    // The noop program to log data
+    pub log_wrapper: Program<'info, Noop>,
+
+    // The spl account compression program
+    pub compression_program: Program<'info, SplAccountCompression>,
+
+    // The system program, used to create the conversation tree account
+    pub system_program: Program<'info, System>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[derive(Accounts)]
+pub struct SetConversationFlags<'info> {
+    // One of the participants of the conversation
+    pub participant: Signer<'info>,
+
+    // The conversation pda
+    #[account(
+        mut,
+        seeds = [
+            Conversation::SEED,
+            conversation.participants[0].as_ref(),
+            conversation.participants[1].as_ref(),
+        ],
+        bump = conversation.bump,
+    )]
+    pub conversation: Account<'info, Conversation>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[derive(Accounts)]
+pub struct AcknowledgeMessage<'info> {
+    // The recipient of the message, signs the acknowledgement
+    pub recipient: Signer<'info>,
+
+    // The role of the recipient on the tree, if any, letting them write to a private tree
+    pub role: Option<Account<'info, Role>>,
+
+    // The tree config pda, counting appends and replaces, which may not have been created
+    /// CHECK: Only read and written if it exists, the seeds tie it to the merkle tree
+    #[account(mut, seeds = [TreeConfig::SEED, merkle_tree.key().as_ref()], bump)]
+    pub tree_config: UncheckedAccount<'info>,
+
+    // The pda authority for the merkle tree, only used for signing
+    #[account(
+        seeds = [merkle_tree.key().as_ref()],
+        bump,
+    )]
+    pub tree_authority: SystemAccount<'info>,
+
+    // The merkle tree account
+    /// CHECK: This account is validated by the spl account compression program
+    #[account(
+        mut,
+        owner = spl_account_compression::ID @ CompressedNotesError::InvalidMerkleTreeOwner,
+    )]
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    // The noop program to log data
+    pub log_wrapper: Program<'info, Noop>,
+
+    // The spl account compression program
+    pub compression_program: Program<'info, SplAccountCompression>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateMessage<'info> {
+    // The sender of the message, the only one who can edit it, pays any update fee
+    #[account(mut)]
+    pub sender: Signer<'info>,
+
+    // The pda authority for the merkle tree, only used for signing
+    #[account(
+        seeds = [merkle_tree.key().as_ref()],
+        bump,
+    )]
+    pub tree_authority: SystemAccount<'info>,
+
+    // The merkle tree account
+    /// CHECK: This account is validated by the spl account compression program
+    #[account(
+        mut,
+        owner = spl_account_compression::ID @ CompressedNotesError::InvalidMerkleTreeOwner,
+    )]
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    // The tree config pda, counting appends and replaces, which may not have been created
+    /// CHECK: Only read and written if it exists, the seeds tie it to the merkle tree
+    #[account(mut, seeds = [TreeConfig::SEED, merkle_tree.key().as_ref()], bump)]
+    pub tree_config: UncheckedAccount<'info>,
+
+    // The hold pda of the message replaced, needed to edit messages in a tree with leaves on hold
+    /// CHECK: Checked against the message leaf replaced, which must not be on hold
+    pub hold: Option<UncheckedAccount<'info>>,
+
+    // The tree treasury pda, needed to edit messages in a tree charging an update fee
+    #[account(mut, seeds = [TREE_TREASURY_SEED, merkle_tree.key().as_ref()], bump)]
+    pub tree_treasury: Option<SystemAccount<'info>>,
+
+    // The system program, needed with the tree treasury to pay the update fee
+    pub system_program: Option<Program<'info, System>>,
+
+    // The noop program to log data
+    pub log_wrapper: Program<'info, Noop>,
+
+    // The spl account compression program
+    pub compression_program: Program<'info, SplAccountCompression>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[derive(Accounts)]
+pub struct BurnExpiredMessage<'info> {
+    // The hold pda of the leaf replaced, needed to replace leaves in a tree with leaves on hold
+    /// CHECK: Checked against the leaf replaced, which must not be on hold
+    pub hold: Option<UncheckedAccount<'info>>,
+
+    // The tree config pda, counting appends and replaces, which may not have been created
+    /// CHECK: Only read and written if it exists, the seeds tie it to the merkle tree
+    #[account(mut, seeds = [TreeConfig::SEED, merkle_tree.key().as_ref()], bump)]
+    pub tree_config: UncheckedAccount<'info>,
+
+    // The pda authority for the merkle tree, only used for signing
+    #[account(
+        seeds = [merkle_tree.key().as_ref()],
+        bump,
+    )]
+    pub tree_authority: SystemAccount<'info>,
+
+    // The merkle tree account
+    /// CHECK: This account is validated by the spl account compression program
+    #[account(
+        mut,
+        owner = spl_account_compression::ID @ CompressedNotesError::InvalidMerkleTreeOwner,
+    )]
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    // The noop program to log data
+    pub log_wrapper: Program<'info, Noop>,
+
+    // The spl account compression program
+    pub compression_program: Program<'info, SplAccountCompression>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct Group {
+    admin: Pubkey, // The admin allowed to change the member list
+    group_id: u64, // Id of the group, unique per admin
+    #[max_len(16)]
+    members: Vec<Pubkey>, // The members who can post to and receive from the group
+    bump: u8,      // The bump seed for the pda
+}
+
+impl Group {
+    // Must match the `max_len` of `members`
+    pub const MAX_MEMBERS: usize = 16;
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct ConversationMeta {
+    group: Pubkey, // The group chat the metadata describes
+    admin: Pubkey, // The admin allowed to update the metadata
+    #[max_len(64)]
+    title: Option<String>, // The title of the chat, if any
+    #[max_len(16)]
+    participants: Vec<Pubkey>, // The participants shown for the chat
+    muted: u16,    // Bit i mutes participants[i]
+    bump: u8,      // The bump seed for the pda
+}
+
+impl ConversationMeta {
+    pub const SEED: &'static [u8] = b"conv_meta";
+    // Must match the `max_len` of `title`
+    pub const MAX_TITLE_LEN: usize = 64;
+
+    // Replaces the title, participants and muted flags, failing if they do not fit
+    pub fn set(
+        &mut self,
+        title: Option<String>,
+        participants: Vec<Pubkey>,
+        muted: u16,
+    ) -> Result<()> {
+        require!(
+            title.as_ref().map_or(0, |title| title.len()) <= ConversationMeta::MAX_TITLE_LEN,
+            CompressedNotesError::TitleTooLong
+        );
+        require!(
+            participants.len() <= Group::MAX_MEMBERS,
+            CompressedNotesError::GroupTooLarge
+        );
+        self.title = title;
+        self.participants = participants;
+        self.muted = muted;
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+#[instruction(group_id: u64)]
+pub struct CreateGroup<'info> {
+    // The admin of the new group, also pays for the group account
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    // The group pda
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + Group::INIT_SPACE,
+        seeds = [b"group", admin.key().as_ref(), &group_id.to_le_bytes()],
+        bump,
+    )]
+    pub group: Account<'info, Group>,
+
+    // The system program, used to create the group account
+    pub system_program: Program<'info, System>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[derive(Accounts)]
+pub struct GroupAdmin<'info> {
+    // The admin of the group
+    pub admin: Signer<'info>,
+
+    // The group pda
+    #[account(
+        mut,
+        seeds = [b"group", group.admin.as_ref(), &group.group_id.to_le_bytes()],
+        bump = group.bump,
+        has_one = admin @ CompressedNotesError::Unauthorized,
+    )]
+    pub group: Account<'info, Group>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[derive(Accounts)]
+pub struct CreateConversationMeta<'info> {
+    // The admin of the group, also pays for the metadata account
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    // The group chat the metadata describes
+    #[account(
+        seeds = [b"group", group.admin.as_ref(), &group.group_id.to_le_bytes()],
+        bump = group.bump,
+        has_one = admin @ CompressedNotesError::Unauthorized,
+    )]
+    pub group: Account<'info, Group>,
+
+    // The metadata pda, one per group
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + ConversationMeta::INIT_SPACE,
+        seeds = [ConversationMeta::SEED, group.key().as_ref()],
+        bump,
+    )]
+    pub conversation_meta: Account<'info, ConversationMeta>,
+
+    // The system program, used to create the metadata account
+    pub system_program: Program<'info, System>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateConversationMeta<'info> {
+    // The admin of the metadata
+    pub admin: Signer<'info>,
+
+    // The metadata pda
+    #[account(
+        mut,
+        seeds = [ConversationMeta::SEED, conversation_meta.group.as_ref()],
+        bump = conversation_meta.bump,
+        has_one = admin @ CompressedNotesError::Unauthorized,
+    )]
+    pub conversation_meta: Account<'info, ConversationMeta>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[derive(Accounts)]
+pub struct GroupMessageAccounts<'info> {
+    // The sender of the message, must be a member of the group, also pays the append fee of
+    // a tree charging one
+    #[account(mut)]
+    pub sender: Signer<'info>,
+
+    // The role of the sender on the tree, if any, letting them write to a private tree
+    pub role: Option<Account<'info, Role>>,
+
+    // The group the message is sent to
+    #[account(
+        seeds = [b"group", group.admin.as_ref(), &group.group_id.to_le_bytes()],
+        bump = group.bump,
+    )]
+    pub group: Account<'info, Group>,
+
+    // The tree treasury pda, needed to append to a tree charging an append fee
+    #[account(mut, seeds = [TREE_TREASURY_SEED, merkle_tree.key().as_ref()], bump)]
+    pub tree_treasury: Option<SystemAccount<'info>>,
+
+    // The signer's token account paying the fee, needed to append to a tree charging a token fee
+    #[account(mut)]
+    pub payer_token_account: Option<Account<'info, TokenAccount>>,
+
+    // The fee vault pda of the tree's fee mint, needed to append to a tree charging a token fee
+    #[account(mut)]
+    pub fee_vault: Option<Account<'info, TokenAccount>>,
+
+    // The spl token program, needed to append to a tree charging a token fee
+    pub token_program: Option<Program<'info, Token>>,
+
+    // A token account of the sender, needed to append to a token- or nft-gated tree
+    pub gate_token_account: Option<Account<'info, TokenAccount>>,
+
+    // The metadata account of the sender's nft, needed to append to an nft-gated tree
+    pub gate_metadata: Option<Account<'info, MetadataAccount>>,
+
+    // The sender's subscription to the tree, needed to append to a tree selling access
+    pub subscription: Option<Account<'info, TreeSubscription>>,
+
+    // The tree config pda, counting appends and replaces, which may not have been created
+    /// CHECK: Only read and written if it exists, the seeds tie it to the merkle tree
+    #[account(mut, seeds = [TreeConfig::SEED, merkle_tree.key().as_ref()], bump)]
+    pub tree_config: UncheckedAccount<'info>,
+
+    // The pda authority for the merkle tree, only used for signing
+    #[account(
+        seeds = [merkle_tree.key().as_ref()],
+        bump,
+    )]
+    pub tree_authority: SystemAccount<'info>,
+
+    // The merkle tree account
+    /// CHECK: This account is validated by the spl account compression program
+    #[account(
+        mut,
+        owner = spl_account_compression::ID @ CompressedNotesError::InvalidMerkleTreeOwner,
+    )]
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    // The noop program to log data
+    pub log_wrapper: Program<'info, Noop>,
+
+    // The spl account compression program
+    pub compression_program: Program<'info, SplAccountCompression>,
+
+    // The system program, needed to append to a tree charging an append fee
+    pub system_program: Option<Program<'info, System>>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
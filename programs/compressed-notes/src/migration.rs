@@ -0,0 +1,618 @@
+//! Tree migrations copying the notes of a frozen tree into another tree of the same authority,
+//! logging the source and destination id of every copied leaf.
+
+use anchor_lang::prelude::*;
+use anchor_spl::metadata::MetadataAccount;
+use anchor_spl::token::{Token, TokenAccount};
+use spl_account_compression::{
+    cpi::{accounts::VerifyLeaf, verify_leaf},
+    program::SplAccountCompression,
+    wrap_application_data_v1, Noop,
+};
+
+use crate::{
+    hash_note_leaf_v2, instruction_flags, leaf_schema, resolve_note_owner, CompressedNotesError,
+    DidDocument, FeeOperation, GlobalConfig, NoteId, NoteLog, NoteStatus, RateState, RecentLeaves,
+    TreeConfig, TreeCpi, TreeState, TreeSubscription, TREE_TREASURY_SEED,
+};
+
+// Opens a migration pda at `[b"migration", source_tree]` from the source tree to a destination
+// tree with the same authority, freezing the source so its leaves stop changing.
+pub fn process_begin_migration(
+    ctx: Context<BeginMigration>,
+    compact: bool, // Whether tombstoned leaves are left behind
+) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::BEGIN_MIGRATION)?;
+
+    TreeConfig::freeze_for_migration(
+        &ctx.accounts.source_config,
+        &ctx.accounts.dest_config,
+        &ctx.accounts.authority.key(),
+    )?;
+
+    let migration = &mut ctx.accounts.migration;
+    migration.authority = ctx.accounts.authority.key();
+    migration.source_tree = ctx.accounts.source_tree.key();
+    migration.dest_tree = ctx.accounts.dest_tree.key();
+    migration.compact = compact;
+    migration.bump = *ctx.bumps.get("migration").unwrap();
+    Ok(())
+}
+
+// Proves a note leaf of the source tree under `root` and appends it to the destination tree.
+// Leaves migrate in index order, so one at or below the last migrated fails, and a compacting
+// migration refuses deleted and redacted leaves, which are skipped.
+pub fn process_migrate_leaf<'info>(
+    ctx: Context<'_, '_, '_, 'info, MigrateLeaf<'info>>,
+    index: u32,           // Index of the leaf in the source tree
+    root: [u8; 32],       // Root of the source tree the leaf is proven against
+    payload: LeafPayload, // The note committed into the leaf
+) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::MIGRATE_LEAF)?;
+
+    let migration = &ctx.accounts.migration;
+    require!(
+        index >= migration.next_index,
+        CompressedNotesError::LeafAlreadyMigrated
+    );
+    require!(
+        !migration.compact || payload.status.retains_content(),
+        CompressedNotesError::LeafTombstoned
+    );
+
+    // CPI to verify the leaf in the source tree, the proof is passed as remaining accounts
+    let leaf_node = payload.leaf();
+    TreeState::load(&ctx.accounts.source_tree)?.require_leaf(index)?;
+    let cpi_ctx = CpiContext::new(
+        ctx.accounts.compression_program.to_account_info(),
+        VerifyLeaf {
+            merkle_tree: ctx.accounts.source_tree.to_account_info(),
+        },
+    )
+    .with_remaining_accounts(ctx.remaining_accounts.to_vec());
+    verify_leaf(cpi_ctx, root, leaf_node, index)?;
+
+    // Append the same leaf to the destination tree, counted on its config
+    TreeConfig::record_append(
+        &ctx.accounts.dest_config,
+        &ctx.accounts.dest_tree,
+        &ctx.accounts.authority.key(),
+        None,
+    )?;
+    // A destination tree charging an append fee takes it from the authority into its treasury
+    TreeConfig::record_fee(
+        &ctx.accounts.dest_config,
+        &ctx.accounts.authority,
+        ctx.accounts.tree_treasury.as_ref(),
+        ctx.accounts.system_program.as_ref(),
+        FeeOperation::Append(payload.note.len()),
+    )?;
+    // A destination tree charging a token append fee takes it from the authority into its vault
+    TreeConfig::record_token_fee(
+        &ctx.accounts.dest_config,
+        &ctx.accounts.authority,
+        ctx.accounts.payer_token_account.as_ref(),
+        ctx.accounts.fee_vault.as_ref(),
+        ctx.accounts.token_program.as_ref(),
+    )?;
+    // A token- or nft-gated destination tree only accepts an authority holding its gate
+    TreeConfig::require_gate(
+        &ctx.accounts.dest_config,
+        &ctx.accounts.authority.key(),
+        ctx.accounts.gate_token_account.as_ref(),
+        ctx.accounts.gate_metadata.as_ref(),
+    )?;
+    // A tree selling access only accepts posters with an active subscription
+    TreeConfig::require_subscription(
+        &ctx.accounts.dest_config,
+        &ctx.accounts.authority.key(),
+        ctx.accounts.subscription.as_deref(),
+    )?;
+    let dest_id = NoteId::next(&ctx.accounts.dest_tree, leaf_schema::V2)?;
+    TreeCpi::new(
+        &ctx.accounts.compression_program,
+        &ctx.accounts.dest_authority,
+        &ctx.accounts.dest_tree,
+        &ctx.accounts.log_wrapper,
+        *ctx.bumps.get("dest_authority").unwrap(),
+    )
+    .append(leaf_node)?;
+
+    let migration = &mut ctx.accounts.migration;
+    migration.next_index = index + 1;
+    migration.migrated += 1;
+
+    // Log the mapping from the source leaf to its copy for indexers
+    let source_id = NoteId::new(migration.source_tree, index, leaf_schema::V2);
+    let migration_log = MigrationLog::new(leaf_node, source_id, dest_id);
+    wrap_application_data_v1(migration_log.try_to_vec()?, &ctx.accounts.log_wrapper)?;
+    Ok(())
+}
+
+// Retires the source tree in favour of the destination tree, closing the migration. The
+// source stays frozen and links the destination as its successor.
+pub fn process_finish_migration(ctx: Context<FinishMigration>) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::FINISH_MIGRATION)?;
+
+    let migration = &ctx.accounts.migration;
+    TreeConfig::retire(&ctx.accounts.source_config, &migration.dest_tree)?;
+
+    // Log the retirement for indexers
+    let retire_log = TreeRetireLog::new(
+        migration.source_tree,
+        migration.dest_tree,
+        migration.migrated,
+        Clock::get()?.slot,
+    );
+    wrap_application_data_v1(retire_log.try_to_vec()?, &ctx.accounts.log_wrapper)?;
+    Ok(())
+}
+
+// Moves a note from the source tree to the destination tree, deleting it in the source, without
+// a migration. Locked notes cannot be deleted, so they cannot be moved either.
+pub fn process_move_note(
+    ctx: Context<MoveNote>,
+    src_index: u32,     // Index of the note leaf in the source tree
+    root: [u8; 32],     // Root of the source tree the leaf is proven against
+    note: String,       // The note message
+    status: NoteStatus, // The current status of the note, kept in the destination tree
+) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::MOVE_NOTE)?;
+
+    let owner = resolve_note_owner(&ctx.accounts.owner.key(), &ctx.accounts.did)?;
+    let leaf_node = hash_note_leaf_v2(&note, &owner, status);
+    // The source leaf is deleted, dropping the note content
+    let deleted = status.transition(NoteStatus::Deleted)?;
+    let tombstone = hash_note_leaf_v2("", &owner, deleted);
+
+    // CPI to replace the source leaf with its tombstone, failing unless it is at `src_index`
+    TreeConfig::record_replace(
+        &ctx.accounts.source_config,
+        &ctx.accounts.source_tree,
+        &root,
+        &leaf_node,
+        ctx.accounts.hold.as_deref(),
+    )?;
+    TreeCpi::new(
+        &ctx.accounts.compression_program,
+        &ctx.accounts.source_authority,
+        &ctx.accounts.source_tree,
+        &ctx.accounts.log_wrapper,
+        *ctx.bumps.get("source_authority").unwrap(),
+    )
+    .replace(root, leaf_node, tombstone, src_index)?;
+
+    // CPI to append the same leaf to the destination tree
+    TreeConfig::record_append(
+        &ctx.accounts.dest_config,
+        &ctx.accounts.dest_tree,
+        &ctx.accounts.owner.key(),
+        None,
+    )?;
+    // Count the append against the rate limits of the destination tree
+    TreeConfig::record_rate(
+        &ctx.accounts.dest_config,
+        ctx.accounts.rate_state.as_deref_mut(),
+    )?;
+    // A destination tree refusing duplicate leaves checks the leaf against its recent leaves
+    TreeConfig::record_leaf(
+        &ctx.accounts.dest_config,
+        ctx.accounts.recent_leaves.as_deref_mut(),
+        &leaf_node,
+    )?;
+    // A destination tree charging an append fee takes it from the owner into its treasury
+    TreeConfig::record_fee(
+        &ctx.accounts.dest_config,
+        &ctx.accounts.owner,
+        ctx.accounts.tree_treasury.as_ref(),
+        ctx.accounts.system_program.as_ref(),
+        FeeOperation::Append(note.len()),
+    )?;
+    // A destination tree charging a token append fee takes it from the owner into its vault
+    TreeConfig::record_token_fee(
+        &ctx.accounts.dest_config,
+        &ctx.accounts.owner,
+        ctx.accounts.payer_token_account.as_ref(),
+        ctx.accounts.fee_vault.as_ref(),
+        ctx.accounts.token_program.as_ref(),
+    )?;
+    // A token- or nft-gated destination tree only accepts owners holding its gate
+    TreeConfig::require_gate(
+        &ctx.accounts.dest_config,
+        &ctx.accounts.owner.key(),
+        ctx.accounts.gate_token_account.as_ref(),
+        ctx.accounts.gate_metadata.as_ref(),
+    )?;
+    // A tree selling access only accepts posters with an active subscription
+    TreeConfig::require_subscription(
+        &ctx.accounts.dest_config,
+        &ctx.accounts.owner.key(),
+        ctx.accounts.subscription.as_deref(),
+    )?;
+    let dest_id = NoteId::next(&ctx.accounts.dest_tree, leaf_schema::V2)?;
+    TreeCpi::new(
+        &ctx.accounts.compression_program,
+        &ctx.accounts.dest_authority,
+        &ctx.accounts.dest_tree,
+        &ctx.accounts.log_wrapper,
+        *ctx.bumps.get("dest_authority").unwrap(),
+    )
+    .append(leaf_node)?;
+
+    // Log both leaves, then the link between them, for indexers
+    let source_id = NoteId::new(ctx.accounts.source_tree.key(), src_index, leaf_schema::V2);
+    let logs = [
+        NoteLog::new(tombstone, source_id, owner, String::new(), deleted).try_to_vec()?,
+        NoteLog::new(leaf_node, dest_id, owner, note, status).try_to_vec()?,
+        NoteMoveLog::new(leaf_node, source_id, dest_id).try_to_vec()?,
+    ];
+    for log in logs {
+        wrap_application_data_v1(log, &ctx.accounts.log_wrapper)?;
+    }
+    Ok(())
+}
+
+// The preimage of a note leaf being migrated
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct LeafPayload {
+    pub owner: Pubkey,      // Pubkey of the note owner
+    pub note: String,       // The note message
+    pub status: NoteStatus, // The status committed into the leaf node
+}
+
+impl LeafPayload {
+    // The note leaf committing to this payload
+    pub fn leaf(&self) -> [u8; 32] {
+        hash_note_leaf_v2(&self.note, &self.owner, self.status)
+    }
+}
+
+#[derive(AnchorSerialize)]
+pub struct MigrationLog {
+    leaf_node: [u8; 32], // The leaf node hash, the same in both trees
+    source_id: NoteId,   // The id of the leaf in the source tree
+    dest_id: NoteId,     // The id of the copy in the destination tree
+}
+
+impl MigrationLog {
+    // Constructs a new migration log from given leaf node, source and destination id
+    pub fn new(leaf_node: [u8; 32], source_id: NoteId, dest_id: NoteId) -> Self {
+        Self {
+            leaf_node,
+            source_id,
+            dest_id,
+        }
+    }
+}
+
+#[derive(AnchorSerialize)]
+pub struct NoteMoveLog {
+    leaf_node: [u8; 32], // The leaf node hash of the moved note
+    source_id: NoteId,   // The id of the note in the source tree, now deleted
+    dest_id: NoteId,     // The id of the note in the destination tree
+}
+
+impl NoteMoveLog {
+    // Constructs a new note move log from given leaf node, source and destination id
+    pub fn new(leaf_node: [u8; 32], source_id: NoteId, dest_id: NoteId) -> Self {
+        Self {
+            leaf_node,
+            source_id,
+            dest_id,
+        }
+    }
+}
+
+#[derive(AnchorSerialize)]
+pub struct TreeRetireLog {
+    merkle_tree: Pubkey, // The tree retired
+    next_tree: Pubkey,   // The tree its leaves were copied to
+    migrated: u64,       // The number of leaves copied
+    slot: u64,           // The slot the tree was retired
+}
+
+impl TreeRetireLog {
+    // Constructs a new tree retire log from given tree, successor, leaves copied and slot
+    pub fn new(merkle_tree: Pubkey, next_tree: Pubkey, migrated: u64, slot: u64) -> Self {
+        Self {
+            merkle_tree,
+            next_tree,
+            migrated,
+            slot,
+        }
+    }
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct Migration {
+    authority: Pubkey,   // The authority of both trees, who migrates the leaves
+    source_tree: Pubkey, // The tree leaves are copied from
+    dest_tree: Pubkey,   // The tree leaves are copied to
+    next_index: u32,     // The lowest source index that can still be migrated
+    migrated: u64,       // The number of leaves copied so far
+    compact: bool,       // Whether tombstoned leaves are left behind
+    bump: u8,            // The bump seed for the pda
+}
+
+impl Migration {
+    pub const SEED: &'static [u8] = b"migration";
+}
+
+#[derive(Accounts)]
+pub struct BeginMigration<'info> {
+    // The authority of both trees, pays for the migration account
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    // The migration pda, one per source tree
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + Migration::INIT_SPACE,
+        seeds = [Migration::SEED, source_tree.key().as_ref()],
+        bump,
+    )]
+    pub migration: Account<'info, Migration>,
+
+    // The tree config pda of the source tree, frozen by this instruction
+    /// CHECK: Parsed as a tree config, the seeds tie it to the source tree
+    #[account(mut, seeds = [TreeConfig::SEED, source_tree.key().as_ref()], bump)]
+    pub source_config: UncheckedAccount<'info>,
+
+    // The tree leaves are copied from
+    /// CHECK: Only used to derive the source pdas
+    pub source_tree: UncheckedAccount<'info>,
+
+    // The tree config pda of the destination tree
+    /// CHECK: Parsed as a tree config, the seeds tie it to the destination tree
+    #[account(seeds = [TreeConfig::SEED, dest_tree.key().as_ref()], bump)]
+    pub dest_config: UncheckedAccount<'info>,
+
+    // The tree leaves are copied to
+    /// CHECK: Only used to derive the destination pdas
+    pub dest_tree: UncheckedAccount<'info>,
+
+    // The system program, used to create the migration account
+    pub system_program: Program<'info, System>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateLeaf<'info> {
+    // The authority that began the migration, also pays the append fee of a destination tree
+    // charging one
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    // The migration pda
+    #[account(
+        mut,
+        seeds = [Migration::SEED, source_tree.key().as_ref()],
+        bump = migration.bump,
+        has_one = authority @ CompressedNotesError::Unauthorized,
+        has_one = source_tree,
+        has_one = dest_tree,
+    )]
+    pub migration: Account<'info, Migration>,
+
+    // The tree leaves are copied from
+    /// CHECK: This account is validated by the spl account compression program
+    #[account(
+        owner = spl_account_compression::ID @ CompressedNotesError::InvalidMerkleTreeOwner,
+    )]
+    pub source_tree: UncheckedAccount<'info>,
+
+    // The tree config pda of the destination tree
+    /// CHECK: Only read and written if it exists, the seeds tie it to the destination tree
+    #[account(mut, seeds = [TreeConfig::SEED, dest_tree.key().as_ref()], bump)]
+    pub dest_config: UncheckedAccount<'info>,
+
+    // The pda authority for the destination tree, only used for signing
+    #[account(
+        seeds = [dest_tree.key().as_ref()],
+        bump,
+    )]
+    pub dest_authority: SystemAccount<'info>,
+
+    // The tree leaves are copied to
+    /// CHECK: This account is validated by the spl account compression program
+    #[account(
+        mut,
+        owner = spl_account_compression::ID @ CompressedNotesError::InvalidMerkleTreeOwner,
+    )]
+    pub dest_tree: UncheckedAccount<'info>,
+
+    // The destination tree treasury pda, needed to migrate into a tree charging an append fee
+    #[account(mut, seeds = [TREE_TREASURY_SEED, dest_tree.key().as_ref()], bump)]
+    pub tree_treasury: Option<SystemAccount<'info>>,
+
+    // The signer's token account paying the fee, needed to migrate into a tree charging a token fee
+    #[account(mut)]
+    pub payer_token_account: Option<Account<'info, TokenAccount>>,
+
+    // The fee vault pda of the tree's fee mint, needed to migrate into a tree charging a token fee
+    #[account(mut)]
+    pub fee_vault: Option<Account<'info, TokenAccount>>,
+
+    // The spl token program, needed to migrate into a tree charging a token fee
+    pub token_program: Option<Program<'info, Token>>,
+
+    // A token account of the authority, needed to migrate into a token- or nft-gated tree
+    pub gate_token_account: Option<Account<'info, TokenAccount>>,
+
+    // The metadata account of the authority's nft, needed to migrate into an nft-gated tree
+    pub gate_metadata: Option<Account<'info, MetadataAccount>>,
+
+    // The authority's subscription to the destination tree, needed to migrate into a tree selling access
+    pub subscription: Option<Account<'info, TreeSubscription>>,
+
+    // The noop program to log data
+    pub log_wrapper: Program<'info, Noop>,
+
+    // The spl account compression program
+    pub compression_program: Program<'info, SplAccountCompression>,
+
+    // The system program, needed to migrate into a tree charging an append fee
+    pub system_program: Option<Program<'info, System>>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[derive(Accounts)]
+pub struct FinishMigration<'info> {
+    // The authority that began the migration, refunded the migration rent
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    // The migration pda, closed by this instruction
+    #[account(
+        mut,
+        close = authority,
+        seeds = [Migration::SEED, source_tree.key().as_ref()],
+        bump = migration.bump,
+        has_one = authority @ CompressedNotesError::Unauthorized,
+        has_one = source_tree,
+    )]
+    pub migration: Account<'info, Migration>,
+
+    // The tree config pda of the source tree, linked to the destination tree
+    /// CHECK: Parsed as a tree config, the seeds tie it to the source tree
+    #[account(mut, seeds = [TreeConfig::SEED, source_tree.key().as_ref()], bump)]
+    pub source_config: UncheckedAccount<'info>,
+
+    // The tree leaves were copied from
+    /// CHECK: Only used to derive the source pdas
+    pub source_tree: UncheckedAccount<'info>,
+
+    // The noop program to log data
+    pub log_wrapper: Program<'info, Noop>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[derive(Accounts)]
+pub struct MoveNote<'info> {
+    // The owner of the note, also pays the append fee of a destination tree charging one
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    // The DID document owning the note, if any, the owner must be one of its controllers
+    pub did: Option<Account<'info, DidDocument>>,
+
+    // The tree config pda of the source tree, which may not have been created
+    /// CHECK: Only read and written if it exists, the seeds tie it to the source tree
+    #[account(mut, seeds = [TreeConfig::SEED, source_tree.key().as_ref()], bump)]
+    pub source_config: UncheckedAccount<'info>,
+
+    // The pda authority for the source tree, only used for signing
+    #[account(
+        seeds = [source_tree.key().as_ref()],
+        bump,
+    )]
+    pub source_authority: SystemAccount<'info>,
+
+    // The tree the note is moved out of
+    /// CHECK: This account is validated by the spl account compression program
+    #[account(
+        mut,
+        owner = spl_account_compression::ID @ CompressedNotesError::InvalidMerkleTreeOwner,
+    )]
+    pub source_tree: UncheckedAccount<'info>,
+
+    // The hold pda of the note leaf, needed to move notes out of a tree with leaves on hold
+    /// CHECK: Checked against the note leaf, which must not be on hold
+    pub hold: Option<UncheckedAccount<'info>>,
+
+    // The tree config pda of the destination tree, which may not have been created
+    /// CHECK: Only read and written if it exists, the seeds tie it to the destination tree
+    #[account(mut, seeds = [TreeConfig::SEED, dest_tree.key().as_ref()], bump)]
+    pub dest_config: UncheckedAccount<'info>,
+
+    // The pda authority for the destination tree, only used for signing
+    #[account(
+        seeds = [dest_tree.key().as_ref()],
+        bump,
+    )]
+    pub dest_authority: SystemAccount<'info>,
+
+    // The tree the note is moved into
+    /// CHECK: This account is validated by the spl account compression program
+    #[account(
+        mut,
+        owner = spl_account_compression::ID @ CompressedNotesError::InvalidMerkleTreeOwner,
+    )]
+    pub dest_tree: UncheckedAccount<'info>,
+
+    // The rate state of the owner on the destination tree, needed to move into a rate limited tree
+    #[account(
+        mut,
+        seeds = [RateState::SEED, dest_tree.key().as_ref(), owner.key().as_ref()],
+        bump,
+    )]
+    pub rate_state: Option<Account<'info, RateState>>,
+
+    // The recent leaves pda of the destination tree, needed to move into a tree refusing duplicates
+    #[account(
+        mut,
+        seeds = [RecentLeaves::SEED, dest_tree.key().as_ref()],
+        bump,
+    )]
+    pub recent_leaves: Option<Account<'info, RecentLeaves>>,
+
+    // The destination tree treasury pda, needed to move into a tree charging an append fee
+    #[account(mut, seeds = [TREE_TREASURY_SEED, dest_tree.key().as_ref()], bump)]
+    pub tree_treasury: Option<SystemAccount<'info>>,
+
+    // The signer's token account paying the fee, needed to move into a tree charging a token fee
+    #[account(mut)]
+    pub payer_token_account: Option<Account<'info, TokenAccount>>,
+
+    // The fee vault pda of the tree's fee mint, needed to move into a tree charging a token fee
+    #[account(mut)]
+    pub fee_vault: Option<Account<'info, TokenAccount>>,
+
+    // The spl token program, needed to move into a tree charging a token fee
+    pub token_program: Option<Program<'info, Token>>,
+
+    // A token account of the owner, needed to move into a token- or nft-gated tree
+    pub gate_token_account: Option<Account<'info, TokenAccount>>,
+
+    // The metadata account of the owner's nft, needed to move into an nft-gated tree
+    pub gate_metadata: Option<Account<'info, MetadataAccount>>,
+
+    // The owner's subscription to the destination tree, needed to move into a tree selling access
+    pub subscription: Option<Account<'info, TreeSubscription>>,
+
+    // The noop program to log data
+    pub log_wrapper: Program<'info, Noop>,
+
+    // The spl account compression program
+    pub compression_program: Program<'info, SplAccountCompression>,
+
+    // The system program, needed to move into a tree charging an append fee
+    pub system_program: Option<Program<'info, System>>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
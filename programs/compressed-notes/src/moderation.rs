@@ -0,0 +1,285 @@
+//! Moderator takedowns and reports, logging who took a note down and why so anyone holding the
+//! original note can prove what was removed.
+
+use anchor_lang::prelude::*;
+use spl_account_compression::{program::SplAccountCompression, wrap_application_data_v1, Noop};
+
+use anchor_lang::solana_program::keccak;
+
+use crate::{
+    hash_note_leaf_v2, instruction_flags, leaf_domain, leaf_schema, CompressedNotesError,
+    GlobalConfig, NoteId, NoteStatus, Role, RoleKind, TreeConfig,
+};
+
+// Hashes the leaf a takedown leaves in place of `original_leaf` of `owner`.
+// The takedown domain keeps it apart from a note its owner redacted, since only
+// takedowns can be slashed.
+pub fn hash_takedown_leaf(original_leaf: &[u8; 32], owner: &Pubkey) -> [u8; 32] {
+    keccak::hashv(&[
+        &leaf_domain::tag(leaf_domain::TAKEDOWN),
+        original_leaf.as_ref(),
+        owner.as_ref(),
+    ])
+    .to_bytes()
+}
+
+// Flags the leaf `leaf_hash` of `merkle_tree` for moderators, counting the report once per
+// wallet. The leaf is not checked against the tree, a bogus report only costs the reporter rent.
+pub fn process_report_leaf(
+    ctx: Context<ReportLeaf>,
+    merkle_tree: Pubkey, // The tree holding the leaf
+    leaf_hash: [u8; 32], // The leaf node reported
+    reason: u16,         // Why the leaf is reported, as defined by the app
+) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::REPORT_LEAF)?;
+
+    let now = Clock::get()?.unix_timestamp;
+    let report = &mut ctx.accounts.report;
+    if report.count == 0 {
+        report.merkle_tree = merkle_tree;
+        report.leaf_hash = leaf_hash;
+        report.first_reported_at = now;
+        report.bump = *ctx.bumps.get("report").unwrap();
+    }
+    report.count += 1;
+    report.last_reason = reason;
+    report.last_reported_at = now;
+    ctx.accounts.report_receipt.bump = *ctx.bumps.get("report_receipt").unwrap();
+
+    // Log the report so moderation tooling can follow flagged content
+    let report_log = ReportLog::new(
+        merkle_tree,
+        leaf_hash,
+        ctx.accounts.reporter.key(),
+        reason,
+        report.count,
+    );
+    wrap_application_data_v1(report_log.try_to_vec()?, &ctx.accounts.log_wrapper)?;
+    Ok(())
+}
+
+// Replaces a note leaf with its takedown leaf on behalf of the tree's moderators, logging the
+// reason code the app chose. Drafts, tombstoned notes and leaves on legal hold are refused.
+pub fn process_takedown_note<'info>(
+    ctx: Context<'_, '_, '_, 'info, TakedownNote<'info>>,
+    index: u32,         // Index of the note leaf in the merkle tree
+    root: [u8; 32],     // Root the note leaf is proven against
+    payload: String,    // The note message
+    owner: Pubkey,      // The owner committed into the leaf
+    status: NoteStatus, // The current status of the note
+    reason_code: u16,   // Why the note is taken down, as defined by the app
+) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::TAKEDOWN_NOTE)?;
+
+    let original_leaf = hash_note_leaf_v2(&payload, &owner, status);
+    // Only notes that could be redacted can be taken down
+    status.transition(NoteStatus::Redacted)?;
+    let leaf_node = hash_takedown_leaf(&original_leaf, &owner);
+
+    // Log the takedown so indexers drop the content and auditors can verify it
+    let note_id = NoteId::new(ctx.accounts.merkle_tree.key(), index, leaf_schema::V2);
+    let takedown_log = TakedownLog::new(
+        leaf_node,
+        note_id,
+        original_leaf,
+        ctx.accounts.moderator.key(),
+        reason_code,
+        Clock::get()?.slot,
+    );
+    wrap_application_data_v1(takedown_log.try_to_vec()?, &ctx.accounts.log_wrapper)?;
+
+    // Count the replace on the tree config, failing if the tree is frozen or the leaf held
+    TreeConfig::record_replace(
+        &ctx.accounts.tree_config,
+        &ctx.accounts.merkle_tree,
+        &root,
+        &original_leaf,
+        ctx.accounts.hold.as_deref(),
+    )?;
+
+    // CPI to replace the note leaf with its takedown leaf, failing unless it is at `index`
+    tree_cpi!(ctx)
+        .with_proof(ctx.remaining_accounts)
+        .replace(root, original_leaf, leaf_node, index)
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct Report {
+    merkle_tree: Pubkey,    // The tree holding the leaf
+    leaf_hash: [u8; 32],    // The leaf node reported
+    count: u32,             // The number of wallets that reported the leaf
+    last_reason: u16,       // The reason of the latest report, as defined by the app
+    first_reported_at: i64, // The unix timestamp of the first report
+    last_reported_at: i64,  // The unix timestamp of the latest report
+    bump: u8,               // The bump seed for the pda
+}
+
+impl Report {
+    pub const SEED: &'static [u8] = b"report";
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct ReportReceipt {
+    bump: u8, // The bump seed for the pda
+}
+
+impl ReportReceipt {
+    pub const SEED: &'static [u8] = b"report_receipt";
+}
+
+#[derive(AnchorSerialize)]
+pub struct ReportLog {
+    merkle_tree: Pubkey, // The tree holding the leaf
+    leaf_hash: [u8; 32], // The leaf node reported
+    reporter: Pubkey,    // Pubkey of the reporter
+    reason: u16,         // Why the leaf was reported, as defined by the app
+    count: u32,          // The number of wallets that reported the leaf so far
+}
+
+impl ReportLog {
+    // Constructs a new report log from given tree, leaf, reporter, reason and count
+    pub fn new(
+        merkle_tree: Pubkey,
+        leaf_hash: [u8; 32],
+        reporter: Pubkey,
+        reason: u16,
+        count: u32,
+    ) -> Self {
+        Self {
+            merkle_tree,
+            leaf_hash,
+            reporter,
+            reason,
+            count,
+        }
+    }
+}
+
+#[derive(AnchorSerialize)]
+pub struct TakedownLog {
+    leaf_node: [u8; 32],     // The takedown leaf node hash replacing the note
+    note_id: NoteId,         // The id of the leaf
+    original_leaf: [u8; 32], // The leaf node hash of the note taken down
+    moderator: Pubkey,       // Pubkey of the moderator
+    reason_code: u16,        // Why the note was taken down, as defined by the app
+    slot: u64,               // The slot of the takedown
+}
+
+impl TakedownLog {
+    // Constructs a new takedown log from given leaf node, id, original leaf, moderator,
+    // reason and slot
+    pub fn new(
+        leaf_node: [u8; 32],
+        note_id: NoteId,
+        original_leaf: [u8; 32],
+        moderator: Pubkey,
+        reason_code: u16,
+        slot: u64,
+    ) -> Self {
+        Self {
+            leaf_node,
+            note_id,
+            original_leaf,
+            moderator,
+            reason_code,
+            slot,
+        }
+    }
+}
+
+#[derive(Accounts)]
+#[instruction(merkle_tree: Pubkey, leaf_hash: [u8; 32])]
+pub struct ReportLeaf<'info> {
+    // The reporter, pays for the report receipt and the report if it is the first
+    #[account(mut)]
+    pub reporter: Signer<'info>,
+
+    // The report pda of the leaf, created on its first report
+    #[account(
+        init_if_needed,
+        payer = reporter,
+        space = 8 + Report::INIT_SPACE,
+        seeds = [Report::SEED, merkle_tree.as_ref(), leaf_hash.as_ref()],
+        bump,
+    )]
+    pub report: Account<'info, Report>,
+
+    // The report receipt pda, created once per report and reporter so each reports once
+    #[account(
+        init,
+        payer = reporter,
+        space = 8 + ReportReceipt::INIT_SPACE,
+        seeds = [ReportReceipt::SEED, report.key().as_ref(), reporter.key().as_ref()],
+        bump,
+    )]
+    pub report_receipt: Account<'info, ReportReceipt>,
+
+    // The noop program to log data
+    pub log_wrapper: Program<'info, Noop>,
+
+    // The system program, used to create the report and receipt accounts
+    pub system_program: Program<'info, System>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[derive(Accounts)]
+pub struct TakedownNote<'info> {
+    // The moderator taking the note down
+    pub moderator: Signer<'info>,
+
+    // The role of the signer, which must include the moderator role
+    #[account(
+        constraint = TreeConfig::grants_in(
+            &tree_config,
+            Some(&role),
+            &moderator.key(),
+            RoleKind::Moderator,
+        )? @ CompressedNotesError::Unauthorized,
+    )]
+    pub role: Account<'info, Role>,
+
+    // The hold pda of the note leaf, needed to take notes down in a tree with leaves on hold
+    /// CHECK: Checked against the note leaf, which must not be on hold
+    pub hold: Option<UncheckedAccount<'info>>,
+
+    // The tree config pda, counting appends and replaces, which may not have been created
+    /// CHECK: Only read and written if it exists, the seeds tie it to the merkle tree
+    #[account(mut, seeds = [TreeConfig::SEED, merkle_tree.key().as_ref()], bump)]
+    pub tree_config: UncheckedAccount<'info>,
+
+    // The pda authority for the merkle tree, only used for signing
+    #[account(
+        seeds = [merkle_tree.key().as_ref()],
+        bump,
+    )]
+    pub tree_authority: SystemAccount<'info>,
+
+    // The merkle tree account
+    /// CHECK: This account is validated by the spl account compression program
+    #[account(
+        mut,
+        owner = spl_account_compression::ID @ CompressedNotesError::InvalidMerkleTreeOwner,
+    )]
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    // The noop program to log data
+    pub log_wrapper: Program<'info, Noop>,
+
+    // The spl account compression program
+    pub compression_program: Program<'info, SplAccountCompression>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
@@ -0,0 +1,426 @@
+//! M-of-n multisigs holding a tree's authority in a pda at `[b"multisig", merkle_tree]`, acting
+//! on the tree through proposals at `[b"proposal", multisig, index]`.
+
+use anchor_lang::prelude::*;
+use spl_account_compression::Noop;
+
+use crate::{instruction_flags, CompressedNotesError, GlobalConfig, Role, RoleKind, TreeConfig};
+
+// Creates a multisig of `members` for the tree and hands it the tree config authority.
+pub fn process_create_tree_multisig(
+    ctx: Context<CreateTreeMultisig>,
+    members: Vec<Pubkey>, // The keys that can propose and approve actions
+    threshold: u8,        // The number of approvals an action needs to execute
+) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::CREATE_TREE_MULTISIG)?;
+
+    let multisig = &mut ctx.accounts.multisig;
+    multisig.merkle_tree = ctx.accounts.merkle_tree.key();
+    multisig.set_members(members, threshold)?;
+    multisig.bump = *ctx.bumps.get("multisig").unwrap();
+
+    // The multisig holds the authority from now on, logged like any other transfer
+    ctx.accounts.tree_config.apply_action(
+        &ctx.accounts.authority.key(),
+        &TreeAction::TransferAuthority {
+            new_authority: multisig.key(),
+        },
+        &ctx.accounts.log_wrapper,
+    )
+}
+
+// Proposes an action on the tree, approved by the proposing member.
+pub fn process_propose_tree_action(
+    ctx: Context<ProposeTreeAction>,
+    action: TreeAction, // The change to apply to the tree config once approved
+) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::PROPOSE_TREE_ACTION)?;
+
+    let proposer = ctx.accounts.member.key();
+    let multisig = &mut ctx.accounts.multisig;
+    multisig.require_member(&proposer)?;
+    action.validate()?;
+
+    let proposal = &mut ctx.accounts.proposal;
+    proposal.multisig = multisig.key();
+    proposal.index = multisig.proposal_count;
+    proposal.proposer = proposer;
+    proposal.action = action;
+    proposal.approvals = vec![proposer];
+    proposal.executed = false;
+    proposal.bump = *ctx.bumps.get("proposal").unwrap();
+    multisig.proposal_count += 1;
+    Ok(())
+}
+
+// Approves a pending proposal as another member of the multisig.
+pub fn process_approve_tree_action(ctx: Context<ApproveTreeAction>) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::APPROVE_TREE_ACTION)?;
+
+    let member = ctx.accounts.member.key();
+    ctx.accounts.multisig.require_member(&member)?;
+    let proposal = &mut ctx.accounts.proposal;
+    require!(
+        !proposal.executed,
+        CompressedNotesError::ProposalAlreadyExecuted
+    );
+    require!(
+        !proposal.approvals.contains(&member),
+        CompressedNotesError::AlreadyApproved
+    );
+    proposal.approvals.push(member);
+    Ok(())
+}
+
+// Applies a proposal approved by the threshold of members to the tree config, callable by
+// anyone. A proposal executes once, and fails once the multisig handed the authority away.
+pub fn process_execute_tree_action(ctx: Context<ExecuteTreeAction>) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::EXECUTE_TREE_ACTION)?;
+
+    let proposal = &mut ctx.accounts.proposal;
+    require!(
+        !proposal.executed,
+        CompressedNotesError::ProposalAlreadyExecuted
+    );
+    require!(
+        proposal.approvals.len() >= ctx.accounts.multisig.threshold as usize,
+        CompressedNotesError::NotEnoughApprovals
+    );
+    proposal.executed = true;
+
+    ctx.accounts.tree_config.apply_action(
+        &ctx.accounts.multisig.key(),
+        &proposal.action,
+        &ctx.accounts.log_wrapper,
+    )?;
+
+    // The config does not hold roles, role actions write the role pda of their member
+    match proposal.action {
+        TreeAction::GrantRole { member, kind } => {
+            let role = ctx
+                .accounts
+                .role
+                .as_mut()
+                .ok_or(CompressedNotesError::RoleAccountMissing)?;
+            role.set(
+                ctx.accounts.merkle_tree.key(),
+                member,
+                kind,
+                ctx.accounts.multisig.key(),
+                ctx.accounts.tree_config.authority_epoch(),
+                *ctx.bumps.get("role").unwrap(),
+            );
+        }
+        TreeAction::RevokeRole { .. } => {
+            let role = ctx
+                .accounts
+                .role
+                .as_ref()
+                .ok_or(CompressedNotesError::RoleAccountMissing)?;
+            role.close(ctx.accounts.executor.to_account_info())?;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+// A change to a tree config the multisig holding its authority can make
+#[derive(AnchorSerialize, AnchorDeserialize, InitSpace, Clone, Debug, PartialEq, Eq)]
+pub enum TreeAction {
+    Freeze, // Refuse appends and replaces, like freeze_tree
+    Thaw,   // Allow appends and replaces again, like thaw_tree
+    // Replace the metadata, like set_tree_metadata
+    SetMetadata {
+        #[max_len(32)]
+        name: String,
+        #[max_len(256)]
+        description: String,
+        #[max_len(200)]
+        image_uri: String,
+    },
+    // Hand the tree to another authority, like transfer_tree_authority
+    TransferAuthority {
+        new_authority: Pubkey,
+    },
+    // Grant a member a role, like grant_role
+    GrantRole {
+        member: Pubkey,
+        kind: RoleKind,
+    },
+    // Revoke the role of a member, like revoke_role
+    RevokeRole {
+        member: Pubkey,
+    },
+}
+
+impl TreeAction {
+    // The member whose role pda the action writes, none for actions on the config only
+    pub fn role_member(&self) -> Option<Pubkey> {
+        match self {
+            TreeAction::GrantRole { member, .. } | TreeAction::RevokeRole { member } => {
+                Some(*member)
+            }
+            _ => None,
+        }
+    }
+
+    // Fails if the action cannot be applied to any tree config, so it is never proposed
+    fn validate(&self) -> Result<()> {
+        if let TreeAction::SetMetadata {
+            name,
+            description,
+            image_uri,
+        } = self
+        {
+            require!(
+                name.len() <= TreeConfig::MAX_NAME_LEN
+                    && description.len() <= TreeConfig::MAX_DESCRIPTION_LEN
+                    && image_uri.len() <= TreeConfig::MAX_IMAGE_URI_LEN,
+                CompressedNotesError::TreeMetadataTooLong
+            );
+        }
+        Ok(())
+    }
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct TreeMultisig {
+    merkle_tree: Pubkey, // The tree administered
+    #[max_len(10)]
+    members: Vec<Pubkey>, // The keys that can propose and approve actions
+    threshold: u8,       // The number of approvals an action needs to execute
+    proposal_count: u64, // The number of proposals made, the index of the next one
+    bump: u8,            // The bump seed for the pda
+}
+
+impl TreeMultisig {
+    pub const SEED: &'static [u8] = b"multisig";
+    // Must match the `max_len` of `members`
+    pub const MAX_MEMBERS: usize = 10;
+
+    // Fails unless `key` is one of the members
+    pub fn require_member(&self, key: &Pubkey) -> Result<()> {
+        require!(
+            self.members.contains(key),
+            CompressedNotesError::NotMultisigMember
+        );
+        Ok(())
+    }
+
+    // Sets distinct members and a threshold they can reach
+    fn set_members(&mut self, members: Vec<Pubkey>, threshold: u8) -> Result<()> {
+        require!(
+            (1..=TreeMultisig::MAX_MEMBERS).contains(&members.len())
+                && (1..=members.len()).contains(&(threshold as usize)),
+            CompressedNotesError::InvalidMultisig
+        );
+        require!(
+            members
+                .iter()
+                .enumerate()
+                .all(|(i, member)| !members[..i].contains(member)),
+            CompressedNotesError::InvalidMultisig
+        );
+        self.members = members;
+        self.threshold = threshold;
+        Ok(())
+    }
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct TreeProposal {
+    multisig: Pubkey,   // The multisig the proposal is made to
+    index: u64,         // Index of the proposal in the multisig
+    proposer: Pubkey,   // The member who made the proposal
+    action: TreeAction, // The change applied to the tree config once approved
+    #[max_len(10)]
+    approvals: Vec<Pubkey>, // The members who approved, the proposer first
+    executed: bool,     // Whether the action was applied
+    bump: u8,           // The bump seed for the pda
+}
+
+impl TreeProposal {
+    pub const SEED: &'static [u8] = b"proposal";
+}
+
+#[derive(Accounts)]
+pub struct CreateTreeMultisig<'info> {
+    // The current authority of the tree, also pays for the multisig account
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    // The multisig pda, one per tree
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + TreeMultisig::INIT_SPACE,
+        seeds = [TreeMultisig::SEED, merkle_tree.key().as_ref()],
+        bump,
+    )]
+    pub multisig: Account<'info, TreeMultisig>,
+
+    // The tree config pda, handed to the multisig
+    #[account(mut, seeds = [TreeConfig::SEED, merkle_tree.key().as_ref()], bump)]
+    pub tree_config: Account<'info, TreeConfig>,
+
+    // The merkle tree account
+    /// CHECK: Only used to derive the multisig and tree config pdas
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    // The noop program to log data
+    pub log_wrapper: Program<'info, Noop>,
+
+    // The system program, used to create the multisig account
+    pub system_program: Program<'info, System>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[derive(Accounts)]
+pub struct ProposeTreeAction<'info> {
+    // A member of the multisig, also pays for the proposal account
+    #[account(mut)]
+    pub member: Signer<'info>,
+
+    // The multisig pda
+    #[account(
+        mut,
+        seeds = [TreeMultisig::SEED, multisig.merkle_tree.as_ref()],
+        bump = multisig.bump,
+    )]
+    pub multisig: Account<'info, TreeMultisig>,
+
+    // The proposal pda, at the next index of the multisig
+    #[account(
+        init,
+        payer = member,
+        space = 8 + TreeProposal::INIT_SPACE,
+        seeds = [
+            TreeProposal::SEED,
+            multisig.key().as_ref(),
+            &multisig.proposal_count.to_le_bytes(),
+        ],
+        bump,
+    )]
+    pub proposal: Account<'info, TreeProposal>,
+
+    // The system program, used to create the proposal account
+    pub system_program: Program<'info, System>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[derive(Accounts)]
+pub struct ApproveTreeAction<'info> {
+    // A member of the multisig who did not approve yet
+    pub member: Signer<'info>,
+
+    // The multisig pda
+    #[account(
+        seeds = [TreeMultisig::SEED, multisig.merkle_tree.as_ref()],
+        bump = multisig.bump,
+    )]
+    pub multisig: Account<'info, TreeMultisig>,
+
+    // The proposal pda
+    #[account(
+        mut,
+        seeds = [
+            TreeProposal::SEED,
+            multisig.key().as_ref(),
+            &proposal.index.to_le_bytes(),
+        ],
+        bump = proposal.bump,
+        has_one = multisig,
+    )]
+    pub proposal: Account<'info, TreeProposal>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[derive(Accounts)]
+pub struct ExecuteTreeAction<'info> {
+    // Anyone, pays for or is refunded the config resized by a metadata change
+    #[account(mut)]
+    pub executor: Signer<'info>,
+
+    // The multisig pda holding the authority of the tree
+    #[account(
+        seeds = [TreeMultisig::SEED, merkle_tree.key().as_ref()],
+        bump = multisig.bump,
+        has_one = merkle_tree,
+    )]
+    pub multisig: Account<'info, TreeMultisig>,
+
+    // The proposal pda, marked executed
+    #[account(
+        mut,
+        seeds = [
+            TreeProposal::SEED,
+            multisig.key().as_ref(),
+            &proposal.index.to_le_bytes(),
+        ],
+        bump = proposal.bump,
+        has_one = multisig,
+    )]
+    pub proposal: Account<'info, TreeProposal>,
+
+    // The tree config pda, resized to fit the action
+    #[account(
+        mut,
+        seeds = [TreeConfig::SEED, merkle_tree.key().as_ref()],
+        bump,
+        realloc = tree_config.space_for(&proposal.action),
+        realloc::payer = executor,
+        realloc::zero = false,
+    )]
+    pub tree_config: Account<'info, TreeConfig>,
+
+    // The role pda of the member of a role action, created by grants and closed by revocations
+    #[account(
+        init_if_needed,
+        payer = executor,
+        space = 8 + Role::INIT_SPACE,
+        seeds = [
+            Role::SEED,
+            merkle_tree.key().as_ref(),
+            proposal.action.role_member().unwrap_or_default().as_ref(),
+        ],
+        bump,
+    )]
+    pub role: Option<Account<'info, Role>>,
+
+    // The merkle tree account
+    /// CHECK: Only used to derive the multisig, tree config and role pdas
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    // The noop program to log data
+    pub log_wrapper: Program<'info, Noop>,
+
+    // The system program, used to pay for the resized config and role accounts
+    pub system_program: Program<'info, System>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
@@ -0,0 +1,51 @@
+//! Nft-gated trees, restricting posting to holders of an nft of a verified Metaplex collection.
+
+use anchor_lang::prelude::*;
+use anchor_spl::metadata::MetadataAccount;
+use anchor_spl::token::TokenAccount;
+
+use crate::{instruction_flags, CompressedNotesError, TreeConfigAuthority};
+
+// Requires posters to a tree to hold an nft of `collection`, or lets anyone post again if none,
+// lifting any token gate. Appends then take the `gate_token_account` and `gate_metadata`.
+pub fn process_set_nft_gate(
+    ctx: Context<TreeConfigAuthority>,
+    collection: Option<Pubkey>, // The collection posters must hold an nft of, none for no gate
+) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::SET_NFT_GATE)?;
+
+    ctx.accounts.tree_config.set_nft_gate(collection);
+    Ok(())
+}
+
+// Fails unless `gate_token_account` is a token account of `poster` holding an nft
+// whose metadata `gate_metadata` names `collection` as verified
+pub fn require_collection_holder(
+    collection: &Pubkey,
+    poster: &Pubkey,
+    gate_token_account: Option<&Account<TokenAccount>>,
+    gate_metadata: Option<&Account<MetadataAccount>>,
+) -> Result<()> {
+    let (Some(gate_token_account), Some(gate_metadata)) = (gate_token_account, gate_metadata)
+    else {
+        return err!(CompressedNotesError::GateMetadataMissing);
+    };
+    require!(
+        gate_token_account.owner == *poster && gate_token_account.mint == gate_metadata.mint,
+        CompressedNotesError::InvalidGateTokenAccount
+    );
+    require!(
+        gate_token_account.amount >= 1,
+        CompressedNotesError::BelowGateBalance
+    );
+    // Only the collection authority can verify an nft into the collection
+    match &gate_metadata.collection {
+        Some(nft_collection) if nft_collection.verified && nft_collection.key == *collection => {
+            Ok(())
+        }
+        _ => err!(CompressedNotesError::NotInGateCollection),
+    }
+}
@@ -0,0 +1,123 @@
+//! Canonical note identifiers, naming a leaf by its tree, index and leaf schema.
+
+use anchor_lang::prelude::*;
+
+use crate::TreeState;
+
+// Leaf schema versions. The hashing of every schema is pinned by `test_vectors`.
+// `tag` is the two byte domain of the schema, see `leaf_domain`.
+pub mod leaf_schema {
+    // keccak256(content || owner), used by broadcasts and messages sent before V5
+    pub const V1: u16 = 1;
+    // keccak256(content || owner || status), used by notes
+    pub const V2: u16 = 2;
+    // keccak256(tag || subject || claims_hash || expiry), used by certificates
+    pub const V3: u16 = 3;
+    // keccak256(tag || keccak256(content) || organization || posted_at), used by org notes
+    pub const V4: u16 = 4;
+    // keccak256(tag || keccak256(content) || sender || recipient || nonce || edit_count
+    // || priority || parent), used by direct messages
+    pub const V5: u16 = 5;
+    // keccak256(tag || original_leaf || acknowledger), used by message acknowledgements
+    pub const V6: u16 = 6;
+    // keccak256(tag || keccak256(content) || status || owners sorted), used by shared notes
+    pub const V7: u16 = 7;
+    // keccak256(tag || keccak256(content) || owner || status || eth_address), used by notes
+    // attributed to the owner's linked ethereum address
+    pub const V8: u16 = 8;
+    // keccak256(tag || keccak256(content) || sender || expires_at), used by ttl messages
+    pub const V9: u16 = 9;
+    // keccak256(tag || keccak256(content) || sender || edit_count || parent || priority), used
+    // by edited messages
+    pub const V10: u16 = 10;
+    // keccak256(tag || keccak256(ciphertext) || sender || nonce || sender_ephemeral_pubkey),
+    // used by encrypted messages
+    pub const V11: u16 = 11;
+    // keccak256(tag || keccak256(content) || sender || original_leaf || original_sender), used
+    // by forwarded messages
+    pub const V12: u16 = 12;
+    // keccak256(tag || keccak256(content) || sender || recipients), used by group messages
+    pub const V13: u16 = 13;
+}
+
+// Domains of the leaf layouts. Every layout but the legacy V1 and V2 ones hashes
+// `tag(kind)` first, a distinct kind per layout, so no two layouts share a preimage and
+// no instruction can pass a leaf of one layout off as another. `PREFIX` never occurs in
+// UTF-8 content, so a legacy preimage only starts with it when its content is empty, and
+// then it is the owner followed by at most a parent leaf and a priority, 32, 33, 64 or 65
+// bytes, lengths no tagged preimage has.
+pub mod leaf_domain {
+    pub const PREFIX: u8 = 0xff;
+
+    pub const CERTIFICATE: u8 = 3;
+    pub const ORG_NOTE: u8 = 4;
+    pub const DIRECT_MESSAGE: u8 = 5;
+    pub const ACKNOWLEDGEMENT: u8 = 6;
+    pub const SHARED_NOTE: u8 = 7;
+    pub const ATTRIBUTED_NOTE: u8 = 8;
+    pub const TTL_MESSAGE: u8 = 9;
+    pub const EDITED_MESSAGE: u8 = 10;
+    pub const ENCRYPTED_MESSAGE: u8 = 11;
+    pub const FORWARDED_MESSAGE: u8 = 12;
+    pub const GROUP_MESSAGE: u8 = 13;
+    pub const REVOKED_CERTIFICATE: u8 = 14;
+    pub const TAKEDOWN: u8 = 15;
+
+    // The bytes a leaf of the `kind` layout hashes first
+    pub const fn tag(kind: u8) -> [u8; 2] {
+        [PREFIX, kind]
+    }
+}
+
+// Serialized as `merkle_tree (32) || leaf_index (u32 le) || schema_version (u16 le)`, the same
+// 38 bytes as its borsh encoding, whose base58 form is used in links and QR codes
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NoteId {
+    pub merkle_tree: Pubkey, // The tree holding the leaf
+    pub leaf_index: u32,     // Index of the leaf in the merkle tree
+    pub schema_version: u16, // The leaf schema used to hash the leaf
+}
+
+impl NoteId {
+    // Length of the serialized id in bytes
+    pub const LEN: usize = 32 + 4 + 2;
+
+    pub fn new(merkle_tree: Pubkey, leaf_index: u32, schema_version: u16) -> Self {
+        Self {
+            merkle_tree,
+            leaf_index,
+            schema_version,
+        }
+    }
+
+    // Serializes the id into its canonical bytes
+    pub fn to_bytes(&self) -> [u8; NoteId::LEN] {
+        let mut bytes = [0; NoteId::LEN];
+        bytes[..32].copy_from_slice(self.merkle_tree.as_ref());
+        bytes[32..36].copy_from_slice(&self.leaf_index.to_le_bytes());
+        bytes[36..].copy_from_slice(&self.schema_version.to_le_bytes());
+        bytes
+    }
+
+    // Parses an id from its canonical bytes
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != NoteId::LEN {
+            return None;
+        }
+        Some(Self {
+            merkle_tree: Pubkey::new_from_array(bytes[..32].try_into().ok()?),
+            leaf_index: u32::from_le_bytes(bytes[32..36].try_into().ok()?),
+            schema_version: u16::from_le_bytes(bytes[36..].try_into().ok()?),
+        })
+    }
+
+    // The id the next leaf appended to `merkle_tree` will get
+    pub fn next(merkle_tree: &AccountInfo, schema_version: u16) -> Result<Self> {
+        let tree_state = TreeState::load(merkle_tree)?;
+        Ok(Self::new(
+            merkle_tree.key(),
+            tree_state.next_index,
+            schema_version,
+        ))
+    }
+}
@@ -0,0 +1,471 @@
+//! Offers on notes, escrowing lamports in the offer pda until a deadline, and two-step note
+//! transfers. Compiled in with the `offers` feature.
+
+use anchor_lang::prelude::*;
+use anchor_lang::system_program::{transfer, Transfer};
+use spl_account_compression::{program::SplAccountCompression, wrap_application_data_v1, Noop};
+
+use crate::{
+    hash_note_leaf_v2, instruction_flags, leaf_schema, resolve_note_owner, CompressedNotesError,
+    DidDocument, GlobalConfig, NoteId, NoteLog, NoteStatus, TreeConfig,
+};
+
+// Escrows `amount` lamports as an offer on the note at `index`, valid until `expiry`.
+pub fn process_make_offer(
+    ctx: Context<MakeOffer>,
+    index: u32,  // Index of the note leaf in the merkle tree
+    amount: u64, // Lamports offered for the note
+    expiry: i64, // Unix timestamp after which the offer can no longer be accepted
+) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::MAKE_OFFER)?;
+    require!(
+        expiry > Clock::get()?.unix_timestamp,
+        CompressedNotesError::OfferExpired
+    );
+
+    let offer = &mut ctx.accounts.offer;
+    offer.buyer = ctx.accounts.buyer.key();
+    offer.merkle_tree = ctx.accounts.merkle_tree.key();
+    offer.index = index;
+    offer.amount = amount;
+    offer.expiry = expiry;
+    offer.bump = *ctx.bumps.get("offer").unwrap();
+
+    // Move the offered lamports into the offer pda
+    let cpi_ctx = CpiContext::new(
+        ctx.accounts.system_program.to_account_info(),
+        Transfer {
+            from: ctx.accounts.buyer.to_account_info(),
+            to: ctx.accounts.offer.to_account_info(),
+        },
+    );
+    transfer(cpi_ctx, amount)
+}
+
+// Hands the note over to the buyer and pays the escrowed lamports to the owner.
+pub fn process_accept_offer<'info>(
+    ctx: Context<'_, '_, '_, 'info, AcceptOffer<'info>>,
+    root: [u8; 32],     // Root the note leaf is proven against
+    note: String,       // The note message
+    status: NoteStatus, // The current status of the note
+) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::ACCEPT_OFFER)?;
+
+    let offer = &ctx.accounts.offer;
+    require!(
+        Clock::get()?.unix_timestamp < offer.expiry,
+        CompressedNotesError::OfferExpired
+    );
+
+    // The old leaf only verifies if the signer, or the DID document it controls, owns the note
+    let owner = resolve_note_owner(&ctx.accounts.owner.key(), &ctx.accounts.did)?;
+    let old_leaf = hash_note_leaf_v2(&note, &owner, status);
+    let status = status.transfer()?;
+    let new_leaf = hash_note_leaf_v2(&note, &offer.buyer, status);
+
+    // Log out for indexers
+    let note_id = NoteId::new(offer.merkle_tree, offer.index, leaf_schema::V2);
+    let note_log = NoteLog::new(new_leaf, note_id, offer.buyer, note, status);
+    // Log the "note log" data using noop program
+    wrap_application_data_v1(note_log.try_to_vec()?, &ctx.accounts.log_wrapper)?;
+
+    // Count the replace on the tree config, failing if the tree is frozen, the root is older
+    // than its recent roots or the leaf is on hold
+    TreeConfig::record_replace(
+        &ctx.accounts.tree_config,
+        &ctx.accounts.merkle_tree,
+        &root,
+        &old_leaf,
+        ctx.accounts.hold.as_deref(),
+    )?;
+    // CPI to replace the owner's leaf node with the buyer's
+    tree_cpi!(ctx).with_proof(ctx.remaining_accounts).replace(
+        root,
+        old_leaf,
+        new_leaf,
+        offer.index,
+    )?;
+
+    // Pay the escrow to the owner, the rent goes back to the buyer when the offer closes
+    let amount = offer.amount;
+    **ctx
+        .accounts
+        .offer
+        .to_account_info()
+        .try_borrow_mut_lamports()? -= amount;
+    **ctx.accounts.owner.try_borrow_mut_lamports()? += amount;
+    Ok(())
+}
+
+// Offers the note at `index` to `new_owner`, who takes it over by accepting.
+pub fn process_offer_note_transfer(
+    ctx: Context<OfferNoteTransfer>,
+    index: u32,         // Index of the note leaf in the merkle tree
+    new_owner: Pubkey,  // The owner the note is offered to
+    note_owner: Pubkey, // The owner the note leaf commits to, the signer or its DID document
+) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::OFFER_NOTE_TRANSFER)?;
+
+    // The leaf is only verified on acceptance, the transfer offer just records who may accept
+    let owner = resolve_note_owner(&ctx.accounts.signer.key(), &ctx.accounts.did)?;
+    require_keys_eq!(owner, note_owner, CompressedNotesError::Unauthorized);
+
+    let transfer_offer = &mut ctx.accounts.transfer_offer;
+    transfer_offer.owner = owner;
+    transfer_offer.offered_by = ctx.accounts.signer.key();
+    transfer_offer.new_owner = new_owner;
+    transfer_offer.merkle_tree = ctx.accounts.merkle_tree.key();
+    transfer_offer.index = index;
+    transfer_offer.bump = *ctx.bumps.get("transfer_offer").unwrap();
+    Ok(())
+}
+
+// Hands the note over to the new owner of a transfer offer, who signs for it.
+pub fn process_accept_note_transfer<'info>(
+    ctx: Context<'_, '_, '_, 'info, AcceptNoteTransfer<'info>>,
+    root: [u8; 32],     // Root the note leaf is proven against
+    note: String,       // The note message
+    status: NoteStatus, // The current status of the note
+) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::ACCEPT_NOTE_TRANSFER)?;
+
+    // The old leaf only verifies if the note is still held by the owner who offered it
+    let transfer_offer = &ctx.accounts.transfer_offer;
+    let old_leaf = hash_note_leaf_v2(&note, &transfer_offer.owner, status);
+    let status = status.transfer()?;
+    let new_leaf = hash_note_leaf_v2(&note, &transfer_offer.new_owner, status);
+
+    // Log out for indexers
+    let note_id = NoteId::new(
+        transfer_offer.merkle_tree,
+        transfer_offer.index,
+        leaf_schema::V2,
+    );
+    let note_log = NoteLog::new(new_leaf, note_id, transfer_offer.new_owner, note, status);
+    // Log the "note log" data using noop program
+    wrap_application_data_v1(note_log.try_to_vec()?, &ctx.accounts.log_wrapper)?;
+
+    // Count the replace on the tree config, failing if the tree is frozen, the root is older
+    // than its recent roots or the leaf is on hold
+    TreeConfig::record_replace(
+        &ctx.accounts.tree_config,
+        &ctx.accounts.merkle_tree,
+        &root,
+        &old_leaf,
+        ctx.accounts.hold.as_deref(),
+    )?;
+    // CPI to replace the owner's leaf node with the new owner's
+    tree_cpi!(ctx).with_proof(ctx.remaining_accounts).replace(
+        root,
+        old_leaf,
+        new_leaf,
+        transfer_offer.index,
+    )
+}
+
+// Withdraws a transfer offer, refunding the rent to the signer who offered it.
+pub fn process_cancel_note_transfer(ctx: Context<CancelNoteTransfer>) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::CANCEL_NOTE_TRANSFER)?;
+    Ok(())
+}
+
+// Withdraws an offer, refunding the escrow and rent to the buyer.
+pub fn process_cancel_offer(ctx: Context<CancelOffer>) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::CANCEL_OFFER)?;
+    Ok(())
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct Offer {
+    buyer: Pubkey,       // The buyer who escrowed the offer
+    merkle_tree: Pubkey, // The tree holding the note
+    index: u32,          // Index of the note leaf in the merkle tree
+    amount: u64,         // Lamports escrowed on top of the rent
+    expiry: i64,         // Unix timestamp after which the offer can no longer be accepted
+    bump: u8,            // The bump seed for the pda
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct TransferOffer {
+    owner: Pubkey, // The owner the note leaf commits to, the signer or its DID document
+    offered_by: Pubkey, // The signer who offered the note, refunded the rent
+    new_owner: Pubkey, // The owner the note is offered to
+    merkle_tree: Pubkey, // The tree holding the note
+    index: u32,    // Index of the note leaf in the merkle tree
+    bump: u8,      // The bump seed for the pda
+}
+
+impl TransferOffer {
+    pub const SEED: &'static [u8] = b"transfer_offer";
+}
+
+#[derive(Accounts)]
+#[instruction(index: u32)]
+pub struct MakeOffer<'info> {
+    // The buyer, pays the escrow and the offer account
+    #[account(mut)]
+    pub buyer: Signer<'info>,
+
+    // The merkle tree holding the note
+    /// CHECK: Only used as a seed, the leaf is verified when the offer is accepted
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    // The offer pda, one per buyer and note
+    #[account(
+        init,
+        payer = buyer,
+        space = 8 + Offer::INIT_SPACE,
+        seeds = [
+            b"offer",
+            merkle_tree.key().as_ref(),
+            &index.to_le_bytes(),
+            buyer.key().as_ref(),
+        ],
+        bump,
+    )]
+    pub offer: Account<'info, Offer>,
+
+    // The system program, used to create the offer account and escrow the lamports
+    pub system_program: Program<'info, System>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptOffer<'info> {
+    // The owner of the note, receives the escrowed lamports
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    // The DID document owning the note, if any, the owner must be one of its controllers
+    pub did: Option<Account<'info, DidDocument>>,
+
+    // The buyer, receives the note and the offer rent
+    /// CHECK: Checked against the offer buyer
+    #[account(mut)]
+    pub buyer: UncheckedAccount<'info>,
+
+    // The offer pda, closed by this instruction
+    #[account(
+        mut,
+        close = buyer,
+        seeds = [
+            b"offer",
+            merkle_tree.key().as_ref(),
+            &offer.index.to_le_bytes(),
+            buyer.key().as_ref(),
+        ],
+        bump = offer.bump,
+        has_one = buyer,
+        has_one = merkle_tree,
+    )]
+    pub offer: Account<'info, Offer>,
+
+    // The hold pda of the leaf replaced, needed to replace leaves in a tree with leaves on hold
+    /// CHECK: Checked against the leaf replaced, which must not be on hold
+    pub hold: Option<UncheckedAccount<'info>>,
+
+    // The tree config pda, counting appends and replaces, which may not have been created
+    /// CHECK: Only read and written if it exists, the seeds tie it to the merkle tree
+    #[account(mut, seeds = [TreeConfig::SEED, merkle_tree.key().as_ref()], bump)]
+    pub tree_config: UncheckedAccount<'info>,
+
+    // The pda authority for the merkle tree, only used for signing
+    #[account(
+        seeds = [merkle_tree.key().as_ref()],
+        bump,
+    )]
+    pub tree_authority: SystemAccount<'info>,
+
+    // The merkle tree account
+    /// CHECK: This account is validated by the spl account compression program
+    #[account(
+        mut,
+        owner = spl_account_compression::ID @ CompressedNotesError::InvalidMerkleTreeOwner,
+    )]
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    // The noop program to log data
+    pub log_wrapper: Program<'info, Noop>,
+
+    // The spl account compression program
+    pub compression_program: Program<'info, SplAccountCompression>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[derive(Accounts)]
+pub struct CancelOffer<'info> {
+    // The buyer, receives the escrow and the offer rent
+    #[account(mut)]
+    pub buyer: Signer<'info>,
+
+    // The offer pda, closed by this instruction
+    #[account(
+        mut,
+        close = buyer,
+        seeds = [
+            b"offer",
+            offer.merkle_tree.as_ref(),
+            &offer.index.to_le_bytes(),
+            buyer.key().as_ref(),
+        ],
+        bump = offer.bump,
+        has_one = buyer @ CompressedNotesError::Unauthorized,
+    )]
+    pub offer: Account<'info, Offer>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[derive(Accounts)]
+#[instruction(index: u32, new_owner: Pubkey, note_owner: Pubkey)]
+pub struct OfferNoteTransfer<'info> {
+    // The owner of the note, or a controller of the DID document owning it, pays for the
+    // transfer offer account
+    #[account(mut)]
+    pub signer: Signer<'info>,
+
+    // The DID document owning the note, if any, the signer must be one of its controllers
+    pub did: Option<Account<'info, DidDocument>>,
+
+    // The merkle tree holding the note
+    /// CHECK: Only used as a seed, the leaf is verified when the transfer is accepted
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    // The transfer offer pda, one per note and owner
+    #[account(
+        init,
+        payer = signer,
+        space = 8 + TransferOffer::INIT_SPACE,
+        seeds = [
+            TransferOffer::SEED,
+            merkle_tree.key().as_ref(),
+            &index.to_le_bytes(),
+            note_owner.as_ref(),
+        ],
+        bump,
+    )]
+    pub transfer_offer: Account<'info, TransferOffer>,
+
+    // The system program, used to create the transfer offer account
+    pub system_program: Program<'info, System>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptNoteTransfer<'info> {
+    // The new owner the note was offered to
+    pub new_owner: Signer<'info>,
+
+    // The signer who offered the note, refunded the transfer offer rent
+    /// CHECK: Checked against the transfer offer
+    #[account(mut)]
+    pub offered_by: UncheckedAccount<'info>,
+
+    // The transfer offer pda, closed by this instruction
+    #[account(
+        mut,
+        close = offered_by,
+        seeds = [
+            TransferOffer::SEED,
+            merkle_tree.key().as_ref(),
+            &transfer_offer.index.to_le_bytes(),
+            transfer_offer.owner.as_ref(),
+        ],
+        bump = transfer_offer.bump,
+        has_one = new_owner @ CompressedNotesError::Unauthorized,
+        has_one = offered_by,
+        has_one = merkle_tree,
+    )]
+    pub transfer_offer: Account<'info, TransferOffer>,
+
+    // The hold pda of the leaf replaced, needed to replace leaves in a tree with leaves on hold
+    /// CHECK: Checked against the leaf replaced, which must not be on hold
+    pub hold: Option<UncheckedAccount<'info>>,
+
+    // The tree config pda, counting appends and replaces, which may not have been created
+    /// CHECK: Only read and written if it exists, the seeds tie it to the merkle tree
+    #[account(mut, seeds = [TreeConfig::SEED, merkle_tree.key().as_ref()], bump)]
+    pub tree_config: UncheckedAccount<'info>,
+
+    // The pda authority for the merkle tree, only used for signing
+    #[account(
+        seeds = [merkle_tree.key().as_ref()],
+        bump,
+    )]
+    pub tree_authority: SystemAccount<'info>,
+
+    // The merkle tree account
+    /// CHECK: This account is validated by the spl account compression program
+    #[account(
+        mut,
+        owner = spl_account_compression::ID @ CompressedNotesError::InvalidMerkleTreeOwner,
+    )]
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    // The noop program to log data
+    pub log_wrapper: Program<'info, Noop>,
+
+    // The spl account compression program
+    pub compression_program: Program<'info, SplAccountCompression>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[derive(Accounts)]
+pub struct CancelNoteTransfer<'info> {
+    // The signer who offered the note, refunded the transfer offer rent
+    #[account(mut)]
+    pub offered_by: Signer<'info>,
+
+    // The transfer offer pda, closed by this instruction
+    #[account(
+        mut,
+        close = offered_by,
+        seeds = [
+            TransferOffer::SEED,
+            transfer_offer.merkle_tree.as_ref(),
+            &transfer_offer.index.to_le_bytes(),
+            transfer_offer.owner.as_ref(),
+        ],
+        bump = transfer_offer.bump,
+        has_one = offered_by @ CompressedNotesError::Unauthorized,
+    )]
+    pub transfer_offer: Account<'info, TransferOffer>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
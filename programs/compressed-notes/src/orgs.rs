@@ -0,0 +1,734 @@
+//! Organizations whose seated members post notes owned by the organization pda, metered per
+//! period for billing. Compiled in with the `orgs` feature.
+
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::keccak;
+use anchor_spl::metadata::MetadataAccount;
+use anchor_spl::token::{Token, TokenAccount};
+use spl_account_compression::{program::SplAccountCompression, wrap_application_data_v1, Noop};
+
+use crate::{
+    instruction_flags, leaf_domain, leaf_schema, pay_treasury, require_clean_text,
+    CompressedNotesError, FeeOperation, GlobalConfig, NoteId, Role, TreeConfig, TreeSubscription,
+    TREASURY_SEED, TREE_TREASURY_SEED,
+};
+
+// Hashes an org note, its organization and the time it was posted into the leaf node.
+// The org note domain keeps the leaf from passing for another layout, since anyone can
+// prune an org note once its retention ends.
+pub fn hash_org_note_leaf(note: &str, organization: &Pubkey, posted_at: i64) -> [u8; 32] {
+    let note_hash = keccak::hash(note.as_bytes());
+    keccak::hashv(&[
+        &leaf_domain::tag(leaf_domain::ORG_NOTE),
+        note_hash.as_ref(),
+        organization.as_ref(),
+        &posted_at.to_le_bytes(),
+    ])
+    .to_bytes()
+}
+
+// Creates an organization owned by the signer, with no seats yet.
+pub fn process_create_organization(ctx: Context<CreateOrganization>, org_id: u64) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::CREATE_ORGANIZATION)?;
+
+    let organization = &mut ctx.accounts.organization;
+    organization.owner = ctx.accounts.owner.key();
+    organization.org_id = org_id;
+    organization.retention_tier = RetentionTier::Forever;
+    organization.bump = *ctx.bumps.get("organization").unwrap();
+    Ok(())
+}
+
+// Buys `count` more seats, paying the seat price for each into the protocol treasury.
+pub fn process_purchase_seats(ctx: Context<PurchaseSeats>, count: u32) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::PURCHASE_SEATS)?;
+
+    pay_treasury(
+        &ctx.accounts.system_program,
+        &ctx.accounts.owner,
+        &ctx.accounts.treasury,
+        count as u64 * Organization::SEAT_PRICE_LAMPORTS,
+    )?;
+    ctx.accounts.organization.seat_limit += count;
+    Ok(())
+}
+
+// Assigns one of the organization's free seats to `member`.
+pub fn process_add_seat(ctx: Context<AddSeat>, member: Pubkey) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::ADD_SEAT)?;
+
+    let organization = &mut ctx.accounts.organization;
+    require!(
+        organization.seat_count < organization.seat_limit,
+        CompressedNotesError::NoSeatsAvailable
+    );
+    organization.seat_count += 1;
+
+    let seat = &mut ctx.accounts.seat;
+    seat.organization = organization.key();
+    seat.member = member;
+    seat.bump = *ctx.bumps.get("seat").unwrap();
+    Ok(())
+}
+
+// Frees a member's seat, refunding the seat rent to the owner.
+pub fn process_remove_seat(ctx: Context<RemoveSeat>) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::REMOVE_SEAT)?;
+
+    ctx.accounts.organization.seat_count -= 1;
+    Ok(())
+}
+
+// Changes how long the organization's notes are kept before they can be pruned.
+pub fn process_set_retention_tier(
+    ctx: Context<SetRetentionTier>,
+    retention_tier: RetentionTier,
+) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::SET_RETENTION_TIER)?;
+
+    let organization = &mut ctx.accounts.organization;
+    let retention_log = RetentionTierLog::new(
+        organization.key(),
+        organization.retention_tier,
+        retention_tier,
+        Clock::get()?.slot,
+    );
+    organization.retention_tier = retention_tier;
+
+    // Log the tier change so indexers know which notes will be pruned
+    wrap_application_data_v1(retention_log.try_to_vec()?, &ctx.accounts.log_wrapper)?;
+    Ok(())
+}
+
+// Appends a note owned by the organization, posted by a seated member.
+pub fn process_append_org_note(ctx: Context<OrgNoteAccounts>, note: String) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::APPEND_ORG_NOTE)?;
+    // Refuse text that indexers would display deceptively
+    require_clean_text(&note)?;
+
+    // Meter the note against the organization's usage for the current period
+    let now = Clock::get()?.unix_timestamp;
+    ctx.accounts.org_usage.record(now, note.len())?;
+
+    // A tree charging an append fee takes it from the member into the tree treasury
+    TreeConfig::record_fee(
+        &ctx.accounts.tree_config,
+        &ctx.accounts.member,
+        ctx.accounts.tree_treasury.as_ref(),
+        ctx.accounts.system_program.as_ref(),
+        FeeOperation::Append(note.len()),
+    )?;
+    // A tree charging a token append fee takes it from the member into its fee vault
+    TreeConfig::record_token_fee(
+        &ctx.accounts.tree_config,
+        &ctx.accounts.member,
+        ctx.accounts.payer_token_account.as_ref(),
+        ctx.accounts.fee_vault.as_ref(),
+        ctx.accounts.token_program.as_ref(),
+    )?;
+    // A token- or nft-gated tree only accepts members holding its gate
+    TreeConfig::require_gate(
+        &ctx.accounts.tree_config,
+        &ctx.accounts.member.key(),
+        ctx.accounts.gate_token_account.as_ref(),
+        ctx.accounts.gate_metadata.as_ref(),
+    )?;
+    // A tree selling access only accepts posters with an active subscription
+    TreeConfig::require_subscription(
+        &ctx.accounts.tree_config,
+        &ctx.accounts.member.key(),
+        ctx.accounts.subscription.as_deref(),
+    )?;
+
+    let organization = ctx.accounts.organization.key();
+    // Hash the note, committing to the organization as its owner and to the posting time
+    let leaf_node = hash_org_note_leaf(&note, &organization, now);
+    // Create a new "org note log" using the leaf node hash, the id the leaf will get and note.
+    let note_id = NoteId::next(&ctx.accounts.merkle_tree, leaf_schema::V4)?;
+    let org_note_log = OrgNoteLog::new(
+        leaf_node,
+        note_id,
+        organization,
+        ctx.accounts.member.key(),
+        note,
+        now,
+    );
+    // Log the "org note log" data using noop program
+    wrap_application_data_v1(org_note_log.try_to_vec()?, &ctx.accounts.log_wrapper)?;
+
+    // Count the append on the tree config, failing if the tree is frozen or private
+    TreeConfig::record_append(
+        &ctx.accounts.tree_config,
+        &ctx.accounts.merkle_tree,
+        &ctx.accounts.member.key(),
+        ctx.accounts.role.as_deref(),
+    )?;
+    // CPI to append the leaf node to the merkle tree
+    tree_cpi!(ctx).append(leaf_node)?;
+    Ok(())
+}
+
+// Tombstones an org note older than the organization's retention tier, callable by anyone.
+pub fn process_prune_org_note<'info>(
+    ctx: Context<'_, '_, '_, 'info, PruneOrgNote<'info>>,
+    root: [u8; 32], // Root the note leaf is proven against
+    note: String,   // The note
+    posted_at: i64, // The posting time committed into the leaf
+    index: u32,     // Index of the note leaf in the merkle tree
+) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::PRUNE_ORG_NOTE)?;
+
+    // The tier is read at prune time, so lowering it also applies to older notes
+    let retention_seconds = ctx.accounts.organization.retention_tier.retention_seconds();
+    let now = Clock::get()?.unix_timestamp;
+    require!(
+        retention_seconds.is_some_and(|seconds| now >= posted_at + seconds),
+        CompressedNotesError::RetentionNotElapsed
+    );
+
+    let leaf_node = hash_org_note_leaf(&note, &ctx.accounts.organization.key(), posted_at);
+
+    // Log the prune so indexers drop the note content
+    let note_id = NoteId::new(ctx.accounts.merkle_tree.key(), index, leaf_schema::V4);
+    let prune_log = OrgNotePruneLog::new(leaf_node, note_id);
+    wrap_application_data_v1(prune_log.try_to_vec()?, &ctx.accounts.log_wrapper)?;
+
+    // Count the replace on the tree config, failing if the tree is frozen, the root is older
+    // than its recent roots or the leaf is on hold
+    TreeConfig::record_replace(
+        &ctx.accounts.tree_config,
+        &ctx.accounts.merkle_tree,
+        &root,
+        &leaf_node,
+        ctx.accounts.hold.as_deref(),
+    )?;
+    // CPI to replace the note leaf with an empty leaf
+    tree_cpi!(ctx)
+        .with_proof(ctx.remaining_accounts)
+        .replace(root, leaf_node, [0; 32], index)?;
+    Ok(())
+}
+
+// Opens the usage pda of an organization for the current period, callable by anyone.
+pub fn process_open_usage_period(ctx: Context<OpenUsagePeriod>, period: u64) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::OPEN_USAGE_PERIOD)?;
+    require!(
+        period == OrgUsage::period_at(Clock::get()?.unix_timestamp),
+        CompressedNotesError::UsagePeriodClosed
+    );
+
+    let org_usage = &mut ctx.accounts.org_usage;
+    org_usage.organization = ctx.accounts.organization.key();
+    org_usage.period = period;
+    org_usage.bump = *ctx.bumps.get("org_usage").unwrap();
+    Ok(())
+}
+
+// Freezes the usage of a period that has ended, callable by anyone.
+pub fn process_finalize_usage_period(ctx: Context<FinalizeUsagePeriod>) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::FINALIZE_USAGE_PERIOD)?;
+
+    let org_usage = &mut ctx.accounts.org_usage;
+    require!(
+        org_usage.period < OrgUsage::period_at(Clock::get()?.unix_timestamp),
+        CompressedNotesError::UsagePeriodNotEnded
+    );
+    org_usage.finalized = true;
+    Ok(())
+}
+
+#[derive(AnchorSerialize)]
+pub struct OrgNoteLog {
+    leaf_node: [u8; 32],  // The leaf node hash
+    note_id: NoteId,      // The id of the leaf
+    organization: Pubkey, // The organization owning the note
+    member: Pubkey,       // Pubkey of the member who posted the note
+    note: String,         // The note
+    posted_at: i64,       // Unix timestamp the note was posted at
+}
+
+impl OrgNoteLog {
+    // Constructs a new org note log from given leaf node, id, organization, member and note
+    pub fn new(
+        leaf_node: [u8; 32],
+        note_id: NoteId,
+        organization: Pubkey,
+        member: Pubkey,
+        note: String,
+        posted_at: i64,
+    ) -> Self {
+        Self {
+            leaf_node,
+            note_id,
+            organization,
+            member,
+            note,
+            posted_at,
+        }
+    }
+}
+
+#[derive(AnchorSerialize)]
+pub struct OrgNotePruneLog {
+    leaf_node: [u8; 32], // The leaf node hash of the pruned note
+    note_id: NoteId,     // The id of the leaf
+}
+
+impl OrgNotePruneLog {
+    // Constructs a new prune log from given leaf node and id
+    pub fn new(leaf_node: [u8; 32], note_id: NoteId) -> Self {
+        Self { leaf_node, note_id }
+    }
+}
+
+#[derive(AnchorSerialize)]
+pub struct RetentionTierLog {
+    organization: Pubkey,    // The organization whose tier changed
+    old_tier: RetentionTier, // The tier before the change
+    new_tier: RetentionTier, // The tier after the change
+    slot: u64,               // The slot the tier was changed
+}
+
+impl RetentionTierLog {
+    // Constructs a new retention tier log from given organization, tiers and slot
+    pub fn new(
+        organization: Pubkey,
+        old_tier: RetentionTier,
+        new_tier: RetentionTier,
+        slot: u64,
+    ) -> Self {
+        Self {
+            organization,
+            old_tier,
+            new_tier,
+            slot,
+        }
+    }
+}
+
+// How long an organization keeps its notes before anyone can prune them
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq, InitSpace)]
+pub enum RetentionTier {
+    Days90,
+    OneYear,
+    Forever,
+}
+
+impl RetentionTier {
+    // The age after which a note can be pruned, none if notes are kept forever
+    pub fn retention_seconds(&self) -> Option<i64> {
+        const DAY: i64 = 24 * 60 * 60;
+        match self {
+            RetentionTier::Days90 => Some(90 * DAY),
+            RetentionTier::OneYear => Some(365 * DAY),
+            RetentionTier::Forever => None,
+        }
+    }
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct Organization {
+    owner: Pubkey,                 // The owner allowed to buy and assign seats
+    org_id: u64,                   // Id of the organization, unique per owner
+    seat_limit: u32,               // The number of seats bought
+    seat_count: u32,               // The number of seats assigned to members
+    retention_tier: RetentionTier, // How long the organization's notes are kept
+    bump: u8,                      // The bump seed for the pda
+}
+
+impl Organization {
+    pub const SEED: &'static [u8] = b"org";
+    // The price of one seat, paid into the protocol treasury
+    pub const SEAT_PRICE_LAMPORTS: u64 = 10_000_000;
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct Seat {
+    organization: Pubkey, // The organization the seat belongs to
+    member: Pubkey,       // The member holding the seat
+    bump: u8,             // The bump seed for the pda
+}
+
+impl Seat {
+    pub const SEED: &'static [u8] = b"seat";
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct OrgUsage {
+    organization: Pubkey, // The organization the usage is billed to
+    period: u64,          // The billing period, counted in `PERIOD_SECONDS` since the unix epoch
+    append_count: u64,    // The number of notes appended in the period
+    bytes: u64,           // The total note bytes appended in the period
+    finalized: bool,      // Whether the period ended and the counts are final
+    bump: u8,             // The bump seed for the pda
+}
+
+impl OrgUsage {
+    pub const SEED: &'static [u8] = b"org_usage";
+    // The length of a billing period, 30 days
+    pub const PERIOD_SECONDS: i64 = 30 * 24 * 60 * 60;
+
+    // The billing period a unix timestamp falls in
+    pub fn period_at(unix_timestamp: i64) -> u64 {
+        (unix_timestamp / OrgUsage::PERIOD_SECONDS) as u64
+    }
+
+    // Counts one appended note of `bytes` bytes, only while the period is current
+    pub fn record(&mut self, unix_timestamp: i64, bytes: usize) -> Result<()> {
+        require!(
+            !self.finalized && self.period == OrgUsage::period_at(unix_timestamp),
+            CompressedNotesError::UsagePeriodClosed
+        );
+        self.append_count += 1;
+        self.bytes += bytes as u64;
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+#[instruction(org_id: u64)]
+pub struct CreateOrganization<'info> {
+    // The owner of the new organization, also pays for the organization account
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    // The organization pda
+    #[account(
+        init,
+        payer = owner,
+        space = 8 + Organization::INIT_SPACE,
+        seeds = [Organization::SEED, owner.key().as_ref(), &org_id.to_le_bytes()],
+        bump,
+    )]
+    pub organization: Account<'info, Organization>,
+
+    // The system program, used to create the organization account
+    pub system_program: Program<'info, System>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[derive(Accounts)]
+pub struct PurchaseSeats<'info> {
+    // The owner of the organization, pays for the seats
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    // The organization pda
+    #[account(
+        mut,
+        seeds = [Organization::SEED, organization.owner.as_ref(), &organization.org_id.to_le_bytes()],
+        bump = organization.bump,
+        has_one = owner @ CompressedNotesError::Unauthorized,
+    )]
+    pub organization: Account<'info, Organization>,
+
+    // The protocol treasury pda, receives the seat price
+    #[account(mut, seeds = [TREASURY_SEED], bump)]
+    pub treasury: SystemAccount<'info>,
+
+    // The system program, used to pay the treasury
+    pub system_program: Program<'info, System>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[derive(Accounts)]
+#[instruction(member: Pubkey)]
+pub struct AddSeat<'info> {
+    // The owner of the organization, pays for the seat account
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    // The organization pda
+    #[account(
+        mut,
+        seeds = [Organization::SEED, organization.owner.as_ref(), &organization.org_id.to_le_bytes()],
+        bump = organization.bump,
+        has_one = owner @ CompressedNotesError::Unauthorized,
+    )]
+    pub organization: Account<'info, Organization>,
+
+    // The seat pda, one per organization and member
+    #[account(
+        init,
+        payer = owner,
+        space = 8 + Seat::INIT_SPACE,
+        seeds = [Seat::SEED, organization.key().as_ref(), member.as_ref()],
+        bump,
+    )]
+    pub seat: Account<'info, Seat>,
+
+    // The system program, used to create the seat account
+    pub system_program: Program<'info, System>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[derive(Accounts)]
+pub struct RemoveSeat<'info> {
+    // The owner of the organization, receives the seat rent
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    // The organization pda
+    #[account(
+        mut,
+        seeds = [Organization::SEED, organization.owner.as_ref(), &organization.org_id.to_le_bytes()],
+        bump = organization.bump,
+        has_one = owner @ CompressedNotesError::Unauthorized,
+    )]
+    pub organization: Account<'info, Organization>,
+
+    // The seat pda, closed by this instruction
+    #[account(
+        mut,
+        close = owner,
+        seeds = [Seat::SEED, organization.key().as_ref(), seat.member.as_ref()],
+        bump = seat.bump,
+    )]
+    pub seat: Account<'info, Seat>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[derive(Accounts)]
+pub struct SetRetentionTier<'info> {
+    // The owner of the organization
+    pub owner: Signer<'info>,
+
+    // The organization pda
+    #[account(
+        mut,
+        seeds = [Organization::SEED, organization.owner.as_ref(), &organization.org_id.to_le_bytes()],
+        bump = organization.bump,
+        has_one = owner @ CompressedNotesError::Unauthorized,
+    )]
+    pub organization: Account<'info, Organization>,
+
+    // The noop program to log data
+    pub log_wrapper: Program<'info, Noop>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[derive(Accounts)]
+pub struct PruneOrgNote<'info> {
+    // The organization owning the note
+    #[account(
+        seeds = [Organization::SEED, organization.owner.as_ref(), &organization.org_id.to_le_bytes()],
+        bump = organization.bump,
+    )]
+    pub organization: Account<'info, Organization>,
+
+    // The hold pda of the leaf replaced, needed to replace leaves in a tree with leaves on hold
+    /// CHECK: Checked against the leaf replaced, which must not be on hold
+    pub hold: Option<UncheckedAccount<'info>>,
+
+    // The tree config pda, counting appends and replaces, which may not have been created
+    /// CHECK: Only read and written if it exists, the seeds tie it to the merkle tree
+    #[account(mut, seeds = [TreeConfig::SEED, merkle_tree.key().as_ref()], bump)]
+    pub tree_config: UncheckedAccount<'info>,
+
+    // The pda authority for the merkle tree, only used for signing
+    #[account(
+        seeds = [merkle_tree.key().as_ref()],
+        bump,
+    )]
+    pub tree_authority: SystemAccount<'info>,
+
+    // The merkle tree account
+    /// CHECK: This account is validated by the spl account compression program
+    #[account(
+        mut,
+        owner = spl_account_compression::ID @ CompressedNotesError::InvalidMerkleTreeOwner,
+    )]
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    // The noop program to log data
+    pub log_wrapper: Program<'info, Noop>,
+
+    // The spl account compression program
+    pub compression_program: Program<'info, SplAccountCompression>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[derive(Accounts)]
+pub struct OrgNoteAccounts<'info> {
+    // The member posting the note, must hold a seat, also pays the append fee of a tree
+    // charging one
+    #[account(mut)]
+    pub member: Signer<'info>,
+
+    // The role of the member on the tree, if any, letting them write to a private tree
+    pub role: Option<Account<'info, Role>>,
+
+    // The organization owning the note
+    #[account(
+        seeds = [Organization::SEED, organization.owner.as_ref(), &organization.org_id.to_le_bytes()],
+        bump = organization.bump,
+    )]
+    pub organization: Account<'info, Organization>,
+
+    // The member's seat in the organization
+    #[account(
+        seeds = [Seat::SEED, organization.key().as_ref(), member.key().as_ref()],
+        bump = seat.bump,
+    )]
+    pub seat: Account<'info, Seat>,
+
+    // The organization's usage for the current period
+    #[account(
+        mut,
+        seeds = [OrgUsage::SEED, organization.key().as_ref(), &org_usage.period.to_le_bytes()],
+        bump = org_usage.bump,
+    )]
+    pub org_usage: Account<'info, OrgUsage>,
+
+    // The tree treasury pda, needed to append to a tree charging an append fee
+    #[account(mut, seeds = [TREE_TREASURY_SEED, merkle_tree.key().as_ref()], bump)]
+    pub tree_treasury: Option<SystemAccount<'info>>,
+
+    // The signer's token account paying the fee, needed to append to a tree charging a token fee
+    #[account(mut)]
+    pub payer_token_account: Option<Account<'info, TokenAccount>>,
+
+    // The fee vault pda of the tree's fee mint, needed to append to a tree charging a token fee
+    #[account(mut)]
+    pub fee_vault: Option<Account<'info, TokenAccount>>,
+
+    // The spl token program, needed to append to a tree charging a token fee
+    pub token_program: Option<Program<'info, Token>>,
+
+    // A token account of the member, needed to append to a token- or nft-gated tree
+    pub gate_token_account: Option<Account<'info, TokenAccount>>,
+
+    // The metadata account of the member's nft, needed to append to an nft-gated tree
+    pub gate_metadata: Option<Account<'info, MetadataAccount>>,
+
+    // The member's subscription to the tree, needed to append to a tree selling access
+    pub subscription: Option<Account<'info, TreeSubscription>>,
+
+    // The tree config pda, counting appends and replaces, which may not have been created
+    /// CHECK: Only read and written if it exists, the seeds tie it to the merkle tree
+    #[account(mut, seeds = [TreeConfig::SEED, merkle_tree.key().as_ref()], bump)]
+    pub tree_config: UncheckedAccount<'info>,
+
+    // The pda authority for the merkle tree, only used for signing
+    #[account(
+        seeds = [merkle_tree.key().as_ref()],
+        bump,
+    )]
+    pub tree_authority: SystemAccount<'info>,
+
+    // The merkle tree account
+    /// CHECK: This account is validated by the spl account compression program
+    #[account(
+        mut,
+        owner = spl_account_compression::ID @ CompressedNotesError::InvalidMerkleTreeOwner,
+    )]
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    // The noop program to log data
+    pub log_wrapper: Program<'info, Noop>,
+
+    // The spl account compression program
+    pub compression_program: Program<'info, SplAccountCompression>,
+
+    // The system program, needed to append to a tree charging an append fee
+    pub system_program: Option<Program<'info, System>>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[derive(Accounts)]
+#[instruction(period: u64)]
+pub struct OpenUsagePeriod<'info> {
+    // Pays for the usage account
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    // The organization the usage is billed to
+    #[account(
+        seeds = [Organization::SEED, organization.owner.as_ref(), &organization.org_id.to_le_bytes()],
+        bump = organization.bump,
+    )]
+    pub organization: Account<'info, Organization>,
+
+    // The usage pda, one per organization and period
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + OrgUsage::INIT_SPACE,
+        seeds = [OrgUsage::SEED, organization.key().as_ref(), &period.to_le_bytes()],
+        bump,
+    )]
+    pub org_usage: Account<'info, OrgUsage>,
+
+    // The system program, used to create the usage account
+    pub system_program: Program<'info, System>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[derive(Accounts)]
+pub struct FinalizeUsagePeriod<'info> {
+    // The usage pda of the ended period
+    #[account(
+        mut,
+        seeds = [OrgUsage::SEED, org_usage.organization.as_ref(), &org_usage.period.to_le_bytes()],
+        bump = org_usage.bump,
+    )]
+    pub org_usage: Account<'info, OrgUsage>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
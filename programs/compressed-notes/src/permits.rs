@@ -0,0 +1,326 @@
+//! Gasless appends, submitted by a relayer with an ed25519 permit the owner signed off-chain.
+
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::instruction::Instruction;
+use anchor_lang::solana_program::sysvar::instructions::{
+    self as sysvar_instructions, load_current_index_checked, load_instruction_at_checked,
+};
+use anchor_lang::solana_program::{ed25519_program, keccak};
+use anchor_spl::metadata::MetadataAccount;
+use anchor_spl::token::{Token, TokenAccount};
+use spl_account_compression::{program::SplAccountCompression, wrap_application_data_v1, Noop};
+
+use crate::{
+    hash_note_leaf_v2, instruction_flags, leaf_schema, require_clean_text, CompressedNotesError,
+    FeeOperation, GlobalConfig, NoteId, NoteLog, NoteStatus, RateState, RecentLeaves, Stake,
+    TreeConfig, TreeSubscription, MAX_NOTE_LEN, TREE_TREASURY_SEED,
+};
+
+// Prefix of every permit message, so no other signed message doubles as a permit
+pub const PERMIT_DOMAIN: &[u8] = b"compressed-notes:append_note_with_permit";
+
+// Hashes the tree, note and expiry into the message an owner signs to permit an append
+pub fn permit_message(merkle_tree: &Pubkey, note: &str, expiry: i64) -> [u8; 32] {
+    keccak::hashv(&[
+        PERMIT_DOMAIN,
+        crate::ID.as_ref(),
+        merkle_tree.as_ref(),
+        &keccak::hash(note.as_bytes()).to_bytes(),
+        &expiry.to_le_bytes(),
+    ])
+    .to_bytes()
+}
+
+// Appends a published note owned by `owner`, who permitted it with an ed25519 signature
+// verified by the instruction before this one, paid for by the relayer. The permit pda at
+// `[b"permit", owner, permit_message]` makes each permit usable once.
+pub fn process_append_note_with_permit(
+    ctx: Context<AppendNoteWithPermit>,
+    note: String,        // The note message
+    owner: Pubkey,       // The owner who signed the permit, committed into the leaf
+    signature: [u8; 64], // The owner's ed25519 signature of the permit message
+    expiry: i64,         // Unix timestamp after which the permit can no longer be submitted
+) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::APPEND_NOTE_WITH_PERMIT)?;
+    require!(
+        note.len() <= MAX_NOTE_LEN,
+        CompressedNotesError::NoteTooLong
+    );
+    // Refuse text that indexers would display deceptively
+    require_clean_text(&note)?;
+    require!(
+        Clock::get()?.unix_timestamp <= expiry,
+        CompressedNotesError::PermitExpired
+    );
+
+    // The ed25519 program verified the signature in the instruction before this one
+    let message = permit_message(&ctx.accounts.merkle_tree.key(), &note, expiry);
+    let instructions = &ctx.accounts.instructions;
+    let current_index = load_current_index_checked(instructions)?;
+    require!(current_index > 0, CompressedNotesError::InvalidPermit);
+    let verify_ix = load_instruction_at_checked((current_index - 1).into(), instructions)?;
+    require_ed25519_signature(&verify_ix, &owner, &signature, &message)?;
+
+    let permit = &mut ctx.accounts.permit;
+    permit.relayer = ctx.accounts.relayer.key();
+    permit.expiry = expiry;
+    permit.bump = *ctx.bumps.get("permit").unwrap();
+
+    // Hash the "note message" which will be stored as leaf node in the merkle tree
+    let status = NoteStatus::Published;
+    let leaf_node = hash_note_leaf_v2(&note, &owner, status);
+    let note_len = note.len();
+    // Create a new "note log" using the leaf node hash, the id the leaf will get and note.
+    let note_id = NoteId::next(&ctx.accounts.merkle_tree, leaf_schema::V2)?;
+    let note_log = NoteLog::new(leaf_node, note_id, owner, note, status);
+    // Log the "note log" data using noop program
+    wrap_application_data_v1(note_log.try_to_vec()?, &ctx.accounts.log_wrapper)?;
+
+    // A tree restricting cpi callers only accepts the programs it allows
+    TreeConfig::require_caller(&ctx.accounts.tree_config, Some(instructions))?;
+    // A tree charging an append fee takes it from the relayer into the tree treasury
+    TreeConfig::record_fee(
+        &ctx.accounts.tree_config,
+        &ctx.accounts.relayer,
+        ctx.accounts.tree_treasury.as_ref(),
+        Some(&ctx.accounts.system_program),
+        FeeOperation::Append(note_len),
+    )?;
+    // A tree charging a token append fee takes it from the relayer into its fee vault
+    TreeConfig::record_token_fee(
+        &ctx.accounts.tree_config,
+        &ctx.accounts.relayer,
+        ctx.accounts.payer_token_account.as_ref(),
+        ctx.accounts.fee_vault.as_ref(),
+        ctx.accounts.token_program.as_ref(),
+    )?;
+    // A token- or nft-gated tree only accepts posters holding its gate
+    TreeConfig::require_gate(
+        &ctx.accounts.tree_config,
+        &owner,
+        ctx.accounts.gate_token_account.as_ref(),
+        ctx.accounts.gate_metadata.as_ref(),
+    )?;
+    // A tree asking posters to stake only accepts posters with enough staked
+    TreeConfig::record_stake(&ctx.accounts.tree_config, ctx.accounts.stake.as_deref_mut())?;
+    // A tree selling access only accepts posters with an active subscription
+    TreeConfig::require_subscription(
+        &ctx.accounts.tree_config,
+        &owner,
+        ctx.accounts.subscription.as_deref(),
+    )?;
+    // Count the append against the rate limits of the tree the owner appends to
+    TreeConfig::record_rate(
+        &ctx.accounts.tree_config,
+        ctx.accounts.rate_state.as_deref_mut(),
+    )?;
+    // A tree refusing duplicate leaves checks the leaf against its recent leaves
+    TreeConfig::record_leaf(
+        &ctx.accounts.tree_config,
+        ctx.accounts.recent_leaves.as_deref_mut(),
+        &leaf_node,
+    )?;
+    // Count the append on the tree config, failing if the tree is frozen or private
+    TreeConfig::record_append(
+        &ctx.accounts.tree_config,
+        &ctx.accounts.merkle_tree,
+        &owner,
+        None,
+    )?;
+    // CPI to append the leaf node to the merkle tree
+    tree_cpi!(ctx).append(leaf_node)
+}
+
+// Closes an expired permit, refunding the rent to the relayer who submitted it.
+pub fn process_close_expired_permit(ctx: Context<CloseExpiredPermit>) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::CLOSE_EXPIRED_PERMIT)?;
+
+    // An expired permit can no longer be submitted, so forgetting it allows no replay
+    require!(
+        Clock::get()?.unix_timestamp > ctx.accounts.permit.expiry,
+        CompressedNotesError::PermitNotExpired
+    );
+    Ok(())
+}
+
+// Fails unless `ix` is an ed25519 program instruction verifying exactly one `signature` by
+// `signer` over `message`, all held in its own data
+pub fn require_ed25519_signature(
+    ix: &Instruction,
+    signer: &Pubkey,
+    signature: &[u8; 64],
+    message: &[u8],
+) -> Result<()> {
+    require_keys_eq!(
+        ix.program_id,
+        ed25519_program::ID,
+        CompressedNotesError::InvalidPermit
+    );
+    // One signature count byte and a padding byte, then seven u16 offsets
+    let data = &ix.data;
+    require!(
+        data.len() >= 16 && data[0] == 1,
+        CompressedNotesError::InvalidPermit
+    );
+    let offset = |i: usize| u16::from_le_bytes([data[2 + 2 * i], data[3 + 2 * i]]);
+    let (signature_offset, signature_ix) = (offset(0), offset(1));
+    let (public_key_offset, public_key_ix) = (offset(2), offset(3));
+    let (message_offset, message_size, message_ix) = (offset(4), offset(5), offset(6));
+    // u16::MAX points into the ed25519 instruction's own data
+    require!(
+        [signature_ix, public_key_ix, message_ix] == [u16::MAX; 3],
+        CompressedNotesError::InvalidPermit
+    );
+
+    let field = |offset: u16, len: usize| data.get(offset as usize..offset as usize + len);
+    require!(
+        field(public_key_offset, 32) == Some(signer.as_ref())
+            && field(signature_offset, 64) == Some(&signature[..])
+            && message_size as usize == message.len()
+            && field(message_offset, message.len()) == Some(message),
+        CompressedNotesError::InvalidPermit
+    );
+    Ok(())
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct NotePermit {
+    relayer: Pubkey, // The relayer who submitted the permit, refunded the rent
+    expiry: i64,     // Unix timestamp after which the permit can no longer be submitted
+    bump: u8,        // The bump seed for the pda
+}
+
+impl NotePermit {
+    pub const SEED: &'static [u8] = b"permit";
+}
+
+#[derive(Accounts)]
+#[instruction(note: String, owner: Pubkey, signature: [u8; 64], expiry: i64)]
+pub struct AppendNoteWithPermit<'info> {
+    // The relayer submitting the permit, pays for the transaction and the permit account
+    #[account(mut)]
+    pub relayer: Signer<'info>,
+
+    // The permit pda, created once per permit so it cannot be submitted again
+    #[account(
+        init,
+        payer = relayer,
+        space = 8 + NotePermit::INIT_SPACE,
+        seeds = [
+            NotePermit::SEED,
+            owner.as_ref(),
+            &permit_message(&merkle_tree.key(), &note, expiry),
+        ],
+        bump,
+    )]
+    pub permit: Account<'info, NotePermit>,
+
+    // The rate state of the owner on the tree, needed to append to a rate limited tree
+    #[account(
+        mut,
+        seeds = [RateState::SEED, merkle_tree.key().as_ref(), owner.as_ref()],
+        bump,
+    )]
+    pub rate_state: Option<Account<'info, RateState>>,
+
+    // The recent leaves pda of the tree, needed to append to a tree refusing duplicate leaves
+    #[account(
+        mut,
+        seeds = [RecentLeaves::SEED, merkle_tree.key().as_ref()],
+        bump,
+    )]
+    pub recent_leaves: Option<Account<'info, RecentLeaves>>,
+
+    // The instructions sysvar, used to read the ed25519 instruction verifying the permit
+    /// CHECK: The address constraint ties it to the instructions sysvar
+    #[account(address = sysvar_instructions::ID)]
+    pub instructions: UncheckedAccount<'info>,
+
+    // The tree treasury pda, needed to append to a tree charging an append fee
+    #[account(mut, seeds = [TREE_TREASURY_SEED, merkle_tree.key().as_ref()], bump)]
+    pub tree_treasury: Option<SystemAccount<'info>>,
+
+    // The signer's token account paying the fee, needed to append to a tree charging a token fee
+    #[account(mut)]
+    pub payer_token_account: Option<Account<'info, TokenAccount>>,
+
+    // The fee vault pda of the tree's fee mint, needed to append to a tree charging a token fee
+    #[account(mut)]
+    pub fee_vault: Option<Account<'info, TokenAccount>>,
+
+    // The spl token program, needed to append to a tree charging a token fee
+    pub token_program: Option<Program<'info, Token>>,
+
+    // A token account of the poster, needed to append to a token- or nft-gated tree
+    pub gate_token_account: Option<Account<'info, TokenAccount>>,
+
+    // The metadata account of the poster's nft, needed to append to an nft-gated tree
+    pub gate_metadata: Option<Account<'info, MetadataAccount>>,
+
+    // The stake pda of the owner on the tree, needed to append to a tree asking posters to stake
+    #[account(
+        mut,
+        seeds = [Stake::SEED, merkle_tree.key().as_ref(), owner.as_ref()],
+        bump,
+    )]
+    pub stake: Option<Account<'info, Stake>>,
+
+    // The poster's subscription to the tree, needed to append to a tree selling access
+    pub subscription: Option<Account<'info, TreeSubscription>>,
+
+    // The tree config pda, counting appends and replaces, which may not have been created
+    /// CHECK: Only read and written if it exists, the seeds tie it to the merkle tree
+    #[account(mut, seeds = [TreeConfig::SEED, merkle_tree.key().as_ref()], bump)]
+    pub tree_config: UncheckedAccount<'info>,
+
+    // The pda authority for the merkle tree, only used for signing
+    #[account(
+        seeds = [merkle_tree.key().as_ref()],
+        bump,
+    )]
+    pub tree_authority: SystemAccount<'info>,
+
+    // The merkle tree account
+    /// CHECK: This account is validated by the spl account compression program
+    #[account(
+        mut,
+        owner = spl_account_compression::ID @ CompressedNotesError::InvalidMerkleTreeOwner,
+    )]
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    // The noop program to log data
+    pub log_wrapper: Program<'info, Noop>,
+
+    // The spl account compression program
+    pub compression_program: Program<'info, SplAccountCompression>,
+
+    // The system program, used to create the permit account
+    pub system_program: Program<'info, System>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[derive(Accounts)]
+pub struct CloseExpiredPermit<'info> {
+    // The relayer who submitted the permit, refunded the permit rent
+    /// CHECK: Checked against the permit relayer
+    #[account(mut)]
+    pub relayer: UncheckedAccount<'info>,
+
+    // The expired permit pda, closed by this instruction
+    #[account(mut, close = relayer, has_one = relayer)]
+    pub permit: Account<'info, NotePermit>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
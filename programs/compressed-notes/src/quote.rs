@@ -0,0 +1,115 @@
+//! Price quotes returning what an append to a tree costs, so wallets show it before signing.
+
+use anchor_lang::prelude::*;
+
+#[cfg(feature = "messaging")]
+use crate::Conversation;
+use crate::{instruction_flags, FeeOperation, GlobalConfig, TreeConfig};
+
+// Lamports the network charges per transaction signature, before priority fees
+pub const LAMPORTS_PER_SIGNATURE: u64 = 5000;
+
+// The kinds of leaves that can be appended
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AppendKind {
+    Note,         // append_note
+    Draft,        // append_draft
+    Message,      // append_message
+    GroupMessage, // append_group_message
+    Broadcast,    // broadcast
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug)]
+pub struct QuoteOptions {
+    pub kind: AppendKind,       // The instruction the quote is for
+    pub new_conversation: bool, // Whether append_message will create the conversation account
+    pub priority: u8,           // The priority of the message, see `message_priority`
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Quote {
+    pub network_fee: u64,               // Signature fees for the transaction
+    pub rent: u64,                      // Rent for the accounts the append creates
+    pub protocol_fee: u64,              // Fees charged by this program
+    pub total: u64,                     // The sum of the above
+    pub token_fee_mint: Option<Pubkey>, // The mint of the token fee the tree charges, if any
+    pub token_fee: u64,                 // Tokens of `token_fee_mint` paid, not in the total
+}
+
+impl Quote {
+    // Prices an append of `content_len` bytes to the tree of `tree_config` with the given options
+    pub fn for_append(
+        tree_config: &AccountInfo,
+        content_len: u32,
+        options: QuoteOptions,
+    ) -> Result<Quote> {
+        // Every append is signed by its author alone
+        let network_fee = LAMPORTS_PER_SIGNATURE;
+
+        let rent = match options.kind {
+            #[cfg(feature = "messaging")]
+            AppendKind::Message if options.new_conversation => {
+                Rent::get()?.minimum_balance(8 + Conversation::INIT_SPACE)
+            }
+            _ => 0,
+        };
+
+        let protocol_fee = append_fee(tree_config, content_len)?;
+        let (token_fee_mint, token_fee) = append_token_fee(tree_config)?.unzip();
+
+        Ok(Quote {
+            network_fee,
+            rent,
+            protocol_fee,
+            total: network_fee + rent + protocol_fee,
+            token_fee_mint,
+            token_fee: token_fee.unwrap_or(0),
+        })
+    }
+}
+
+// The lamports the fee schedule of the tree charges an append, none if it has no tree config
+pub fn append_fee(tree_config: &AccountInfo, content_len: u32) -> Result<u64> {
+    if tree_config.data_is_empty() {
+        return Ok(0);
+    }
+    let config = Account::<TreeConfig>::try_from(tree_config)?;
+    Ok(config.fee_for(FeeOperation::Append(content_len as usize)))
+}
+
+// The mint and amount of the token fee the tree charges an append, none if it has no tree config
+pub fn append_token_fee(tree_config: &AccountInfo) -> Result<Option<(Pubkey, u64)>> {
+    if tree_config.data_is_empty() {
+        return Ok(None);
+    }
+    Ok(Account::<TreeConfig>::try_from(tree_config)?.token_fee())
+}
+
+// Returns the quote for an append.
+pub fn process_quote_append(
+    ctx: Context<QuoteAppend>,
+    content_len: u32,      // Length of the note or message in bytes
+    options: QuoteOptions, // What is being appended
+) -> Result<Quote> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::QUOTE_APPEND)?;
+    Quote::for_append(&ctx.accounts.tree_config, content_len, options)
+}
+
+#[derive(Accounts)]
+pub struct QuoteAppend<'info> {
+    // The merkle tree appended to
+    /// CHECK: Only used to derive the tree config
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    // The tree config pda, pricing appends, which may not have been created
+    /// CHECK: Only read if it exists, the seeds tie it to the merkle tree
+    #[account(seeds = [TreeConfig::SEED, merkle_tree.key().as_ref()], bump)]
+    pub tree_config: UncheckedAccount<'info>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
@@ -0,0 +1,90 @@
+//! Per-sender rate limits on appends, counted in a rate state pda at
+//! `[b"rate", merkle_tree, sender]` passed to appends as the optional `rate_state` account.
+
+use anchor_lang::prelude::*;
+
+use crate::{instruction_flags, CompressedNotesError, GlobalConfig};
+
+// Creates the rate state of the signer on the tree, counting no appends yet.
+pub fn process_create_rate_state(ctx: Context<CreateRateState>) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::CREATE_RATE_STATE)?;
+
+    let rate_state = &mut ctx.accounts.rate_state;
+    rate_state.merkle_tree = ctx.accounts.merkle_tree.key();
+    rate_state.sender = ctx.accounts.sender.key();
+    rate_state.bump = *ctx.bumps.get("rate_state").unwrap();
+    Ok(())
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct RateState {
+    merkle_tree: Pubkey,           // The tree the appends are counted on
+    sender: Pubkey,                // The sender whose appends are counted
+    last_append_slot: Option<u64>, // The slot of the sender's last append, if any
+    epoch: u64,                    // The epoch `epoch_appends` are counted in
+    epoch_appends: u32,            // The number of appends in `epoch`
+    bump: u8,                      // The bump seed for the pda
+}
+
+impl RateState {
+    pub const SEED: &'static [u8] = b"rate";
+
+    // Counts an append at `clock`, failing if it comes less than `min_slots` after the last
+    // one or exceeds `max_appends_per_epoch`, where zero leaves either unlimited
+    pub fn record(
+        &mut self,
+        min_slots: u64,
+        max_appends_per_epoch: u32,
+        clock: &Clock,
+    ) -> Result<()> {
+        if let Some(last_append_slot) = self.last_append_slot {
+            require!(
+                clock.slot - last_append_slot >= min_slots,
+                CompressedNotesError::RateLimited
+            );
+        }
+        if self.epoch != clock.epoch {
+            self.epoch = clock.epoch;
+            self.epoch_appends = 0;
+        }
+        require!(
+            max_appends_per_epoch == 0 || self.epoch_appends < max_appends_per_epoch,
+            CompressedNotesError::RateLimited
+        );
+        self.epoch_appends += 1;
+        self.last_append_slot = Some(clock.slot);
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+pub struct CreateRateState<'info> {
+    // The sender, pays for the rate state account
+    #[account(mut)]
+    pub sender: Signer<'info>,
+
+    // The rate state pda, one per tree and sender
+    #[account(
+        init,
+        payer = sender,
+        space = 8 + RateState::INIT_SPACE,
+        seeds = [RateState::SEED, merkle_tree.key().as_ref(), sender.key().as_ref()],
+        bump,
+    )]
+    pub rate_state: Account<'info, RateState>,
+
+    // The merkle tree account
+    /// CHECK: Only used to derive the rate state pda
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    // The system program, used to create the rate state account
+    pub system_program: Program<'info, System>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
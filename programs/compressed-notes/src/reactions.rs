@@ -0,0 +1,70 @@
+//! Emoji reactions on messages and notes, logged without touching the
+//! reacted-to leaf. Compiled in with the `reactions` feature.
+
+use anchor_lang::prelude::*;
+use spl_account_compression::{wrap_application_data_v1, Noop};
+
+use crate::{instruction_flags, CompressedNotesError, GlobalConfig};
+
+// Reacts to a message or note leaf with an emoji.
+pub fn process_react(
+    ctx: Context<ReactionAccounts>,
+    leaf_hash: [u8; 32],
+    emoji: String,
+) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::REACT)?;
+    require!(
+        !emoji.is_empty() && emoji.len() <= ReactionLog::MAX_EMOJI_LEN,
+        CompressedNotesError::InvalidReaction
+    );
+
+    // Create a new "reaction log" for the leaf, signed by the reactor
+    let reaction_log = ReactionLog::new(
+        leaf_hash,
+        ctx.accounts.reactor.key(),
+        emoji,
+        Clock::get()?.slot,
+    );
+    // Log the "reaction log" data using noop program
+    wrap_application_data_v1(reaction_log.try_to_vec()?, &ctx.accounts.log_wrapper)?;
+    Ok(())
+}
+
+#[derive(AnchorSerialize)]
+pub struct ReactionLog {
+    leaf_node: [u8; 32], // The leaf node hash of the message or note reacted to
+    reactor: Pubkey,     // Pubkey of the signer who reacted
+    emoji: String,       // The reaction, a single emoji or short code
+    slot: u64,           // The slot of the reaction
+}
+
+impl ReactionLog {
+    // Long enough for multi codepoint emoji such as flags and skin tones
+    pub const MAX_EMOJI_LEN: usize = 32;
+
+    // Constructs a new reaction log for the given leaf node, reactor and emoji
+    pub fn new(leaf_node: [u8; 32], reactor: Pubkey, emoji: String, slot: u64) -> Self {
+        Self {
+            leaf_node,
+            reactor,
+            emoji,
+            slot,
+        }
+    }
+}
+
+#[derive(Accounts)]
+pub struct ReactionAccounts<'info> {
+    // The signer reacting to the leaf
+    pub reactor: Signer<'info>,
+
+    // The noop program to log data
+    pub log_wrapper: Program<'info, Noop>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
@@ -0,0 +1,126 @@
+//! Read receipts and delivery confirmations emitted by message recipients.
+//! Compiled in with the `receipts` feature.
+
+use anchor_lang::prelude::*;
+use spl_account_compression::{wrap_application_data_v1, Noop};
+
+use crate::{instruction_flags, Conversation, GlobalConfig, Inbox};
+
+// Marks a message as read by its recipient.
+pub fn process_mark_read(ctx: Context<ReceiptAccounts>, leaf_hash: [u8; 32]) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::MARK_READ)?;
+
+    // Count one message fewer as unread in the reader's inbox
+    let inbox = &mut ctx.accounts.inbox;
+    inbox.init(ctx.accounts.reader.key(), *ctx.bumps.get("inbox").unwrap());
+    inbox.mark_read();
+
+    // Create a new "read receipt log" for the leaf, signed by the reader
+    let receipt_log = ReadReceiptLog::new(leaf_hash, ctx.accounts.reader.key(), Clock::get()?.slot);
+    // Log the "read receipt log" data using noop program
+    wrap_application_data_v1(receipt_log.try_to_vec()?, &ctx.accounts.log_wrapper)?;
+    Ok(())
+}
+
+// Confirms delivery of a message by its recipient, counted on the conversation if given.
+pub fn process_confirm_delivery(ctx: Context<ConfirmDelivery>, leaf_hash: [u8; 32]) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::CONFIRM_DELIVERY)?;
+
+    let recipient = ctx.accounts.recipient.key();
+    if let Some(conversation) = &mut ctx.accounts.conversation {
+        conversation.record_delivery(&recipient)?;
+    }
+
+    // Create a new "delivery log" for the leaf, signed by the recipient
+    let delivery_log = DeliveryLog::new(leaf_hash, recipient, Clock::get()?.slot);
+    // Log the "delivery log" data using noop program
+    wrap_application_data_v1(delivery_log.try_to_vec()?, &ctx.accounts.log_wrapper)?;
+    Ok(())
+}
+
+#[derive(AnchorSerialize)]
+pub struct ReadReceiptLog {
+    leaf_node: [u8; 32], // The leaf node hash of the message that was read
+    reader: Pubkey,      // Pubkey of the recipient who read the message
+    slot: u64,           // The slot the message was marked as read
+}
+
+impl ReadReceiptLog {
+    // Constructs a new read receipt for the given leaf node and reader
+    pub fn new(leaf_node: [u8; 32], reader: Pubkey, slot: u64) -> Self {
+        Self {
+            leaf_node,
+            reader,
+            slot,
+        }
+    }
+}
+
+#[derive(AnchorSerialize)]
+pub struct DeliveryLog {
+    leaf_node: [u8; 32], // The leaf node hash of the message that was delivered
+    recipient: Pubkey,   // Pubkey of the recipient who confirmed the delivery
+    slot: u64,           // The slot the delivery was confirmed
+}
+
+impl DeliveryLog {
+    // Constructs a new delivery log for the given leaf node and recipient
+    pub fn new(leaf_node: [u8; 32], recipient: Pubkey, slot: u64) -> Self {
+        Self {
+            leaf_node,
+            recipient,
+            slot,
+        }
+    }
+}
+
+#[derive(Accounts)]
+pub struct ReceiptAccounts<'info> {
+    // The recipient marking the message, must sign the receipt
+    #[account(mut)]
+    pub reader: Signer<'info>,
+
+    // The reader's inbox, its unread count goes down by one
+    #[account(
+        init_if_needed,
+        payer = reader,
+        space = 8 + Inbox::INIT_SPACE,
+        seeds = [Inbox::SEED, reader.key().as_ref()],
+        bump,
+    )]
+    pub inbox: Account<'info, Inbox>,
+
+    // The noop program to log data
+    pub log_wrapper: Program<'info, Noop>,
+
+    // The system program, used to create the inbox account
+    pub system_program: Program<'info, System>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[derive(Accounts)]
+pub struct ConfirmDelivery<'info> {
+    // The recipient confirming the delivery, must sign the confirmation
+    pub recipient: Signer<'info>,
+
+    // The conversation the message belongs to, if the confirmation should be counted.
+    // Only the program creates conversation accounts, so it can only be a conversation pda
+    #[account(mut)]
+    pub conversation: Option<Account<'info, Conversation>>,
+
+    // The noop program to log data
+    pub log_wrapper: Program<'info, Noop>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
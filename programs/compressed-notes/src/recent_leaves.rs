@@ -0,0 +1,175 @@
+//! Duplicate leaf prevention with a bloom filter of recent leaves in a pda at
+//! `[b"recent_leaves", merkle_tree]`, passed to appends as the optional `recent_leaves` account.
+
+use anchor_lang::prelude::*;
+
+use crate::{instruction_flags, CompressedNotesError, GlobalConfig, Role, TreeConfig};
+
+// Bytes of each generation of the filter, 2048 bits
+pub const FILTER_BYTES: usize = 256;
+// Bits set per leaf in the filter
+pub const FILTER_HASHES: usize = 3;
+
+// Opts the tree in to duplicate leaf prevention, refusing repeats for one to two windows of
+// `window_slots`. The filter also refuses leaves it never held more often past a hundred or so
+// leaves per window, so busy trees should use shorter windows.
+pub fn process_create_recent_leaves(
+    ctx: Context<CreateRecentLeaves>,
+    window_slots: u64, // The slots each generation of the filter covers
+) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::CREATE_RECENT_LEAVES)?;
+    require!(window_slots > 0, CompressedNotesError::InvalidDedupeWindow);
+
+    let recent_leaves = &mut ctx.accounts.recent_leaves;
+    recent_leaves.merkle_tree = ctx.accounts.merkle_tree.key();
+    recent_leaves.window_slots = window_slots;
+    recent_leaves.window_start = Clock::get()?.slot;
+    recent_leaves.bump = *ctx.bumps.get("recent_leaves").unwrap();
+    ctx.accounts.tree_config.set_dedupe_leaves(true);
+    Ok(())
+}
+
+// Opts the tree out of duplicate leaf prevention, refunding the filter rent to the signer.
+pub fn process_close_recent_leaves(ctx: Context<CloseRecentLeaves>) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::CLOSE_RECENT_LEAVES)?;
+
+    ctx.accounts.tree_config.set_dedupe_leaves(false);
+    Ok(())
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct RecentLeaves {
+    merkle_tree: Pubkey,          // The tree whose appended leaves are kept
+    window_slots: u64,            // The slots each generation of the filter covers
+    window_start: u64,            // The slot the current generation started at
+    current: [u8; FILTER_BYTES],  // The leaves appended since `window_start`
+    previous: [u8; FILTER_BYTES], // The leaves appended in the window before
+    bump: u8,                     // The bump seed for the pda
+}
+
+impl RecentLeaves {
+    pub const SEED: &'static [u8] = b"recent_leaves";
+
+    // Records `leaf` appended at `slot`, failing if the filter may already hold it
+    pub fn record(&mut self, leaf: &[u8; 32], slot: u64) -> Result<()> {
+        let elapsed = slot.saturating_sub(self.window_start);
+        if elapsed >= 2 * self.window_slots {
+            self.previous = [0; FILTER_BYTES];
+            self.current = [0; FILTER_BYTES];
+            self.window_start = slot;
+        } else if elapsed >= self.window_slots {
+            self.previous = self.current;
+            self.current = [0; FILTER_BYTES];
+            self.window_start += self.window_slots;
+        }
+
+        let bits = Self::bits(leaf);
+        let holds = |filter: &[u8; FILTER_BYTES]| {
+            bits.iter()
+                .all(|&bit| filter[bit / 8] & (1 << (bit % 8)) != 0)
+        };
+        require!(
+            !holds(&self.current) && !holds(&self.previous),
+            CompressedNotesError::DuplicateLeaf
+        );
+        for bit in bits {
+            self.current[bit / 8] |= 1 << (bit % 8);
+        }
+        Ok(())
+    }
+
+    // The filter bits of a leaf, taken from its bytes since leaves are already hashes
+    fn bits(leaf: &[u8; 32]) -> [usize; FILTER_HASHES] {
+        let mut bits = [0; FILTER_HASHES];
+        for (i, bit) in bits.iter_mut().enumerate() {
+            let word = u16::from_le_bytes([leaf[2 * i], leaf[2 * i + 1]]) as usize;
+            *bit = word % (FILTER_BYTES * 8);
+        }
+        bits
+    }
+}
+
+#[derive(Accounts)]
+pub struct CreateRecentLeaves<'info> {
+    // The authority of the tree or an admin, pays for the filter account
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    // The role of the signer, needed unless it is the tree authority
+    pub role: Option<Account<'info, Role>>,
+
+    // The recent leaves pda, one per tree
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + RecentLeaves::INIT_SPACE,
+        seeds = [RecentLeaves::SEED, merkle_tree.key().as_ref()],
+        bump,
+    )]
+    pub recent_leaves: Account<'info, RecentLeaves>,
+
+    // The tree config pda, marked as keeping recent leaves
+    #[account(
+        mut,
+        seeds = [TreeConfig::SEED, merkle_tree.key().as_ref()],
+        bump,
+        constraint = tree_config.is_admin(&authority.key(), role.as_deref())
+            @ CompressedNotesError::Unauthorized,
+    )]
+    pub tree_config: Account<'info, TreeConfig>,
+
+    // The merkle tree account
+    /// CHECK: Only used to derive the recent leaves and tree config pdas
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    // The system program, used to create the filter account
+    pub system_program: Program<'info, System>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[derive(Accounts)]
+pub struct CloseRecentLeaves<'info> {
+    // The authority of the tree or an admin, refunded the filter rent
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    // The role of the signer, needed unless it is the tree authority
+    pub role: Option<Account<'info, Role>>,
+
+    // The recent leaves pda, closed by this instruction
+    #[account(
+        mut,
+        close = authority,
+        seeds = [RecentLeaves::SEED, merkle_tree.key().as_ref()],
+        bump = recent_leaves.bump,
+    )]
+    pub recent_leaves: Account<'info, RecentLeaves>,
+
+    // The tree config pda, no longer marked as keeping recent leaves
+    #[account(
+        mut,
+        seeds = [TreeConfig::SEED, merkle_tree.key().as_ref()],
+        bump,
+        constraint = tree_config.is_admin(&authority.key(), role.as_deref())
+            @ CompressedNotesError::Unauthorized,
+    )]
+    pub tree_config: Account<'info, TreeConfig>,
+
+    // The merkle tree account
+    /// CHECK: Only used to derive the recent leaves and tree config pdas
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
@@ -0,0 +1,203 @@
+//! Per-member roles on a tree in a role pda at `[b"role", merkle_tree, member]`, passed to the
+//! instructions checking them as the optional `role` account.
+
+use anchor_lang::prelude::*;
+
+use crate::{instruction_flags, CompressedNotesError, GlobalConfig, TreeConfig};
+
+// Grants `member` the role `kind` on the tree, replacing any role they held. The role only holds
+// until the tree authority is transferred.
+pub fn process_grant_role(ctx: Context<GrantRole>, member: Pubkey, kind: RoleKind) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::GRANT_ROLE)?;
+
+    // Only the authority makes or unmakes admins
+    let role = &mut ctx.accounts.role;
+    if kind == RoleKind::Admin || (role.member == member && role.kind == RoleKind::Admin) {
+        require!(
+            ctx.accounts
+                .tree_config
+                .is_authority(&ctx.accounts.authority.key()),
+            CompressedNotesError::Unauthorized
+        );
+    }
+
+    role.set(
+        ctx.accounts.merkle_tree.key(),
+        member,
+        kind,
+        ctx.accounts.authority.key(),
+        ctx.accounts.tree_config.authority_epoch(),
+        *ctx.bumps.get("role").unwrap(),
+    );
+    Ok(())
+}
+
+// Revokes the role of a member, refunding the role rent to the signer.
+pub fn process_revoke_role(ctx: Context<RevokeRole>) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::REVOKE_ROLE)?;
+
+    // Only the authority unmakes admins
+    if ctx.accounts.role.kind == RoleKind::Admin {
+        require!(
+            ctx.accounts
+                .tree_config
+                .is_authority(&ctx.accounts.authority.key()),
+            CompressedNotesError::Unauthorized
+        );
+    }
+    Ok(())
+}
+
+// The roles a member can hold on a tree, each including the ones before it
+#[derive(
+    AnchorSerialize,
+    AnchorDeserialize,
+    InitSpace,
+    Clone,
+    Copy,
+    Debug,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+)]
+pub enum RoleKind {
+    Reader,     // Listed as a member, cannot change the tree
+    Writer,     // Appends to and updates notes in a private tree
+    Moderator,  // Takes notes down, see `moderation`
+    Compliance, // Puts leaves on legal hold, see `legal_holds`
+    Admin,      // Runs the tree config instructions short of closing or handing the tree away
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct Role {
+    merkle_tree: Pubkey,  // The tree the role is held on
+    member: Pubkey,       // The member holding the role
+    kind: RoleKind,       // The role held
+    granted_by: Pubkey,   // The authority or admin who granted the role
+    authority_epoch: u32, // The authority epoch of the tree config when the role was granted
+    bump: u8,             // The bump seed for the pda
+}
+
+impl Role {
+    pub const SEED: &'static [u8] = b"role";
+
+    // Whether `role` is held by `member` on `merkle_tree` and includes `kind`, granted under
+    // the current `authority_epoch` of its tree config
+    pub fn grants(
+        role: Option<&Role>,
+        merkle_tree: &Pubkey,
+        authority_epoch: u32,
+        member: &Pubkey,
+        kind: RoleKind,
+    ) -> bool {
+        role.is_some_and(|role| {
+            role.merkle_tree == *merkle_tree
+                && role.authority_epoch == authority_epoch
+                && role.member == *member
+                && role.kind >= kind
+        })
+    }
+
+    // Grants the role, replacing the one held before
+    pub fn set(
+        &mut self,
+        merkle_tree: Pubkey,
+        member: Pubkey,
+        kind: RoleKind,
+        granted_by: Pubkey,
+        authority_epoch: u32,
+        bump: u8,
+    ) {
+        self.merkle_tree = merkle_tree;
+        self.member = member;
+        self.kind = kind;
+        self.granted_by = granted_by;
+        self.authority_epoch = authority_epoch;
+        self.bump = bump;
+    }
+}
+
+#[derive(Accounts)]
+#[instruction(member: Pubkey)]
+pub struct GrantRole<'info> {
+    // The authority of the tree or an admin, pays for the role account
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    // The role of the signer, needed unless it is the tree authority
+    pub signer_role: Option<Account<'info, Role>>,
+
+    // The role pda of the member, created on their first role
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = 8 + Role::INIT_SPACE,
+        seeds = [Role::SEED, merkle_tree.key().as_ref(), member.as_ref()],
+        bump,
+    )]
+    pub role: Account<'info, Role>,
+
+    // The tree config pda
+    #[account(
+        seeds = [TreeConfig::SEED, merkle_tree.key().as_ref()],
+        bump,
+        constraint = tree_config.is_admin(&authority.key(), signer_role.as_deref())
+            @ CompressedNotesError::Unauthorized,
+    )]
+    pub tree_config: Account<'info, TreeConfig>,
+
+    // The merkle tree account
+    /// CHECK: Only used to derive the role and tree config pdas
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    // The system program, used to create the role account
+    pub system_program: Program<'info, System>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[derive(Accounts)]
+pub struct RevokeRole<'info> {
+    // The authority of the tree or an admin, refunded the role rent
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    // The role of the signer, needed unless it is the tree authority
+    pub signer_role: Option<Account<'info, Role>>,
+
+    // The role pda revoked, closed by this instruction
+    #[account(
+        mut,
+        close = authority,
+        seeds = [Role::SEED, merkle_tree.key().as_ref(), role.member.as_ref()],
+        bump = role.bump,
+    )]
+    pub role: Account<'info, Role>,
+
+    // The tree config pda
+    #[account(
+        seeds = [TreeConfig::SEED, merkle_tree.key().as_ref()],
+        bump,
+        constraint = tree_config.is_admin(&authority.key(), signer_role.as_deref())
+            @ CompressedNotesError::Unauthorized,
+    )]
+    pub tree_config: Account<'info, TreeConfig>,
+
+    // The merkle tree account
+    /// CHECK: Only used to derive the role and tree config pdas
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
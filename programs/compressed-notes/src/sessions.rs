@@ -0,0 +1,133 @@
+//! Session keys in a pda at `[b"session", owner, session_key]`, signing appends for their owner
+//! without a wallet prompt per note or message.
+
+use anchor_lang::prelude::*;
+
+use crate::{instruction_flags, CompressedNotesError, GlobalConfig};
+
+// Creates a session letting `session_key` sign the instructions in `scope` for the signer, which
+// only covers `append_note`, `append_draft` and the instructions appending direct messages.
+pub fn process_create_session(
+    ctx: Context<CreateSession>,
+    session_key: Pubkey, // The key allowed to sign for the owner
+    expires_at: i64,     // Unix timestamp after which the session key can no longer sign
+    scope: u128,         // Bitmask of the `instruction_flags` the session key can sign
+) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::CREATE_SESSION)?;
+    require!(
+        expires_at > Clock::get()?.unix_timestamp,
+        CompressedNotesError::InvalidExpiry
+    );
+
+    let session = &mut ctx.accounts.session;
+    session.owner = ctx.accounts.owner.key();
+    session.session_key = session_key;
+    session.expires_at = expires_at;
+    session.scope = scope;
+    session.bump = *ctx.bumps.get("session").unwrap();
+    Ok(())
+}
+
+// Ends a session before it expires, refunding the session rent to the owner.
+pub fn process_revoke_session(ctx: Context<RevokeSession>) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::REVOKE_SESSION)?;
+    Ok(())
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct Session {
+    owner: Pubkey,       // The owner the session key signs for
+    session_key: Pubkey, // The key allowed to sign for the owner
+    expires_at: i64,     // Unix timestamp after which the session key can no longer sign
+    scope: u128,         // Bitmask of the `instruction_flags` the session key can sign
+    bump: u8,            // The bump seed for the pda
+}
+
+impl Session {
+    pub const SEED: &'static [u8] = b"session";
+
+    // The key `signer` acts for: the session's owner if a session is given, otherwise the
+    // signer itself. Only used to derive pdas, the handler checks the session with `resolve`
+    pub fn acting_for(signer: &Pubkey, session: &Option<Account<Session>>) -> Pubkey {
+        session.as_ref().map_or(*signer, |session| session.owner)
+    }
+
+    // Resolves the key `signer` acts for in `instruction`: the session's owner if a session
+    // is given, in which case the signer must be its unexpired session key with the
+    // instruction in scope, otherwise the signer itself
+    pub fn resolve(
+        signer: &Pubkey,
+        session: &Option<Account<Session>>,
+        instruction: u128,
+    ) -> Result<Pubkey> {
+        let Some(session) = session else {
+            return Ok(*signer);
+        };
+        require_keys_eq!(
+            session.session_key,
+            *signer,
+            CompressedNotesError::InvalidSession
+        );
+        require!(
+            Clock::get()?.unix_timestamp <= session.expires_at,
+            CompressedNotesError::SessionExpired
+        );
+        require!(
+            session.scope & instruction == instruction,
+            CompressedNotesError::OutsideSessionScope
+        );
+        Ok(session.owner)
+    }
+}
+
+#[derive(Accounts)]
+#[instruction(session_key: Pubkey)]
+pub struct CreateSession<'info> {
+    // The owner the session key signs for, pays for the session account
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    // The session pda, one per owner and session key
+    #[account(
+        init,
+        payer = owner,
+        space = 8 + Session::INIT_SPACE,
+        seeds = [Session::SEED, owner.key().as_ref(), session_key.as_ref()],
+        bump,
+    )]
+    pub session: Account<'info, Session>,
+
+    // The system program, used to create the session account
+    pub system_program: Program<'info, System>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[derive(Accounts)]
+pub struct RevokeSession<'info> {
+    // The owner of the session, refunded the session rent
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    // The session pda revoked, closed by this instruction
+    #[account(
+        mut,
+        close = owner,
+        seeds = [Session::SEED, owner.key().as_ref(), session.session_key.as_ref()],
+        bump = session.bump,
+    )]
+    pub session: Account<'info, Session>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
@@ -0,0 +1,250 @@
+//! Co-owned notes, whose leaf commits to every owner, each of whom signs appends and updates.
+
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::keccak;
+use spl_account_compression::wrap_application_data_v1;
+
+use crate::{
+    instruction_flags, leaf_domain, leaf_schema, require_clean_text, CompressedNotesError,
+    FeeOperation, NoteAccounts, NoteId, NoteStatus, TreeConfig, TreeState, MAX_NOTE_LEN,
+};
+
+// Most co-owners of a shared note besides the signer, bounded by the transaction size
+pub const MAX_CO_OWNERS: usize = 8;
+
+// Hashes a shared note's message, status and owners into the leaf node. The message is
+// hashed on its own first, so a crafted message cannot shift where the owners sit
+pub fn hash_shared_note_leaf(note: &str, owners: &[Pubkey], status: NoteStatus) -> [u8; 32] {
+    let note_hash = keccak::hash(note.as_bytes()).to_bytes();
+    let status = [status as u8];
+    let tag = leaf_domain::tag(leaf_domain::SHARED_NOTE);
+    let mut data: Vec<&[u8]> = vec![&tag, &note_hash, &status];
+    data.extend(owners.iter().map(|owner| owner.as_ref()));
+    keccak::hashv(&data).to_bytes()
+}
+
+// Appends a published note owned jointly by the signer and `co_owners`, who sign as remaining
+// accounts in the order of `co_owners`. A session or DID passed is ignored.
+pub fn process_append_shared_note<'info>(
+    ctx: Context<'_, '_, '_, 'info, NoteAccounts<'info>>,
+    note: String,           // The note message
+    co_owners: Vec<Pubkey>, // The owners besides the signer, each signing in the remaining accounts
+) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::APPEND_SHARED_NOTE)?;
+    require!(
+        note.len() <= MAX_NOTE_LEN,
+        CompressedNotesError::NoteTooLong
+    );
+    // Refuse text that indexers would display deceptively
+    require_clean_text(&note)?;
+
+    let signer = ctx.accounts.owner.key();
+    let owners = shared_owners(&signer, &co_owners, ctx.remaining_accounts)?;
+    let status = NoteStatus::Published;
+    // Hash the "note message" which will be stored as leaf node in the merkle tree
+    let leaf_node = hash_shared_note_leaf(&note, &owners, status);
+    let note_len = note.len();
+
+    // Create a new "shared note log" using the leaf node hash, the id the leaf will get and note.
+    let note_id = NoteId::next(&ctx.accounts.merkle_tree, leaf_schema::V7)?;
+    let note_log = SharedNoteLog::new(leaf_node, note_id, owners, note, status);
+    // Log the "shared note log" data using noop program
+    wrap_application_data_v1(note_log.try_to_vec()?, &ctx.accounts.log_wrapper)?;
+
+    // Count the append against the rate limits of the tree the signer appends to
+    TreeConfig::record_rate(
+        &ctx.accounts.tree_config,
+        ctx.accounts.rate_state.as_deref_mut(),
+    )?;
+    // A tree refusing duplicate leaves checks the leaf against its recent leaves
+    TreeConfig::record_leaf(
+        &ctx.accounts.tree_config,
+        ctx.accounts.recent_leaves.as_deref_mut(),
+        &leaf_node,
+    )?;
+    // A tree charging an append fee takes it from the signer into the tree treasury
+    TreeConfig::record_fee(
+        &ctx.accounts.tree_config,
+        ctx.accounts.fee_payer(),
+        ctx.accounts.tree_treasury.as_ref(),
+        ctx.accounts.system_program.as_ref(),
+        FeeOperation::Append(note_len),
+    )?;
+    // A tree charging a token append fee takes it from the signer into its fee vault
+    TreeConfig::record_token_fee(
+        &ctx.accounts.tree_config,
+        ctx.accounts.fee_payer(),
+        ctx.accounts.payer_token_account.as_ref(),
+        ctx.accounts.fee_vault.as_ref(),
+        ctx.accounts.token_program.as_ref(),
+    )?;
+    // A token- or nft-gated tree only accepts posters holding its gate
+    TreeConfig::require_gate(
+        &ctx.accounts.tree_config,
+        &signer,
+        ctx.accounts.gate_token_account.as_ref(),
+        ctx.accounts.gate_metadata.as_ref(),
+    )?;
+    // A tree asking posters to stake only accepts posters with enough staked
+    TreeConfig::record_stake(&ctx.accounts.tree_config, ctx.accounts.stake.as_deref_mut())?;
+    // A tree selling access only accepts posters with an active subscription
+    TreeConfig::require_subscription(
+        &ctx.accounts.tree_config,
+        &signer,
+        ctx.accounts.subscription.as_deref(),
+    )?;
+    // A tree restricting cpi callers only accepts the programs it allows
+    TreeConfig::require_caller(
+        &ctx.accounts.tree_config,
+        ctx.accounts.instructions.as_deref(),
+    )?;
+    // Count the append on the tree config, failing if the tree is frozen or private
+    TreeConfig::record_append(
+        &ctx.accounts.tree_config,
+        &ctx.accounts.merkle_tree,
+        &signer,
+        ctx.accounts.role.as_deref(),
+    )?;
+    // CPI to append the leaf node to the merkle tree
+    tree_cpi!(ctx).append(leaf_node)
+}
+
+// Replaces a shared note with a new message, signed by the same quorum of owners in any order.
+pub fn process_update_shared_note<'info>(
+    ctx: Context<'_, '_, '_, 'info, NoteAccounts<'info>>,
+    index: u32,             // Index of the note leaf in the merkle tree
+    root: [u8; 32],         // Root the old leaf is proven against
+    old_note: String,       // The current note message
+    new_note: String,       // The note message replacing it
+    status: NoteStatus,     // The current status of the note
+    co_owners: Vec<Pubkey>, // The owners besides the signer, each signing in the remaining accounts
+) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::UPDATE_SHARED_NOTE)?;
+    require!(
+        new_note.len() <= MAX_NOTE_LEN,
+        CompressedNotesError::NoteTooLong
+    );
+    // Refuse text that indexers would display deceptively
+    require_clean_text(&new_note)?;
+
+    // The co-owners sign first in the remaining accounts, the proof nodes follow them
+    let signer = ctx.accounts.owner.key();
+    let (signers, proof) = ctx
+        .remaining_accounts
+        .split_at(co_owners.len().min(ctx.remaining_accounts.len()));
+    let owners = shared_owners(&signer, &co_owners, signers)?;
+    // The old leaf only matches if the owners are exactly those of the note
+    let old_leaf = hash_shared_note_leaf(&old_note, &owners, status);
+    // An edit that changes nothing fails, so clients do not mistake it for an update
+    require!(old_note != new_note, CompressedNotesError::NoChange);
+
+    // A tree restricting cpi callers only accepts the programs it allows
+    TreeConfig::require_caller(
+        &ctx.accounts.tree_config,
+        ctx.accounts.instructions.as_deref(),
+    )?;
+    // A private tree only lets those who can write to it edit their notes
+    TreeConfig::require_writable(
+        &ctx.accounts.tree_config,
+        &signer,
+        ctx.accounts.role.as_deref(),
+    )?;
+    // A tree charging an update fee takes it from the signer into the tree treasury
+    TreeConfig::record_fee(
+        &ctx.accounts.tree_config,
+        ctx.accounts.fee_payer(),
+        ctx.accounts.tree_treasury.as_ref(),
+        ctx.accounts.system_program.as_ref(),
+        FeeOperation::Update(new_note.len()),
+    )?;
+    // Count the replace on the tree config, failing if the tree is frozen or the root is
+    // older than its recent roots
+    TreeConfig::record_replace(
+        &ctx.accounts.tree_config,
+        &ctx.accounts.merkle_tree,
+        &root,
+        &old_leaf,
+        ctx.accounts.hold.as_deref(),
+    )?;
+    TreeState::load(&ctx.accounts.merkle_tree)?.require_leaf(index)?;
+
+    // Editing a draft keeps it a draft, editing anything else marks it edited
+    let new_status = status.transition(status.edited())?;
+    let new_leaf = hash_shared_note_leaf(&new_note, &owners, new_status);
+
+    // Log out for indexers
+    let note_id = NoteId::new(ctx.accounts.merkle_tree.key(), index, leaf_schema::V7);
+    let note_log = SharedNoteLog::new(new_leaf, note_id, owners, new_note, new_status);
+    // Log the "shared note log" data using noop program
+    wrap_application_data_v1(note_log.try_to_vec()?, &ctx.accounts.log_wrapper)?;
+
+    // CPI to replace the old leaf node with the new one, failing unless the old leaf matches
+    tree_cpi!(ctx)
+        .with_proof(proof)
+        .replace(root, old_leaf, new_leaf, index)
+}
+
+// Checks every co-owner signed in `signers`, in the order given, and returns all owners of
+// the note sorted, failing unless there are 1 to `MAX_CO_OWNERS` distinct co-owners
+fn shared_owners(
+    signer: &Pubkey,
+    co_owners: &[Pubkey],
+    signers: &[AccountInfo],
+) -> Result<Vec<Pubkey>> {
+    require!(
+        !co_owners.is_empty() && co_owners.len() <= MAX_CO_OWNERS,
+        CompressedNotesError::InvalidCoOwners
+    );
+    require!(
+        signers.len() == co_owners.len()
+            && signers
+                .iter()
+                .zip(co_owners)
+                .all(|(account, co_owner)| account.is_signer && account.key == co_owner),
+        CompressedNotesError::MissingCoOwnerSignature
+    );
+
+    let mut owners = co_owners.to_vec();
+    owners.push(*signer);
+    owners.sort();
+    owners.dedup();
+    require!(
+        owners.len() == co_owners.len() + 1,
+        CompressedNotesError::InvalidCoOwners
+    );
+    Ok(owners)
+}
+
+#[derive(AnchorSerialize)]
+pub struct SharedNoteLog {
+    leaf_node: [u8; 32], // The leaf node hash
+    note_id: NoteId,     // The id of the leaf
+    owners: Vec<Pubkey>, // Pubkeys of every owner of the note, sorted
+    note: String,        // The note message
+    status: NoteStatus,  // The status committed into the leaf node
+}
+
+impl SharedNoteLog {
+    // Constructs a new shared note log from given leaf node, id, owners, message and status
+    pub fn new(
+        leaf_node: [u8; 32],
+        note_id: NoteId,
+        owners: Vec<Pubkey>,
+        note: String,
+        status: NoteStatus,
+    ) -> Self {
+        Self {
+            leaf_node,
+            note_id,
+            owners,
+            note,
+            status,
+        }
+    }
+}
@@ -0,0 +1,310 @@
+//! Stake-to-post, locking lamports of each poster in a pda at `[b"stake", merkle_tree, poster]`
+//! that moderators slash into the tree treasury after a takedown.
+
+use anchor_lang::prelude::*;
+use anchor_lang::system_program::{transfer, Transfer};
+use spl_account_compression::{program::SplAccountCompression, wrap_application_data_v1, Noop};
+
+use crate::{
+    hash_takedown_leaf, instruction_flags, CompressedNotesError, GlobalConfig, Role, RoleKind,
+    TreeConfig, TreeConfigAuthority, TREE_TREASURY_SEED,
+};
+
+// The slots a stake stays locked after its poster's last append, about two days
+pub const STAKE_LOCK_SLOTS: u64 = 432_000;
+
+// Sets the lamports posters to the tree must stake, zero for none. Appends then take the
+// poster's stake as the optional `stake` account.
+pub fn process_set_min_stake(ctx: Context<TreeConfigAuthority>, min_stake: u64) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::SET_MIN_STAKE)?;
+
+    ctx.accounts.tree_config.set_min_stake(min_stake);
+    Ok(())
+}
+
+// Locks `amount` more lamports of the signer in their stake on the tree.
+pub fn process_stake_for_posting(ctx: Context<StakeForPosting>, amount: u64) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::STAKE_FOR_POSTING)?;
+
+    let cpi_ctx = CpiContext::new(
+        ctx.accounts.system_program.to_account_info(),
+        Transfer {
+            from: ctx.accounts.staker.to_account_info(),
+            to: ctx.accounts.stake.to_account_info(),
+        },
+    );
+    transfer(cpi_ctx, amount)?;
+
+    // A stake pda created by this instruction is still zeroed
+    let stake = &mut ctx.accounts.stake;
+    if stake.owner == Pubkey::default() {
+        stake.merkle_tree = ctx.accounts.merkle_tree.key();
+        stake.owner = ctx.accounts.staker.key();
+        stake.bump = *ctx.bumps.get("stake").unwrap();
+    }
+    stake.amount += amount;
+    Ok(())
+}
+
+// Returns `amount` staked lamports to the signer, once their stake is unlocked.
+pub fn process_withdraw_stake(ctx: Context<WithdrawStake>, amount: u64) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::WITHDRAW_STAKE)?;
+
+    let stake = &mut ctx.accounts.stake;
+    let slot = Clock::get()?.slot;
+    require!(
+        stake.last_post_slot.saturating_add(STAKE_LOCK_SLOTS) <= slot,
+        CompressedNotesError::StakeLocked
+    );
+    stake.take(amount)?;
+    **stake.to_account_info().try_borrow_mut_lamports()? -= amount;
+    **ctx.accounts.staker.try_borrow_mut_lamports()? += amount;
+    Ok(())
+}
+
+// Moves `amount` staked lamports of a poster into the tree treasury, proving the
+// takedown leaf at `index` of a note of theirs taken down, once per takedown as recorded in a
+// slash receipt pda at `[b"slash_receipt", merkle_tree, takedown_leaf]`.
+pub fn process_slash_stake<'info>(
+    ctx: Context<'_, '_, '_, 'info, SlashStake<'info>>,
+    index: u32,              // Index of the takedown leaf in the merkle tree
+    root: [u8; 32],          // Root the takedown leaf is proven against
+    original_leaf: [u8; 32], // The leaf node of the note taken down
+    amount: u64,             // The staked lamports slashed
+) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::SLASH_STAKE)?;
+
+    // Verify or Fails, only a takedown leaves the takedown leaf of the poster
+    let owner = ctx.accounts.stake.owner;
+    let leaf_node = hash_takedown_leaf(&original_leaf, &owner);
+    tree_cpi!(ctx)
+        .with_proof(ctx.remaining_accounts)
+        .verify(root, leaf_node, index)?;
+    ctx.accounts.slash_receipt.bump = *ctx.bumps.get("slash_receipt").unwrap();
+
+    let stake = &mut ctx.accounts.stake;
+    stake.take(amount)?;
+    **stake.to_account_info().try_borrow_mut_lamports()? -= amount;
+    **ctx.accounts.tree_treasury.try_borrow_mut_lamports()? += amount;
+
+    // Log the slash so posters and auditors can follow moderation penalties
+    let slash_log = SlashLog::new(
+        ctx.accounts.merkle_tree.key(),
+        owner,
+        ctx.accounts.moderator.key(),
+        leaf_node,
+        amount,
+        Clock::get()?.slot,
+    );
+    wrap_application_data_v1(slash_log.try_to_vec()?, &ctx.accounts.log_wrapper)?;
+    Ok(())
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct Stake {
+    merkle_tree: Pubkey, // The tree the stake is posted on
+    owner: Pubkey,       // The poster who staked
+    amount: u64,         // The lamports staked, besides the rent of the pda
+    last_post_slot: u64, // The slot of the poster's last append, zero before any
+    bump: u8,            // The bump seed for the pda
+}
+
+impl Stake {
+    pub const SEED: &'static [u8] = b"stake";
+
+    // Records an append at `slot`, failing unless at least `min_stake` lamports are staked
+    pub fn record_post(&mut self, min_stake: u64, slot: u64) -> Result<()> {
+        require!(
+            self.amount >= min_stake,
+            CompressedNotesError::BelowMinStake
+        );
+        self.last_post_slot = slot;
+        Ok(())
+    }
+
+    // Takes `amount` lamports off the stake, failing if fewer are staked
+    fn take(&mut self, amount: u64) -> Result<()> {
+        self.amount = self
+            .amount
+            .checked_sub(amount)
+            .ok_or(CompressedNotesError::InsufficientStake)?;
+        Ok(())
+    }
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct SlashReceipt {
+    bump: u8, // The bump seed for the pda
+}
+
+impl SlashReceipt {
+    pub const SEED: &'static [u8] = b"slash_receipt";
+}
+
+#[derive(AnchorSerialize)]
+pub struct SlashLog {
+    merkle_tree: Pubkey, // The tree the stake is posted on
+    owner: Pubkey,       // Pubkey of the poster slashed
+    moderator: Pubkey,   // Pubkey of the moderator
+    leaf_node: [u8; 32], // The takedown leaf node proven
+    amount: u64,         // The lamports slashed
+    slot: u64,           // The slot of the slash
+}
+
+impl SlashLog {
+    // Constructs a new slash log from given tree, owner, moderator, leaf node, amount and slot
+    pub fn new(
+        merkle_tree: Pubkey,
+        owner: Pubkey,
+        moderator: Pubkey,
+        leaf_node: [u8; 32],
+        amount: u64,
+        slot: u64,
+    ) -> Self {
+        Self {
+            merkle_tree,
+            owner,
+            moderator,
+            leaf_node,
+            amount,
+            slot,
+        }
+    }
+}
+
+#[derive(Accounts)]
+pub struct StakeForPosting<'info> {
+    // The poster staking, pays the stake and the stake account
+    #[account(mut)]
+    pub staker: Signer<'info>,
+
+    // The stake pda, one per tree and poster, created on the first stake
+    #[account(
+        init_if_needed,
+        payer = staker,
+        space = 8 + Stake::INIT_SPACE,
+        seeds = [Stake::SEED, merkle_tree.key().as_ref(), staker.key().as_ref()],
+        bump,
+    )]
+    pub stake: Account<'info, Stake>,
+
+    // The merkle tree account
+    /// CHECK: Only used to derive the stake pda
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    // The system program, used to create and fund the stake account
+    pub system_program: Program<'info, System>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawStake<'info> {
+    // The poster who staked, receives the withdrawn lamports
+    #[account(mut)]
+    pub staker: Signer<'info>,
+
+    // The stake pda withdrawn from
+    #[account(
+        mut,
+        seeds = [Stake::SEED, merkle_tree.key().as_ref(), staker.key().as_ref()],
+        bump = stake.bump,
+    )]
+    pub stake: Account<'info, Stake>,
+
+    // The merkle tree account
+    /// CHECK: Only used to derive the stake pda
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[derive(Accounts)]
+#[instruction(index: u32, root: [u8; 32], original_leaf: [u8; 32])]
+pub struct SlashStake<'info> {
+    // The moderator slashing the stake, pays for the slash receipt
+    #[account(mut)]
+    pub moderator: Signer<'info>,
+
+    // The role of the signer, which must include the moderator role
+    #[account(
+        constraint = tree_config.grants(Some(&role), &moderator.key(), RoleKind::Moderator)
+            @ CompressedNotesError::Unauthorized,
+    )]
+    pub role: Account<'info, Role>,
+
+    // The stake pda slashed
+    #[account(
+        mut,
+        seeds = [Stake::SEED, merkle_tree.key().as_ref(), stake.owner.as_ref()],
+        bump = stake.bump,
+    )]
+    pub stake: Account<'info, Stake>,
+
+    // The slash receipt pda of the takedown, created by its only slash
+    #[account(
+        init,
+        payer = moderator,
+        space = 8 + SlashReceipt::INIT_SPACE,
+        seeds = [
+            SlashReceipt::SEED,
+            merkle_tree.key().as_ref(),
+            hash_takedown_leaf(&original_leaf, &stake.owner).as_ref(),
+        ],
+        bump,
+    )]
+    pub slash_receipt: Account<'info, SlashReceipt>,
+
+    // The tree treasury pda, receives the slashed lamports
+    #[account(mut, seeds = [TREE_TREASURY_SEED, merkle_tree.key().as_ref()], bump)]
+    pub tree_treasury: SystemAccount<'info>,
+
+    // The tree config pda, only read for the authority epoch of the role
+    #[account(seeds = [TreeConfig::SEED, merkle_tree.key().as_ref()], bump)]
+    pub tree_config: Account<'info, TreeConfig>,
+
+    // The pda authority for the merkle tree, only used to check the tree
+    #[account(
+        seeds = [merkle_tree.key().as_ref()],
+        bump,
+    )]
+    pub tree_authority: SystemAccount<'info>,
+
+    // The merkle tree account, only read
+    /// CHECK: This account is validated by the spl account compression program
+    #[account(
+        owner = spl_account_compression::ID @ CompressedNotesError::InvalidMerkleTreeOwner,
+    )]
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    // The noop program to log data
+    pub log_wrapper: Program<'info, Noop>,
+
+    // The spl account compression program
+    pub compression_program: Program<'info, SplAccountCompression>,
+
+    // The system program, used to create the slash receipt
+    pub system_program: Program<'info, System>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
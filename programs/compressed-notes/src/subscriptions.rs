@@ -0,0 +1,195 @@
+//! Paid subscriptions to trees in a pda at `[b"tree_subscription", merkle_tree, subscriber]`,
+//! separate from the free channel subscriptions of `channels`.
+
+use anchor_lang::prelude::*;
+
+use crate::{
+    instruction_flags, pay_treasury, top_up_tree_treasury, CompressedNotesError, GlobalConfig,
+    Role, TreeConfig, TREE_TREASURY_SEED,
+};
+
+// The seconds of access bought per day of a subscription
+pub const SECONDS_PER_DAY: i64 = 86_400;
+
+// Sets the lamports a day of access to the tree costs, zero to stop selling access, topping the
+// tree treasury up like `set_append_fee`. While a tree sells access, the instructions appending
+// to it take the poster's subscription as the optional `subscription` account.
+pub fn process_set_subscription_price(
+    ctx: Context<SetSubscriptionPrice>,
+    price_per_day: u64,
+) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::SET_SUBSCRIPTION_PRICE)?;
+
+    if price_per_day > 0 {
+        top_up_tree_treasury(
+            &ctx.accounts.system_program,
+            &ctx.accounts.authority,
+            &ctx.accounts.tree_treasury,
+        )?;
+    }
+    ctx.accounts
+        .tree_config
+        .set_subscription_price(price_per_day);
+    Ok(())
+}
+
+// Buys `duration` days of access to `merkle_tree` for the signer, paying the tree treasury and
+// extending a subscription that has not expired yet.
+pub fn process_subscribe_to_tree(
+    ctx: Context<SubscribeToTree>,
+    merkle_tree: Pubkey, // The premium tree subscribed to
+    duration: u32,       // The days of access bought
+) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::SUBSCRIBE_TO_TREE)?;
+
+    let price = ctx.accounts.tree_config.subscription_cost(duration)?;
+    pay_treasury(
+        &ctx.accounts.system_program,
+        &ctx.accounts.subscriber,
+        &ctx.accounts.tree_treasury,
+        price,
+    )?;
+
+    // A subscription pda created by this instruction is still zeroed, and starts now
+    let now = Clock::get()?.unix_timestamp;
+    let subscription = &mut ctx.accounts.subscription;
+    if subscription.subscriber == Pubkey::default() {
+        subscription.merkle_tree = merkle_tree;
+        subscription.subscriber = ctx.accounts.subscriber.key();
+        subscription.bump = *ctx.bumps.get("subscription").unwrap();
+    }
+    subscription.expires_at =
+        subscription.expires_at.max(now) + i64::from(duration) * SECONDS_PER_DAY;
+    Ok(())
+}
+
+// Fails unless `subscriber` has an active subscription to `merkle_tree`, for apps gating reads.
+pub fn process_check_subscription(
+    ctx: Context<CheckSubscription>,
+    merkle_tree: Pubkey, // The premium tree
+    subscriber: Pubkey,  // Pubkey of the member whose access is checked
+) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::CHECK_SUBSCRIPTION)?;
+
+    ctx.accounts
+        .subscription
+        .require_active(&merkle_tree, &subscriber)
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct TreeSubscription {
+    merkle_tree: Pubkey, // The premium tree subscribed to
+    subscriber: Pubkey,  // Pubkey of the subscriber
+    expires_at: i64,     // The unix timestamp access ends
+    bump: u8,            // The bump seed for the pda
+}
+
+impl TreeSubscription {
+    pub const SEED: &'static [u8] = b"tree_subscription";
+
+    // Fails unless this is an unexpired subscription of `subscriber` to `merkle_tree`
+    pub fn require_active(&self, merkle_tree: &Pubkey, subscriber: &Pubkey) -> Result<()> {
+        require!(
+            self.merkle_tree == *merkle_tree && self.subscriber == *subscriber,
+            CompressedNotesError::SubscriptionMissing
+        );
+        require!(
+            self.expires_at > Clock::get()?.unix_timestamp,
+            CompressedNotesError::SubscriptionExpired
+        );
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+pub struct SetSubscriptionPrice<'info> {
+    // The authority of the tree or an admin, tops the tree treasury up to its rent
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    // The role of the signer, needed unless it is the tree authority
+    pub role: Option<Account<'info, Role>>,
+
+    // The tree config pda, recording the subscription price
+    #[account(
+        mut,
+        seeds = [TreeConfig::SEED, merkle_tree.key().as_ref()],
+        bump,
+        constraint = tree_config.is_admin(&authority.key(), role.as_deref())
+            @ CompressedNotesError::Unauthorized,
+    )]
+    pub tree_config: Account<'info, TreeConfig>,
+
+    // The tree treasury pda, collecting the subscription payments
+    #[account(mut, seeds = [TREE_TREASURY_SEED, merkle_tree.key().as_ref()], bump)]
+    pub tree_treasury: SystemAccount<'info>,
+
+    // The merkle tree account
+    /// CHECK: Only used to derive the tree config and tree treasury pdas
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    // The system program, used to top the tree treasury up
+    pub system_program: Program<'info, System>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[derive(Accounts)]
+#[instruction(merkle_tree: Pubkey)]
+pub struct SubscribeToTree<'info> {
+    // The subscriber, pays for the access and the subscription account
+    #[account(mut)]
+    pub subscriber: Signer<'info>,
+
+    // The tree subscription pda, one per tree and subscriber, created on the first purchase
+    #[account(
+        init_if_needed,
+        payer = subscriber,
+        space = 8 + TreeSubscription::INIT_SPACE,
+        seeds = [TreeSubscription::SEED, merkle_tree.as_ref(), subscriber.key().as_ref()],
+        bump,
+    )]
+    pub subscription: Account<'info, TreeSubscription>,
+
+    // The tree config pda, naming the subscription price
+    #[account(seeds = [TreeConfig::SEED, merkle_tree.as_ref()], bump)]
+    pub tree_config: Account<'info, TreeConfig>,
+
+    // The tree treasury pda, receives the payment
+    #[account(mut, seeds = [TREE_TREASURY_SEED, merkle_tree.as_ref()], bump)]
+    pub tree_treasury: SystemAccount<'info>,
+
+    // The system program, used to create the subscription account and pay the treasury
+    pub system_program: Program<'info, System>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[derive(Accounts)]
+#[instruction(merkle_tree: Pubkey, subscriber: Pubkey)]
+pub struct CheckSubscription<'info> {
+    // The tree subscription pda checked
+    #[account(
+        seeds = [TreeSubscription::SEED, merkle_tree.as_ref(), subscriber.as_ref()],
+        bump = subscription.bump,
+    )]
+    pub subscription: Account<'info, TreeSubscription>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
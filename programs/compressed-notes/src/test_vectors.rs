@@ -0,0 +1,379 @@
+//! Known-answer vectors for the leaf hashing of every leaf schema, also shipped as
+//! JSON in `tests/fixtures/leaf-vectors.json` for client implementations.
+
+use crate::NoteStatus;
+use anchor_lang::prelude::*;
+
+// Known inputs -> leaf hash for one leaf schema version, fields a schema does not use are none
+#[allow(dead_code)] // Some fields are only hashed by feature gated subsystems
+struct LeafVector {
+    schema_version: u16,             // The leaf schema the vector was produced with
+    content: &'static str,           // The note or message, or the claims of a certificate
+    owner: Pubkey,                   // The owner, sender, subject, organization or acknowledger
+    recipient: Option<Pubkey>,       // The recipient of a direct message
+    co_owners: &'static [Pubkey],    // The owners of a shared note besides `owner`, or recipients
+    status: Option<NoteStatus>,      // The note status
+    nonce: Option<u64>,              // The nonce of a direct message
+    timestamp: Option<i64>,          // The expiry of a certificate or ttl message, or org note time
+    eth_address: Option<[u8; 20]>,   // The linked ethereum address of an attributed note
+    original_leaf: Option<[u8; 32]>, // The leaf node an acknowledgement is for or a forward is of
+    original_sender: Option<Pubkey>, // The sender of a forwarded message
+    edit_count: Option<u32>,         // The edits of an edited message
+    key_exchange: Option<([u8; 24], [u8; 32])>, // The nonce and ephemeral key of an encryption
+    leaf_hash: &'static str,         // The expected leaf node hash, hex encoded
+}
+
+// A vector with no inputs, the base the vectors below fill in
+const BLANK: LeafVector = LeafVector {
+    schema_version: 0,
+    content: "",
+    owner: Pubkey::new_from_array([0; 32]),
+    recipient: None,
+    co_owners: &[],
+    status: None,
+    nonce: None,
+    timestamp: None,
+    eth_address: None,
+    original_leaf: None,
+    original_sender: None,
+    edit_count: None,
+    key_exchange: None,
+    leaf_hash: "",
+};
+
+impl LeafVector {
+    // Recomputes the leaf node with the program's hashing, none if its subsystem is not compiled
+    fn compute(&self) -> Option<[u8; 32]> {
+        let status = || self.status.expect("the schema commits to a status");
+        match self.schema_version {
+            crate::leaf_schema::V1 => Some(crate::hash_note_leaf(self.content, &self.owner)),
+            crate::leaf_schema::V2 => Some(crate::hash_note_leaf_v2(
+                self.content,
+                &self.owner,
+                status(),
+            )),
+            #[cfg(feature = "certificates")]
+            crate::leaf_schema::V3 => Some(crate::hash_certificate_leaf(
+                &self.owner,
+                &anchor_lang::solana_program::keccak::hash(self.content.as_bytes()).to_bytes(),
+                self.timestamp.expect("certificates commit to an expiry"),
+            )),
+            #[cfg(feature = "orgs")]
+            crate::leaf_schema::V4 => Some(crate::hash_org_note_leaf(
+                self.content,
+                &self.owner,
+                self.timestamp.expect("org notes commit to a posting time"),
+            )),
+            #[cfg(feature = "messaging")]
+            crate::leaf_schema::V5 => Some(crate::hash_message_leaf_v2(
+                self.content,
+                &self.owner,
+                &self
+                    .recipient
+                    .expect("direct messages commit to a recipient"),
+                self.nonce.expect("direct messages commit to a nonce"),
+                None,
+                0,
+                crate::message_priority::NORMAL,
+            )),
+            #[cfg(feature = "messaging")]
+            crate::leaf_schema::V6 => Some(crate::hash_acknowledgement_leaf(
+                &self
+                    .original_leaf
+                    .expect("acknowledgements commit to a leaf"),
+                &self.owner,
+            )),
+            crate::leaf_schema::V7 => {
+                let mut owners = self.co_owners.to_vec();
+                owners.push(self.owner);
+                owners.sort();
+                Some(crate::hash_shared_note_leaf(
+                    self.content,
+                    &owners,
+                    status(),
+                ))
+            }
+            crate::leaf_schema::V8 => Some(crate::hash_attributed_note_leaf(
+                self.content,
+                &self.owner,
+                status(),
+                &self
+                    .eth_address
+                    .expect("attributed notes commit to an address"),
+            )),
+            #[cfg(feature = "messaging")]
+            crate::leaf_schema::V9 => Some(crate::hash_ttl_message_leaf(
+                self.content,
+                &self.owner,
+                self.timestamp.expect("ttl messages commit to an expiry"),
+            )),
+            #[cfg(feature = "messaging")]
+            crate::leaf_schema::V10 => Some(crate::hash_edited_message_leaf(
+                self.content,
+                &self.owner,
+                None,
+                self.edit_count
+                    .expect("edited messages commit to an edit count"),
+                crate::message_priority::NORMAL,
+            )),
+            #[cfg(feature = "messaging")]
+            crate::leaf_schema::V11 => {
+                let (nonce, sender_ephemeral_pubkey) = self
+                    .key_exchange
+                    .expect("encrypted messages commit to a key exchange");
+                Some(crate::hash_encrypted_message_leaf(
+                    self.content.as_bytes(),
+                    &self.owner,
+                    &nonce,
+                    &sender_ephemeral_pubkey,
+                ))
+            }
+            #[cfg(feature = "messaging")]
+            crate::leaf_schema::V12 => Some(crate::hash_forwarded_message_leaf(
+                self.content,
+                &self.owner,
+                &self.original_leaf.expect("forwards commit to a leaf"),
+                &self
+                    .original_sender
+                    .expect("forwards commit to the original sender"),
+            )),
+            #[cfg(feature = "messaging")]
+            crate::leaf_schema::V13 => Some(crate::hash_group_message_leaf(
+                self.content,
+                &self.owner,
+                self.co_owners,
+            )),
+            _ => None,
+        }
+    }
+}
+
+const LEAF_VECTORS: &[LeafVector] = &[
+    LeafVector {
+        schema_version: 1,
+        content: "hello world",
+        leaf_hash: "215e2c5e77c43fe08eba8d350d2a2d1d000c7c766f20e378aa011bfa461932de",
+        ..BLANK
+    },
+    LeafVector {
+        schema_version: 1,
+        content: "hello world",
+        owner: Pubkey::new_from_array([1; 32]),
+        leaf_hash: "e13824038eef6e414a4b2c0966ac526ba0bb59f75e5b925db19e75f14e180974",
+        ..BLANK
+    },
+    LeafVector {
+        schema_version: 1,
+        content: "",
+        owner: Pubkey::new_from_array([1; 32]),
+        leaf_hash: "cebc8882fecbec7fb80d2cf4b312bec018884c2d66667c67a90508214bd8bafc",
+        ..BLANK
+    },
+    LeafVector {
+        schema_version: 1,
+        content: "updated note",
+        owner: Pubkey::new_from_array([1; 32]),
+        leaf_hash: "c10de262aa2a6fc34d61b2c663e2fe4ee38fe9078baa6c50601a35eae525dcb8",
+        ..BLANK
+    },
+    LeafVector {
+        schema_version: 1,
+        content: "héllo, wörld ✓",
+        owner: Pubkey::new_from_array([2; 32]),
+        leaf_hash: "ebd1cbc7896fa6a786809d668f8a91d7c12fe3f2de49bfb918195df05b42cbdc",
+        ..BLANK
+    },
+    LeafVector {
+        schema_version: 2,
+        content: "hello world",
+        owner: Pubkey::new_from_array([1; 32]),
+        status: Some(NoteStatus::Published),
+        leaf_hash: "764b4d9c633aa9eec0037806cff4a82517d5a8d0b128c5b744469c62644d00be",
+        ..BLANK
+    },
+    LeafVector {
+        schema_version: 2,
+        content: "draft note",
+        owner: Pubkey::new_from_array([1; 32]),
+        status: Some(NoteStatus::Draft),
+        leaf_hash: "506ef16c83e3f5daa9e19308747f605e365e4d8ed729c1878879b74e52b8b4d0",
+        ..BLANK
+    },
+    LeafVector {
+        schema_version: 2,
+        content: "updated note",
+        owner: Pubkey::new_from_array([1; 32]),
+        status: Some(NoteStatus::Edited),
+        leaf_hash: "810aa4a8dcf4f3c9fd48f397d31b9c1eb661164adae2dbef9315a3fbeeb61f37",
+        ..BLANK
+    },
+    LeafVector {
+        schema_version: 2,
+        content: "",
+        owner: Pubkey::new_from_array([1; 32]),
+        status: Some(NoteStatus::Deleted),
+        leaf_hash: "a79e7a4d390ed890064ecec9b037fc7c0108ad91fcf3df263eba2489f9986578",
+        ..BLANK
+    },
+    LeafVector {
+        schema_version: 2,
+        content: "",
+        owner: Pubkey::new_from_array([2; 32]),
+        status: Some(NoteStatus::Redacted),
+        leaf_hash: "39306fae22eb4fa05b74d271c79ec8193069f5709aff926476bf9c9f4bb60b22",
+        ..BLANK
+    },
+    LeafVector {
+        schema_version: 3,
+        content: "kyc:passed",
+        owner: Pubkey::new_from_array([1; 32]),
+        timestamp: Some(1_700_000_000),
+        leaf_hash: "56d08510a4157b9bb4247de8b83eb2c5d8978aa44fc973eeccee44a1cacb6a65",
+        ..BLANK
+    },
+    LeafVector {
+        schema_version: 4,
+        content: "quarterly report",
+        owner: Pubkey::new_from_array([3; 32]),
+        timestamp: Some(1_700_000_000),
+        leaf_hash: "82dc5d0b831409a6d04925f8ec78db6a384fb3b5249eab64fb6b2bc74c42b06c",
+        ..BLANK
+    },
+    LeafVector {
+        schema_version: 5,
+        content: "hello bob",
+        owner: Pubkey::new_from_array([1; 32]),
+        recipient: Some(Pubkey::new_from_array([2; 32])),
+        nonce: Some(7),
+        leaf_hash: "f9b8e26cc2511d67124f1040783bbc72f2a157bd4f11955b599ff1cf3a3f25b4",
+        ..BLANK
+    },
+    LeafVector {
+        schema_version: 6,
+        owner: Pubkey::new_from_array([2; 32]),
+        original_leaf: Some([9; 32]),
+        leaf_hash: "010b64fd05e3f9ef49c04ad1d44c2c8d8352625fe44467c8cb414c6b9e0c42a8",
+        ..BLANK
+    },
+    LeafVector {
+        schema_version: 7,
+        content: "shared plan",
+        owner: Pubkey::new_from_array([2; 32]),
+        co_owners: &[Pubkey::new_from_array([1; 32])],
+        status: Some(NoteStatus::Published),
+        leaf_hash: "a2e19dbd8a3ad24f0e472d6e477a17dd68d739c7367e7ff5fee064b62fea6d5f",
+        ..BLANK
+    },
+    LeafVector {
+        schema_version: 8,
+        content: "hello world",
+        owner: Pubkey::new_from_array([1; 32]),
+        status: Some(NoteStatus::Published),
+        eth_address: Some([0xab; 20]),
+        leaf_hash: "94bf5f119620c5fec443bca83b9e3e790fce7d6fb614ac405b39e1adf9945133",
+        ..BLANK
+    },
+    LeafVector {
+        schema_version: 9,
+        content: "gone soon",
+        owner: Pubkey::new_from_array([1; 32]),
+        timestamp: Some(1_700_000_000),
+        leaf_hash: "04495a8be42e4fe25fa4c6ff625564313e0b501fe2bd569132d50addc58f81b0",
+        ..BLANK
+    },
+    LeafVector {
+        schema_version: 10,
+        content: "hello again bob",
+        owner: Pubkey::new_from_array([1; 32]),
+        edit_count: Some(1),
+        leaf_hash: "c9bf109ca2f5655d7c1daca162fc647ed19ae00ba41e64ae4f8f8b8ad1191119",
+        ..BLANK
+    },
+    LeafVector {
+        schema_version: 11,
+        content: "ciphertext",
+        owner: Pubkey::new_from_array([1; 32]),
+        key_exchange: Some(([4; 24], [5; 32])),
+        leaf_hash: "0bcf9a062a4117e2d339a16c1fd41f51507bf41e19b4b8fc7871d4e7bb996f81",
+        ..BLANK
+    },
+    LeafVector {
+        schema_version: 12,
+        content: "hello bob",
+        owner: Pubkey::new_from_array([2; 32]),
+        original_leaf: Some([9; 32]),
+        original_sender: Some(Pubkey::new_from_array([1; 32])),
+        leaf_hash: "bd93336626b33dc304e789233d71d15366108eff615bed4340caede640e91b22",
+        ..BLANK
+    },
+    LeafVector {
+        schema_version: 13,
+        content: "hello group",
+        owner: Pubkey::new_from_array([1; 32]),
+        co_owners: &[
+            Pubkey::new_from_array([2; 32]),
+            Pubkey::new_from_array([3; 32]),
+        ],
+        leaf_hash: "7930e78b47203f2cae764c9132ad914cc3d222f66acda44293dfd35a8e701d43",
+        ..BLANK
+    },
+];
+
+// The JSON fixtures clients check their hashing against
+const FIXTURES: &str = include_str!("../../../tests/fixtures/leaf-vectors.json");
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[test]
+fn leaf_vectors_match_program_hashing() {
+    for vector in LEAF_VECTORS {
+        if let Some(leaf) = vector.compute() {
+            assert_eq!(
+                hex(&leaf),
+                vector.leaf_hash,
+                "schema {} vector for {:?}",
+                vector.schema_version,
+                vector.content
+            );
+        }
+    }
+}
+
+#[test]
+fn leaf_vectors_cover_every_schema() {
+    for schema_version in crate::leaf_schema::V1..=crate::leaf_schema::V13 {
+        assert!(
+            LEAF_VECTORS
+                .iter()
+                .any(|vector| vector.schema_version == schema_version),
+            "no vector for schema {}",
+            schema_version
+        );
+    }
+}
+
+#[test]
+fn leaf_vectors_are_in_the_fixtures() {
+    for vector in LEAF_VECTORS {
+        assert!(
+            FIXTURES.contains(vector.leaf_hash),
+            "the fixtures miss the schema {} vector {}",
+            vector.schema_version,
+            vector.leaf_hash
+        );
+    }
+}
+
+#[cfg(all(feature = "orgs", feature = "messaging"))]
+#[test]
+fn org_notes_do_not_pass_for_ttl_messages() {
+    // Anyone can burn an expired ttl message, so a ttl message of the organization expiring
+    // when the note was posted must not hash to the org note
+    let organization = Pubkey::new_from_array([3; 32]);
+    assert_ne!(
+        crate::hash_org_note_leaf("quarterly report", &organization, 1_700_000_000),
+        crate::hash_ttl_message_leaf("quarterly report", &organization, 1_700_000_000)
+    );
+}
@@ -0,0 +1,43 @@
+//! Checks on the text of notes and messages before it is hashed into a leaf, refusing rather than
+//! altering text indexers would display deceptively.
+
+use anchor_lang::prelude::*;
+use unicode_normalization::is_nfc;
+
+use crate::CompressedNotesError;
+
+// Fails unless `text` is free of control and invisible characters and in NFC form. Newlines,
+// tabs and zero-width joiners are allowed, and only new text is checked, so old leaves stay
+// editable
+pub fn require_clean_text(text: &str) -> Result<()> {
+    require!(
+        !text
+            .chars()
+            .any(|c| c.is_control() && c != '\n' && c != '\t'),
+        CompressedNotesError::ControlCharacter
+    );
+    require!(
+        !text.chars().any(is_invisible),
+        CompressedNotesError::InvisibleCharacter
+    );
+    require!(is_nfc(text), CompressedNotesError::TextNotNormalized);
+    Ok(())
+}
+
+// Whether `c` renders as nothing or reorders the text around it
+fn is_invisible(c: char) -> bool {
+    matches!(
+        c,
+        '\u{00AD}' // Soft hyphen
+            | '\u{034F}' // Combining grapheme joiner
+            | '\u{061C}' // Arabic letter mark
+            | '\u{115F}' | '\u{1160}' | '\u{3164}' | '\u{FFA0}' // Hangul fillers
+            | '\u{180E}' // Mongolian vowel separator
+            | '\u{200B}' // Zero-width space
+            | '\u{200E}' | '\u{200F}' // Left-to-right and right-to-left marks
+            | '\u{202A}'..='\u{202E}' // Bidirectional embeddings and overrides
+            | '\u{2060}'..='\u{2064}' // Word joiner and invisible operators
+            | '\u{2066}'..='\u{2069}' // Bidirectional isolates
+            | '\u{FEFF}' // Zero-width no-break space
+    )
+}
@@ -0,0 +1,170 @@
+//! Tips paying the owner a note leaf commits to, in lamports or an spl token.
+
+use anchor_lang::prelude::*;
+use anchor_lang::system_program::{transfer, Transfer};
+use anchor_spl::token::{self, Token, TokenAccount};
+use spl_account_compression::{program::SplAccountCompression, wrap_application_data_v1, Noop};
+
+use crate::{
+    hash_note_leaf_for, instruction_flags, CompressedNotesError, GlobalConfig, IdentityLink,
+    NoteId, NoteStatus,
+};
+
+// Pays `amount` to the owner of the note leaf at `index`, proven from its message and status,
+// in the token of `tipper_token_account` if passed. Shared notes cannot be tipped.
+pub fn process_tip_note<'info>(
+    ctx: Context<'_, '_, '_, 'info, TipNote<'info>>,
+    index: u32,         // Index of the note leaf in the merkle tree
+    root: [u8; 32],     // Root the note leaf is proven against
+    note: String,       // The note message
+    status: NoteStatus, // The current status of the note
+    amount: u64,        // Lamports, or tokens of the tipper's token account, paid to the owner
+) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::TIP_NOTE)?;
+    require!(amount > 0, CompressedNotesError::ZeroTip);
+
+    // Verify or Fails, the leaf commits to the owner paid
+    let owner = ctx.accounts.owner.key();
+    let (leaf_node, schema_version) =
+        hash_note_leaf_for(&note, &owner, status, &ctx.accounts.identity_link)?;
+    tree_cpi!(ctx)
+        .with_proof(ctx.remaining_accounts)
+        .verify(root, leaf_node, index)?;
+
+    let accounts = &ctx.accounts;
+    let mint = match (
+        &accounts.tipper_token_account,
+        &accounts.owner_token_account,
+        &accounts.token_program,
+    ) {
+        (Some(tipper_token_account), Some(owner_token_account), Some(token_program)) => {
+            require_keys_eq!(
+                owner_token_account.owner,
+                owner,
+                CompressedNotesError::InvalidTipAccount
+            );
+            let cpi_ctx = CpiContext::new(
+                token_program.to_account_info(),
+                token::Transfer {
+                    from: tipper_token_account.to_account_info(),
+                    to: owner_token_account.to_account_info(),
+                    authority: accounts.tipper.to_account_info(),
+                },
+            );
+            token::transfer(cpi_ctx, amount)?;
+            Some(owner_token_account.mint)
+        }
+        (None, None, _) => {
+            let cpi_ctx = CpiContext::new(
+                accounts.system_program.to_account_info(),
+                Transfer {
+                    from: accounts.tipper.to_account_info(),
+                    to: accounts.owner.to_account_info(),
+                },
+            );
+            transfer(cpi_ctx, amount)?;
+            None
+        }
+        _ => return err!(CompressedNotesError::InvalidTipAccount),
+    };
+
+    // Log the tip so creator monetization UIs can attribute it to the note
+    let note_id = NoteId::new(accounts.merkle_tree.key(), index, schema_version);
+    let tip_log = TipLog::new(
+        leaf_node,
+        note_id,
+        accounts.tipper.key(),
+        owner,
+        amount,
+        mint,
+    );
+    wrap_application_data_v1(tip_log.try_to_vec()?, &accounts.log_wrapper)?;
+    Ok(())
+}
+
+#[derive(AnchorSerialize)]
+pub struct TipLog {
+    leaf_node: [u8; 32],  // The leaf node hash of the note tipped
+    note_id: NoteId,      // The id of the leaf
+    tipper: Pubkey,       // Pubkey of the tipper
+    owner: Pubkey,        // Pubkey of the note owner paid
+    amount: u64,          // Lamports, or tokens of `mint`, paid
+    mint: Option<Pubkey>, // The mint of the tokens paid, none for lamports
+}
+
+impl TipLog {
+    // Constructs a new tip log from given leaf node, id, tipper, owner, amount and mint
+    pub fn new(
+        leaf_node: [u8; 32],
+        note_id: NoteId,
+        tipper: Pubkey,
+        owner: Pubkey,
+        amount: u64,
+        mint: Option<Pubkey>,
+    ) -> Self {
+        Self {
+            leaf_node,
+            note_id,
+            tipper,
+            owner,
+            amount,
+            mint,
+        }
+    }
+}
+
+#[derive(Accounts)]
+pub struct TipNote<'info> {
+    // The tipper, pays the tip
+    #[account(mut)]
+    pub tipper: Signer<'info>,
+
+    // The owner committed into the note leaf, paid the tip in lamports
+    /// CHECK: Checked against the note leaf, only credited
+    #[account(mut)]
+    pub owner: UncheckedAccount<'info>,
+
+    // The identity link of the owner, needed to tip an attributed note
+    pub identity_link: Option<Account<'info, IdentityLink>>,
+
+    // The tipper's token account, needed to tip in an spl token
+    #[account(mut)]
+    pub tipper_token_account: Option<Account<'info, TokenAccount>>,
+
+    // A token account of the owner of the same mint, needed to tip in an spl token
+    #[account(mut)]
+    pub owner_token_account: Option<Account<'info, TokenAccount>>,
+
+    // The spl token program, needed to tip in an spl token
+    pub token_program: Option<Program<'info, Token>>,
+
+    // The pda authority for the merkle tree, only used to check the tree
+    #[account(
+        seeds = [merkle_tree.key().as_ref()],
+        bump,
+    )]
+    pub tree_authority: SystemAccount<'info>,
+
+    // The merkle tree account, only read
+    /// CHECK: This account is validated by the spl account compression program
+    #[account(
+        owner = spl_account_compression::ID @ CompressedNotesError::InvalidMerkleTreeOwner,
+    )]
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    // The noop program to log data
+    pub log_wrapper: Program<'info, Noop>,
+
+    // The spl account compression program
+    pub compression_program: Program<'info, SplAccountCompression>,
+
+    // The system program, used to pay tips in lamports
+    pub system_program: Program<'info, System>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
@@ -0,0 +1,166 @@
+//! Append fees paid in an spl token into a fee vault pda at `[b"fee_vault", merkle_tree, mint]`,
+//! charged alongside the lamport append fee of `treasury`.
+
+use anchor_lang::prelude::*;
+use anchor_spl::token::{transfer, Mint, Token, TokenAccount, Transfer};
+
+use crate::{instruction_flags, CompressedNotesError, GlobalConfig, Role, TreeConfig};
+
+pub const FEE_VAULT_SEED: &[u8] = b"fee_vault";
+
+// Charges `amount` of the fee mint for each append to the tree, zero for none, creating the fee
+// vault of the mint. Appends then take the `payer_token_account`, `fee_vault` and
+// `token_program` accounts.
+pub fn process_set_token_append_fee(ctx: Context<SetTokenAppendFee>, amount: u64) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::SET_TOKEN_APPEND_FEE)?;
+
+    let fee_mint = (amount > 0).then(|| ctx.accounts.fee_mint.key());
+    ctx.accounts.tree_config.set_token_fee(fee_mint, amount);
+    Ok(())
+}
+
+// Withdraws `amount` tokens from a fee vault of the tree to `destination`, including vaults of
+// mints no longer charged.
+pub fn process_withdraw_token_fees(ctx: Context<WithdrawTokenFees>, amount: u64) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::WITHDRAW_TOKEN_FEES)?;
+
+    // Define the seeds for pda signing
+    let merkle_tree = ctx.accounts.merkle_tree.key();
+    let mint = ctx.accounts.fee_vault.mint;
+    let bump = *ctx.bumps.get("fee_vault").unwrap();
+    let signer_seeds: &[&[&[u8]]] =
+        &[&[FEE_VAULT_SEED, merkle_tree.as_ref(), mint.as_ref(), &[bump]]];
+    let cpi_ctx = CpiContext::new_with_signer(
+        ctx.accounts.token_program.to_account_info(),
+        Transfer {
+            from: ctx.accounts.fee_vault.to_account_info(),
+            to: ctx.accounts.destination.to_account_info(),
+            authority: ctx.accounts.fee_vault.to_account_info(),
+        },
+        signer_seeds,
+    );
+    transfer(cpi_ctx, amount)
+}
+
+// Moves `amount` tokens from `payer_token_account` into `fee_vault`, signed by `payer`,
+// failing unless `fee_vault` is the vault of `fee_mint` for `merkle_tree`
+pub fn pay_token_fee<'info>(
+    token_program: &Program<'info, Token>,
+    payer: &Signer<'info>,
+    payer_token_account: &Account<'info, TokenAccount>,
+    fee_vault: &Account<'info, TokenAccount>,
+    merkle_tree: &Pubkey,
+    fee_mint: &Pubkey,
+    amount: u64,
+) -> Result<()> {
+    let (expected_vault, _) = Pubkey::find_program_address(
+        &[FEE_VAULT_SEED, merkle_tree.as_ref(), fee_mint.as_ref()],
+        &crate::ID,
+    );
+    require_keys_eq!(
+        fee_vault.key(),
+        expected_vault,
+        CompressedNotesError::InvalidFeeVault
+    );
+    let cpi_ctx = CpiContext::new(
+        token_program.to_account_info(),
+        Transfer {
+            from: payer_token_account.to_account_info(),
+            to: fee_vault.to_account_info(),
+            authority: payer.to_account_info(),
+        },
+    );
+    transfer(cpi_ctx, amount)
+}
+
+#[derive(Accounts)]
+pub struct SetTokenAppendFee<'info> {
+    // The authority of the tree or an admin, pays for the fee vault if it is created
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    // The role of the signer, needed unless it is the tree authority
+    pub role: Option<Account<'info, Role>>,
+
+    // The tree config pda, recording the token append fee
+    #[account(
+        mut,
+        seeds = [TreeConfig::SEED, merkle_tree.key().as_ref()],
+        bump,
+        constraint = tree_config.is_admin(&authority.key(), role.as_deref())
+            @ CompressedNotesError::Unauthorized,
+    )]
+    pub tree_config: Account<'info, TreeConfig>,
+
+    // The mint the fee is paid in
+    pub fee_mint: Account<'info, Mint>,
+
+    // The fee vault pda of the tree for the mint, created on the first fee in it
+    #[account(
+        init_if_needed,
+        payer = authority,
+        token::mint = fee_mint,
+        token::authority = fee_vault,
+        seeds = [FEE_VAULT_SEED, merkle_tree.key().as_ref(), fee_mint.key().as_ref()],
+        bump,
+    )]
+    pub fee_vault: Account<'info, TokenAccount>,
+
+    // The merkle tree account
+    /// CHECK: Only used to derive the tree config and fee vault pdas
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    // The spl token program, used to create the fee vault
+    pub token_program: Program<'info, Token>,
+
+    // The system program, used to create the fee vault
+    pub system_program: Program<'info, System>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawTokenFees<'info> {
+    // The authority of the tree
+    pub authority: Signer<'info>,
+
+    // The tree config pda, naming the tree authority
+    #[account(
+        seeds = [TreeConfig::SEED, merkle_tree.key().as_ref()],
+        bump,
+        constraint = tree_config.is_authority(&authority.key())
+            @ CompressedNotesError::Unauthorized,
+    )]
+    pub tree_config: Account<'info, TreeConfig>,
+
+    // The fee vault pda withdrawn from
+    #[account(
+        mut,
+        seeds = [FEE_VAULT_SEED, merkle_tree.key().as_ref(), fee_vault.mint.as_ref()],
+        bump,
+    )]
+    pub fee_vault: Account<'info, TokenAccount>,
+
+    // The token account receiving the withdrawn tokens
+    #[account(mut)]
+    pub destination: Account<'info, TokenAccount>,
+
+    // The merkle tree account
+    /// CHECK: Only used to derive the tree config and fee vault pdas
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    // The spl token program, used to move the tokens
+    pub token_program: Program<'info, Token>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
@@ -0,0 +1,49 @@
+//! Token-gated trees, restricting posting to holders of a balance of a mint.
+
+use anchor_lang::prelude::*;
+use anchor_spl::token::TokenAccount;
+
+use crate::{instruction_flags, CompressedNotesError, TreeConfigAuthority};
+
+// Requires posters to a tree to hold `token_gate`, or lets anyone post again if none, lifting any
+// nft gate. Appends then take a token account of the poster as the optional `gate_token_account`.
+pub fn process_set_token_gate(
+    ctx: Context<TreeConfigAuthority>,
+    token_gate: Option<TokenGate>, // The balance posters must hold, none for no gate
+) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::SET_TOKEN_GATE)?;
+
+    ctx.accounts.tree_config.set_token_gate(token_gate);
+    Ok(())
+}
+
+// The balance of a mint posters to a tree must hold
+#[derive(AnchorSerialize, AnchorDeserialize, InitSpace, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TokenGate {
+    pub mint: Pubkey,     // The mint posters must hold
+    pub min_balance: u64, // The fewest tokens of the mint they must hold
+}
+
+impl TokenGate {
+    // Fails unless `gate_token_account` is a token account of `poster` holding the gate
+    pub fn require_holder(
+        &self,
+        poster: &Pubkey,
+        gate_token_account: Option<&Account<TokenAccount>>,
+    ) -> Result<()> {
+        let gate_token_account =
+            gate_token_account.ok_or(CompressedNotesError::GateTokenAccountMissing)?;
+        require!(
+            gate_token_account.owner == *poster && gate_token_account.mint == self.mint,
+            CompressedNotesError::InvalidGateTokenAccount
+        );
+        require!(
+            gate_token_account.amount >= self.min_balance,
+            CompressedNotesError::BelowGateBalance
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,207 @@
+//! The protocol treasury at `[b"treasury"]` and the tree treasuries at
+//! `[b"tree_treasury", merkle_tree]` that append and operation fees are paid into.
+
+use anchor_lang::prelude::*;
+use anchor_lang::system_program::{transfer, Transfer};
+
+use crate::{instruction_flags, CompressedNotesError, GlobalConfig, Role, TreeConfig};
+
+pub const TREASURY_SEED: &[u8] = b"treasury";
+pub const TREE_TREASURY_SEED: &[u8] = b"tree_treasury";
+
+// Moves `amount` lamports from `payer` into the protocol treasury
+pub fn pay_treasury<'info>(
+    system_program: &Program<'info, System>,
+    payer: &Signer<'info>,
+    treasury: &SystemAccount<'info>,
+    amount: u64,
+) -> Result<()> {
+    let cpi_ctx = CpiContext::new(
+        system_program.to_account_info(),
+        Transfer {
+            from: payer.to_account_info(),
+            to: treasury.to_account_info(),
+        },
+    );
+    transfer(cpi_ctx, amount)
+}
+
+// Tops `tree_treasury` up to its rent exempt minimum from `payer`, since a treasury below
+// it cannot be paid amounts smaller than it
+pub fn top_up_tree_treasury<'info>(
+    system_program: &Program<'info, System>,
+    payer: &Signer<'info>,
+    tree_treasury: &SystemAccount<'info>,
+) -> Result<()> {
+    let minimum = Rent::get()?.minimum_balance(0);
+    let balance = tree_treasury.lamports();
+    if balance < minimum {
+        pay_treasury(system_program, payer, tree_treasury, minimum - balance)?;
+    }
+    Ok(())
+}
+
+// Withdraws `amount` lamports from the protocol treasury to the admin.
+pub fn process_withdraw_treasury(ctx: Context<WithdrawTreasury>, amount: u64) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::WITHDRAW_TREASURY)?;
+
+    // Define the seeds for pda signing
+    let bump = *ctx.bumps.get("treasury").unwrap();
+    let signer_seeds: &[&[&[u8]]] = &[&[TREASURY_SEED, &[bump]]];
+    let cpi_ctx = CpiContext::new_with_signer(
+        ctx.accounts.system_program.to_account_info(),
+        Transfer {
+            from: ctx.accounts.treasury.to_account_info(),
+            to: ctx.accounts.admin.to_account_info(),
+        },
+        signer_seeds,
+    );
+    transfer(cpi_ctx, amount)
+}
+
+// Sets the lamports each append to the tree pays into its tree treasury, zero for none. Appends
+// then fail with `TreeTreasuryMissing` without the tree treasury and system program, and an
+// optional sponsoring `payer` pays the fee instead of the author the leaf commits to.
+pub fn process_set_append_fee(ctx: Context<SetAppendFee>, fee_lamports: u64) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::SET_APPEND_FEE)?;
+
+    if fee_lamports > 0 {
+        top_up_tree_treasury(
+            &ctx.accounts.system_program,
+            &ctx.accounts.authority,
+            &ctx.accounts.tree_treasury,
+        )?;
+    }
+    ctx.accounts.tree_config.set_fee_lamports(fee_lamports);
+    Ok(())
+}
+
+// Withdraws `amount` lamports from the tree treasury to the tree authority, keeping the rent
+// exempt minimum in it.
+pub fn process_withdraw_tree_treasury(
+    ctx: Context<WithdrawTreeTreasury>,
+    amount: u64,
+) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::WITHDRAW_TREE_TREASURY)?;
+    let minimum = Rent::get()?.minimum_balance(0);
+    require!(
+        amount
+            <= ctx
+                .accounts
+                .tree_treasury
+                .lamports()
+                .saturating_sub(minimum),
+        CompressedNotesError::InsufficientTreasury
+    );
+
+    // Define the seeds for pda signing
+    let merkle_tree = ctx.accounts.merkle_tree.key();
+    let bump = *ctx.bumps.get("tree_treasury").unwrap();
+    let signer_seeds: &[&[&[u8]]] = &[&[TREE_TREASURY_SEED, merkle_tree.as_ref(), &[bump]]];
+    let cpi_ctx = CpiContext::new_with_signer(
+        ctx.accounts.system_program.to_account_info(),
+        Transfer {
+            from: ctx.accounts.tree_treasury.to_account_info(),
+            to: ctx.accounts.authority.to_account_info(),
+        },
+        signer_seeds,
+    );
+    transfer(cpi_ctx, amount)
+}
+
+#[derive(Accounts)]
+pub struct WithdrawTreasury<'info> {
+    // The admin of the global config, receives the withdrawn lamports
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    // The global config pda
+    #[account(
+        seeds = [GlobalConfig::SEED],
+        bump = global_config.bump,
+        has_one = admin @ CompressedNotesError::Unauthorized,
+    )]
+    pub global_config: Account<'info, GlobalConfig>,
+
+    // The protocol treasury pda
+    #[account(mut, seeds = [TREASURY_SEED], bump)]
+    pub treasury: SystemAccount<'info>,
+
+    // The system program, used to move the lamports
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetAppendFee<'info> {
+    // The authority of the tree or an admin, tops the tree treasury up to its rent
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    // The role of the signer, needed unless it is the tree authority
+    pub role: Option<Account<'info, Role>>,
+
+    // The tree config pda, recording the append fee
+    #[account(
+        mut,
+        seeds = [TreeConfig::SEED, merkle_tree.key().as_ref()],
+        bump,
+        constraint = tree_config.is_admin(&authority.key(), role.as_deref())
+            @ CompressedNotesError::Unauthorized,
+    )]
+    pub tree_config: Account<'info, TreeConfig>,
+
+    // The tree treasury pda, collecting the append fees
+    #[account(mut, seeds = [TREE_TREASURY_SEED, merkle_tree.key().as_ref()], bump)]
+    pub tree_treasury: SystemAccount<'info>,
+
+    // The merkle tree account
+    /// CHECK: Only used to derive the tree config and tree treasury pdas
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    // The system program, used to top the tree treasury up
+    pub system_program: Program<'info, System>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawTreeTreasury<'info> {
+    // The authority of the tree, receives the withdrawn lamports
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    // The tree config pda, naming the tree authority
+    #[account(
+        seeds = [TreeConfig::SEED, merkle_tree.key().as_ref()],
+        bump,
+        constraint = tree_config.is_authority(&authority.key())
+            @ CompressedNotesError::Unauthorized,
+    )]
+    pub tree_config: Account<'info, TreeConfig>,
+
+    // The tree treasury pda
+    #[account(mut, seeds = [TREE_TREASURY_SEED, merkle_tree.key().as_ref()], bump)]
+    pub tree_treasury: SystemAccount<'info>,
+
+    // The merkle tree account
+    /// CHECK: Only used to derive the tree config and tree treasury pdas
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    // The system program, used to move the lamports
+    pub system_program: Program<'info, System>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
@@ -0,0 +1,1459 @@
+//! Per-tree configuration in a config pda at `[b"config", merkle_tree]`, which the note and
+//! message instructions only read and update if it exists, so trees without one keep working.
+
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::sysvar::instructions::{
+    load_current_index_checked, load_instruction_at_checked,
+};
+use anchor_spl::metadata::MetadataAccount;
+use anchor_spl::token::{Token, TokenAccount};
+use spl_account_compression::{program::SplAccountCompression, wrap_application_data_v1, Noop};
+
+use crate::{
+    instruction_flags, leaf_schema, pay_token_fee, pay_treasury, require_collection_holder,
+    CompressedNotesError, FeeOperation, FeeSchedule, GlobalConfig, Hold, RateState, RecentLeaves,
+    Role, RoleKind, Stake, TokenGate, TreeAction, TreeState, TreeSubscription,
+};
+
+// Replaces the metadata of the tree, resizing the config to fit it.
+pub fn process_set_tree_metadata(
+    ctx: Context<SetTreeMetadata>,
+    name: String,        // The name of the tree
+    description: String, // What the tree holds
+    image_uri: String,   // Uri of an image shown for the tree
+) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::SET_TREE_METADATA)?;
+
+    ctx.accounts
+        .tree_config
+        .set_metadata(name, description, image_uri)
+}
+
+// Lets `writer` append to the tree while it is private, growing the config to fit it.
+pub fn process_add_writer(ctx: Context<AddWriter>, writer: Pubkey) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::ADD_WRITER)?;
+
+    ctx.accounts.tree_config.add_writer(writer)
+}
+
+// Revokes `writer`, shrinking the config and refunding the rent to the authority.
+pub fn process_remove_writer(ctx: Context<RemoveWriter>, writer: Pubkey) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::REMOVE_WRITER)?;
+
+    ctx.accounts.tree_config.remove_writer(writer)
+}
+
+// Makes the tree public or private, restricting who can append to it.
+pub fn process_set_tree_visibility(
+    ctx: Context<TreeConfigAuthority>,
+    visibility: Visibility, // Who can append to the tree from now on
+) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::SET_TREE_VISIBILITY)?;
+
+    ctx.accounts.tree_config.visibility = visibility;
+    Ok(())
+}
+
+// Limits how often each sender can append notes to the tree, zero lifting a limit.
+pub fn process_set_rate_limits(
+    ctx: Context<TreeConfigAuthority>,
+    min_append_slots: u64,      // The slots a sender waits between appends
+    max_appends_per_epoch: u32, // The appends a sender makes per epoch
+) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::SET_RATE_LIMITS)?;
+
+    let tree_config = &mut ctx.accounts.tree_config;
+    tree_config.min_append_slots = min_append_slots;
+    tree_config.max_appends_per_epoch = max_appends_per_epoch;
+    Ok(())
+}
+
+// Sets which programs can call the note and message instructions on the tree through a cpi.
+// An allowlisted program is trusted with whatever it invokes in turn.
+pub fn process_set_cpi_policy(
+    ctx: Context<TreeConfigAuthority>,
+    cpi_policy: CpiPolicy, // Which programs can call in from now on
+) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::SET_CPI_POLICY)?;
+    if let CpiPolicy::Allowlist { programs } = &cpi_policy {
+        require!(
+            programs.len() <= TreeConfig::MAX_CPI_CALLERS,
+            CompressedNotesError::TooManyCpiCallers
+        );
+    }
+
+    ctx.accounts.tree_config.cpi_policy = cpi_policy;
+    Ok(())
+}
+
+// Freezes the tree, failing every append and replace through the tree config.
+pub fn process_freeze_tree(ctx: Context<TreeConfigAuthority>) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::FREEZE_TREE)?;
+    set_frozen(ctx, true)
+}
+
+// Thaws a frozen tree, allowing appends and replaces again.
+pub fn process_thaw_tree(ctx: Context<TreeConfigAuthority>) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::THAW_TREE)?;
+    set_frozen(ctx, false)
+}
+
+// Sets the frozen flag on the tree config and logs the change for indexers
+fn set_frozen(ctx: Context<TreeConfigAuthority>, frozen: bool) -> Result<()> {
+    ctx.accounts.tree_config.frozen = frozen;
+
+    let freeze_log = TreeFreezeLog::new(
+        ctx.accounts.merkle_tree.key(),
+        ctx.accounts.tree_config.schema_version,
+        ctx.accounts.authority.key(),
+        frozen,
+        Clock::get()?.slot,
+    );
+    wrap_application_data_v1(freeze_log.try_to_vec()?, &ctx.accounts.log_wrapper)?;
+    Ok(())
+}
+
+// Freezes the tree on behalf of the protocol, logging why. Only the protocol can thaw it.
+pub fn process_emergency_freeze_tree(
+    ctx: Context<EmergencyFreezeTree>,
+    reason_code: u16, // Why the tree is frozen, as defined by the protocol, never zero
+) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::EMERGENCY_FREEZE_TREE)?;
+    require!(reason_code != 0, CompressedNotesError::MissingReasonCode);
+    set_emergency_frozen(ctx, true, reason_code)
+}
+
+// Thaws a tree frozen by the protocol, logging why.
+pub fn process_emergency_thaw_tree(
+    ctx: Context<EmergencyFreezeTree>,
+    reason_code: u16, // Why the tree is thawed, as defined by the protocol
+) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::EMERGENCY_THAW_TREE)?;
+    set_emergency_frozen(ctx, false, reason_code)
+}
+
+// Sets the emergency frozen flag on the tree config and logs the change for indexers
+fn set_emergency_frozen(
+    ctx: Context<EmergencyFreezeTree>,
+    frozen: bool,
+    reason_code: u16,
+) -> Result<()> {
+    ctx.accounts.tree_config.emergency_frozen = frozen;
+
+    let freeze_log = EmergencyFreezeLog::new(
+        ctx.accounts.merkle_tree.key(),
+        ctx.accounts.tree_config.schema_version,
+        ctx.accounts.admin.key(),
+        frozen,
+        reason_code,
+        Clock::get()?.slot,
+    );
+    wrap_application_data_v1(freeze_log.try_to_vec()?, &ctx.accounts.log_wrapper)?;
+    Ok(())
+}
+
+// Hands the tree config to `new_authority`, who can then close the tree. The tree authority pda
+// signing for the compression program stays the same.
+pub fn process_transfer_tree_authority(
+    ctx: Context<TreeConfigAuthority>,
+    new_authority: Pubkey, // Pubkey of the new tree authority
+) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::TRANSFER_TREE_AUTHORITY)?;
+    // Admins cannot hand the tree away, only the authority itself
+    require!(
+        ctx.accounts
+            .tree_config
+            .is_authority(&ctx.accounts.authority.key()),
+        CompressedNotesError::Unauthorized
+    );
+
+    ctx.accounts.tree_config.set_authority(new_authority);
+
+    // Log the transfer for indexers
+    let authority_log = TreeAuthorityLog::new(
+        ctx.accounts.merkle_tree.key(),
+        ctx.accounts.tree_config.schema_version,
+        ctx.accounts.authority.key(),
+        new_authority,
+        Clock::get()?.slot,
+    );
+    wrap_application_data_v1(authority_log.try_to_vec()?, &ctx.accounts.log_wrapper)?;
+    Ok(())
+}
+
+// Links the tree to the successor appends continue in once it is full, which must belong to
+// the same authority and check appends the same way.
+pub fn process_set_next_tree(ctx: Context<SetNextTree>) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::SET_NEXT_TREE)?;
+    // A migrated tree keeps pointing at the tree its leaves were copied to
+    require!(
+        !ctx.accounts.tree_config.frozen,
+        CompressedNotesError::TreeFrozen
+    );
+    require!(
+        ctx.accounts
+            .tree_config
+            .has_same_policy(&ctx.accounts.next_config),
+        CompressedNotesError::SuccessorPolicyMismatch
+    );
+
+    ctx.accounts.tree_config.next_tree = Some(ctx.accounts.next_tree.key());
+    Ok(())
+}
+
+// Closes an empty note tree, paying the tree and config rent back to its authority. The
+// compression program only closes a tree once every leaf was replaced with an empty node.
+pub fn process_close_tree(ctx: Context<CloseTree>) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::CLOSE_TREE)?;
+
+    // Log the final state of the tree for indexers
+    let tree_state = TreeState::load(&ctx.accounts.merkle_tree)?;
+    let close_log = TreeCloseLog::new(
+        ctx.accounts.merkle_tree.key(),
+        ctx.accounts.tree_config.schema_version,
+        ctx.accounts.authority.key(),
+        tree_state.root,
+        tree_state.sequence_number,
+        Clock::get()?.slot,
+    );
+    wrap_application_data_v1(close_log.try_to_vec()?, &ctx.accounts.log_wrapper)?;
+
+    // CPI to close the tree, failing unless it is empty
+    tree_cpi!(ctx).close(&ctx.accounts.authority)
+}
+
+// Closes an empty tree like `close_tree`, keeping its final state in a tombstone pda, so
+// auditors can still check historical proofs once the tree account is gone.
+pub fn process_decommission_tree(ctx: Context<DecommissionTree>) -> Result<()> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::DECOMMISSION_TREE)?;
+
+    let tree_state = TreeState::load(&ctx.accounts.merkle_tree)?;
+    let slot = Clock::get()?.slot;
+    ctx.accounts.tree_tombstone.set_inner(TreeTombstone {
+        merkle_tree: ctx.accounts.merkle_tree.key(),
+        schema_version: ctx.accounts.tree_config.schema_version,
+        authority: ctx.accounts.authority.key(),
+        root: tree_state.root,
+        sequence_number: tree_state.sequence_number,
+        leaf_count: tree_state.next_index as u64,
+        slot,
+        bump: *ctx.bumps.get("tree_tombstone").unwrap(),
+    });
+
+    // Log the final state of the tree for auditors and indexers
+    let checkpoint_log = TreeCheckpointLog::new(
+        ctx.accounts.merkle_tree.key(),
+        ctx.accounts.tree_config.schema_version,
+        tree_state.root,
+        tree_state.sequence_number,
+        tree_state.next_index as u64,
+        slot,
+    );
+    wrap_application_data_v1(checkpoint_log.try_to_vec()?, &ctx.accounts.log_wrapper)?;
+    let close_log = TreeCloseLog::new(
+        ctx.accounts.merkle_tree.key(),
+        ctx.accounts.tree_config.schema_version,
+        ctx.accounts.authority.key(),
+        tree_state.root,
+        tree_state.sequence_number,
+        slot,
+    );
+    wrap_application_data_v1(close_log.try_to_vec()?, &ctx.accounts.log_wrapper)?;
+
+    // CPI to close the tree, failing unless it is empty
+    tree_cpi!(ctx).close(&ctx.accounts.authority)
+}
+
+#[derive(AnchorSerialize)]
+pub struct TreeCheckpointLog {
+    merkle_tree: Pubkey,  // The tree decommissioned
+    schema_version: u16,  // The leaf schema of the tree
+    root: [u8; 32],       // The final root of the tree
+    sequence_number: u64, // The number of changes applied to the tree
+    leaf_count: u64,      // The number of leaves appended to the tree
+    slot: u64,            // The slot the tree was decommissioned
+}
+
+impl TreeCheckpointLog {
+    // Constructs a new tree checkpoint log from given tree, schema version, root, sequence
+    // number, leaf count and slot
+    pub fn new(
+        merkle_tree: Pubkey,
+        schema_version: u16,
+        root: [u8; 32],
+        sequence_number: u64,
+        leaf_count: u64,
+        slot: u64,
+    ) -> Self {
+        Self {
+            merkle_tree,
+            schema_version,
+            root,
+            sequence_number,
+            leaf_count,
+            slot,
+        }
+    }
+}
+
+#[derive(AnchorSerialize)]
+pub struct TreeCloseLog {
+    merkle_tree: Pubkey,  // The tree closed
+    schema_version: u16,  // The leaf schema of the tree
+    authority: Pubkey,    // Pubkey of the tree authority, refunded the rent
+    root: [u8; 32],       // The root of the tree when it was closed
+    sequence_number: u64, // The number of changes applied to the tree when it was closed
+    slot: u64,            // The slot the tree was closed
+}
+
+impl TreeCloseLog {
+    // Constructs a new tree close log from given tree, schema version, authority, root,
+    // sequence number and slot
+    pub fn new(
+        merkle_tree: Pubkey,
+        schema_version: u16,
+        authority: Pubkey,
+        root: [u8; 32],
+        sequence_number: u64,
+        slot: u64,
+    ) -> Self {
+        Self {
+            merkle_tree,
+            schema_version,
+            authority,
+            root,
+            sequence_number,
+            slot,
+        }
+    }
+}
+
+#[derive(AnchorSerialize)]
+pub struct TreeAuthorityLog {
+    merkle_tree: Pubkey,   // The tree transferred
+    schema_version: u16,   // The leaf schema of the tree
+    old_authority: Pubkey, // Pubkey of the previous tree authority
+    new_authority: Pubkey, // Pubkey of the new tree authority
+    slot: u64,             // The slot of the transfer
+}
+
+impl TreeAuthorityLog {
+    // Constructs a new tree authority log from given tree, schema version, old and new
+    // authority and slot
+    pub fn new(
+        merkle_tree: Pubkey,
+        schema_version: u16,
+        old_authority: Pubkey,
+        new_authority: Pubkey,
+        slot: u64,
+    ) -> Self {
+        Self {
+            merkle_tree,
+            schema_version,
+            old_authority,
+            new_authority,
+            slot,
+        }
+    }
+}
+
+#[derive(AnchorSerialize)]
+pub struct TreeFreezeLog {
+    merkle_tree: Pubkey, // The tree frozen or thawed
+    schema_version: u16, // The leaf schema of the tree
+    authority: Pubkey,   // Pubkey of the tree authority
+    frozen: bool,        // Whether the tree was frozen or thawed
+    slot: u64,           // The slot of the change
+}
+
+impl TreeFreezeLog {
+    // Constructs a new tree freeze log from given tree, schema version, authority, frozen
+    // flag and slot
+    pub fn new(
+        merkle_tree: Pubkey,
+        schema_version: u16,
+        authority: Pubkey,
+        frozen: bool,
+        slot: u64,
+    ) -> Self {
+        Self {
+            merkle_tree,
+            schema_version,
+            authority,
+            frozen,
+            slot,
+        }
+    }
+}
+
+#[derive(AnchorSerialize)]
+pub struct EmergencyFreezeLog {
+    merkle_tree: Pubkey, // The tree frozen or thawed
+    schema_version: u16, // The leaf schema of the tree
+    admin: Pubkey,       // Pubkey of the global config admin
+    frozen: bool,        // Whether the tree was frozen or thawed
+    reason_code: u16,    // Why the tree was frozen or thawed, as defined by the protocol
+    slot: u64,           // The slot of the change
+}
+
+impl EmergencyFreezeLog {
+    // Constructs a new emergency freeze log from given tree, schema version, admin, frozen
+    // flag, reason and slot
+    pub fn new(
+        merkle_tree: Pubkey,
+        schema_version: u16,
+        admin: Pubkey,
+        frozen: bool,
+        reason_code: u16,
+        slot: u64,
+    ) -> Self {
+        Self {
+            merkle_tree,
+            schema_version,
+            admin,
+            frozen,
+            reason_code,
+            slot,
+        }
+    }
+}
+
+// The final state of a decommissioned tree, kept after the tree account is closed
+#[account]
+#[derive(InitSpace)]
+pub struct TreeTombstone {
+    merkle_tree: Pubkey,  // The tree decommissioned
+    schema_version: u16,  // The leaf schema of the tree
+    authority: Pubkey,    // The authority that decommissioned the tree
+    root: [u8; 32],       // The final root of the tree
+    sequence_number: u64, // The number of changes applied to the tree
+    leaf_count: u64,      // The number of leaves appended to the tree
+    slot: u64,            // The slot the tree was decommissioned
+    bump: u8,             // The bump seed for the pda
+}
+
+impl TreeTombstone {
+    pub const SEED: &'static [u8] = b"tombstone";
+}
+
+// Who can append to a tree
+#[derive(AnchorSerialize, AnchorDeserialize, InitSpace, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Visibility {
+    Public,  // Any signer can append
+    Private, // Only the tree authority and writers can append
+}
+
+// Which programs can call the note and message instructions on a tree through a cpi
+#[derive(AnchorSerialize, AnchorDeserialize, InitSpace, Clone, Debug, PartialEq, Eq)]
+pub enum CpiPolicy {
+    Any,        // Any program, or a user transaction directly
+    DirectOnly, // Only a user transaction directly
+    Allowlist {
+        #[max_len(4)]
+        programs: Vec<Pubkey>, // The programs allowed besides a user transaction directly
+    },
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct TreeConfig {
+    creator: Pubkey,             // The creator of the tree
+    authority: Pubkey,           // The current authority of the tree, the only one who can close it
+    merkle_tree: Pubkey,         // The tree configured
+    max_depth: u32,              // Max depth of the merkle tree
+    max_buffer_size: u32,        // Max buffer size of the merkle tree
+    canopy_depth: u32,           // Depth of the canopy cached after the tree
+    schema_version: u16,         // The leaf schema the tree was created with, see `leaf_schema`
+    created_at: i64,             // The unix timestamp the tree was created
+    num_appended: u64,           // The number of leaves appended through this program
+    replace_count: u64,          // The number of leaves replaced through this program
+    frozen: bool,                // Whether appends and replaces are refused
+    next_tree: Option<Pubkey>,   // The tree appends continue in once this one is full
+    visibility: Visibility,      // Who can append to the tree
+    recent_roots: [[u8; 32]; 8], // The roots before the latest changes, a ring buffer
+    root_cursor: u8,             // The slot of `recent_roots` the next root is written to
+    min_append_slots: u64,       // The slots a sender waits between appends, zero for none
+    max_appends_per_epoch: u32,  // The appends a sender makes per epoch, zero for unlimited
+    cpi_policy: CpiPolicy,       // Which programs can call in through a cpi
+    dedupe_leaves: bool,         // Whether appends are checked against the recent leaves pda
+    held_leaves: u32,            // The number of leaves on legal hold
+    emergency_frozen: bool,      // Whether the global config admin froze the tree
+    fees: FeeSchedule,           // Lamports operations pay into the tree treasury, zeroes for none
+    fee_mint: Option<Pubkey>,    // The mint of the token append fee, none for no token fee
+    fee_token_amount: u64,       // Tokens of the fee mint each append pays into its fee vault
+    gate: Option<TokenGate>,     // The token balance posters must hold, none for no gate
+    nft_gate: Option<Pubkey>,    // The collection posters must hold an nft of, none for no gate
+    min_stake: u64,              // Lamports posters must stake on the tree, zero for none
+    subscription_price: u64,     // Lamports a day of access costs, zero for a free tree
+    authority_epoch: u32,        // Authority transfers so far, voiding the roles granted before
+    bump: u8,                    // The bump seed for the pda
+    // The writers and metadata are counted empty in `INIT_SPACE`, see `TreeConfig::space`
+    #[max_len(0)]
+    writers: Vec<Pubkey>, // Who can append besides the authority while the tree is private
+    #[max_len(0)]
+    name: String, // The name of the tree
+    #[max_len(0)]
+    description: String, // What the tree holds
+    #[max_len(0)]
+    image_uri: String, // Uri of an image shown for the tree
+}
+
+impl TreeConfig {
+    pub const SEED: &'static [u8] = b"config";
+    // Seed of the tree accounts `create_named_tree` creates, followed by the creator and namespace
+    pub const NAMED_TREE_SEED: &'static [u8] = b"tree";
+    pub const MAX_NAMESPACE_LEN: usize = 32;
+    pub const MAX_NAME_LEN: usize = 32;
+    pub const MAX_DESCRIPTION_LEN: usize = 256;
+    pub const MAX_IMAGE_URI_LEN: usize = 200;
+    pub const MAX_WRITERS: usize = 16;
+    // Must match the `max_len` of `CpiPolicy::Allowlist`
+    pub const MAX_CPI_CALLERS: usize = 4;
+    // Must match the length of `recent_roots`
+    pub const RECENT_ROOTS: usize = 8;
+
+    // The account size of a config holding the given metadata and number of writers
+    pub fn space(name: &str, description: &str, image_uri: &str, writers: usize) -> usize {
+        8 + TreeConfig::INIT_SPACE + name.len() + description.len() + image_uri.len() + writers * 32
+    }
+
+    // The account size of this config once it holds `writers` writers
+    pub fn space_with_writers(&self, writers: usize) -> usize {
+        TreeConfig::space(&self.name, &self.description, &self.image_uri, writers)
+    }
+
+    // Adds a writer, failing if it is already listed or the list is full
+    pub fn add_writer(&mut self, writer: Pubkey) -> Result<()> {
+        require!(
+            !self.writers.contains(&writer),
+            CompressedNotesError::InvalidWriter
+        );
+        require!(
+            self.writers.len() < TreeConfig::MAX_WRITERS,
+            CompressedNotesError::TooManyWriters
+        );
+        self.writers.push(writer);
+        Ok(())
+    }
+
+    // Removes a writer, failing if it is not listed
+    pub fn remove_writer(&mut self, writer: Pubkey) -> Result<()> {
+        let position = self
+            .writers
+            .iter()
+            .position(|listed| *listed == writer)
+            .ok_or(CompressedNotesError::InvalidWriter)?;
+        self.writers.remove(position);
+        Ok(())
+    }
+
+    // Replaces the metadata, failing if any part is too long
+    pub fn set_metadata(
+        &mut self,
+        name: String,
+        description: String,
+        image_uri: String,
+    ) -> Result<()> {
+        require!(
+            name.len() <= TreeConfig::MAX_NAME_LEN
+                && description.len() <= TreeConfig::MAX_DESCRIPTION_LEN
+                && image_uri.len() <= TreeConfig::MAX_IMAGE_URI_LEN,
+            CompressedNotesError::TreeMetadataTooLong
+        );
+        self.name = name;
+        self.description = description;
+        self.image_uri = image_uri;
+        Ok(())
+    }
+
+    // The account size of this config once `action` is applied
+    pub fn space_for(&self, action: &TreeAction) -> usize {
+        match action {
+            TreeAction::SetMetadata {
+                name,
+                description,
+                image_uri,
+            } => TreeConfig::space(name, description, image_uri, self.writers.len()),
+            _ => self.space_with_writers(self.writers.len()),
+        }
+    }
+
+    // Applies `action` for `authority`, failing unless it holds the authority of the tree.
+    // Freezes, thaws and transfers are logged like the instructions making them directly.
+    pub fn apply_action(
+        &mut self,
+        authority: &Pubkey,
+        action: &TreeAction,
+        log_wrapper: &Program<Noop>,
+    ) -> Result<()> {
+        require_keys_eq!(
+            self.authority,
+            *authority,
+            CompressedNotesError::Unauthorized
+        );
+        match action {
+            TreeAction::Freeze | TreeAction::Thaw => {
+                self.frozen = *action == TreeAction::Freeze;
+                let freeze_log = TreeFreezeLog::new(
+                    self.merkle_tree,
+                    self.schema_version,
+                    *authority,
+                    self.frozen,
+                    Clock::get()?.slot,
+                );
+                wrap_application_data_v1(freeze_log.try_to_vec()?, log_wrapper)
+            }
+            TreeAction::SetMetadata {
+                name,
+                description,
+                image_uri,
+            } => self.set_metadata(name.clone(), description.clone(), image_uri.clone()),
+            // The role pda is written by `execute_tree_action`
+            TreeAction::GrantRole { .. } | TreeAction::RevokeRole { .. } => Ok(()),
+            TreeAction::TransferAuthority { new_authority } => {
+                self.set_authority(*new_authority);
+                let authority_log = TreeAuthorityLog::new(
+                    self.merkle_tree,
+                    self.schema_version,
+                    *authority,
+                    *new_authority,
+                    Clock::get()?.slot,
+                );
+                wrap_application_data_v1(authority_log.try_to_vec()?, log_wrapper)
+            }
+        }
+    }
+
+    // Records the creator and parameters of a new tree
+    pub fn init(
+        &mut self,
+        creator: Pubkey,
+        merkle_tree: Pubkey,
+        tree_state: &TreeState,
+        created_at: i64,
+        bump: u8,
+    ) {
+        self.creator = creator;
+        self.authority = creator;
+        self.merkle_tree = merkle_tree;
+        self.max_depth = tree_state.max_depth;
+        self.max_buffer_size = tree_state.max_buffer_size;
+        self.canopy_depth = tree_state.canopy_depth;
+        // Note trees hash their leaves with the v2 note schema
+        self.schema_version = leaf_schema::V2;
+        self.created_at = created_at;
+        self.bump = bump;
+    }
+
+    // Counts a leaf appended to `merkle_tree` by `appender` holding `role`, if any, on the
+    // config in `tree_config`, if it exists, failing if the tree is private and the appender
+    // cannot write to it
+    pub fn record_append(
+        tree_config: &AccountInfo,
+        merkle_tree: &AccountInfo,
+        appender: &Pubkey,
+        role: Option<&Role>,
+    ) -> Result<()> {
+        TreeConfig::update(tree_config, merkle_tree, |config, _| {
+            require!(
+                config.can_write(appender, role),
+                CompressedNotesError::TreeNotWritable
+            );
+            config.num_appended += 1;
+            Ok(())
+        })
+    }
+
+    // Counts an append on `rate_state` against the rate limits of the config in
+    // `tree_config`, if it exists and limits the tree, failing without a rate state
+    pub fn record_rate(
+        tree_config: &AccountInfo,
+        rate_state: Option<&mut RateState>,
+    ) -> Result<()> {
+        if tree_config.data_is_empty() {
+            return Ok(());
+        }
+        let config = Account::<TreeConfig>::try_from(tree_config)?;
+        if config.min_append_slots == 0 && config.max_appends_per_epoch == 0 {
+            return Ok(());
+        }
+        rate_state
+            .ok_or(CompressedNotesError::RateStateMissing)?
+            .record(
+                config.min_append_slots,
+                config.max_appends_per_epoch,
+                &Clock::get()?,
+            )
+    }
+
+    // Records `leaf` in `recent_leaves` if the config in `tree_config` exists and keeps
+    // recent leaves, failing without the recent leaves pda or if the leaf is a duplicate
+    pub fn record_leaf(
+        tree_config: &AccountInfo,
+        recent_leaves: Option<&mut RecentLeaves>,
+        leaf: &[u8; 32],
+    ) -> Result<()> {
+        if tree_config.data_is_empty() {
+            return Ok(());
+        }
+        let config = Account::<TreeConfig>::try_from(tree_config)?;
+        if !config.dedupe_leaves {
+            return Ok(());
+        }
+        recent_leaves
+            .ok_or(CompressedNotesError::RecentLeavesMissing)?
+            .record(leaf, Clock::get()?.slot)
+    }
+
+    // Counts a leaf put on hold or released, given whether it was held before
+    pub fn count_hold(&mut self, was_held: bool, held: bool) {
+        match (was_held, held) {
+            (false, true) => self.held_leaves += 1,
+            (true, false) => self.held_leaves -= 1,
+            _ => {}
+        }
+    }
+
+    // Sets the base lamports each append pays into the tree treasury
+    pub fn set_fee_lamports(&mut self, fee_lamports: u64) {
+        self.fees.base_fee = fee_lamports;
+    }
+
+    // Sets the lamports appends, updates and deletes pay into the tree treasury
+    pub fn set_fee_schedule(&mut self, fees: FeeSchedule) {
+        self.fees = fees;
+    }
+
+    // Moves the fee of `operation` from `payer` into `tree_treasury` if the config in
+    // `tree_config` exists and charges one, failing without the tree treasury or system program
+    pub fn record_fee<'info>(
+        tree_config: &AccountInfo,
+        payer: &Signer<'info>,
+        tree_treasury: Option<&SystemAccount<'info>>,
+        system_program: Option<&Program<'info, System>>,
+        operation: FeeOperation,
+    ) -> Result<()> {
+        if tree_config.data_is_empty() {
+            return Ok(());
+        }
+        let config = Account::<TreeConfig>::try_from(tree_config)?;
+        let fee = config.fees.fee_for(operation);
+        if fee == 0 {
+            return Ok(());
+        }
+        let tree_treasury = tree_treasury.ok_or(CompressedNotesError::TreeTreasuryMissing)?;
+        let system_program = system_program.ok_or(CompressedNotesError::TreeTreasuryMissing)?;
+        pay_treasury(system_program, payer, tree_treasury, fee)
+    }
+
+    // The lamports `operation` pays into the tree treasury
+    pub fn fee_for(&self, operation: FeeOperation) -> u64 {
+        self.fees.fee_for(operation)
+    }
+
+    // The mint and amount of the token each append pays into its fee vault, if any
+    pub fn token_fee(&self) -> Option<(Pubkey, u64)> {
+        self.fee_mint.map(|mint| (mint, self.fee_token_amount))
+    }
+
+    // Sets the mint and amount of the token each append pays into its fee vault
+    pub fn set_token_fee(&mut self, fee_mint: Option<Pubkey>, fee_token_amount: u64) {
+        self.fee_mint = fee_mint;
+        self.fee_token_amount = fee_token_amount;
+    }
+
+    // Moves the token append fee from `payer_token_account` into `fee_vault` if the config
+    // in `tree_config` exists and charges one, failing without the token accounts or
+    // token program
+    pub fn record_token_fee<'info>(
+        tree_config: &AccountInfo,
+        payer: &Signer<'info>,
+        payer_token_account: Option<&Account<'info, TokenAccount>>,
+        fee_vault: Option<&Account<'info, TokenAccount>>,
+        token_program: Option<&Program<'info, Token>>,
+    ) -> Result<()> {
+        if tree_config.data_is_empty() {
+            return Ok(());
+        }
+        let config = Account::<TreeConfig>::try_from(tree_config)?;
+        let Some(fee_mint) = config.fee_mint else {
+            return Ok(());
+        };
+        let payer_token_account =
+            payer_token_account.ok_or(CompressedNotesError::FeeVaultMissing)?;
+        let fee_vault = fee_vault.ok_or(CompressedNotesError::FeeVaultMissing)?;
+        let token_program = token_program.ok_or(CompressedNotesError::FeeVaultMissing)?;
+        pay_token_fee(
+            token_program,
+            payer,
+            payer_token_account,
+            fee_vault,
+            &config.merkle_tree,
+            &fee_mint,
+            config.fee_token_amount,
+        )
+    }
+
+    // Sets the token balance posters to the tree must hold, lifting any nft gate
+    pub fn set_token_gate(&mut self, token_gate: Option<TokenGate>) {
+        if token_gate.is_some() {
+            self.nft_gate = None;
+        }
+        self.gate = token_gate;
+    }
+
+    // Sets the collection posters to the tree must hold an nft of, lifting any token gate
+    pub fn set_nft_gate(&mut self, collection: Option<Pubkey>) {
+        if collection.is_some() {
+            self.gate = None;
+        }
+        self.nft_gate = collection;
+    }
+
+    // Fails if the config in `tree_config` exists and gates posting on a token balance or
+    // a collection, unless `gate_token_account` is a token account of `poster` holding it,
+    // and for collections `gate_metadata` the metadata of its nft
+    pub fn require_gate(
+        tree_config: &AccountInfo,
+        poster: &Pubkey,
+        gate_token_account: Option<&Account<TokenAccount>>,
+        gate_metadata: Option<&Account<MetadataAccount>>,
+    ) -> Result<()> {
+        if tree_config.data_is_empty() {
+            return Ok(());
+        }
+        let config = Account::<TreeConfig>::try_from(tree_config)?;
+        if let Some(token_gate) = config.gate {
+            return token_gate.require_holder(poster, gate_token_account);
+        }
+        match config.nft_gate {
+            Some(collection) => {
+                require_collection_holder(&collection, poster, gate_token_account, gate_metadata)
+            }
+            None => Ok(()),
+        }
+    }
+
+    // Sets the lamports posters to the tree must stake
+    pub fn set_min_stake(&mut self, min_stake: u64) {
+        self.min_stake = min_stake;
+    }
+
+    // Records an append on `stake` if the config in `tree_config` exists and asks posters
+    // to stake, failing without the stake pda or if it holds less than the tree asks
+    pub fn record_stake(tree_config: &AccountInfo, stake: Option<&mut Stake>) -> Result<()> {
+        if tree_config.data_is_empty() {
+            return Ok(());
+        }
+        let config = Account::<TreeConfig>::try_from(tree_config)?;
+        if config.min_stake == 0 {
+            return Ok(());
+        }
+        stake
+            .ok_or(CompressedNotesError::StakeMissing)?
+            .record_post(config.min_stake, Clock::get()?.slot)
+    }
+
+    // Sets the lamports a day of access to the tree costs
+    pub fn set_subscription_price(&mut self, subscription_price: u64) {
+        self.subscription_price = subscription_price;
+    }
+
+    // The lamports `duration` days of access to the tree cost, failing if it sells none
+    pub fn subscription_cost(&self, duration: u32) -> Result<u64> {
+        require!(
+            self.subscription_price > 0,
+            CompressedNotesError::NotPremiumTree
+        );
+        self.subscription_price
+            .checked_mul(duration.into())
+            .ok_or_else(|| error!(CompressedNotesError::SubscriptionTooLong))
+    }
+
+    // Fails if the config in `tree_config` exists and sells access, unless `poster` is the
+    // tree authority or `subscription` is their active subscription to the tree
+    pub fn require_subscription(
+        tree_config: &AccountInfo,
+        poster: &Pubkey,
+        subscription: Option<&TreeSubscription>,
+    ) -> Result<()> {
+        if tree_config.data_is_empty() {
+            return Ok(());
+        }
+        let config = Account::<TreeConfig>::try_from(tree_config)?;
+        if config.subscription_price == 0 || config.authority == *poster {
+            return Ok(());
+        }
+        subscription
+            .ok_or(CompressedNotesError::SubscriptionMissing)?
+            .require_active(&config.merkle_tree, poster)
+    }
+
+    // Sets whether appends are checked against the tree's recent leaves pda
+    pub fn set_dedupe_leaves(&mut self, dedupe_leaves: bool) {
+        self.dedupe_leaves = dedupe_leaves;
+    }
+
+    // Fails if the config in `tree_config` exists and restricts cpi callers, unless the
+    // instructions sysvar is given and the top level instruction is this program's or an
+    // allowlisted program's
+    pub fn require_caller(
+        tree_config: &AccountInfo,
+        instructions: Option<&AccountInfo>,
+    ) -> Result<()> {
+        if tree_config.data_is_empty() {
+            return Ok(());
+        }
+        let config = Account::<TreeConfig>::try_from(tree_config)?;
+        if config.cpi_policy == CpiPolicy::Any {
+            return Ok(());
+        }
+        let instructions = instructions.ok_or(CompressedNotesError::InstructionsSysvarMissing)?;
+        // A cpi runs inside the top level instruction of the program calling in
+        let current_index = load_current_index_checked(instructions)?;
+        let caller = load_instruction_at_checked(current_index.into(), instructions)?.program_id;
+        let allowed = match &config.cpi_policy {
+            CpiPolicy::Allowlist { programs } => caller == crate::ID || programs.contains(&caller),
+            _ => caller == crate::ID,
+        };
+        require!(allowed, CompressedNotesError::CpiCallerNotAllowed);
+        Ok(())
+    }
+
+    // Fails if the config in `tree_config` exists and `writer` holding `role`, if any,
+    // cannot write to its tree, because it is private
+    pub fn require_writable(
+        tree_config: &AccountInfo,
+        writer: &Pubkey,
+        role: Option<&Role>,
+    ) -> Result<()> {
+        if tree_config.data_is_empty() {
+            return Ok(());
+        }
+        let config = Account::<TreeConfig>::try_from(tree_config)?;
+        require!(
+            config.can_write(writer, role),
+            CompressedNotesError::TreeNotWritable
+        );
+        Ok(())
+    }
+
+    // Whether `key` holding `role`, if any, can write to the tree
+    fn can_write(&self, key: &Pubkey, role: Option<&Role>) -> bool {
+        self.visibility == Visibility::Public
+            || self.is_authority(key)
+            || self.writers.contains(key)
+            || self.grants(role, key, RoleKind::Writer)
+    }
+
+    // Whether `key` is the authority of the tree
+    pub fn is_authority(&self, key: &Pubkey) -> bool {
+        self.authority == *key
+    }
+
+    // Whether `key` holding `role`, if any, can run the tree config instructions
+    pub fn is_admin(&self, key: &Pubkey, role: Option<&Role>) -> bool {
+        self.is_authority(key) || self.grants(role, key, RoleKind::Admin)
+    }
+
+    // Whether `role` is held by `key` on the tree under its current authority and includes `kind`
+    pub fn grants(&self, role: Option<&Role>, key: &Pubkey, kind: RoleKind) -> bool {
+        Role::grants(role, &self.merkle_tree, self.authority_epoch, key, kind)
+    }
+
+    // Like `grants` for the config in `tree_config`, no role is held without a config
+    pub fn grants_in(
+        tree_config: &AccountInfo,
+        role: Option<&Role>,
+        key: &Pubkey,
+        kind: RoleKind,
+    ) -> Result<bool> {
+        if tree_config.data_is_empty() {
+            return Ok(false);
+        }
+        Ok(Account::<TreeConfig>::try_from(tree_config)?.grants(role, key, kind))
+    }
+
+    // The number of authority transfers of the tree, roles granted before the last one are void
+    pub fn authority_epoch(&self) -> u32 {
+        self.authority_epoch
+    }
+
+    // Hands the tree to `new_authority`, voiding the roles granted by the previous authority
+    fn set_authority(&mut self, new_authority: Pubkey) {
+        self.authority = new_authority;
+        self.authority_epoch = self.authority_epoch.wrapping_add(1);
+    }
+
+    // Counts `old_leaf` replaced in `merkle_tree` under `root` on the config in
+    // `tree_config`, if it exists, failing if `root` is neither the current root nor a
+    // recent one, or if the tree has leaves on hold and `hold` does not show `old_leaf`
+    // is not one of them
+    pub fn record_replace(
+        tree_config: &AccountInfo,
+        merkle_tree: &AccountInfo,
+        root: &[u8; 32],
+        old_leaf: &[u8; 32],
+        hold: Option<&AccountInfo>,
+    ) -> Result<()> {
+        TreeConfig::update(tree_config, merkle_tree, |config, current_root| {
+            require!(
+                root == current_root || config.is_recent_root(root),
+                CompressedNotesError::RootTooOld
+            );
+            if config.held_leaves > 0 {
+                Hold::require_unheld(hold, merkle_tree.key, old_leaf)?;
+            }
+            config.replace_count += 1;
+            Ok(())
+        })
+    }
+
+    // Whether `root` is one of the roots the tree had before its latest changes
+    pub fn is_recent_root(&self, root: &[u8; 32]) -> bool {
+        // Slots not yet written hold zeroes, which no tree root hashes to
+        *root != [0; 32] && self.recent_roots.contains(root)
+    }
+
+    // Whether appends to the tree of `other` are checked the same way as to this tree, so a
+    // full tree can continue in it while checking appends against the full tree's accounts
+    pub fn has_same_policy(&self, other: &TreeConfig) -> bool {
+        self.authority == other.authority
+            && self.visibility == other.visibility
+            && self.writers == other.writers
+            && self.min_append_slots == other.min_append_slots
+            && self.max_appends_per_epoch == other.max_appends_per_epoch
+            && self.cpi_policy == other.cpi_policy
+            && self.dedupe_leaves == other.dedupe_leaves
+            && self.fees == other.fees
+            && self.fee_mint == other.fee_mint
+            && self.fee_token_amount == other.fee_token_amount
+            && self.gate == other.gate
+            && self.nft_gate == other.nft_gate
+            && self.min_stake == other.min_stake
+            && self.subscription_price == other.subscription_price
+    }
+
+    // Checks `next_tree`, configured in `next_config`, is the successor the authority linked
+    // to the tree of the config in `tree_config` and still has the same policy
+    pub fn require_successor(
+        tree_config: &AccountInfo,
+        next_config: &AccountInfo,
+        next_tree: &Pubkey,
+    ) -> Result<()> {
+        let config = Account::<TreeConfig>::try_from(tree_config)?;
+        let next = Account::<TreeConfig>::try_from(next_config)?;
+        require!(
+            config.next_tree == Some(*next_tree) && next.merkle_tree == *next_tree,
+            CompressedNotesError::InvalidSuccessorTree
+        );
+        require!(
+            config.has_same_policy(&next),
+            CompressedNotesError::SuccessorPolicyMismatch
+        );
+        Ok(())
+    }
+
+    // Freezes the tree of the config in `source_config` ahead of a migration into the tree
+    // of the config in `dest_config`. Both trees must belong to `authority`.
+    pub fn freeze_for_migration(
+        source_config: &AccountInfo,
+        dest_config: &AccountInfo,
+        authority: &Pubkey,
+    ) -> Result<()> {
+        let mut source = Account::<TreeConfig>::try_from(source_config)?;
+        let dest = Account::<TreeConfig>::try_from(dest_config)?;
+        require_keys_eq!(
+            source.authority,
+            *authority,
+            CompressedNotesError::Unauthorized
+        );
+        require_keys_eq!(
+            dest.authority,
+            *authority,
+            CompressedNotesError::Unauthorized
+        );
+        source.frozen = true;
+        source.exit(&crate::ID)
+    }
+
+    // Retires the tree of the config in `tree_config` after a migration into `next_tree`,
+    // which becomes its successor. The tree stays frozen.
+    pub fn retire(tree_config: &AccountInfo, next_tree: &Pubkey) -> Result<()> {
+        let mut config = Account::<TreeConfig>::try_from(tree_config)?;
+        config.frozen = true;
+        config.next_tree = Some(*next_tree);
+        config.exit(&crate::ID)
+    }
+
+    // Applies `change` to the config in `tree_config` ahead of a change to `merkle_tree`,
+    // failing if the tree is frozen. `change` gets the current root of the tree, which
+    // the config keeps as a recent root once the tree moves on from it. A config that
+    // was never created freezes and counts nothing.
+    fn update(
+        tree_config: &AccountInfo,
+        merkle_tree: &AccountInfo,
+        change: impl FnOnce(&mut TreeConfig, &[u8; 32]) -> Result<()>,
+    ) -> Result<()> {
+        if tree_config.data_is_empty() {
+            return Ok(());
+        }
+        let mut config = Account::<TreeConfig>::try_from(tree_config)?;
+        require!(!config.frozen, CompressedNotesError::TreeFrozen);
+        require!(
+            !config.emergency_frozen,
+            CompressedNotesError::TreeEmergencyFrozen
+        );
+        let current_root = TreeState::load(merkle_tree)?.root;
+        change(&mut config, &current_root)?;
+        let cursor = config.root_cursor as usize;
+        config.recent_roots[cursor] = current_root;
+        config.root_cursor = ((cursor + 1) % TreeConfig::RECENT_ROOTS) as u8;
+        config.exit(&crate::ID)
+    }
+}
+
+#[derive(Accounts)]
+#[instruction(name: String, description: String, image_uri: String)]
+pub struct SetTreeMetadata<'info> {
+    // The current authority of the tree or an admin, pays for or is refunded the resized config
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    // The role of the signer, needed unless it is the tree authority
+    pub role: Option<Account<'info, Role>>,
+
+    // The tree config pda, resized to fit the metadata
+    #[account(
+        mut,
+        seeds = [TreeConfig::SEED, merkle_tree.key().as_ref()],
+        bump = tree_config.bump,
+        constraint = tree_config.is_admin(&authority.key(), role.as_deref())
+            @ CompressedNotesError::Unauthorized,
+        has_one = merkle_tree,
+        realloc = TreeConfig::space(&name, &description, &image_uri, tree_config.writers.len()),
+        realloc::payer = authority,
+        realloc::zero = false,
+    )]
+    pub tree_config: Account<'info, TreeConfig>,
+
+    // The merkle tree account
+    /// CHECK: Only used to derive the tree config pda
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    // The system program, used to pay for the resized config
+    pub system_program: Program<'info, System>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[derive(Accounts)]
+pub struct AddWriter<'info> {
+    // The current authority of the tree or an admin, pays for the grown config
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    // The role of the signer, needed unless it is the tree authority
+    pub role: Option<Account<'info, Role>>,
+
+    // The tree config pda, grown by one writer
+    #[account(
+        mut,
+        seeds = [TreeConfig::SEED, merkle_tree.key().as_ref()],
+        bump = tree_config.bump,
+        constraint = tree_config.is_admin(&authority.key(), role.as_deref())
+            @ CompressedNotesError::Unauthorized,
+        has_one = merkle_tree,
+        realloc = tree_config.space_with_writers(tree_config.writers.len() + 1),
+        realloc::payer = authority,
+        realloc::zero = false,
+    )]
+    pub tree_config: Account<'info, TreeConfig>,
+
+    // The merkle tree account
+    /// CHECK: Only used to derive the tree config pda
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    // The system program, used to pay for the resized config
+    pub system_program: Program<'info, System>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[derive(Accounts)]
+pub struct RemoveWriter<'info> {
+    // The current authority of the tree or an admin, refunded the rent of the removed writer
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    // The role of the signer, needed unless it is the tree authority
+    pub role: Option<Account<'info, Role>>,
+
+    // The tree config pda, shrunk by one writer
+    #[account(
+        mut,
+        seeds = [TreeConfig::SEED, merkle_tree.key().as_ref()],
+        bump = tree_config.bump,
+        constraint = tree_config.is_admin(&authority.key(), role.as_deref())
+            @ CompressedNotesError::Unauthorized,
+        has_one = merkle_tree,
+        realloc = tree_config.space_with_writers(tree_config.writers.len().saturating_sub(1)),
+        realloc::payer = authority,
+        realloc::zero = false,
+    )]
+    pub tree_config: Account<'info, TreeConfig>,
+
+    // The merkle tree account
+    /// CHECK: Only used to derive the tree config pda
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    // The system program, required to resize the config
+    pub system_program: Program<'info, System>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[derive(Accounts)]
+pub struct TreeConfigAuthority<'info> {
+    // The current authority of the tree or an admin, see `roles`
+    pub authority: Signer<'info>,
+
+    // The role of the signer, needed unless it is the tree authority
+    pub role: Option<Account<'info, Role>>,
+
+    // The tree config pda
+    #[account(
+        mut,
+        seeds = [TreeConfig::SEED, merkle_tree.key().as_ref()],
+        bump = tree_config.bump,
+        constraint = tree_config.is_admin(&authority.key(), role.as_deref())
+            @ CompressedNotesError::Unauthorized,
+        has_one = merkle_tree,
+    )]
+    pub tree_config: Account<'info, TreeConfig>,
+
+    // The merkle tree account
+    /// CHECK: Only used to derive the tree config pda
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    // The noop program to log data
+    pub log_wrapper: Program<'info, Noop>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[derive(Accounts)]
+pub struct SetNextTree<'info> {
+    // The authority of both trees
+    pub authority: Signer<'info>,
+
+    // The config pda of the tree being linked
+    #[account(
+        mut,
+        seeds = [TreeConfig::SEED, merkle_tree.key().as_ref()],
+        bump = tree_config.bump,
+        has_one = authority @ CompressedNotesError::Unauthorized,
+        has_one = merkle_tree,
+    )]
+    pub tree_config: Account<'info, TreeConfig>,
+
+    // The merkle tree account
+    /// CHECK: Only used to derive the tree config pda
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    // The config pda of the successor tree
+    #[account(
+        seeds = [TreeConfig::SEED, next_tree.key().as_ref()],
+        bump = next_config.bump,
+        constraint = next_config.merkle_tree == next_tree.key()
+            && next_tree.key() != merkle_tree.key() @ CompressedNotesError::InvalidSuccessorTree,
+    )]
+    pub next_config: Account<'info, TreeConfig>,
+
+    // The successor merkle tree account
+    /// CHECK: Only used to derive the successor config pda
+    pub next_tree: UncheckedAccount<'info>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[derive(Accounts)]
+pub struct EmergencyFreezeTree<'info> {
+    // The admin of the global config
+    pub admin: Signer<'info>,
+
+    // The tree config pda
+    #[account(
+        mut,
+        seeds = [TreeConfig::SEED, merkle_tree.key().as_ref()],
+        bump = tree_config.bump,
+        has_one = merkle_tree,
+    )]
+    pub tree_config: Account<'info, TreeConfig>,
+
+    // The merkle tree account
+    /// CHECK: Only used to derive the tree config pda
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    // The noop program to log data
+    pub log_wrapper: Program<'info, Noop>,
+
+    // The global config, naming the admin and used to check the instruction is enabled
+    #[account(
+        seeds = [GlobalConfig::SEED],
+        bump = global_config.bump,
+        has_one = admin @ CompressedNotesError::Unauthorized,
+    )]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[derive(Accounts)]
+pub struct CloseTree<'info> {
+    // The authority of the tree, receives the tree and config rent
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    // The tree config pda, closed by this instruction
+    #[account(
+        mut,
+        close = authority,
+        seeds = [TreeConfig::SEED, merkle_tree.key().as_ref()],
+        bump = tree_config.bump,
+        has_one = authority @ CompressedNotesError::Unauthorized,
+        has_one = merkle_tree,
+    )]
+    pub tree_config: Account<'info, TreeConfig>,
+
+    // The pda authority for the merkle tree, only used for signing
+    #[account(
+        seeds = [merkle_tree.key().as_ref()],
+        bump,
+    )]
+    pub tree_authority: SystemAccount<'info>,
+
+    // The merkle tree account
+    /// CHECK: This account is validated by the spl account compression program
+    #[account(
+        mut,
+        owner = spl_account_compression::ID @ CompressedNotesError::InvalidMerkleTreeOwner,
+    )]
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    // The noop program to log data
+    pub log_wrapper: Program<'info, Noop>,
+
+    // The spl account compression program
+    pub compression_program: Program<'info, SplAccountCompression>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
+
+#[derive(Accounts)]
+pub struct DecommissionTree<'info> {
+    // The authority of the tree, pays for the tombstone and receives the tree and config rent
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    // The tree config pda, closed by this instruction
+    #[account(
+        mut,
+        close = authority,
+        seeds = [TreeConfig::SEED, merkle_tree.key().as_ref()],
+        bump = tree_config.bump,
+        has_one = authority @ CompressedNotesError::Unauthorized,
+        has_one = merkle_tree,
+    )]
+    pub tree_config: Account<'info, TreeConfig>,
+
+    // The tombstone pda, keeping the final state of the tree
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + TreeTombstone::INIT_SPACE,
+        seeds = [TreeTombstone::SEED, merkle_tree.key().as_ref()],
+        bump,
+    )]
+    pub tree_tombstone: Account<'info, TreeTombstone>,
+
+    // The pda authority for the merkle tree, only used for signing
+    #[account(
+        seeds = [merkle_tree.key().as_ref()],
+        bump,
+    )]
+    pub tree_authority: SystemAccount<'info>,
+
+    // The merkle tree account
+    /// CHECK: This account is validated by the spl account compression program
+    #[account(
+        mut,
+        owner = spl_account_compression::ID @ CompressedNotesError::InvalidMerkleTreeOwner,
+    )]
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    // The noop program to log data
+    pub log_wrapper: Program<'info, Noop>,
+
+    // The spl account compression program
+    pub compression_program: Program<'info, SplAccountCompression>,
+
+    // The system program, used to create the tombstone account
+    pub system_program: Program<'info, System>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
@@ -0,0 +1,268 @@
+//! Read-only access to the state of a spl account compression tree account, parsed from its raw
+//! bytes for any depth, buffer size and canopy.
+
+use anchor_lang::{prelude::*, solana_program::keccak::hashv};
+use spl_account_compression::{
+    canopy::fill_in_proof_from_canopy,
+    state::{
+        merkle_tree_get_size, ConcurrentMerkleTreeHeader, CONCURRENT_MERKLE_TREE_HEADER_SIZE_V1,
+    },
+};
+
+use crate::{instruction_flags, CompressedNotesError, GlobalConfig};
+
+// The (max_depth, max_buffer_size) pairs the spl account compression program supports
+pub const VALID_TREE_PARAMETERS: [(u32, u32); 26] = [
+    (3, 8),
+    (5, 8),
+    (14, 64),
+    (14, 256),
+    (14, 1024),
+    (14, 2048),
+    (15, 64),
+    (16, 64),
+    (17, 64),
+    (18, 64),
+    (19, 64),
+    (20, 64),
+    (20, 256),
+    (20, 1024),
+    (20, 2048),
+    (24, 64),
+    (24, 256),
+    (24, 512),
+    (24, 1024),
+    (24, 2048),
+    (26, 512),
+    (26, 1024),
+    (26, 2048),
+    (30, 512),
+    (30, 1024),
+    (30, 2048),
+];
+
+// Returns the number of leaves that can still be appended to a tree.
+pub fn process_get_tree_capacity(ctx: Context<ReadTree>) -> Result<u64> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::GET_TREE_CAPACITY)?;
+    Ok(TreeState::load(&ctx.accounts.merkle_tree)?.remaining_capacity())
+}
+
+// Returns the current root and sequence number of a tree.
+pub fn process_get_tree_root(ctx: Context<ReadTree>) -> Result<TreeRoot> {
+    // Check the instruction is enabled on this deployment
+    ctx.accounts
+        .global_config
+        .require_enabled(instruction_flags::GET_TREE_ROOT)?;
+    let state = TreeState::load(&ctx.accounts.merkle_tree)?;
+    Ok(TreeRoot {
+        root: state.root,
+        sequence_number: state.sequence_number,
+    })
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TreeRoot {
+    pub root: [u8; 32],       // The current root
+    pub sequence_number: u64, // The number of changes applied to the tree
+}
+
+// The parts of a tree account's state this program reads
+pub struct TreeState {
+    pub authority: Pubkey,    // The authority the tree was initialized with
+    pub max_depth: u32,       // Max depth of the merkle tree
+    pub max_buffer_size: u32, // Max buffer size of the merkle tree
+    pub sequence_number: u64, // The number of changes applied to the tree
+    pub root: [u8; 32],       // The current root
+    pub next_index: u32,      // Index the next appended leaf gets, also the number of leaves
+    pub canopy_depth: u32,    // Depth of the canopy cached after the tree
+}
+
+impl TreeState {
+    // Parses the state of an initialized tree account
+    pub fn load(merkle_tree: &AccountInfo) -> Result<Self> {
+        require_keys_eq!(
+            *merkle_tree.owner,
+            spl_account_compression::ID,
+            CompressedNotesError::InvalidTreeAccount
+        );
+        let data = merkle_tree.try_borrow_data()?;
+        require!(
+            data.len() >= CONCURRENT_MERKLE_TREE_HEADER_SIZE_V1,
+            CompressedNotesError::InvalidTreeAccount
+        );
+        let (header, tree) = data.split_at(CONCURRENT_MERKLE_TREE_HEADER_SIZE_V1);
+        // The authority follows the account type, the version, max buffer size and max depth
+        let authority = Pubkey::try_from(&header[10..42]).unwrap();
+        let header = ConcurrentMerkleTreeHeader::try_from_slice(header)?;
+        header.assert_valid()?;
+        let tree_size = merkle_tree_get_size(&header)?;
+        require!(
+            tree.len() >= tree_size,
+            CompressedNotesError::InvalidTreeAccount
+        );
+
+        let max_depth = header.get_max_depth();
+        let max_buffer_size = header.get_max_buffer_size();
+        // A change log is a root, a path of max_depth nodes, an index and padding
+        let change_log_size = 32 + 32 * max_depth as usize + 8;
+        let change_logs = 24;
+        let active_index = read_u64(tree, 8) as usize;
+        let root_offset = change_logs + active_index * change_log_size;
+        // The rightmost proof is max_depth nodes and the rightmost leaf, then its index
+        let rightmost_proof = change_logs + max_buffer_size as usize * change_log_size;
+        let rightmost_index = rightmost_proof + 32 * max_depth as usize + 32;
+        // The canopy holds every node of its top levels bar the root, 2^(depth + 1) - 2 nodes
+        let canopy_nodes = (tree.len() - tree_size) / 32;
+        let canopy_depth = (canopy_nodes + 2).trailing_zeros().saturating_sub(1);
+
+        Ok(Self {
+            authority,
+            max_depth,
+            max_buffer_size,
+            sequence_number: read_u64(tree, 0),
+            root: tree[root_offset..root_offset + 32].try_into().unwrap(),
+            next_index: u32::from_le_bytes(
+                tree[rightmost_index..rightmost_index + 4]
+                    .try_into()
+                    .unwrap(),
+            ),
+            canopy_depth,
+        })
+    }
+
+    // The number of leaves that can still be appended
+    pub fn remaining_capacity(&self) -> u64 {
+        (1u64 << self.max_depth).saturating_sub(self.next_index as u64)
+    }
+
+    // Fails unless a leaf was appended at `index`
+    pub fn require_leaf(&self, index: u32) -> Result<()> {
+        require!(
+            index < self.next_index,
+            CompressedNotesError::LeafIndexOutOfBounds
+        );
+        Ok(())
+    }
+
+    // Whether every leaf of the tree has been appended
+    pub fn is_full(&self) -> bool {
+        self.remaining_capacity() == 0
+    }
+
+    // Recomputes the root `proof` proves `leaf` at `index` against, completing the proof
+    // from the canopy and then empty subtrees like the compression program does. Clients under
+    // concurrent writes send the last proof they fetched, accepted while its root is recent
+    pub fn proven_root(
+        merkle_tree: &AccountInfo,
+        leaf: [u8; 32],
+        index: u32,
+        proof: &[AccountInfo],
+    ) -> Result<[u8; 32]> {
+        let state = TreeState::load(merkle_tree)?;
+        let mut nodes: Vec<[u8; 32]> = proof.iter().map(|node| node.key.to_bytes()).collect();
+        // The canopy is the last bytes of the account
+        let data = merkle_tree.try_borrow_data()?;
+        let canopy_size = ((2 << state.canopy_depth) - 2) * 32;
+        fill_in_proof_from_canopy(
+            &data[data.len() - canopy_size..],
+            state.max_depth,
+            index,
+            &mut nodes,
+        )?;
+
+        let (mut node, mut empty_node) = (leaf, [0; 32]);
+        for level in 0..state.max_depth as usize {
+            let sibling = nodes.get(level).copied().unwrap_or(empty_node);
+            let parent = if index >> level & 1 == 0 {
+                hashv(&[&node, &sibling])
+            } else {
+                hashv(&[&sibling, &node])
+            };
+            node = parent.to_bytes();
+            empty_node = hashv(&[&empty_node, &empty_node]).to_bytes();
+        }
+        Ok(node)
+    }
+
+    // Fails unless the compression program supports a tree of this depth and buffer size,
+    // logging the nearest pair it does support
+    pub fn require_valid_parameters(max_depth: u32, max_buffer_size: u32) -> Result<()> {
+        if VALID_TREE_PARAMETERS.contains(&(max_depth, max_buffer_size)) {
+            return Ok(());
+        }
+        // Nearest by depth first, then by buffer size
+        let (depth, buffer_size) = VALID_TREE_PARAMETERS
+            .iter()
+            .min_by_key(|(depth, buffer_size)| {
+                (
+                    depth.abs_diff(max_depth),
+                    buffer_size.abs_diff(max_buffer_size),
+                )
+            })
+            .unwrap();
+        msg!(
+            "Unsupported max depth {} and max buffer size {}, the nearest supported are {} and {}",
+            max_depth,
+            max_buffer_size,
+            depth,
+            buffer_size
+        );
+        err!(CompressedNotesError::UnsupportedTreeParameters)
+    }
+
+    // The size of a tree account with the given parameters and a canopy of `canopy_depth` levels
+    pub fn account_size(max_depth: u32, max_buffer_size: u32, canopy_depth: u32) -> Result<usize> {
+        TreeState::require_valid_parameters(max_depth, max_buffer_size)?;
+        // The header fields are private, so the header is initialized from zeroed bytes
+        let mut header = ConcurrentMerkleTreeHeader::try_from_slice(
+            &[0; CONCURRENT_MERKLE_TREE_HEADER_SIZE_V1],
+        )?;
+        header.initialize(max_depth, max_buffer_size, &Pubkey::default(), 0);
+        let tree_size = merkle_tree_get_size(&header)?;
+        require!(
+            canopy_depth <= max_depth,
+            CompressedNotesError::InvalidCanopyDepth
+        );
+        let canopy_size = ((2 << canopy_depth) - 2) * 32;
+        Ok(CONCURRENT_MERKLE_TREE_HEADER_SIZE_V1 + tree_size + canopy_size)
+    }
+
+    // Fails unless `merkle_tree` was allocated with exactly the size of a tree with the given
+    // parameters, logging the expected and actual sizes
+    pub fn require_account_size(
+        merkle_tree: &AccountInfo,
+        max_depth: u32,
+        max_buffer_size: u32,
+        canopy_depth: u32,
+    ) -> Result<()> {
+        let expected = TreeState::account_size(max_depth, max_buffer_size, canopy_depth)?;
+        let actual = merkle_tree.data_len();
+        if actual != expected {
+            msg!(
+                "Invalid tree account size: expected {} bytes, actual {} bytes",
+                expected,
+                actual
+            );
+            return err!(CompressedNotesError::InvalidTreeAccountSize);
+        }
+        Ok(())
+    }
+}
+
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+#[derive(Accounts)]
+pub struct ReadTree<'info> {
+    // The merkle tree to read
+    /// CHECK: Parsed as a tree account owned by the spl account compression program
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    // The global config, used to check the instruction is enabled
+    #[account(seeds = [GlobalConfig::SEED], bump = global_config.bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+}
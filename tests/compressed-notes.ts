@@ -7,16 +7,82 @@ import {
   PublicKey,
   sendAndConfirmTransaction,
   Connection,
+  SYSVAR_INSTRUCTIONS_PUBKEY,
+  Ed25519Program,
+  Secp256k1Program,
 } from "@solana/web3.js"
 import {
   ValidDepthSizePair,
   createAllocTreeIx,
+  getConcurrentMerkleTreeAccountSize,
   SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
   SPL_NOOP_PROGRAM_ID,
   ConcurrentMerkleTreeAccount,
 } from "@solana/spl-account-compression"
-import { getHash, getNoteLog } from "./utils"
+import {
+  createTokenAccount,
+  createTokenMint,
+  encodeNoteId,
+  getAcknowledgementHash,
+  getAcknowledgementLog,
+  getAttributedNoteHash,
+  getBlockListAddress,
+  getBroadcastLog,
+  getCertificateHash,
+  getCertificateLog,
+  getConversationAddress,
+  getConversationStateAddress,
+  getConversationTreeAddress,
+  getCrossPostLog,
+  getCrossPostTreeAccounts,
+  getDeliveryLog,
+  getEditLog,
+  getEditedMessageHash,
+  getEmergencyFreezeLog,
+  getEncryptedMessageHash,
+  getEncryptedMessageLog,
+  getForwardedMessageHash,
+  getForwardedMessageLog,
+  getGroupMessageHash,
+  getGroupMessageLog,
+  getHash,
+  getInboxAddress,
+  getInboxTreeAddress,
+  getKeyReleaseLog,
+  getLinkMessage,
+  getMessageHashV2,
+  getMessageLog,
+  getMigrationLog,
+  getNoteLog,
+  getNoteMoveLog,
+  getOrgNoteHash,
+  getPermitMessage,
+  getOrgNoteLog,
+  getOutboxLog,
+  getReactionLog,
+  getReadReceiptLog,
+  getSharedNoteHash,
+  getTakedownHash,
+  getTakedownLog,
+  getTipLog,
+  getTreeAuthorityLog,
+  getTreeCheckpointLog,
+  getTreeCloseLog,
+  getTreeFreezeLog,
+  getForestAddress,
+  getTreeConfigAddress,
+  getTtlMessageHash,
+  getTypingLog,
+  getUrgentMessageLog,
+  mintTokens,
+  NoteStatus,
+  resolveNoteId,
+  TOKEN_PROGRAM_ID,
+} from "./utils"
+import { keccak256 } from "js-sha3"
 import { assert } from "chai"
+import * as fs from "fs"
+import * as path from "path"
 
 describe("compressed-notes", () => {
   const provider = anchor.AnchorProvider.env()
@@ -43,6 +109,208 @@ describe("compressed-notes", () => {
     program.programId
   )
 
+  // The tree config PDA recording the creator, authority and counters of the tree
+  const treeConfig = getTreeConfigAddress(
+    program.programId,
+    merkleTree.publicKey
+  )
+
+  // The forest PDA listing the trees the provider creates
+  const forest = getForestAddress(program.programId, provider.publicKey)
+
+  // The global config PDA gating which instructions are enabled on this deployment
+  const [globalConfig] = PublicKey.findProgramAddressSync(
+    [Buffer.from("global_config")],
+    program.programId
+  )
+
+  // The protocol treasury PDA collecting protocol fees
+  const [treasury] = PublicKey.findProgramAddressSync(
+    [Buffer.from("treasury")],
+    program.programId
+  )
+
+  // The nonce for messages sent only once, repeated messages need distinct nonces
+  const nonce = new anchor.BN(0)
+
+  // The conversation state PDA recording whether the recipient accepted the provider's messages
+  const getConversationState = (recipient: PublicKey) =>
+    getConversationStateAddress(
+      program.programId,
+      provider.publicKey,
+      recipient
+    )
+
+  // Creates a recipient that accepts a conversation request from the provider
+  async function openConversation(recipient = Keypair.generate()) {
+    const conversationState = getConversationState(recipient.publicKey)
+
+    await program.methods
+      .requestConversation(recipient.publicKey)
+      .accounts({
+        conversationState: conversationState,
+        globalConfig: globalConfig,
+      })
+      .rpc()
+    await program.methods
+      .acceptConversation()
+      .accounts({
+        recipient: recipient.publicKey,
+        conversationState: conversationState,
+        globalConfig: globalConfig,
+      })
+      .signers([recipient])
+      .rpc()
+    return recipient.publicKey
+  }
+
+  // Creates a note tree owned by the provider, or by `creator` in their own forest,
+  // returning the accounts to use it
+  async function createTree(creator = wallet.payer) {
+    const tree = Keypair.generate()
+    const [authority] = PublicKey.findProgramAddressSync(
+      [tree.publicKey.toBuffer()],
+      program.programId
+    )
+    const treeAccounts = {
+      treeConfig: getTreeConfigAddress(program.programId, tree.publicKey),
+      merkleTree: tree.publicKey,
+      treeAuthority: authority,
+      logWrapper: SPL_NOOP_PROGRAM_ID,
+      compressionProgram: SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
+      globalConfig: globalConfig,
+    }
+    const allocTreeIx = await createAllocTreeIx(
+      connection,
+      tree.publicKey,
+      creator.publicKey,
+      { maxDepth: 3, maxBufferSize: 8 },
+      0
+    )
+    const ix = await program.methods
+      .createNoteTree(3, 8, 0)
+      .accounts({
+        ...treeAccounts,
+        creator: creator.publicKey,
+        forest: getForestAddress(program.programId, creator.publicKey),
+        treasury: treasury,
+      })
+      .instruction()
+    const tx = new Transaction().add(allocTreeIx, ix)
+    await sendAndConfirmTransaction(connection, tx, [creator, tree])
+    return treeAccounts
+  }
+
+  it("Leaf Hashes Match Test Vectors", async () => {
+    // Known inputs -> leaf hash pairs shared with the program's test_vectors module
+    const vectors = JSON.parse(
+      fs.readFileSync(
+        path.join(__dirname, "fixtures", "leaf-vectors.json"),
+        "utf8"
+      )
+    )
+
+    for (const vector of vectors) {
+      const owner = new PublicKey(vector.owner)
+      const hash = {
+        1: () => getHash(vector.content, owner),
+        2: () => getHash(vector.content, owner, vector.status),
+        3: () =>
+          getCertificateHash(
+            owner,
+            Buffer.from(keccak256.arrayBuffer(vector.content)),
+            new anchor.BN(vector.timestamp)
+          ),
+        4: () =>
+          getOrgNoteHash(
+            vector.content,
+            owner,
+            new anchor.BN(vector.timestamp)
+          ),
+        5: () =>
+          getMessageHashV2(
+            vector.content,
+            owner,
+            new PublicKey(vector.recipient),
+            new anchor.BN(vector.nonce)
+          ),
+        6: () =>
+          getAcknowledgementHash(
+            Buffer.from(vector.originalLeaf, "hex"),
+            owner
+          ),
+        7: () =>
+          getSharedNoteHash(
+            vector.content,
+            [owner, ...vector.coOwners.map((key) => new PublicKey(key))],
+            vector.status
+          ),
+        8: () =>
+          getAttributedNoteHash(
+            vector.content,
+            owner,
+            vector.status,
+            Buffer.from(vector.ethAddress, "hex")
+          ),
+        9: () =>
+          getTtlMessageHash(
+            vector.content,
+            owner,
+            new anchor.BN(vector.timestamp)
+          ),
+        10: () =>
+          getEditedMessageHash(vector.content, owner, vector.editCount),
+        11: () =>
+          getEncryptedMessageHash(
+            Buffer.from(vector.content),
+            owner,
+            Buffer.from(vector.encryptionNonce, "hex"),
+            Buffer.from(vector.senderEphemeralPubkey, "hex")
+          ),
+        12: () =>
+          getForwardedMessageHash(
+            vector.content,
+            owner,
+            Buffer.from(vector.originalLeaf, "hex"),
+            new PublicKey(vector.originalSender)
+          ),
+        13: () =>
+          getGroupMessageHash(
+            vector.content,
+            owner,
+            vector.recipients.map((key) => new PublicKey(key))
+          ),
+      }[vector.schemaVersion]()
+      assert(
+        hash === vector.leafHash,
+        `schema ${vector.schemaVersion} leaf hash mismatch for "${vector.content}"`
+      )
+    }
+  })
+
+  it("Initialize Global Config", async () => {
+    // The program data account records the upgrade authority of the program
+    const [programData] = PublicKey.findProgramAddressSync(
+      [program.programId.toBuffer()],
+      new PublicKey("BPFLoaderUpgradeab1e11111111111111111111111")
+    )
+
+    await program.methods
+      .initializeGlobalConfig(wallet.publicKey)
+      .accounts({
+        globalConfig: globalConfig,
+        program: program.programId,
+        programData: programData,
+      })
+      .rpc()
+
+    const globalConfigAccount = await program.account.globalConfig.fetch(
+      globalConfig
+    )
+    assert(globalConfigAccount.admin.equals(wallet.publicKey))
+    assert(globalConfigAccount.treeCreationFee.toNumber() === 0)
+  })
+
   it("Create Note Tree", async () => {
     const maxDepthSizePair: ValidDepthSizePair = {
       maxDepth: 3,
@@ -59,79 +327,4910 @@ describe("compressed-notes", () => {
     )
     // instruction to initialize the tree through the Note program
     const ix = await program.methods
-      .createNoteTree(maxDepthSizePair.maxDepth, maxDepthSizePair.maxBufferSize)
+      .createNoteTree(
+        maxDepthSizePair.maxDepth,
+        maxDepthSizePair.maxBufferSize,
+        canopyDepth
+      )
       .accounts({
+        forest: forest,
+        treasury: treasury,
+        treeConfig: treeConfig,
         merkleTree: merkleTree.publicKey,
         treeAuthority: treeAuthority,
         logWrapper: SPL_NOOP_PROGRAM_ID,
         compressionProgram: SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
+        globalConfig: globalConfig,
       })
       .instruction()
     const tx = new Transaction().add(allocTreeIx, ix)
     await sendAndConfirmTransaction(connection, tx, [wallet.payer, merkleTree])
+
+    // The config records the creator and the parameters read back from the tree
+    const treeConfigAccount = await program.account.treeConfig.fetch(treeConfig)
+    assert(treeConfigAccount.creator.equals(provider.publicKey))
+    assert(treeConfigAccount.maxDepth === maxDepthSizePair.maxDepth)
+    assert(treeConfigAccount.maxBufferSize === maxDepthSizePair.maxBufferSize)
+    assert(treeConfigAccount.canopyDepth === canopyDepth)
+    assert(treeConfigAccount.createdAt.toNumber() > 0)
+    assert(treeConfigAccount.numAppended.toNumber() === 0)
+    // Note trees are stamped with the v2 note leaf schema
+    assert(treeConfigAccount.schemaVersion === 2)
+
+    // The tree is listed in the creator's forest
+    const forestAccount = await program.account.forest.fetch(forest)
+    const entry = forestAccount.trees.find((tree) =>
+      tree.merkleTree.equals(merkleTree.publicKey)
+    )
+    assert("notes" in entry.purpose)
+    assert(entry.createdAt.eq(treeConfigAccount.createdAt))
   })
 
-  it("Add Note", async () => {
-    const txSignature = await program.methods
-      .appendNote(firstNote)
+  it("Tree Creation Fee Goes To Treasury", async () => {
+    const fee = new anchor.BN(1_000_000)
+    await program.methods
+      .setTreeCreationFee(fee)
+      .accounts({ globalConfig: globalConfig })
+      .rpc()
+
+    // Creating a tree moves the fee from the creator into the treasury
+    const balanceBefore = await connection.getBalance(treasury)
+    await createTree()
+    const balanceAfter = await connection.getBalance(treasury)
+    assert(balanceAfter === balanceBefore + fee.toNumber())
+
+    // Later tests create trees for free
+    await program.methods
+      .setTreeCreationFee(new anchor.BN(0))
+      .accounts({ globalConfig: globalConfig })
+      .rpc()
+  })
+
+  it("Set Tree Metadata", async () => {
+    const sizeBefore = (await connection.getAccountInfo(treeConfig)).data.length
+    const name = "notes"
+    const description = "the notes of the test suite"
+    const imageUri = "https://example.com/notes.png"
+    await program.methods
+      .setTreeMetadata(name, description, imageUri)
       .accounts({
+        treeConfig: treeConfig,
         merkleTree: merkleTree.publicKey,
-        treeAuthority: treeAuthority,
-        logWrapper: SPL_NOOP_PROGRAM_ID,
-        compressionProgram: SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
+        globalConfig: globalConfig,
       })
       .rpc()
 
-    const noteLog = await getNoteLog(connection, txSignature)
-    const hash = getHash(firstNote, provider.publicKey)
+    // The config grew by exactly the metadata bytes
+    const treeConfigAccount = await program.account.treeConfig.fetch(treeConfig)
+    assert(treeConfigAccount.name === name)
+    assert(treeConfigAccount.description === description)
+    assert(treeConfigAccount.imageUri === imageUri)
+    const sizeAfter = (await connection.getAccountInfo(treeConfig)).data.length
+    const metadataLength = name.length + description.length + imageUri.length
+    assert(sizeAfter === sizeBefore + metadataLength)
+  })
 
-    assert(hash === Buffer.from(noteLog.leafNode).toString("hex"))
-    assert(firstNote === noteLog.note)
+  it("Tree Account Size Must Match Parameters", async () => {
+    const tree = Keypair.generate()
+    const [authority] = PublicKey.findProgramAddressSync(
+      [tree.publicKey.toBuffer()],
+      program.programId
+    )
+    // The account is allocated without a canopy
+    const allocTreeIx = await createAllocTreeIx(
+      connection,
+      tree.publicKey,
+      wallet.publicKey,
+      { maxDepth: 3, maxBufferSize: 8 },
+      0
+    )
+    try {
+      await program.methods
+        .createNoteTree(3, 8, 2)
+        .accounts({
+          forest: forest,
+          treasury: treasury,
+          treeConfig: getTreeConfigAddress(program.programId, tree.publicKey),
+          merkleTree: tree.publicKey,
+          treeAuthority: authority,
+          logWrapper: SPL_NOOP_PROGRAM_ID,
+          compressionProgram: SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
+          globalConfig: globalConfig,
+        })
+        .preInstructions([allocTreeIx])
+        .signers([tree])
+        .rpc()
+      assert.fail("a tree without a canopy should not be created with one")
+    } catch (error) {
+      assert(error.error.errorCode.code === "InvalidTreeAccountSize")
+      // The logs name the size the account should have had
+      const expected = getConcurrentMerkleTreeAccountSize(3, 8, 2)
+      const actual = getConcurrentMerkleTreeAccountSize(3, 8, 0)
+      assert(
+        error.logs.some((log: string) =>
+          log.includes(`expected ${expected} bytes, actual ${actual} bytes`)
+        )
+      )
+    }
   })
 
-  it("Add Max Size Note", async () => {
-    // Size of note is limited by max transaction size of 1232 bytes, minus additional data required for the instruction
-    const txSignature = await program.methods
-      .appendNote(secondNote)
+  it("Create Note Tree Allocated By The Program", async () => {
+    const tree = Keypair.generate()
+    const [authority] = PublicKey.findProgramAddressSync(
+      [tree.publicKey.toBuffer()],
+      program.programId
+    )
+    const allocatedTreeConfig = getTreeConfigAddress(
+      program.programId,
+      tree.publicKey
+    )
+    // No allocation instruction, the tree keypair only signs for its account
+    await program.methods
+      .createAllocatedNoteTree(3, 8, 1)
       .accounts({
-        merkleTree: merkleTree.publicKey,
-        treeAuthority: treeAuthority,
+        forest: forest,
+        treasury: treasury,
+        treeConfig: allocatedTreeConfig,
+        merkleTree: tree.publicKey,
+        treeAuthority: authority,
         logWrapper: SPL_NOOP_PROGRAM_ID,
         compressionProgram: SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
+        globalConfig: globalConfig,
       })
+      .signers([tree])
       .rpc()
 
-    const noteLog = await getNoteLog(connection, txSignature)
-    const hash = getHash(secondNote, provider.publicKey)
+    const treeAccount = await connection.getAccountInfo(tree.publicKey)
+    assert(treeAccount.owner.equals(SPL_ACCOUNT_COMPRESSION_PROGRAM_ID))
+    assert(
+      treeAccount.data.length === getConcurrentMerkleTreeAccountSize(3, 8, 1)
+    )
+    const treeConfigAccount = await program.account.treeConfig.fetch(
+      allocatedTreeConfig
+    )
+    assert(treeConfigAccount.canopyDepth === 1)
+  })
 
-    assert(hash === Buffer.from(noteLog.leafNode).toString("hex"))
-    assert(secondNote === noteLog.note)
+  it("Unsupported Tree Parameters Are Rejected", async () => {
+    const tree = Keypair.generate()
+    const [authority] = PublicKey.findProgramAddressSync(
+      [tree.publicKey.toBuffer()],
+      program.programId
+    )
+    try {
+      await program.methods
+        .createAllocatedNoteTree(4, 8, 0)
+        .accounts({
+          forest: forest,
+          treasury: treasury,
+          treeConfig: getTreeConfigAddress(program.programId, tree.publicKey),
+          merkleTree: tree.publicKey,
+          treeAuthority: authority,
+          logWrapper: SPL_NOOP_PROGRAM_ID,
+          compressionProgram: SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
+          globalConfig: globalConfig,
+        })
+        .signers([tree])
+        .rpc()
+      assert.fail("a depth of 4 should be refused")
+    } catch (error) {
+      assert(error.error.errorCode.code === "UnsupportedTreeParameters")
+      assert(
+        error.logs.some((log) =>
+          log.includes("the nearest supported are 3 and 8")
+        )
+      )
+    }
   })
 
-  it("Update First Note", async () => {
+  it("Create Named Tree", async () => {
+    // The tree, its authority and config all follow from the creator and namespace
+    const namespace = "journal"
+    const [namedTree] = PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("tree"),
+        provider.publicKey.toBuffer(),
+        Buffer.from(namespace),
+      ],
+      program.programId
+    )
+    const [authority] = PublicKey.findProgramAddressSync(
+      [namedTree.toBuffer()],
+      program.programId
+    )
+    const namedTreeAccounts = {
+      treeConfig: getTreeConfigAddress(program.programId, namedTree),
+      merkleTree: namedTree,
+      treeAuthority: authority,
+      logWrapper: SPL_NOOP_PROGRAM_ID,
+      compressionProgram: SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
+      globalConfig: globalConfig,
+    }
+    await program.methods
+      .createNamedTree(namespace, 3, 8, 0)
+      .accounts({ ...namedTreeAccounts, forest: forest, treasury: treasury })
+      .rpc()
+
+    await program.methods.appendNote("named").accounts(namedTreeAccounts).rpc()
+    const treeConfigAccount = await program.account.treeConfig.fetch(
+      namedTreeAccounts.treeConfig
+    )
+    assert(treeConfigAccount.merkleTree.equals(namedTree))
+    assert(treeConfigAccount.numAppended.toNumber() === 1)
+
+    // The same namespace cannot name a second tree
+    try {
+      await program.methods
+        .createNamedTree(namespace, 3, 8, 0)
+        .accounts({ ...namedTreeAccounts, forest: forest, treasury: treasury })
+        .rpc()
+      assert.fail("a namespace should only name one tree")
+    } catch (error) {
+      assert(error.logs.some((log) => log.includes("already in use")))
+    }
+  })
+
+  it("Close Empty Note Tree", async () => {
+    const emptyTree = Keypair.generate()
+    const [emptyTreeAuthority] = PublicKey.findProgramAddressSync(
+      [emptyTree.publicKey.toBuffer()],
+      program.programId
+    )
+    const treeAccounts = {
+      treeConfig: getTreeConfigAddress(program.programId, emptyTree.publicKey),
+      merkleTree: emptyTree.publicKey,
+      treeAuthority: emptyTreeAuthority,
+      logWrapper: SPL_NOOP_PROGRAM_ID,
+      compressionProgram: SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
+      globalConfig: globalConfig,
+    }
+
+    const allocTreeIx = await createAllocTreeIx(
+      connection,
+      emptyTree.publicKey,
+      wallet.publicKey,
+      { maxDepth: 3, maxBufferSize: 8 },
+      0
+    )
+    const ix = await program.methods
+      .createNoteTree(3, 8, 0)
+      .accounts({ ...treeAccounts, forest: forest, treasury: treasury })
+      .instruction()
+    const tx = new Transaction().add(allocTreeIx, ix)
+    await sendAndConfirmTransaction(connection, tx, [wallet.payer, emptyTree])
+
+    const treeConfigAccount = await program.account.treeConfig.fetch(
+      treeAccounts.treeConfig
+    )
+    assert(treeConfigAccount.creator.equals(wallet.publicKey))
+
+    const txSignature = await program.methods
+      .closeTree()
+      .accounts(treeAccounts)
+      .rpc()
+
+    // The tree and its config are gone, their rent went back to the creator
+    const closeLog = await getTreeCloseLog(connection, txSignature)
+    assert(closeLog.merkleTree.equals(emptyTree.publicKey))
+    assert(closeLog.schemaVersion === treeConfigAccount.schemaVersion)
+    assert((await connection.getAccountInfo(emptyTree.publicKey)) === null)
+    assert((await connection.getAccountInfo(treeAccounts.treeConfig)) === null)
+  })
+
+  it("Decommission Empty Note Tree", async () => {
+    const tree = await createTree()
+    const [treeTombstone] = PublicKey.findProgramAddressSync(
+      [Buffer.from("tombstone"), tree.merkleTree.toBuffer()],
+      program.programId
+    )
     const merkleTreeAccount =
       await ConcurrentMerkleTreeAccount.fromAccountAddress(
         connection,
-        merkleTree.publicKey
+        tree.merkleTree
       )
+    const finalRoot = merkleTreeAccount.getCurrentRoot()
 
-    const rootKey = merkleTreeAccount.tree.changeLogs[0].root
-    const root = Array.from(rootKey.toBuffer())
+    const txSignature = await program.methods
+      .decommissionTree()
+      .accounts({ ...tree, treeTombstone: treeTombstone })
+      .rpc()
+
+    // The tree is gone, its final state stays in the tombstone and the log
+    assert((await connection.getAccountInfo(tree.merkleTree)) === null)
+    const checkpointLog = await getTreeCheckpointLog(connection, txSignature)
+    assert(checkpointLog.merkleTree.equals(tree.merkleTree))
+    assert(Buffer.from(checkpointLog.root).equals(finalRoot))
+    const tombstoneAccount = await program.account.treeTombstone.fetch(
+      treeTombstone
+    )
+    assert(tombstoneAccount.merkleTree.equals(tree.merkleTree))
+    assert(Buffer.from(tombstoneAccount.root).equals(finalRoot))
+    assert(tombstoneAccount.leafCount.toNumber() === 0)
+    assert(tombstoneAccount.sequenceNumber.eq(checkpointLog.sequenceNumber))
+  })
+
+  it("Transfer Tree Authority", async () => {
+    const tree = Keypair.generate()
+    const [authority] = PublicKey.findProgramAddressSync(
+      [tree.publicKey.toBuffer()],
+      program.programId
+    )
+    const treeAccounts = {
+      treeConfig: getTreeConfigAddress(program.programId, tree.publicKey),
+      merkleTree: tree.publicKey,
+      treeAuthority: authority,
+      logWrapper: SPL_NOOP_PROGRAM_ID,
+      compressionProgram: SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
+      globalConfig: globalConfig,
+    }
+
+    const allocTreeIx = await createAllocTreeIx(
+      connection,
+      tree.publicKey,
+      wallet.publicKey,
+      { maxDepth: 3, maxBufferSize: 8 },
+      0
+    )
+    const ix = await program.methods
+      .createNoteTree(3, 8, 0)
+      .accounts({ ...treeAccounts, forest: forest, treasury: treasury })
+      .instruction()
+    const tx = new Transaction().add(allocTreeIx, ix)
+    await sendAndConfirmTransaction(connection, tx, [wallet.payer, tree])
 
+    // Hand the tree to a new wallet, the creator stays on record
+    const newAuthority = Keypair.generate()
     const txSignature = await program.methods
-      .updateNote(0, root, firstNote, updatedNote)
+      .transferTreeAuthority(newAuthority.publicKey)
+      .accounts(treeAccounts)
+      .rpc()
+    const authorityLog = await getTreeAuthorityLog(connection, txSignature)
+    assert(authorityLog.oldAuthority.equals(wallet.publicKey))
+    assert(authorityLog.newAuthority.equals(newAuthority.publicKey))
+    assert(authorityLog.schemaVersion === 2)
+
+    const treeConfigAccount = await program.account.treeConfig.fetch(
+      treeAccounts.treeConfig
+    )
+    assert(treeConfigAccount.creator.equals(wallet.publicKey))
+    assert(treeConfigAccount.authority.equals(newAuthority.publicKey))
+
+    // The creator can no longer close the tree, the new authority can
+    try {
+      await program.methods.closeTree().accounts(treeAccounts).rpc()
+      assert.fail("the previous authority should not close the tree")
+    } catch (error) {
+      assert(error.error.errorCode.code === "Unauthorized")
+    }
+    await program.methods
+      .closeTree()
+      .accounts({ ...treeAccounts, authority: newAuthority.publicKey })
+      .signers([newAuthority])
+      .rpc()
+    assert((await connection.getAccountInfo(tree.publicKey)) === null)
+  })
+
+  it("Freeze And Thaw Tree", async () => {
+    const tree = Keypair.generate()
+    const [authority] = PublicKey.findProgramAddressSync(
+      [tree.publicKey.toBuffer()],
+      program.programId
+    )
+    const treeAccounts = {
+      treeConfig: getTreeConfigAddress(program.programId, tree.publicKey),
+      merkleTree: tree.publicKey,
+      treeAuthority: authority,
+      logWrapper: SPL_NOOP_PROGRAM_ID,
+      compressionProgram: SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
+      globalConfig: globalConfig,
+    }
+
+    const allocTreeIx = await createAllocTreeIx(
+      connection,
+      tree.publicKey,
+      wallet.publicKey,
+      { maxDepth: 3, maxBufferSize: 8 },
+      0
+    )
+    const ix = await program.methods
+      .createNoteTree(3, 8, 0)
+      .accounts({ ...treeAccounts, forest: forest, treasury: treasury })
+      .instruction()
+    const tx = new Transaction().add(allocTreeIx, ix)
+    await sendAndConfirmTransaction(connection, tx, [wallet.payer, tree])
+
+    const freezeSignature = await program.methods
+      .freezeTree()
+      .accounts(treeAccounts)
+      .rpc()
+    const freezeLog = await getTreeFreezeLog(connection, freezeSignature)
+    assert(freezeLog.frozen)
+
+    // Nothing can be appended to a frozen tree
+    try {
+      await program.methods
+        .appendNote("while frozen")
+        .accounts(treeAccounts)
+        .rpc()
+      assert.fail("a frozen tree should refuse appends")
+    } catch (error) {
+      assert(error.error.errorCode.code === "TreeFrozen")
+    }
+
+    await program.methods.thawTree().accounts(treeAccounts).rpc()
+    await program.methods.appendNote("after thaw").accounts(treeAccounts).rpc()
+    const treeConfigAccount = await program.account.treeConfig.fetch(
+      treeAccounts.treeConfig
+    )
+    assert(!treeConfigAccount.frozen)
+    assert(treeConfigAccount.numAppended.toNumber() === 1)
+  })
+
+  it("Multisig Freezes Tree Once Approved", async () => {
+    const tree = await createTree()
+    const cosigner = Keypair.generate()
+    const [multisig] = PublicKey.findProgramAddressSync(
+      [Buffer.from("multisig"), tree.merkleTree.toBuffer()],
+      program.programId
+    )
+    await program.methods
+      .createTreeMultisig([wallet.publicKey, cosigner.publicKey], 2)
+      .accounts({ ...tree, multisig: multisig })
+      .rpc()
+
+    // The wallet no longer holds the authority itself
+    try {
+      await program.methods.freezeTree().accounts(tree).rpc()
+      assert.fail("the tree should only be frozen through the multisig")
+    } catch (error) {
+      assert(error.error.errorCode.code === "Unauthorized")
+    }
+
+    const [proposal] = PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("proposal"),
+        multisig.toBuffer(),
+        new anchor.BN(0).toArrayLike(Buffer, "le", 8),
+      ],
+      program.programId
+    )
+    await program.methods
+      .proposeTreeAction({ freeze: {} })
       .accounts({
-        merkleTree: merkleTree.publicKey,
-        treeAuthority: treeAuthority,
-        logWrapper: SPL_NOOP_PROGRAM_ID,
-        compressionProgram: SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
+        multisig: multisig,
+        proposal: proposal,
+        globalConfig: globalConfig,
       })
       .rpc()
+    const executeAccounts = { ...tree, multisig: multisig, proposal: proposal }
 
-    const noteLog = await getNoteLog(connection, txSignature)
-    const hash = getHash(updatedNote, provider.publicKey)
+    // The proposer's approval alone is below the threshold
+    try {
+      await program.methods.executeTreeAction().accounts(executeAccounts).rpc()
+      assert.fail("a proposal below the threshold should not execute")
+    } catch (error) {
+      assert(error.error.errorCode.code === "NotEnoughApprovals")
+    }
 
-    assert(hash === Buffer.from(noteLog.leafNode).toString("hex"))
-    assert(updatedNote === noteLog.note)
+    await program.methods
+      .approveTreeAction()
+      .accounts({
+        member: cosigner.publicKey,
+        multisig: multisig,
+        proposal: proposal,
+        globalConfig: globalConfig,
+      })
+      .signers([cosigner])
+      .rpc()
+    const freezeSignature = await program.methods
+      .executeTreeAction()
+      .accounts(executeAccounts)
+      .rpc()
+    const freezeLog = await getTreeFreezeLog(connection, freezeSignature)
+    assert(freezeLog.frozen)
+
+    const treeConfigAccount = await program.account.treeConfig.fetch(
+      tree.treeConfig
+    )
+    assert(treeConfigAccount.authority.equals(multisig))
+    assert(treeConfigAccount.frozen)
+    const proposalAccount = await program.account.treeProposal.fetch(proposal)
+    assert(proposalAccount.executed)
+    assert(proposalAccount.approvals.length === 2)
+  })
+
+  // The private tree of the test below, reused by the role test after it
+  let privateTree: Awaited<ReturnType<typeof createTree>>
+
+  it("Private Tree Only Accepts Its Authority", async () => {
+    privateTree = await createTree()
+    await program.methods
+      .setTreeVisibility({ private: {} })
+      .accounts(privateTree)
+      .rpc()
+
+    const stranger = Keypair.generate()
+    try {
+      await program.methods
+        .appendNote("not my tree")
+        .accounts({ ...privateTree, owner: stranger.publicKey })
+        .signers([stranger])
+        .rpc()
+      assert.fail("a private tree should refuse other appenders")
+    } catch (error) {
+      assert(error.error.errorCode.code === "TreeNotWritable")
+    }
+
+    // The authority can still append
+    await program.methods.appendNote("my tree").accounts(privateTree).rpc()
+  })
+
+  it("Role Holders Write To And Administer Private Tree", async () => {
+    const getRoleAddress = (member: PublicKey) =>
+      PublicKey.findProgramAddressSync(
+        [
+          Buffer.from("role"),
+          privateTree.merkleTree.toBuffer(),
+          member.toBuffer(),
+        ],
+        program.programId
+      )[0]
+    const writer = Keypair.generate()
+    const admin = Keypair.generate()
+    const writerRole = getRoleAddress(writer.publicKey)
+    const adminRole = getRoleAddress(admin.publicKey)
+    await program.methods
+      .grantRole(writer.publicKey, { writer: {} })
+      .accounts({ ...privateTree, role: writerRole })
+      .rpc()
+    await program.methods
+      .grantRole(admin.publicKey, { admin: {} })
+      .accounts({ ...privateTree, role: adminRole })
+      .rpc()
+
+    // The writer role lets its holder append to the private tree
+    await program.methods
+      .appendNote("written with a role")
+      .accounts({ ...privateTree, owner: writer.publicKey, role: writerRole })
+      .signers([writer])
+      .rpc()
+    // The role counts on every path appending to the tree, cross posts included
+    const crossPost = (role?: PublicKey) =>
+      program.methods
+        .crossPost("cross posted with a role")
+        .accounts({
+          owner: writer.publicKey,
+          logWrapper: SPL_NOOP_PROGRAM_ID,
+          compressionProgram: SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
+          globalConfig: globalConfig,
+        })
+        .remainingAccounts(
+          getCrossPostTreeAccounts(program.programId, privateTree.merkleTree, {
+            role,
+          })
+        )
+        .signers([writer])
+        .rpc()
+    await crossPost(writerRole)
+
+    // An admin runs the config instructions, but cannot hand the tree away
+    const adminAccounts = {
+      ...privateTree,
+      authority: admin.publicKey,
+      role: adminRole,
+    }
+    await program.methods
+      .freezeTree()
+      .accounts(adminAccounts)
+      .signers([admin])
+      .rpc()
+    await program.methods
+      .thawTree()
+      .accounts(adminAccounts)
+      .signers([admin])
+      .rpc()
+    try {
+      await program.methods
+        .transferTreeAuthority(admin.publicKey)
+        .accounts(adminAccounts)
+        .signers([admin])
+        .rpc()
+      assert.fail("an admin should not transfer the tree")
+    } catch (error) {
+      assert(error.error.errorCode.code === "Unauthorized")
+    }
+
+    // Once the admin revokes the writer role, the private tree refuses its holder
+    await program.methods
+      .revokeRole()
+      .accounts({
+        ...privateTree,
+        authority: admin.publicKey,
+        signerRole: adminRole,
+        role: writerRole,
+      })
+      .signers([admin])
+      .rpc()
+    try {
+      await program.methods
+        .appendNote("role revoked")
+        .accounts({ ...privateTree, owner: writer.publicKey })
+        .signers([writer])
+        .rpc()
+      assert.fail("a revoked writer should not append")
+    } catch (error) {
+      assert(error.error.errorCode.code === "TreeNotWritable")
+    }
+    try {
+      await crossPost()
+      assert.fail("a revoked writer should not cross post")
+    } catch (error) {
+      assert(error.error.errorCode.code === "TreeNotWritable")
+    }
+  })
+
+  it("Authority Transfer Voids Roles", async () => {
+    const tree = await createTree()
+    await program.methods
+      .setTreeVisibility({ private: {} })
+      .accounts(tree)
+      .rpc()
+    const writer = Keypair.generate()
+    const [writerRole] = PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("role"),
+        tree.merkleTree.toBuffer(),
+        writer.publicKey.toBuffer(),
+      ],
+      program.programId
+    )
+    await program.methods
+      .grantRole(writer.publicKey, { writer: {} })
+      .accounts({ ...tree, role: writerRole })
+      .rpc()
+    const append = () =>
+      program.methods
+        .appendNote("written with a role")
+        .accounts({ ...tree, owner: writer.publicKey, role: writerRole })
+        .signers([writer])
+        .rpc()
+    await append()
+
+    // Handing the tree to a multisig voids the role granted by the wallet
+    const [multisig] = PublicKey.findProgramAddressSync(
+      [Buffer.from("multisig"), tree.merkleTree.toBuffer()],
+      program.programId
+    )
+    await program.methods
+      .createTreeMultisig([wallet.publicKey], 1)
+      .accounts({ ...tree, multisig: multisig })
+      .rpc()
+    try {
+      await append()
+      assert.fail("a role granted by the previous authority should not hold")
+    } catch (error) {
+      assert(error.error.errorCode.code === "TreeNotWritable")
+    }
+
+    // The multisig grants the role again through a proposal
+    const [proposal] = PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("proposal"),
+        multisig.toBuffer(),
+        new anchor.BN(0).toArrayLike(Buffer, "le", 8),
+      ],
+      program.programId
+    )
+    await program.methods
+      .proposeTreeAction({
+        grantRole: { member: writer.publicKey, kind: { writer: {} } },
+      })
+      .accounts({
+        multisig: multisig,
+        proposal: proposal,
+        globalConfig: globalConfig,
+      })
+      .rpc()
+    await program.methods
+      .executeTreeAction()
+      .accounts({
+        ...tree,
+        multisig: multisig,
+        proposal: proposal,
+        role: writerRole,
+      })
+      .rpc()
+    await append()
+    const roleAccount = await program.account.role.fetch(writerRole)
+    assert(roleAccount.grantedBy.equals(multisig))
+  })
+
+  it("Rate Limited Tree Refuses Fast Appends", async () => {
+    // One append per sender and epoch
+    await program.methods
+      .setRateLimits(new anchor.BN(0), 1)
+      .accounts(privateTree)
+      .rpc()
+    const [rateState] = PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("rate"),
+        privateTree.merkleTree.toBuffer(),
+        provider.publicKey.toBuffer(),
+      ],
+      program.programId
+    )
+    await program.methods
+      .createRateState()
+      .accounts({ ...privateTree, rateState: rateState })
+      .rpc()
+
+    const rateLimitedAccounts = { ...privateTree, rateState: rateState }
+    await program.methods
+      .appendNote("within the limit")
+      .accounts(rateLimitedAccounts)
+      .rpc()
+    try {
+      await program.methods
+        .appendNote("over the limit")
+        .accounts(rateLimitedAccounts)
+        .rpc()
+      assert.fail("a second append in the epoch should be refused")
+    } catch (error) {
+      assert(error.error.errorCode.code === "RateLimited")
+    }
+
+    // Without its rate state a sender cannot append at all
+    try {
+      await program.methods.appendNote("uncounted").accounts(privateTree).rpc()
+      assert.fail("an append without a rate state should be refused")
+    } catch (error) {
+      assert(error.error.errorCode.code === "RateStateMissing")
+    }
+
+    // Cross posts count against the same limits
+    try {
+      await program.methods
+        .crossPost("cross posted over the limit")
+        .accounts({
+          logWrapper: SPL_NOOP_PROGRAM_ID,
+          compressionProgram: SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
+          globalConfig: globalConfig,
+        })
+        .remainingAccounts(
+          getCrossPostTreeAccounts(program.programId, privateTree.merkleTree, {
+            rateState,
+          })
+        )
+        .rpc()
+      assert.fail("a cross post over the limit should be refused")
+    } catch (error) {
+      assert(error.error.errorCode.code === "RateLimited")
+    }
+
+    await program.methods
+      .setRateLimits(new anchor.BN(0), 0)
+      .accounts(privateTree)
+      .rpc()
+  })
+
+  it("Tree Restricted To Direct Calls Checks The Caller", async () => {
+    await program.methods
+      .setCpiPolicy({ directOnly: {} })
+      .accounts(privateTree)
+      .rpc()
+
+    // The caller can only be checked against the instructions sysvar
+    try {
+      await program.methods.appendNote("unchecked").accounts(privateTree).rpc()
+      assert.fail("a restricted tree should need the instructions sysvar")
+    } catch (error) {
+      assert(error.error.errorCode.code === "InstructionsSysvarMissing")
+    }
+
+    // A user transaction calls the program directly
+    await program.methods
+      .appendNote("called directly")
+      .accounts({ ...privateTree, instructions: SYSVAR_INSTRUCTIONS_PUBKEY })
+      .rpc()
+
+    await program.methods
+      .setCpiPolicy({ any: {} })
+      .accounts(privateTree)
+      .rpc()
+  })
+
+  it("Shared Note Needs Every Co-Owner To Sign", async () => {
+    const coOwner = Keypair.generate()
+    const coOwnerAccount = (isSigner: boolean) => [
+      { pubkey: coOwner.publicKey, isSigner: isSigner, isWritable: false },
+    ]
+
+    try {
+      await program.methods
+        .appendSharedNote("agreed by both", [coOwner.publicKey])
+        .accounts(privateTree)
+        .remainingAccounts(coOwnerAccount(false))
+        .rpc()
+      assert.fail("a shared note should need the co-owner's signature")
+    } catch (error) {
+      assert(error.error.errorCode.code === "MissingCoOwnerSignature")
+    }
+
+    // The signer cannot also be listed as a co-owner
+    try {
+      await program.methods
+        .appendSharedNote("agreed by one", [provider.publicKey])
+        .accounts(privateTree)
+        .remainingAccounts([
+          { pubkey: provider.publicKey, isSigner: true, isWritable: false },
+        ])
+        .rpc()
+      assert.fail("a shared note should need distinct owners")
+    } catch (error) {
+      assert(error.error.errorCode.code === "InvalidCoOwners")
+    }
+
+    await program.methods
+      .appendSharedNote("agreed by both", [coOwner.publicKey])
+      .accounts(privateTree)
+      .remainingAccounts(coOwnerAccount(true))
+      .signers([coOwner])
+      .rpc()
+  })
+
+  it("Session Key Appends For Its Owner", async () => {
+    const sessionKey = Keypair.generate()
+    const [session] = PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("session"),
+        provider.publicKey.toBuffer(),
+        sessionKey.publicKey.toBuffer(),
+      ],
+      program.programId
+    )
+    // The session key may only sign append_note (bit 1) for the next hour
+    const expiresAt = new anchor.BN(Math.floor(Date.now() / 1000) + 3600)
+    await program.methods
+      .createSession(sessionKey.publicKey, expiresAt, new anchor.BN(1).shln(1))
+      .accounts({ session: session, globalConfig: globalConfig })
+      .rpc()
+
+    // The note is owned by the provider, who can write to the private tree
+    const sessionAccounts = {
+      ...privateTree,
+      owner: sessionKey.publicKey,
+      session: session,
+    }
+    const txSignature = await program.methods
+      .appendNote("signed by a session key")
+      .accounts(sessionAccounts)
+      .signers([sessionKey])
+      .rpc()
+    const noteLog = await getNoteLog(connection, txSignature)
+    assert(noteLog.owner.equals(provider.publicKey))
+
+    try {
+      await program.methods
+        .appendDraft("not in scope")
+        .accounts(sessionAccounts)
+        .signers([sessionKey])
+        .rpc()
+      assert.fail("the session should not cover append_draft")
+    } catch (error) {
+      assert(error.error.errorCode.code === "OutsideSessionScope")
+    }
+
+    await program.methods
+      .revokeSession()
+      .accounts({ session: session, globalConfig: globalConfig })
+      .rpc()
+    assert((await connection.getAccountInfo(session)) === null)
+  })
+
+  // A public tree created by the test below, reused by later tests
+  let publicTree: Awaited<ReturnType<typeof createTree>>
+
+  it("Relayer Appends Note With Owner Permit", async () => {
+    publicTree = await createTree()
+    const owner = Keypair.generate()
+    const note = "relayed note"
+    const expiry = new anchor.BN(Math.floor(Date.now() / 1000) + 3600)
+    const message = getPermitMessage(
+      program.programId,
+      publicTree.merkleTree,
+      note,
+      expiry
+    )
+    // The ed25519 program verifies the owner's signature right before the append
+    const verifyIx = Ed25519Program.createInstructionWithPrivateKey({
+      privateKey: owner.secretKey,
+      message: message,
+    })
+    // The instruction holds the public key, then the signature, then the message
+    const signature = Array.from(verifyIx.data.subarray(48, 112))
+    const [permit] = PublicKey.findProgramAddressSync(
+      [Buffer.from("permit"), owner.publicKey.toBuffer(), message],
+      program.programId
+    )
+    const permitAccounts = {
+      ...publicTree,
+      permit: permit,
+      instructions: SYSVAR_INSTRUCTIONS_PUBKEY,
+    }
+
+    const txSignature = await program.methods
+      .appendNoteWithPermit(note, owner.publicKey, signature, expiry)
+      .accounts(permitAccounts)
+      .preInstructions([verifyIx])
+      .rpc()
+    const noteLog = await getNoteLog(connection, txSignature)
+    assert(noteLog.owner.equals(owner.publicKey))
+
+    // The permit is used up
+    try {
+      await program.methods
+        .appendNoteWithPermit(note, owner.publicKey, signature, expiry)
+        .accounts(permitAccounts)
+        .preInstructions([verifyIx])
+        .rpc()
+      assert.fail("a permit should only be used once")
+    } catch (error) {
+      assert(error.logs.some((log) => log.includes("already in use")))
+    }
+
+    // Without the ed25519 instruction the permit is not verified
+    const otherNote = "not permitted"
+    const otherMessage = getPermitMessage(
+      program.programId,
+      publicTree.merkleTree,
+      otherNote,
+      expiry
+    )
+    try {
+      await program.methods
+        .appendNoteWithPermit(otherNote, owner.publicKey, signature, expiry)
+        .accounts({
+          ...permitAccounts,
+          permit: PublicKey.findProgramAddressSync(
+            [Buffer.from("permit"), owner.publicKey.toBuffer(), otherMessage],
+            program.programId
+          )[0],
+        })
+        .rpc()
+      assert.fail("a permit without its ed25519 instruction should be refused")
+    } catch (error) {
+      assert(error.error.errorCode.code === "InvalidPermit")
+    }
+  })
+
+  it("Linked Ethereum Address Is Attributed In Note Leaves", async () => {
+    const wallet = provider.publicKey
+    const [identityLink] = PublicKey.findProgramAddressSync(
+      [Buffer.from("identity_link"), wallet.toBuffer()],
+      program.programId
+    )
+    // The secp256k1 program verifies the address's personal_sign signature
+    const verifyIx = Secp256k1Program.createInstructionWithPrivateKey({
+      privateKey: Keypair.generate().secretKey.subarray(0, 32),
+      message: Buffer.concat([
+        Buffer.from("\x19Ethereum Signed Message:\n32"),
+        getLinkMessage(program.programId, wallet),
+      ]),
+    })
+    // The instruction holds the address, then the signature and recovery id
+    const ethAddress = Array.from(verifyIx.data.subarray(12, 32))
+    const signature = Array.from(verifyIx.data.subarray(32, 97))
+    const linkAccounts = {
+      identityLink: identityLink,
+      instructions: SYSVAR_INSTRUCTIONS_PUBKEY,
+      globalConfig: globalConfig,
+    }
+
+    // Without the secp256k1 instruction the link is not verified
+    try {
+      await program.methods
+        .linkEthIdentity(ethAddress, signature)
+        .accounts(linkAccounts)
+        .rpc()
+      assert.fail("a link without its secp256k1 instruction should be refused")
+    } catch (error) {
+      assert(error.error.errorCode.code === "InvalidEthSignature")
+    }
+
+    await program.methods
+      .linkEthIdentity(ethAddress, signature)
+      .accounts(linkAccounts)
+      .preInstructions([verifyIx])
+      .rpc()
+    const identityLinkAccount = await program.account.identityLink.fetch(
+      identityLink
+    )
+    const linkedAddress = Buffer.from(identityLinkAccount.ethAddress)
+    assert(linkedAddress.equals(Buffer.from(ethAddress)))
+
+    // Passing the link commits the address into the leaf
+    const note = "attributed note"
+    const txSignature = await program.methods
+      .appendNote(note)
+      .accounts({ ...publicTree, identityLink: identityLink })
+      .rpc()
+    const noteLog = await getNoteLog(connection, txSignature)
+    const hash = getAttributedNoteHash(
+      note,
+      wallet,
+      noteLog.status,
+      Buffer.from(ethAddress)
+    )
+    assert(hash === Buffer.from(noteLog.leafNode).toString("hex"))
+
+    await program.methods
+      .unlinkEthIdentity()
+      .accounts({ identityLink: identityLink, globalConfig: globalConfig })
+      .rpc()
+  })
+
+  it("Deceptive Note Text Is Refused", async () => {
+    const cases = [
+      ["bell\u0007", "ControlCharacter"],
+      ["pay\u200Bpal", "InvisibleCharacter"],
+      ["txt.\u202Eexe", "InvisibleCharacter"],
+      // "e" followed by a combining acute accent composes to "\u00E9" in NFC
+      ["cafe\u0301", "TextNotNormalized"],
+    ]
+    for (const [note, code] of cases) {
+      try {
+        await program.methods.appendNote(note).accounts(publicTree).rpc()
+        assert.fail(`${code} text should be refused`)
+      } catch (error) {
+        assert(error.error.errorCode.code === code)
+      }
+    }
+  })
+
+  it("Tree Keeping Recent Leaves Refuses Duplicates", async () => {
+    const [recentLeaves] = PublicKey.findProgramAddressSync(
+      [Buffer.from("recent_leaves"), publicTree.merkleTree.toBuffer()],
+      program.programId
+    )
+    const recentLeavesAccounts = {
+      recentLeaves: recentLeaves,
+      treeConfig: publicTree.treeConfig,
+      merkleTree: publicTree.merkleTree,
+      globalConfig: globalConfig,
+    }
+    await program.methods
+      .createRecentLeaves(new anchor.BN(1000))
+      .accounts(recentLeavesAccounts)
+      .rpc()
+
+    const dedupeAccounts = { ...publicTree, recentLeaves: recentLeaves }
+    await program.methods
+      .appendNote("sent twice")
+      .accounts(dedupeAccounts)
+      .rpc()
+    try {
+      await program.methods
+        .appendNote("sent twice")
+        .accounts(dedupeAccounts)
+        .rpc()
+      assert.fail("a duplicate leaf should be refused")
+    } catch (error) {
+      assert(error.error.errorCode.code === "DuplicateLeaf")
+    }
+    // A cross post of the same note is the same leaf
+    try {
+      await program.methods
+        .crossPost("sent twice")
+        .accounts({
+          logWrapper: SPL_NOOP_PROGRAM_ID,
+          compressionProgram: SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
+          globalConfig: globalConfig,
+        })
+        .remainingAccounts(
+          getCrossPostTreeAccounts(program.programId, publicTree.merkleTree, {
+            recentLeaves,
+          })
+        )
+        .rpc()
+      assert.fail("a cross posted duplicate should be refused")
+    } catch (error) {
+      assert(error.error.errorCode.code === "DuplicateLeaf")
+    }
+
+    // The tree cannot be appended to without its recent leaves
+    try {
+      await program.methods.appendNote("unchecked").accounts(publicTree).rpc()
+      assert.fail("an append without the recent leaves should be refused")
+    } catch (error) {
+      assert(error.error.errorCode.code === "RecentLeavesMissing")
+    }
+
+    await program.methods
+      .closeRecentLeaves()
+      .accounts(recentLeavesAccounts)
+      .rpc()
+  })
+
+  it("Leaf On Legal Hold Cannot Be Updated", async () => {
+    const [complianceRole] = PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("role"),
+        publicTree.merkleTree.toBuffer(),
+        provider.publicKey.toBuffer(),
+      ],
+      program.programId
+    )
+    await program.methods
+      .grantRole(provider.publicKey, { compliance: {} })
+      .accounts({ ...publicTree, role: complianceRole })
+      .rpc()
+
+    const note = "under investigation"
+    await program.methods.appendNote(note).accounts(publicTree).rpc()
+    const leaf = Buffer.from(
+      getHash(note, provider.publicKey, NoteStatus.Published),
+      "hex"
+    )
+    const [hold] = PublicKey.findProgramAddressSync(
+      [Buffer.from("hold"), publicTree.merkleTree.toBuffer(), leaf],
+      program.programId
+    )
+    const holdAccounts = { ...publicTree, role: complianceRole, hold: hold }
+    await program.methods
+      .setLegalHold(Array.from(leaf), true)
+      .accounts(holdAccounts)
+      .rpc()
+
+    const merkleTreeAccount =
+      await ConcurrentMerkleTreeAccount.fromAccountAddress(
+        connection,
+        publicTree.merkleTree
+      )
+    const root = Array.from(merkleTreeAccount.getCurrentRoot())
+    const index = merkleTreeAccount.tree.rightMostPath.index - 1
+    const update = (accounts) =>
+      program.methods
+        .updateNote(index, root, note, "tampered", { published: {} })
+        .accounts(accounts)
+        .rpc()
+    try {
+      await update({ ...publicTree, hold: hold })
+      assert.fail("a held leaf should not be updated")
+    } catch (error) {
+      assert(error.error.errorCode.code === "LeafOnHold")
+    }
+    // While the tree has leaves on hold, every replace shows its leaf is not one
+    try {
+      await update(publicTree)
+      assert.fail("a replace without the hold pda should be refused")
+    } catch (error) {
+      assert(error.error.errorCode.code === "HoldMissing")
+    }
+    // Nor can the held note be handed over to a new owner
+    const newOwner = Keypair.generate()
+    const indexBuffer = Buffer.alloc(4)
+    indexBuffer.writeUInt32LE(index)
+    const [transferOffer] = PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("transfer_offer"),
+        publicTree.merkleTree.toBuffer(),
+        indexBuffer,
+        provider.publicKey.toBuffer(),
+      ],
+      program.programId
+    )
+    await program.methods
+      .offerNoteTransfer(index, newOwner.publicKey, provider.publicKey)
+      .accounts({
+        merkleTree: publicTree.merkleTree,
+        transferOffer: transferOffer,
+        globalConfig: globalConfig,
+      })
+      .rpc()
+    try {
+      await program.methods
+        .acceptNoteTransfer(root, note, { published: {} })
+        .accounts({
+          ...publicTree,
+          newOwner: newOwner.publicKey,
+          offeredBy: provider.publicKey,
+          transferOffer: transferOffer,
+          hold: hold,
+        })
+        .signers([newOwner])
+        .rpc()
+      assert.fail("a held note should not change owners")
+    } catch (error) {
+      assert(error.error.errorCode.code === "LeafOnHold")
+    }
+    await program.methods
+      .cancelNoteTransfer()
+      .accounts({ transferOffer: transferOffer, globalConfig: globalConfig })
+      .rpc()
+
+    await program.methods
+      .setLegalHold(Array.from(leaf), false)
+      .accounts(holdAccounts)
+      .rpc()
+    assert((await connection.getAccountInfo(hold)) === null)
+    const treeConfigAccount = await program.account.treeConfig.fetch(
+      publicTree.treeConfig
+    )
+    assert(treeConfigAccount.heldLeaves === 0)
+  })
+
+  it("Moderator Takes Down Note With Reason", async () => {
+    // The provider holds the compliance role on the tree, which includes moderator
+    const [moderatorRole] = PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("role"),
+        publicTree.merkleTree.toBuffer(),
+        provider.publicKey.toBuffer(),
+      ],
+      program.programId
+    )
+    const owner = Keypair.generate()
+    const note = "against the rules"
+    await program.methods
+      .appendNote(note)
+      .accounts({ ...publicTree, owner: owner.publicKey })
+      .signers([owner])
+      .rpc()
+
+    // The tree keeps the proof of its rightmost leaf
+    const merkleTreeAccount =
+      await ConcurrentMerkleTreeAccount.fromAccountAddress(
+        connection,
+        publicTree.merkleTree
+      )
+    const root = Array.from(merkleTreeAccount.getCurrentRoot())
+    const rightMostPath = merkleTreeAccount.tree.rightMostPath
+    const proof = rightMostPath.proof.map((node) => ({
+      pubkey: new PublicKey(node),
+      isSigner: false,
+      isWritable: false,
+    }))
+    const txSignature = await program.methods
+      .takedownNote(
+        rightMostPath.index - 1,
+        root,
+        note,
+        owner.publicKey,
+        { published: {} },
+        7
+      )
+      .accounts({ ...publicTree, role: moderatorRole })
+      .remainingAccounts(proof)
+      .rpc()
+
+    const takedownLog = await getTakedownLog(connection, txSignature)
+    const original = getHash(note, owner.publicKey, NoteStatus.Published)
+    assert(original === Buffer.from(takedownLog.originalLeaf).toString("hex"))
+    // The takedown leaf is not the leaf of the owner redacting the note
+    const takedown = getTakedownHash(
+      Buffer.from(original, "hex"),
+      owner.publicKey
+    )
+    assert(takedown === Buffer.from(takedownLog.leafNode).toString("hex"))
+    const redacted = getHash("", owner.publicKey, NoteStatus.Redacted)
+    assert(redacted !== takedown)
+    assert(takedownLog.moderator.equals(provider.publicKey))
+    assert(takedownLog.reasonCode === 7)
+  })
+
+  it("Reports Count Once Per Reporter", async () => {
+    // Reports are not checked against the tree, so any leaf hash will do
+    const spam = getHash("spam", provider.publicKey, NoteStatus.Published)
+    const leafHash = Array.from(Buffer.from(spam, "hex"))
+    const [report] = PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("report"),
+        publicTree.merkleTree.toBuffer(),
+        Buffer.from(leafHash),
+      ],
+      program.programId
+    )
+    const getReportReceipt = (reporter: PublicKey) =>
+      PublicKey.findProgramAddressSync(
+        [Buffer.from("report_receipt"), report.toBuffer(), reporter.toBuffer()],
+        program.programId
+      )[0]
+
+    await program.methods
+      .reportLeaf(publicTree.merkleTree, leafHash, 1)
+      .accounts({
+        report: report,
+        reportReceipt: getReportReceipt(provider.publicKey),
+        logWrapper: SPL_NOOP_PROGRAM_ID,
+        globalConfig: globalConfig,
+      })
+      .rpc()
+
+    // A second reporter raises the count and sets the latest reason
+    const reporter = Keypair.generate()
+    const airdropSignature = await connection.requestAirdrop(
+      reporter.publicKey,
+      anchor.web3.LAMPORTS_PER_SOL
+    )
+    await connection.confirmTransaction(airdropSignature)
+    await program.methods
+      .reportLeaf(publicTree.merkleTree, leafHash, 2)
+      .accounts({
+        reporter: reporter.publicKey,
+        report: report,
+        reportReceipt: getReportReceipt(reporter.publicKey),
+        logWrapper: SPL_NOOP_PROGRAM_ID,
+        globalConfig: globalConfig,
+      })
+      .signers([reporter])
+      .rpc()
+    let reportAccount = await program.account.report.fetch(report)
+    assert(reportAccount.merkleTree.equals(publicTree.merkleTree))
+    assert(reportAccount.count === 2)
+    assert(reportAccount.lastReason === 2)
+
+    // Reporting the same leaf again does not count twice
+    try {
+      await program.methods
+        .reportLeaf(publicTree.merkleTree, leafHash, 1)
+        .accounts({
+          report: report,
+          reportReceipt: getReportReceipt(provider.publicKey),
+          logWrapper: SPL_NOOP_PROGRAM_ID,
+          globalConfig: globalConfig,
+        })
+        .rpc()
+      assert.fail("a reporter should only report a leaf once")
+    } catch (error) {
+      assert(error.logs.some((log) => log.includes("already in use")))
+    }
+    reportAccount = await program.account.report.fetch(report)
+    assert(reportAccount.count === 2)
+  })
+
+  it("Protocol Admin Freezes Abusive Tree", async () => {
+    const emergencyAccounts = {
+      treeConfig: publicTree.treeConfig,
+      merkleTree: publicTree.merkleTree,
+      logWrapper: SPL_NOOP_PROGRAM_ID,
+      globalConfig: globalConfig,
+    }
+
+    // The freeze must say why
+    try {
+      await program.methods
+        .emergencyFreezeTree(0)
+        .accounts(emergencyAccounts)
+        .rpc()
+      assert.fail("an emergency freeze should need a reason code")
+    } catch (error) {
+      assert(error.error.errorCode.code === "MissingReasonCode")
+    }
+
+    const freezeSignature = await program.methods
+      .emergencyFreezeTree(5)
+      .accounts(emergencyAccounts)
+      .rpc()
+    const freezeLog = await getEmergencyFreezeLog(connection, freezeSignature)
+    assert(freezeLog.merkleTree.equals(publicTree.merkleTree))
+    assert(freezeLog.admin.equals(provider.publicKey))
+    assert(freezeLog.frozen)
+    assert(freezeLog.reasonCode === 5)
+
+    // Thawing the tree as its authority does not lift the emergency freeze
+    await program.methods.thawTree().accounts(publicTree).rpc()
+    try {
+      await program.methods
+        .appendNote("while frozen")
+        .accounts(publicTree)
+        .rpc()
+      assert.fail("an emergency frozen tree should refuse appends")
+    } catch (error) {
+      assert(error.error.errorCode.code === "TreeEmergencyFrozen")
+    }
+
+    await program.methods
+      .emergencyThawTree(6)
+      .accounts(emergencyAccounts)
+      .rpc()
+    const treeConfigAccount = await program.account.treeConfig.fetch(
+      publicTree.treeConfig
+    )
+    assert(!treeConfigAccount.emergencyFrozen)
+  })
+
+  it("Appends Pay The Tree Fee Into Its Treasury", async () => {
+    const [treeTreasury] = PublicKey.findProgramAddressSync(
+      [Buffer.from("tree_treasury"), publicTree.merkleTree.toBuffer()],
+      program.programId
+    )
+    const feeAccounts = {
+      ...publicTree,
+      treeTreasury: treeTreasury,
+      systemProgram: anchor.web3.SystemProgram.programId,
+    }
+    const fee = 1000
+    await program.methods
+      .setAppendFee(new anchor.BN(fee))
+      .accounts(feeAccounts)
+      .rpc()
+
+    // The treasury was topped up to its rent exempt minimum
+    const minimum = await connection.getMinimumBalanceForRentExemption(0)
+    const balanceBefore = await connection.getBalance(treeTreasury)
+    assert(balanceBefore >= minimum)
+
+    // Appends must pay the fee into the treasury
+    try {
+      await program.methods.appendNote("unpaid").accounts(publicTree).rpc()
+      assert.fail("an append should need the tree treasury to pay the fee")
+    } catch (error) {
+      assert(error.error.errorCode.code === "TreeTreasuryMissing")
+    }
+    await program.methods.appendNote("paid").accounts(feeAccounts).rpc()
+    assert((await connection.getBalance(treeTreasury)) === balanceBefore + fee)
+
+    // Cross posts pay the fee of every tree they append to
+    const crossPost = (treasury?: PublicKey) =>
+      program.methods
+        .crossPost("cross posted and paid")
+        .accounts({
+          logWrapper: SPL_NOOP_PROGRAM_ID,
+          compressionProgram: SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+          globalConfig: globalConfig,
+        })
+        .remainingAccounts(
+          getCrossPostTreeAccounts(program.programId, publicTree.merkleTree, {
+            treeTreasury: treasury,
+          })
+        )
+        .rpc()
+    try {
+      await crossPost()
+      assert.fail("a cross post should need the tree treasury to pay the fee")
+    } catch (error) {
+      assert(error.error.errorCode.code === "TreeTreasuryMissing")
+    }
+    await crossPost(treeTreasury)
+    assert(
+      (await connection.getBalance(treeTreasury)) === balanceBefore + 2 * fee
+    )
+
+    // The authority withdraws the fees, but not the rent exempt minimum
+    await program.methods
+      .withdrawTreeTreasury(new anchor.BN(2 * fee))
+      .accounts(feeAccounts)
+      .rpc()
+    assert((await connection.getBalance(treeTreasury)) === balanceBefore)
+    try {
+      await program.methods
+        .withdrawTreeTreasury(new anchor.BN(balanceBefore - minimum + 1))
+        .accounts(feeAccounts)
+        .rpc()
+      assert.fail("the treasury should keep its rent exempt minimum")
+    } catch (error) {
+      assert(error.error.errorCode.code === "InsufficientTreasury")
+    }
+
+    await program.methods
+      .setAppendFee(new anchor.BN(0))
+      .accounts(feeAccounts)
+      .rpc()
+  })
+
+  it("Fee Schedule Prices Appends By Their Bytes", async () => {
+    const tree = await createTree()
+    const [treeTreasury] = PublicKey.findProgramAddressSync(
+      [Buffer.from("tree_treasury"), tree.merkleTree.toBuffer()],
+      program.programId
+    )
+    const feeAccounts = {
+      ...tree,
+      treeTreasury: treeTreasury,
+      systemProgram: anchor.web3.SystemProgram.programId,
+    }
+    const [baseFee, perByteFee] = [1000, 10]
+    await program.methods
+      .setFeeSchedule(
+        new anchor.BN(baseFee),
+        new anchor.BN(perByteFee),
+        new anchor.BN(500),
+        new anchor.BN(700)
+      )
+      .accounts(feeAccounts)
+      .rpc()
+    const treeConfigAccount = await program.account.treeConfig.fetch(
+      tree.treeConfig
+    )
+    assert(treeConfigAccount.fees.updateFee.toNumber() === 500)
+    assert(treeConfigAccount.fees.deleteFee.toNumber() === 700)
+
+    // An append pays the base fee and the fee for each byte of the note, as quoted
+    const note = "priced"
+    const quote = await program.methods
+      .quoteAppend(note.length, {
+        kind: { note: {} },
+        newConversation: false,
+        priority: 0,
+      })
+      .accounts({
+        merkleTree: tree.merkleTree,
+        treeConfig: tree.treeConfig,
+        globalConfig: globalConfig,
+      })
+      .view()
+    assert(quote.protocolFee.toNumber() === baseFee + perByteFee * note.length)
+    assert(quote.tokenFeeMint === null)
+    const balanceBefore = await connection.getBalance(treeTreasury)
+    await program.methods.appendNote(note).accounts(feeAccounts).rpc()
+    assert(
+      (await connection.getBalance(treeTreasury)) ===
+        balanceBefore + quote.protocolFee.toNumber()
+    )
+
+    // Redacting a note pays the delete fee, like deleting it
+    const merkleTreeAccount =
+      await ConcurrentMerkleTreeAccount.fromAccountAddress(
+        connection,
+        tree.merkleTree
+      )
+    await program.methods
+      .setNoteStatus(
+        0,
+        Array.from(merkleTreeAccount.getCurrentRoot()),
+        note,
+        { published: {} },
+        { redacted: {} }
+      )
+      .accounts(feeAccounts)
+      .rpc()
+    assert(
+      (await connection.getBalance(treeTreasury)) ===
+        balanceBefore + quote.protocolFee.toNumber() + 700
+    )
+  })
+
+  it("Sponsor Pays The Fee Of An Author's Note", async () => {
+    const tree = await createTree()
+    const [treeTreasury] = PublicKey.findProgramAddressSync(
+      [Buffer.from("tree_treasury"), tree.merkleTree.toBuffer()],
+      program.programId
+    )
+    const feeAccounts = {
+      ...tree,
+      treeTreasury: treeTreasury,
+      systemProgram: anchor.web3.SystemProgram.programId,
+    }
+    const fee = 1000
+    await program.methods
+      .setAppendFee(new anchor.BN(fee))
+      .accounts(feeAccounts)
+      .rpc()
+
+    // The author holds no lamports, the app backend pays the fee
+    const author = Keypair.generate()
+    const balanceBefore = await connection.getBalance(treeTreasury)
+    await program.methods
+      .appendNote("sponsored")
+      .accounts({
+        ...feeAccounts,
+        owner: author.publicKey,
+        payer: provider.publicKey,
+      })
+      .signers([author])
+      .rpc()
+    assert((await connection.getBalance(treeTreasury)) === balanceBefore + fee)
+    assert((await connection.getBalance(author.publicKey)) === 0)
+  })
+
+  it("Appends Pay The Token Fee Into The Fee Vault", async () => {
+    const mint = await createTokenMint(connection, wallet.payer)
+    const payerTokenAccount = await createTokenAccount(
+      connection,
+      wallet.payer,
+      mint,
+      provider.publicKey
+    )
+    await mintTokens(connection, wallet.payer, mint, payerTokenAccount, 10)
+    const [feeVault] = PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("fee_vault"),
+        publicTree.merkleTree.toBuffer(),
+        mint.toBuffer(),
+      ],
+      program.programId
+    )
+    const tokenFeeAccounts = {
+      ...publicTree,
+      feeMint: mint,
+      feeVault: feeVault,
+      payerTokenAccount: payerTokenAccount,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      systemProgram: anchor.web3.SystemProgram.programId,
+    }
+    await program.methods
+      .setTokenAppendFee(new anchor.BN(3))
+      .accounts(tokenFeeAccounts)
+      .rpc()
+
+    // Appends must pay the fee into the fee vault
+    try {
+      await program.methods.appendNote("unpaid").accounts(publicTree).rpc()
+      assert.fail("an append should need the fee vault to pay the token fee")
+    } catch (error) {
+      assert(error.error.errorCode.code === "FeeVaultMissing")
+    }
+    await program.methods
+      .appendNote("paid in tokens")
+      .accounts(tokenFeeAccounts)
+      .rpc()
+    // Cross posts pay the token fee of every tree they append to
+    await program.methods
+      .crossPost("cross posted in tokens")
+      .accounts({
+        logWrapper: SPL_NOOP_PROGRAM_ID,
+        compressionProgram: SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        globalConfig: globalConfig,
+      })
+      .remainingAccounts(
+        getCrossPostTreeAccounts(program.programId, publicTree.merkleTree, {
+          payerTokenAccount,
+          feeVault,
+        })
+      )
+      .rpc()
+    const vaultBalance = await connection.getTokenAccountBalance(feeVault)
+    assert(vaultBalance.value.amount === "6")
+
+    // The authority withdraws the fees back to its token account
+    await program.methods
+      .withdrawTokenFees(new anchor.BN(6))
+      .accounts({ ...tokenFeeAccounts, destination: payerTokenAccount })
+      .rpc()
+    const payerBalance = await connection.getTokenAccountBalance(
+      payerTokenAccount
+    )
+    assert(payerBalance.value.amount === "10")
+
+    await program.methods
+      .setTokenAppendFee(new anchor.BN(0))
+      .accounts(tokenFeeAccounts)
+      .rpc()
+  })
+
+  it("Reader Tips Note Owner", async () => {
+    const owner = Keypair.generate()
+    const note = "worth a tip"
+    await program.methods
+      .appendNote(note)
+      .accounts({ ...publicTree, owner: owner.publicKey })
+      .signers([owner])
+      .rpc()
+
+    // The tree keeps the proof of its rightmost leaf
+    const merkleTreeAccount =
+      await ConcurrentMerkleTreeAccount.fromAccountAddress(
+        connection,
+        publicTree.merkleTree
+      )
+    const root = Array.from(merkleTreeAccount.getCurrentRoot())
+    const rightMostPath = merkleTreeAccount.tree.rightMostPath
+    const proof = rightMostPath.proof.map((node) => ({
+      pubkey: new PublicKey(node),
+      isSigner: false,
+      isWritable: false,
+    }))
+    const tip = anchor.web3.LAMPORTS_PER_SOL / 100
+    const txSignature = await program.methods
+      .tipNote(
+        rightMostPath.index - 1,
+        root,
+        note,
+        { published: {} },
+        new anchor.BN(tip)
+      )
+      .accounts({
+        ...publicTree,
+        owner: owner.publicKey,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      })
+      .remainingAccounts(proof)
+      .rpc()
+
+    // The owner committed into the leaf was paid
+    assert((await connection.getBalance(owner.publicKey)) === tip)
+    const tipLog = await getTipLog(connection, txSignature)
+    const leafNode = getHash(note, owner.publicKey, NoteStatus.Published)
+    assert(leafNode === Buffer.from(tipLog.leafNode).toString("hex"))
+    assert(tipLog.tipper.equals(provider.publicKey))
+    assert(tipLog.owner.equals(owner.publicKey))
+    assert(tipLog.amount.toNumber() === tip)
+    assert(tipLog.mint === null)
+  })
+
+  // A tree created by another wallet, since the provider's forest is nearly full
+  let gatedTree: Awaited<ReturnType<typeof createTree>>
+  const gatedTreeCreator = Keypair.generate()
+
+  it("Token-Gated Tree Only Accepts Holders", async () => {
+    const airdropSignature = await connection.requestAirdrop(
+      gatedTreeCreator.publicKey,
+      anchor.web3.LAMPORTS_PER_SOL
+    )
+    await connection.confirmTransaction(airdropSignature)
+    gatedTree = await createTree(gatedTreeCreator)
+    const creatorAccounts = {
+      ...gatedTree,
+      authority: gatedTreeCreator.publicKey,
+    }
+
+    const mint = await createTokenMint(connection, wallet.payer)
+    await program.methods
+      .setTokenGate({ mint: mint, minBalance: new anchor.BN(5) })
+      .accounts(creatorAccounts)
+      .signers([gatedTreeCreator])
+      .rpc()
+
+    // Posters must pass a token account holding the gate
+    try {
+      await program.methods.appendNote("no account").accounts(gatedTree).rpc()
+      assert.fail("a gated tree should need the poster's token account")
+    } catch (error) {
+      assert(error.error.errorCode.code === "GateTokenAccountMissing")
+    }
+    const gateTokenAccount = await createTokenAccount(
+      connection,
+      wallet.payer,
+      mint,
+      provider.publicKey
+    )
+    await mintTokens(connection, wallet.payer, mint, gateTokenAccount, 4)
+    const gateAccounts = { ...gatedTree, gateTokenAccount: gateTokenAccount }
+    try {
+      await program.methods.appendNote("too few").accounts(gateAccounts).rpc()
+      assert.fail("a gated tree should refuse posters below its balance")
+    } catch (error) {
+      assert(error.error.errorCode.code === "BelowGateBalance")
+    }
+
+    await mintTokens(connection, wallet.payer, mint, gateTokenAccount, 1)
+    await program.methods.appendNote("holder").accounts(gateAccounts).rpc()
+
+    // Cross posts are held to the gate of every tree they append to
+    const crossPost = (account?: PublicKey) =>
+      program.methods
+        .crossPost("cross posted by a holder")
+        .accounts({
+          logWrapper: SPL_NOOP_PROGRAM_ID,
+          compressionProgram: SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
+          globalConfig: globalConfig,
+        })
+        .remainingAccounts(
+          getCrossPostTreeAccounts(program.programId, gatedTree.merkleTree, {
+            gateTokenAccount: account,
+          })
+        )
+        .rpc()
+    try {
+      await crossPost()
+      assert.fail("a gated tree should need the cross poster's token account")
+    } catch (error) {
+      assert(error.error.errorCode.code === "GateTokenAccountMissing")
+    }
+    await crossPost(gateTokenAccount)
+
+    await program.methods
+      .setTokenGate(null)
+      .accounts(creatorAccounts)
+      .signers([gatedTreeCreator])
+      .rpc()
+  })
+
+  it("Nft-Gated Tree Asks For The Nft Metadata", async () => {
+    const creatorAccounts = {
+      ...gatedTree,
+      authority: gatedTreeCreator.publicKey,
+    }
+    const mint = await createTokenMint(connection, wallet.payer)
+    await program.methods
+      .setTokenGate({ mint: mint, minBalance: new anchor.BN(1) })
+      .accounts(creatorAccounts)
+      .signers([gatedTreeCreator])
+      .rpc()
+
+    // Gating on a collection lifts the token gate
+    const collection = Keypair.generate().publicKey
+    await program.methods
+      .setNftGate(collection)
+      .accounts(creatorAccounts)
+      .signers([gatedTreeCreator])
+      .rpc()
+    const treeConfig = await program.account.treeConfig.fetch(
+      gatedTree.treeConfig
+    )
+    assert(treeConfig.gate === null)
+    assert(treeConfig.nftGate.equals(collection))
+
+    // Posters must pass their nft's token account and metadata
+    const gateTokenAccount = await createTokenAccount(
+      connection,
+      wallet.payer,
+      mint,
+      provider.publicKey
+    )
+    try {
+      await program.methods
+        .appendNote("no metadata")
+        .accounts({ ...gatedTree, gateTokenAccount: gateTokenAccount })
+        .rpc()
+      assert.fail("an nft-gated tree should need the nft metadata")
+    } catch (error) {
+      assert(error.error.errorCode.code === "GateMetadataMissing")
+    }
+
+    await program.methods
+      .setNftGate(null)
+      .accounts(creatorAccounts)
+      .signers([gatedTreeCreator])
+      .rpc()
+  })
+
+  it("Staked Posters Append And Moderators Slash Them", async () => {
+    const creatorAccounts = {
+      ...gatedTree,
+      authority: gatedTreeCreator.publicKey,
+    }
+    const minStake = anchor.web3.LAMPORTS_PER_SOL / 100
+    await program.methods
+      .setMinStake(new anchor.BN(minStake))
+      .accounts(creatorAccounts)
+      .signers([gatedTreeCreator])
+      .rpc()
+
+    // Posters must pass a stake holding the minimum
+    try {
+      await program.methods.appendNote("unstaked").accounts(gatedTree).rpc()
+      assert.fail("a protected tree should need the poster's stake")
+    } catch (error) {
+      assert(error.error.errorCode.code === "StakeMissing")
+    }
+    const [stake] = PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("stake"),
+        gatedTree.merkleTree.toBuffer(),
+        provider.publicKey.toBuffer(),
+      ],
+      program.programId
+    )
+    const stakeAccounts = { ...gatedTree, stake: stake }
+    await program.methods
+      .stakeForPosting(new anchor.BN(minStake / 2))
+      .accounts(stakeAccounts)
+      .rpc()
+    try {
+      await program.methods.appendNote("half").accounts(stakeAccounts).rpc()
+      assert.fail("a protected tree should refuse posters below its stake")
+    } catch (error) {
+      assert(error.error.errorCode.code === "BelowMinStake")
+    }
+    await program.methods
+      .stakeForPosting(new anchor.BN(minStake / 2))
+      .accounts(stakeAccounts)
+      .rpc()
+    const note = "staked spam"
+    await program.methods.appendNote(note).accounts(stakeAccounts).rpc()
+
+    // The stake stays locked after an append
+    try {
+      await program.methods
+        .withdrawStake(new anchor.BN(1))
+        .accounts(stakeAccounts)
+        .rpc()
+      assert.fail("a stake should stay locked after an append")
+    } catch (error) {
+      assert(error.error.errorCode.code === "StakeLocked")
+    }
+
+    // A moderator takes the note down, then slashes the stake into the treasury
+    const [moderatorRole] = PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("role"),
+        gatedTree.merkleTree.toBuffer(),
+        provider.publicKey.toBuffer(),
+      ],
+      program.programId
+    )
+    await program.methods
+      .grantRole(provider.publicKey, { moderator: {} })
+      .accounts({ ...creatorAccounts, role: moderatorRole })
+      .signers([gatedTreeCreator])
+      .rpc()
+    let merkleTreeAccount =
+      await ConcurrentMerkleTreeAccount.fromAccountAddress(
+        connection,
+        gatedTree.merkleTree
+      )
+    const rightMostPath = merkleTreeAccount.tree.rightMostPath
+    const index = rightMostPath.index - 1
+    const proof = rightMostPath.proof.map((node) => ({
+      pubkey: new PublicKey(node),
+      isSigner: false,
+      isWritable: false,
+    }))
+    await program.methods
+      .takedownNote(
+        index,
+        Array.from(merkleTreeAccount.getCurrentRoot()),
+        note,
+        provider.publicKey,
+        { published: {} },
+        7
+      )
+      .accounts({ ...gatedTree, role: moderatorRole })
+      .remainingAccounts(proof)
+      .rpc()
+
+    // Replacing a leaf leaves the proof nodes of its siblings as they were
+    merkleTreeAccount = await ConcurrentMerkleTreeAccount.fromAccountAddress(
+      connection,
+      gatedTree.merkleTree
+    )
+    const [treeTreasury] = PublicKey.findProgramAddressSync(
+      [Buffer.from("tree_treasury"), gatedTree.merkleTree.toBuffer()],
+      program.programId
+    )
+    const treasuryBefore = await connection.getBalance(treeTreasury)
+    const original = Buffer.from(
+      getHash(note, provider.publicKey, NoteStatus.Published),
+      "hex"
+    )
+    const [slashReceipt] = PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("slash_receipt"),
+        gatedTree.merkleTree.toBuffer(),
+        Buffer.from(getTakedownHash(original, provider.publicKey), "hex"),
+      ],
+      program.programId
+    )
+    const slash = () =>
+      program.methods
+        .slashStake(
+          index,
+          Array.from(merkleTreeAccount.getCurrentRoot()),
+          Array.from(original),
+          new anchor.BN(minStake)
+        )
+        .accounts({
+          ...gatedTree,
+          role: moderatorRole,
+          stake: stake,
+          treeTreasury: treeTreasury,
+          slashReceipt: slashReceipt,
+        })
+        .remainingAccounts(proof)
+        .rpc()
+    await slash()
+    assert(
+      (await connection.getBalance(treeTreasury)) === treasuryBefore + minStake
+    )
+
+    // A takedown slashes the stake once, its receipt already exists
+    try {
+      await slash()
+      assert.fail("slashed the same takedown twice")
+    } catch (error) {
+      assert(error.logs.some((log) => log.includes("already in use")))
+    }
+    const stakeAccount = await program.account.stake.fetch(stake)
+    assert(stakeAccount.amount.toNumber() === 0)
+
+    await program.methods
+      .setMinStake(new anchor.BN(0))
+      .accounts(creatorAccounts)
+      .signers([gatedTreeCreator])
+      .rpc()
+  })
+
+  it("Subscribers Append To Premium Tree", async () => {
+    const [treeTreasury] = PublicKey.findProgramAddressSync(
+      [Buffer.from("tree_treasury"), gatedTree.merkleTree.toBuffer()],
+      program.programId
+    )
+    const pricePerDay = 1_000_000
+    await program.methods
+      .setSubscriptionPrice(new anchor.BN(pricePerDay))
+      .accounts({
+        ...gatedTree,
+        authority: gatedTreeCreator.publicKey,
+        treeTreasury: treeTreasury,
+      })
+      .signers([gatedTreeCreator])
+      .rpc()
+
+    // Posters other than the tree authority must pass an active subscription
+    try {
+      await program.methods.appendNote("no access").accounts(gatedTree).rpc()
+      assert.fail("a premium tree should need the poster's subscription")
+    } catch (error) {
+      assert(error.error.errorCode.code === "SubscriptionMissing")
+    }
+    const [subscription] = PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("tree_subscription"),
+        gatedTree.merkleTree.toBuffer(),
+        provider.publicKey.toBuffer(),
+      ],
+      program.programId
+    )
+    const treasuryBefore = await connection.getBalance(treeTreasury)
+    await program.methods
+      .subscribeToTree(gatedTree.merkleTree, 2)
+      .accounts({
+        subscription: subscription,
+        treeConfig: gatedTree.treeConfig,
+        treeTreasury: treeTreasury,
+        globalConfig: globalConfig,
+      })
+      .rpc()
+    assert(
+      (await connection.getBalance(treeTreasury)) ===
+        treasuryBefore + 2 * pricePerDay
+    )
+    const subscriptionAccount = await program.account.treeSubscription.fetch(
+      subscription
+    )
+    assert(subscriptionAccount.expiresAt.toNumber() > Date.now() / 1000 + 86400)
+
+    await program.methods
+      .appendNote("premium")
+      .accounts({ ...gatedTree, subscription: subscription })
+      .rpc()
+    // Cross posts into the tree need the subscription too
+    const crossPost = (optional: { subscription?: PublicKey }) =>
+      program.methods
+        .crossPost("premium cross post")
+        .accounts({
+          logWrapper: SPL_NOOP_PROGRAM_ID,
+          compressionProgram: SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
+          globalConfig: globalConfig,
+        })
+        .remainingAccounts(
+          getCrossPostTreeAccounts(
+            program.programId,
+            gatedTree.merkleTree,
+            optional
+          )
+        )
+        .rpc()
+    try {
+      await crossPost({})
+      assert.fail("a premium tree should need the cross poster's subscription")
+    } catch (error) {
+      assert(error.error.errorCode.code === "SubscriptionMissing")
+    }
+    await crossPost({ subscription: subscription })
+    // Apps gating reads check the subscription the same way
+    await program.methods
+      .checkSubscription(gatedTree.merkleTree, provider.publicKey)
+      .accounts({ subscription: subscription, globalConfig: globalConfig })
+      .rpc()
+
+    await program.methods
+      .setSubscriptionPrice(new anchor.BN(0))
+      .accounts({
+        ...gatedTree,
+        authority: gatedTreeCreator.publicKey,
+        treeTreasury: treeTreasury,
+      })
+      .signers([gatedTreeCreator])
+      .rpc()
+  })
+
+  it("Writer Appends To Private Tree", async () => {
+    const privateTree = await createTree()
+    await program.methods
+      .setTreeVisibility({ private: {} })
+      .accounts(privateTree)
+      .rpc()
+
+    const writer = Keypair.generate()
+    const sizeBefore = (
+      await connection.getAccountInfo(privateTree.treeConfig)
+    ).data.length
+    await program.methods
+      .addWriter(writer.publicKey)
+      .accounts(privateTree)
+      .rpc()
+
+    // The config grew by one pubkey and the writer can append
+    const sizeAfter = (
+      await connection.getAccountInfo(privateTree.treeConfig)
+    ).data.length
+    assert(sizeAfter === sizeBefore + 32)
+    await program.methods
+      .appendNote("team note")
+      .accounts({ ...privateTree, owner: writer.publicKey })
+      .signers([writer])
+      .rpc()
+
+    // Once removed, the writer is refused again
+    await program.methods
+      .removeWriter(writer.publicKey)
+      .accounts(privateTree)
+      .rpc()
+    try {
+      await program.methods
+        .appendNote("revoked note")
+        .accounts({ ...privateTree, owner: writer.publicKey })
+        .signers([writer])
+        .rpc()
+      assert.fail("a removed writer should be refused")
+    } catch (error) {
+      assert(error.error.errorCode.code === "TreeNotWritable")
+    }
+    // Every other path appending to the tree checks the writers too
+    try {
+      await program.methods
+        .crossPost("revoked cross post")
+        .accounts({
+          owner: writer.publicKey,
+          logWrapper: SPL_NOOP_PROGRAM_ID,
+          compressionProgram: SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
+          globalConfig: globalConfig,
+        })
+        .remainingAccounts(
+          getCrossPostTreeAccounts(program.programId, privateTree.merkleTree)
+        )
+        .signers([writer])
+        .rpc()
+      assert.fail("a removed writer should not cross post")
+    } catch (error) {
+      assert(error.error.errorCode.code === "TreeNotWritable")
+    }
+  })
+
+  it("Roll Over Full Tree", async () => {
+    const fullTree = await createTree()
+    const successor = await createTree()
+    // A depth 3 tree holds 8 leaves
+    for (let i = 0; i < 8; i++) {
+      await program.methods
+        .appendNote(`note ${i}`)
+        .accounts(fullTree)
+        .rpc()
+    }
+
+    const capacity = await program.methods
+      .getTreeCapacity()
+      .accounts({ merkleTree: fullTree.merkleTree, globalConfig: globalConfig })
+      .view()
+    assert(capacity.toNumber() === 0)
+
+    // Without a successor the append fails before reaching the compression program
+    try {
+      await program.methods.appendNote("note 8").accounts(fullTree).rpc()
+      assert.fail("a full tree should refuse appends")
+    } catch (error) {
+      assert(error.error.errorCode.code === "TreeFull")
+    }
+
+    const successorAccounts = [
+      { pubkey: successor.treeConfig, isSigner: false, isWritable: true },
+      { pubkey: successor.treeAuthority, isSigner: false, isWritable: false },
+      { pubkey: successor.merkleTree, isSigner: false, isWritable: true },
+    ]
+    // Posters cannot pick the successor, only the authority links one
+    try {
+      await program.methods
+        .appendNote("note 8")
+        .accounts(fullTree)
+        .remainingAccounts(successorAccounts)
+        .rpc()
+      assert.fail("an unlinked successor should be refused")
+    } catch (error) {
+      assert(error.error.errorCode.code === "InvalidSuccessorTree")
+    }
+
+    // A successor checking appends differently cannot be linked
+    const limitedTree = await createTree()
+    await program.methods
+      .setRateLimits(new anchor.BN(0), 1)
+      .accounts(limitedTree)
+      .rpc()
+    try {
+      await program.methods
+        .setNextTree()
+        .accounts({
+          ...fullTree,
+          nextConfig: limitedTree.treeConfig,
+          nextTree: limitedTree.merkleTree,
+        })
+        .rpc()
+      assert.fail("a successor with another policy should be refused")
+    } catch (error) {
+      assert(error.error.errorCode.code === "SuccessorPolicyMismatch")
+    }
+
+    await program.methods
+      .setNextTree()
+      .accounts({
+        ...fullTree,
+        nextConfig: successor.treeConfig,
+        nextTree: successor.merkleTree,
+      })
+      .rpc()
+
+    // The ninth note continues in the successor tree
+    const txSignature = await program.methods
+      .appendNote("note 8")
+      .accounts(fullTree)
+      .remainingAccounts(successorAccounts)
+      .rpc()
+    const noteLog = await getNoteLog(connection, txSignature)
+    const noteId = resolveNoteId(noteLog.noteId)
+    assert(noteId.merkleTree.equals(successor.merkleTree))
+    assert(noteId.leafIndex === 0)
+
+    const fullTreeConfig = await program.account.treeConfig.fetch(
+      fullTree.treeConfig
+    )
+    assert(fullTreeConfig.nextTree.equals(successor.merkleTree))
+  })
+
+  it("Get Tree Root", async () => {
+    const tree = await createTree()
+    await program.methods.appendNote("rooted").accounts(tree).rpc()
+
+    const treeRoot = await program.methods
+      .getTreeRoot()
+      .accounts({ merkleTree: tree.merkleTree, globalConfig: globalConfig })
+      .view()
+    const merkleTreeAccount =
+      await ConcurrentMerkleTreeAccount.fromAccountAddress(
+        connection,
+        tree.merkleTree
+      )
+    assert(
+      Buffer.from(treeRoot.root).equals(merkleTreeAccount.getCurrentRoot())
+    )
+    assert(treeRoot.sequenceNumber.eq(merkleTreeAccount.getCurrentSeq()))
+  })
+
+  it("Migrate Leaf To New Tree", async () => {
+    const sourceTree = await createTree()
+    const destTree = await createTree()
+    const note = "moving house"
+    await program.methods.appendNote(note).accounts(sourceTree).rpc()
+
+    const [migration] = PublicKey.findProgramAddressSync(
+      [Buffer.from("migration"), sourceTree.merkleTree.toBuffer()],
+      program.programId
+    )
+    const migrationAccounts = {
+      migration: migration,
+      sourceConfig: sourceTree.treeConfig,
+      sourceTree: sourceTree.merkleTree,
+      destConfig: destTree.treeConfig,
+      destAuthority: destTree.treeAuthority,
+      destTree: destTree.merkleTree,
+      logWrapper: SPL_NOOP_PROGRAM_ID,
+      compressionProgram: SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
+      globalConfig: globalConfig,
+    }
+    await program.methods
+      .beginMigration(false)
+      .accounts(migrationAccounts)
+      .rpc()
+
+    // The source tree is frozen while its leaves are copied
+    const sourceConfig = await program.account.treeConfig.fetch(
+      sourceTree.treeConfig
+    )
+    assert(sourceConfig.frozen)
+
+    // The only leaf's proof is all empty nodes, which the compression program fills in
+    const merkleTreeAccount =
+      await ConcurrentMerkleTreeAccount.fromAccountAddress(
+        connection,
+        sourceTree.merkleTree
+      )
+    const root = Array.from(merkleTreeAccount.getCurrentRoot())
+    const payload = {
+      owner: provider.publicKey,
+      note: note,
+      status: { published: {} },
+    }
+    const txSignature = await program.methods
+      .migrateLeaf(0, root, payload)
+      .accounts(migrationAccounts)
+      .rpc()
+
+    const migrationLog = await getMigrationLog(connection, txSignature)
+    const hash = getHash(note, provider.publicKey, NoteStatus.Published)
+    assert(hash === Buffer.from(migrationLog.leafNode).toString("hex"))
+    const sourceId = resolveNoteId(migrationLog.sourceId)
+    const destId = resolveNoteId(migrationLog.destId)
+    assert(sourceId.merkleTree.equals(sourceTree.merkleTree))
+    assert(destId.merkleTree.equals(destTree.merkleTree))
+    assert(destId.leafIndex === 0)
+
+    // A leaf is never copied twice
+    try {
+      await program.methods
+        .migrateLeaf(0, root, payload)
+        .accounts(migrationAccounts)
+        .rpc()
+      assert.fail("a migrated leaf should not be copied again")
+    } catch (error) {
+      assert(error.error.errorCode.code === "LeafAlreadyMigrated")
+    }
+  })
+
+  it("Compact Tree Without Deleted Notes", async () => {
+    const sourceTree = await createTree()
+    const destTree = await createTree()
+    const deletedNote = "gone soon"
+    const liveNote = "still here"
+
+    // Delete the first note, its leaf no longer commits to the content
+    await program.methods.appendNote(deletedNote).accounts(sourceTree).rpc()
+    let merkleTreeAccount =
+      await ConcurrentMerkleTreeAccount.fromAccountAddress(
+        connection,
+        sourceTree.merkleTree
+      )
+    await program.methods
+      .setNoteStatus(
+        0,
+        Array.from(merkleTreeAccount.getCurrentRoot()),
+        deletedNote,
+        { published: {} },
+        { deleted: {} }
+      )
+      .accounts(sourceTree)
+      .rpc()
+    await program.methods.appendNote(liveNote).accounts(sourceTree).rpc()
+
+    const [migration] = PublicKey.findProgramAddressSync(
+      [Buffer.from("migration"), sourceTree.merkleTree.toBuffer()],
+      program.programId
+    )
+    const migrationAccounts = {
+      migration: migration,
+      sourceConfig: sourceTree.treeConfig,
+      sourceTree: sourceTree.merkleTree,
+      destConfig: destTree.treeConfig,
+      destAuthority: destTree.treeAuthority,
+      destTree: destTree.merkleTree,
+      logWrapper: SPL_NOOP_PROGRAM_ID,
+      compressionProgram: SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
+      globalConfig: globalConfig,
+    }
+    await program.methods.beginMigration(true).accounts(migrationAccounts).rpc()
+
+    merkleTreeAccount = await ConcurrentMerkleTreeAccount.fromAccountAddress(
+      connection,
+      sourceTree.merkleTree
+    )
+    const root = Array.from(merkleTreeAccount.getCurrentRoot())
+
+    // The deleted note is left behind
+    try {
+      await program.methods
+        .migrateLeaf(0, root, {
+          owner: provider.publicKey,
+          note: "",
+          status: { deleted: {} },
+        })
+        .accounts(migrationAccounts)
+        .rpc()
+      assert.fail("a compaction should not copy a deleted note")
+    } catch (error) {
+      assert(error.error.errorCode.code === "LeafTombstoned")
+    }
+
+    // The live note is proven with its deleted sibling, then empty subtrees
+    const deletedLeaf = getHash("", provider.publicKey, NoteStatus.Deleted)
+    const emptyLevel1 = keccak256(Buffer.alloc(64))
+    const emptyLevel2 = keccak256(Buffer.from(emptyLevel1.repeat(2), "hex"))
+    const proof = [deletedLeaf, emptyLevel1, emptyLevel2].map((node) => ({
+      pubkey: new PublicKey(Buffer.from(node, "hex")),
+      isSigner: false,
+      isWritable: false,
+    }))
+    const txSignature = await program.methods
+      .migrateLeaf(1, root, {
+        owner: provider.publicKey,
+        note: liveNote,
+        status: { published: {} },
+      })
+      .accounts(migrationAccounts)
+      .remainingAccounts(proof)
+      .rpc()
+    const migrationLog = await getMigrationLog(connection, txSignature)
+    assert(resolveNoteId(migrationLog.sourceId).leafIndex === 1)
+    assert(resolveNoteId(migrationLog.destId).leafIndex === 0)
+
+    // Finishing retires the source tree in favour of the compacted one
+    await program.methods.finishMigration().accounts(migrationAccounts).rpc()
+    const sourceConfig = await program.account.treeConfig.fetch(
+      sourceTree.treeConfig
+    )
+    assert(sourceConfig.frozen)
+    assert(sourceConfig.nextTree.equals(destTree.merkleTree))
+    assert((await connection.getAccountInfo(migration)) === null)
+  })
+
+  it("Move Note Between Trees", async () => {
+    const sourceTree = await createTree()
+    const destTree = await createTree()
+    const note = "wrong tree"
+    await program.methods.appendNote(note).accounts(sourceTree).rpc()
+
+    const merkleTreeAccount =
+      await ConcurrentMerkleTreeAccount.fromAccountAddress(
+        connection,
+        sourceTree.merkleTree
+      )
+    const root = Array.from(merkleTreeAccount.getCurrentRoot())
+    const txSignature = await program.methods
+      .moveNote(0, root, note, { published: {} })
+      .accounts({
+        sourceConfig: sourceTree.treeConfig,
+        sourceAuthority: sourceTree.treeAuthority,
+        sourceTree: sourceTree.merkleTree,
+        destConfig: destTree.treeConfig,
+        destAuthority: destTree.treeAuthority,
+        destTree: destTree.merkleTree,
+        logWrapper: SPL_NOOP_PROGRAM_ID,
+        compressionProgram: SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
+        globalConfig: globalConfig,
+      })
+      .rpc()
+
+    // The same leaf now lives in the destination tree, linked to its old id
+    const moveLog = await getNoteMoveLog(connection, txSignature)
+    const hash = getHash(note, provider.publicKey, NoteStatus.Published)
+    assert(hash === Buffer.from(moveLog.leafNode).toString("hex"))
+    const sourceId = resolveNoteId(moveLog.sourceId)
+    const destId = resolveNoteId(moveLog.destId)
+    assert(sourceId.merkleTree.equals(sourceTree.merkleTree))
+    assert(destId.merkleTree.equals(destTree.merkleTree))
+
+    // The source leaf was deleted, and both trees counted the change
+    const sourceConfig = await program.account.treeConfig.fetch(
+      sourceTree.treeConfig
+    )
+    assert(sourceConfig.replaceCount.toNumber() === 1)
+    const destConfig = await program.account.treeConfig.fetch(
+      destTree.treeConfig
+    )
+    assert(destConfig.numAppended.toNumber() === 1)
+  })
+
+  it("Stale Root Is Rejected", async () => {
+    const tree = await createTree()
+    await program.methods.appendNote("v0").accounts(tree).rpc()
+    const getRoot = async () => {
+      const merkleTreeAccount =
+        await ConcurrentMerkleTreeAccount.fromAccountAddress(
+          connection,
+          tree.merkleTree
+        )
+      return Array.from(merkleTreeAccount.getCurrentRoot())
+    }
+    const staleRoot = await getRoot()
+
+    // Nine edits push the root the first edit started from out of the recent roots
+    let root = staleRoot
+    for (let i = 0; i < 9; i++) {
+      const status = i === 0 ? { published: {} } : { edited: {} }
+      await program.methods
+        .updateNote(0, root, `v${i}`, `v${i + 1}`, status)
+        .accounts(tree)
+        .rpc()
+      root = await getRoot()
+    }
+
+    // The config keeps the root the latest edit started from
+    const treeConfigAccount = await program.account.treeConfig.fetch(
+      tree.treeConfig
+    )
+    assert(treeConfigAccount.recentRoots.length === 8)
+    assert(
+      !treeConfigAccount.recentRoots.some(
+        (recentRoot) =>
+          Buffer.from(recentRoot).equals(Buffer.from(staleRoot))
+      )
+    )
+
+    try {
+      await program.methods
+        .updateNote(0, staleRoot, "v9", "v10", { edited: {} })
+        .accounts(tree)
+        .rpc()
+      assert.fail("a root older than the recent roots should be refused")
+    } catch (error) {
+      assert(error.error.errorCode.code === "RootTooOld")
+    }
+  })
+
+  it("Leaf Not In The Tree Is Rejected", async () => {
+    const tree = await createTree()
+    await program.methods.appendNote("only note").accounts(tree).rpc()
+    const merkleTreeAccount =
+      await ConcurrentMerkleTreeAccount.fromAccountAddress(
+        connection,
+        tree.merkleTree
+      )
+    const root = Array.from(merkleTreeAccount.getCurrentRoot())
+
+    // Index 1 fits the tree but no leaf was appended there yet
+    try {
+      await program.methods
+        .updateNote(1, root, "only note", "edited", { published: {} })
+        .accounts(tree)
+        .rpc()
+      assert.fail("an index past the appended leaves should be refused")
+    } catch (error) {
+      assert(error.error.errorCode.code === "LeafIndexOutOfBounds")
+    }
+
+    // The note at index 0 holds other content
+    try {
+      await program.methods
+        .updateNote(0, root, "other note", "edited", { published: {} })
+        .accounts(tree)
+        .rpc()
+      assert.fail("a leaf with other content should be refused")
+    } catch (error) {
+      assert(error.error.errorCode.code === "LeafMismatch")
+    }
+  })
+
+  it("Merkle Tree Not Owned By Compression Program Is Rejected", async () => {
+    // The wallet is a system account, its pdas are derived consistently
+    const fakeTree = provider.publicKey
+    const [fakeAuthority] = PublicKey.findProgramAddressSync(
+      [fakeTree.toBuffer()],
+      program.programId
+    )
+    try {
+      await program.methods
+        .appendNote("not a tree")
+        .accounts({
+          treeConfig: getTreeConfigAddress(program.programId, fakeTree),
+          merkleTree: fakeTree,
+          treeAuthority: fakeAuthority,
+          logWrapper: SPL_NOOP_PROGRAM_ID,
+          compressionProgram: SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
+          globalConfig: globalConfig,
+        })
+        .rpc()
+      assert.fail("a tree account owned by another program should be refused")
+    } catch (error) {
+      assert(error.error.errorCode.code === "InvalidMerkleTreeOwner")
+    }
+  })
+
+  it("Update Note With Root Derived From Proof", async () => {
+    const tree = await createTree()
+    await program.methods.appendNote("first").accounts(tree).rpc()
+    await program.methods.appendNote("second").accounts(tree).rpc()
+
+    // Each note is proven with its sibling, then empty subtrees
+    const emptyLevel1 = keccak256(Buffer.alloc(64))
+    const emptyLevel2 = keccak256(Buffer.from(emptyLevel1.repeat(2), "hex"))
+    const getProof = (sibling: string) =>
+      [sibling, emptyLevel1, emptyLevel2].map((node) => ({
+        pubkey: new PublicKey(Buffer.from(node, "hex")),
+        isSigner: false,
+        isWritable: false,
+      }))
+    const first = getHash("first", provider.publicKey, NoteStatus.Published)
+    const second = getHash("second", provider.publicKey, NoteStatus.Published)
+
+    await program.methods
+      .updateNote(1, null, "second", "second edited", { published: {} })
+      .accounts(tree)
+      .remainingAccounts(getProof(first))
+      .rpc()
+
+    // The first note's proof still holds the old second note, its root is recent
+    const txSignature = await program.methods
+      .updateNote(0, null, "first", "first edited", { published: {} })
+      .accounts(tree)
+      .remainingAccounts(getProof(second))
+      .rpc()
+
+    const noteLog = await getNoteLog(connection, txSignature)
+    assert(noteLog.note === "first edited")
+    const treeConfigAccount = await program.account.treeConfig.fetch(
+      tree.treeConfig
+    )
+    assert(treeConfigAccount.replaceCount.toNumber() === 2)
+  })
+
+  it("Add Note", async () => {
+    const txSignature = await program.methods
+      .appendNote(firstNote)
+      .accounts({
+        treeConfig: treeConfig,
+        merkleTree: merkleTree.publicKey,
+        treeAuthority: treeAuthority,
+        logWrapper: SPL_NOOP_PROGRAM_ID,
+        compressionProgram: SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
+        globalConfig: globalConfig,
+      })
+      .rpc()
+
+    const noteLog = await getNoteLog(connection, txSignature)
+    const hash = getHash(firstNote, provider.publicKey, NoteStatus.Published)
+
+    assert(hash === Buffer.from(noteLog.leafNode).toString("hex"))
+    assert(firstNote === noteLog.note)
+
+    // The note is the first leaf of the tree, with the v2 note leaf schema
+    const noteId = resolveNoteId(noteLog.noteId)
+    assert(noteId.merkleTree.equals(merkleTree.publicKey))
+    assert(noteId.leafIndex === 0)
+    assert(noteId.schemaVersion === 2)
+    assert(encodeNoteId(merkleTree.publicKey, 0, 2) === noteLog.noteId)
+
+    // Passing the tree config counts the append
+    const treeConfigAccount = await program.account.treeConfig.fetch(treeConfig)
+    assert(treeConfigAccount.numAppended.toNumber() === 1)
+  })
+
+  it("Add Max Size Note", async () => {
+    // Size of note is limited by max transaction size of 1232 bytes, minus additional data required for the instruction
+    const txSignature = await program.methods
+      .appendNote(secondNote)
+      .accounts({
+        treeConfig: treeConfig,
+        merkleTree: merkleTree.publicKey,
+        treeAuthority: treeAuthority,
+        logWrapper: SPL_NOOP_PROGRAM_ID,
+        compressionProgram: SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
+        globalConfig: globalConfig,
+      })
+      .rpc()
+
+    const noteLog = await getNoteLog(connection, txSignature)
+    const hash = getHash(secondNote, provider.publicKey, NoteStatus.Published)
+
+    assert(hash === Buffer.from(noteLog.leafNode).toString("hex"))
+    assert(secondNote === noteLog.note)
+  })
+
+  it("Update First Note", async () => {
+    const merkleTreeAccount =
+      await ConcurrentMerkleTreeAccount.fromAccountAddress(
+        connection,
+        merkleTree.publicKey
+      )
+
+    const rootKey = merkleTreeAccount.tree.changeLogs[0].root
+    const root = Array.from(rootKey.toBuffer())
+
+    const txSignature = await program.methods
+      .updateNote(0, root, firstNote, updatedNote, { published: {} })
+      .accounts({
+        treeConfig: treeConfig,
+        merkleTree: merkleTree.publicKey,
+        treeAuthority: treeAuthority,
+        logWrapper: SPL_NOOP_PROGRAM_ID,
+        compressionProgram: SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
+        globalConfig: globalConfig,
+      })
+      .rpc()
+
+    const noteLog = await getNoteLog(connection, txSignature)
+    const hash = getHash(updatedNote, provider.publicKey, NoteStatus.Edited)
+
+    assert(hash === Buffer.from(noteLog.leafNode).toString("hex"))
+    assert(updatedNote === noteLog.note)
+    assert(noteLog.status === NoteStatus.Edited)
+
+    // Passing the tree config counts the replace
+    const treeConfigAccount = await program.account.treeConfig.fetch(treeConfig)
+    assert(treeConfigAccount.replaceCount.toNumber() === 1)
+  })
+
+  it("Set Status Of A Note Proven With Its Siblings", async () => {
+    const tree = await createTree()
+    const lockedNote = "locked while not rightmost"
+    const siblingNote = "appended after"
+    await program.methods.appendNote(lockedNote).accounts(tree).rpc()
+    await program.methods.appendNote(siblingNote).accounts(tree).rpc()
+    const merkleTreeAccount =
+      await ConcurrentMerkleTreeAccount.fromAccountAddress(
+        connection,
+        tree.merkleTree
+      )
+
+    // The first note is no longer the rightmost leaf, so its proof is passed in
+    const siblingLeaf = getHash(
+      siblingNote,
+      provider.publicKey,
+      NoteStatus.Published
+    )
+    const emptyLevel1 = keccak256(Buffer.alloc(64))
+    const emptyLevel2 = keccak256(Buffer.from(emptyLevel1.repeat(2), "hex"))
+    const proof = [siblingLeaf, emptyLevel1, emptyLevel2].map((node) => ({
+      pubkey: new PublicKey(Buffer.from(node, "hex")),
+      isSigner: false,
+      isWritable: false,
+    }))
+    const txSignature = await program.methods
+      .setNoteStatus(
+        0,
+        Array.from(merkleTreeAccount.getCurrentRoot()),
+        lockedNote,
+        { published: {} },
+        { locked: {} }
+      )
+      .accounts(tree)
+      .remainingAccounts(proof)
+      .rpc()
+    const noteLog = await getNoteLog(connection, txSignature)
+    assert(resolveNoteId(noteLog.noteId).leafIndex === 0)
+    assert(noteLog.status === NoteStatus.Locked)
+  })
+
+  it("Locked Note Cannot Be Updated", async () => {
+    const merkleTreeAccount =
+      await ConcurrentMerkleTreeAccount.fromAccountAddress(
+        connection,
+        merkleTree.publicKey
+      )
+    const root = Array.from(merkleTreeAccount.getCurrentRoot())
+
+    // Lock the note edited by the previous test
+    const txSignature = await program.methods
+      .setNoteStatus(0, root, updatedNote, { edited: {} }, { locked: {} })
+      .accounts({
+        treeConfig: treeConfig,
+        merkleTree: merkleTree.publicKey,
+        treeAuthority: treeAuthority,
+        logWrapper: SPL_NOOP_PROGRAM_ID,
+        compressionProgram: SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
+        globalConfig: globalConfig,
+      })
+      .rpc()
+
+    const noteLog = await getNoteLog(connection, txSignature)
+    assert(noteLog.status === NoteStatus.Locked)
+
+    try {
+      await program.methods
+        .updateNote(0, root, updatedNote, firstNote, { locked: {} })
+        .accounts({
+          treeConfig: treeConfig,
+          merkleTree: merkleTree.publicKey,
+          treeAuthority: treeAuthority,
+          logWrapper: SPL_NOOP_PROGRAM_ID,
+          compressionProgram: SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
+          globalConfig: globalConfig,
+        })
+        .rpc()
+      assert.fail("a locked note should not be editable")
+    } catch (error) {
+      assert(error.error.errorCode.code === "InvalidNoteTransition")
+    }
+  })
+
+  it("DID Owned Note Survives Key Rotation", async () => {
+    const didId = new anchor.BN(0)
+    const [did] = PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("did"),
+        wallet.publicKey.toBuffer(),
+        didId.toArrayLike(Buffer, "le", 8),
+      ],
+      program.programId
+    )
+    await program.methods
+      .createDid(didId, [wallet.publicKey])
+      .accounts({ did: did, globalConfig: globalConfig })
+      .rpc()
+
+    const accounts = {
+      did: did,
+      treeConfig: treeConfig,
+      merkleTree: merkleTree.publicKey,
+      treeAuthority: treeAuthority,
+      logWrapper: SPL_NOOP_PROGRAM_ID,
+      compressionProgram: SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
+      globalConfig: globalConfig,
+    }
+    const note = "owned by my did"
+    const txSignature = await program.methods
+      .appendNote(note)
+      .accounts(accounts)
+      .rpc()
+
+    // The leaf commits to the DID document, not the signing wallet
+    const noteLog = await getNoteLog(connection, txSignature)
+    assert(noteLog.owner.equals(did))
+    assert(
+      getHash(note, did, NoteStatus.Published) ===
+        Buffer.from(noteLog.leafNode).toString("hex")
+    )
+
+    // Rotate the controller key, the new key can still update the note
+    const newController = Keypair.generate()
+    const airdropSignature = await connection.requestAirdrop(
+      newController.publicKey,
+      anchor.web3.LAMPORTS_PER_SOL
+    )
+    await connection.confirmTransaction(airdropSignature)
+    await program.methods
+      .setDidControllers([newController.publicKey])
+      .accounts({ did: did, globalConfig: globalConfig })
+      .rpc()
+
+    const merkleTreeAccount =
+      await ConcurrentMerkleTreeAccount.fromAccountAddress(
+        connection,
+        merkleTree.publicKey
+      )
+    const root = Array.from(merkleTreeAccount.getCurrentRoot())
+    const index = merkleTreeAccount.tree.rightMostPath.index - 1
+    const rotatedNote = "still owned by my did"
+    const updateSignature = await program.methods
+      .updateNote(index, root, note, rotatedNote, { published: {} })
+      .accounts({ ...accounts, owner: newController.publicKey })
+      .signers([newController])
+      .rpc()
+
+    const updateLog = await getNoteLog(connection, updateSignature)
+    assert(updateLog.owner.equals(did))
+    assert(rotatedNote === updateLog.note)
+  })
+
+  it("Organization Member Posts For The Org", async () => {
+    const orgId = new anchor.BN(0)
+    const [organization] = PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("org"),
+        wallet.publicKey.toBuffer(),
+        orgId.toArrayLike(Buffer, "le", 8),
+      ],
+      program.programId
+    )
+    const member = Keypair.generate()
+    const [seat] = PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("seat"),
+        organization.toBuffer(),
+        member.publicKey.toBuffer(),
+      ],
+      program.programId
+    )
+
+    await program.methods
+      .createOrganization(orgId)
+      .accounts({ organization: organization, globalConfig: globalConfig })
+      .rpc()
+
+    // No seat can be assigned before one is bought
+    try {
+      await program.methods
+        .addSeat(member.publicKey)
+        .accounts({
+          organization: organization,
+          seat: seat,
+          globalConfig: globalConfig,
+        })
+        .rpc()
+      assert.fail("a seat should not be assigned before it is bought")
+    } catch (error) {
+      assert(error.error.errorCode.code === "NoSeatsAvailable")
+    }
+
+    await program.methods
+      .purchaseSeats(1)
+      .accounts({
+        organization: organization,
+        treasury: treasury,
+        globalConfig: globalConfig,
+      })
+      .rpc()
+    await program.methods
+      .addSeat(member.publicKey)
+      .accounts({
+        organization: organization,
+        seat: seat,
+        globalConfig: globalConfig,
+      })
+      .rpc()
+
+    // Org notes are metered into the usage of the current 30 day period
+    const period = new anchor.BN(Math.floor(Date.now() / 1000 / 2_592_000))
+    const [orgUsage] = PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("org_usage"),
+        organization.toBuffer(),
+        period.toArrayLike(Buffer, "le", 8),
+      ],
+      program.programId
+    )
+    await program.methods
+      .openUsagePeriod(period)
+      .accounts({
+        organization: organization,
+        orgUsage: orgUsage,
+        globalConfig: globalConfig,
+      })
+      .rpc()
+
+    const note = "posted for the team"
+    const txSignature = await program.methods
+      .appendOrgNote(note)
+      .accounts({
+        member: member.publicKey,
+        organization: organization,
+        seat: seat,
+        orgUsage: orgUsage,
+        treeConfig: treeConfig,
+        merkleTree: merkleTree.publicKey,
+        treeAuthority: treeAuthority,
+        logWrapper: SPL_NOOP_PROGRAM_ID,
+        compressionProgram: SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
+        globalConfig: globalConfig,
+      })
+      .signers([member])
+      .rpc()
+
+    // The note is owned by the organization, not the member who posted it
+    const orgNoteLog = await getOrgNoteLog(connection, txSignature)
+    assert(orgNoteLog.organization.equals(organization))
+    assert(orgNoteLog.member.equals(member.publicKey))
+    assert(
+      getOrgNoteHash(note, organization, orgNoteLog.postedAt) ===
+        Buffer.from(orgNoteLog.leafNode).toString("hex")
+    )
+    assert(resolveNoteId(orgNoteLog.noteId).schemaVersion === 4)
+
+    // Even on the shortest retention tier, a new note cannot be pruned yet
+    await program.methods
+      .setRetentionTier({ days90: {} })
+      .accounts({
+        organization: organization,
+        logWrapper: SPL_NOOP_PROGRAM_ID,
+        globalConfig: globalConfig,
+      })
+      .rpc()
+    const merkleTreeAccount =
+      await ConcurrentMerkleTreeAccount.fromAccountAddress(
+        connection,
+        merkleTree.publicKey
+      )
+    try {
+      await program.methods
+        .pruneOrgNote(
+          Array.from(merkleTreeAccount.getCurrentRoot()),
+          note,
+          orgNoteLog.postedAt,
+          resolveNoteId(orgNoteLog.noteId).leafIndex
+        )
+        .accounts({
+          organization: organization,
+          treeConfig: treeConfig,
+          merkleTree: merkleTree.publicKey,
+          treeAuthority: treeAuthority,
+          logWrapper: SPL_NOOP_PROGRAM_ID,
+          compressionProgram: SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
+          globalConfig: globalConfig,
+        })
+        .rpc()
+      assert.fail("the note should be kept for 90 days")
+    } catch (error) {
+      assert(error.error.errorCode.code === "RetentionNotElapsed")
+    }
+
+    const orgUsageAccount = await program.account.orgUsage.fetch(orgUsage)
+    assert(orgUsageAccount.numAppended.toNumber() === 1)
+    assert(orgUsageAccount.bytes.toNumber() === note.length)
+
+    // The counts only become final once the period is over
+    try {
+      await program.methods
+        .finalizeUsagePeriod()
+        .accounts({ orgUsage: orgUsage, globalConfig: globalConfig })
+        .rpc()
+      assert.fail("the current period should not be finalized")
+    } catch (error) {
+      assert(error.error.errorCode.code === "UsagePeriodNotEnded")
+    }
+
+    await program.methods
+      .removeSeat()
+      .accounts({
+        organization: organization,
+        seat: seat,
+        globalConfig: globalConfig,
+      })
+      .rpc()
+    assert((await connection.getAccountInfo(seat)) === null)
+  })
+
+  it("Cross Post Note", async () => {
+    const secondTree = Keypair.generate()
+    const [secondTreeAuthority] = PublicKey.findProgramAddressSync(
+      [secondTree.publicKey.toBuffer()],
+      program.programId
+    )
+
+    const allocTreeIx = await createAllocTreeIx(
+      connection,
+      secondTree.publicKey,
+      wallet.publicKey,
+      { maxDepth: 3, maxBufferSize: 8 },
+      0
+    )
+    const ix = await program.methods
+      .createNoteTree(3, 8, 0)
+      .accounts({
+        forest: forest,
+        treasury: treasury,
+        treeConfig: getTreeConfigAddress(
+          program.programId,
+          secondTree.publicKey
+        ),
+        merkleTree: secondTree.publicKey,
+        treeAuthority: secondTreeAuthority,
+        logWrapper: SPL_NOOP_PROGRAM_ID,
+        compressionProgram: SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
+        globalConfig: globalConfig,
+      })
+      .instruction()
+    const tx = new Transaction().add(allocTreeIx, ix)
+    await sendAndConfirmTransaction(connection, tx, [wallet.payer, secondTree])
+
+    // Each tree passes the accounts of a CrossPostTree
+    const trees = [merkleTree.publicKey, secondTree.publicKey]
+    const note = "posted everywhere"
+    const txSignature = await program.methods
+      .crossPost(note)
+      .accounts({
+        logWrapper: SPL_NOOP_PROGRAM_ID,
+        compressionProgram: SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
+        globalConfig: globalConfig,
+      })
+      .remainingAccounts(
+        trees.flatMap((tree) =>
+          getCrossPostTreeAccounts(program.programId, tree)
+        )
+      )
+      .rpc()
+
+    const crossPostLog = await getCrossPostLog(connection, txSignature)
+    const hash = getHash(note, provider.publicKey, NoteStatus.Published)
+
+    assert(hash === Buffer.from(crossPostLog.leafNode).toString("hex"))
+    const noteIds = crossPostLog.noteIds.map(resolveNoteId)
+    assert(noteIds[0].merkleTree.equals(merkleTree.publicKey))
+    assert(noteIds[1].merkleTree.equals(secondTree.publicKey))
+    assert(noteIds[1].leafIndex === 0)
+  })
+
+  it("Cross Post Refuses A Frozen Tree", async () => {
+    const tree = await createTree()
+    await program.methods.freezeTree().accounts(tree).rpc()
+
+    // A cross post goes through the tree config like any other append
+    try {
+      await program.methods
+        .crossPost("posted while frozen")
+        .accounts({
+          logWrapper: SPL_NOOP_PROGRAM_ID,
+          compressionProgram: SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
+          globalConfig: globalConfig,
+        })
+        .remainingAccounts(
+          getCrossPostTreeAccounts(program.programId, tree.merkleTree)
+        )
+        .rpc()
+      assert.fail("a frozen tree should refuse cross posts")
+    } catch (error) {
+      assert(error.error.errorCode.code === "TreeFrozen")
+    }
+  })
+
+  // The buyer of the test below, who hands the note on in the test after it
+  let buyer: Keypair
+
+  it("Accept Offer On Note", async () => {
+    buyer = Keypair.generate()
+    const airdropSignature = await connection.requestAirdrop(
+      buyer.publicKey,
+      anchor.web3.LAMPORTS_PER_SOL
+    )
+    await connection.confirmTransaction(airdropSignature)
+
+    // Offer on the max size note appended at index 1
+    const index = 1
+    const indexBuffer = Buffer.alloc(4)
+    indexBuffer.writeUInt32LE(index)
+    const [offer] = PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("offer"),
+        merkleTree.publicKey.toBuffer(),
+        indexBuffer,
+        buyer.publicKey.toBuffer(),
+      ],
+      program.programId
+    )
+
+    const amount = new anchor.BN(anchor.web3.LAMPORTS_PER_SOL / 10)
+    const expiry = new anchor.BN(Math.floor(Date.now() / 1000) + 3600)
+    await program.methods
+      .makeOffer(index, amount, expiry)
+      .accounts({
+        buyer: buyer.publicKey,
+        merkleTree: merkleTree.publicKey,
+        offer: offer,
+        globalConfig: globalConfig,
+      })
+      .signers([buyer])
+      .rpc()
+
+    const merkleTreeAccount =
+      await ConcurrentMerkleTreeAccount.fromAccountAddress(
+        connection,
+        merkleTree.publicKey
+      )
+    const root = Array.from(merkleTreeAccount.getCurrentRoot())
+
+    const txSignature = await program.methods
+      .acceptOffer(root, secondNote, { published: {} })
+      .accounts({
+        buyer: buyer.publicKey,
+        offer: offer,
+        treeConfig: treeConfig,
+        merkleTree: merkleTree.publicKey,
+        treeAuthority: treeAuthority,
+        logWrapper: SPL_NOOP_PROGRAM_ID,
+        compressionProgram: SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
+        globalConfig: globalConfig,
+      })
+      .rpc()
+
+    // The note now belongs to the buyer and the offer is closed
+    const noteLog = await getNoteLog(connection, txSignature)
+    assert(noteLog.owner.equals(buyer.publicKey))
+    assert((await connection.getAccountInfo(offer)) === null)
+  })
+
+  it("Note Transfer Waits For The New Owner", async () => {
+    // The buyer offers the note at index 1 back to the provider
+    const index = 1
+    const indexBuffer = Buffer.alloc(4)
+    indexBuffer.writeUInt32LE(index)
+    const [transferOffer] = PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("transfer_offer"),
+        merkleTree.publicKey.toBuffer(),
+        indexBuffer,
+        buyer.publicKey.toBuffer(),
+      ],
+      program.programId
+    )
+    await program.methods
+      .offerNoteTransfer(index, provider.publicKey, buyer.publicKey)
+      .accounts({
+        signer: buyer.publicKey,
+        merkleTree: merkleTree.publicKey,
+        transferOffer: transferOffer,
+        globalConfig: globalConfig,
+      })
+      .signers([buyer])
+      .rpc()
+
+    const merkleTreeAccount =
+      await ConcurrentMerkleTreeAccount.fromAccountAddress(
+        connection,
+        merkleTree.publicKey
+      )
+    const root = Array.from(merkleTreeAccount.getCurrentRoot())
+    const acceptAccounts = {
+      offeredBy: buyer.publicKey,
+      transferOffer: transferOffer,
+      treeConfig: treeConfig,
+      merkleTree: merkleTree.publicKey,
+      treeAuthority: treeAuthority,
+      logWrapper: SPL_NOOP_PROGRAM_ID,
+      compressionProgram: SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
+      globalConfig: globalConfig,
+    }
+
+    // Nobody but the new owner can accept the transfer
+    const stranger = Keypair.generate()
+    try {
+      await program.methods
+        .acceptNoteTransfer(root, secondNote, { published: {} })
+        .accounts({ ...acceptAccounts, newOwner: stranger.publicKey })
+        .signers([stranger])
+        .rpc()
+      assert.fail("only the new owner should accept the transfer")
+    } catch (error) {
+      assert(error.error.errorCode.code === "Unauthorized")
+    }
+
+    const txSignature = await program.methods
+      .acceptNoteTransfer(root, secondNote, { published: {} })
+      .accounts(acceptAccounts)
+      .rpc()
+    const noteLog = await getNoteLog(connection, txSignature)
+    assert(noteLog.owner.equals(provider.publicKey))
+    assert((await connection.getAccountInfo(transferOffer)) === null)
+  })
+
+  it("Mark Note Read", async () => {
+    const leafHash = Array.from(
+      Buffer.from(
+        getHash(firstNote, provider.publicKey, NoteStatus.Published),
+        "hex"
+      )
+    )
+
+    const txSignature = await program.methods
+      .markRead(leafHash)
+      .accounts({
+        inbox: getInboxAddress(program.programId, provider.publicKey),
+        logWrapper: SPL_NOOP_PROGRAM_ID,
+        globalConfig: globalConfig,
+      })
+      .rpc()
+
+    const receiptLog = await getReadReceiptLog(connection, txSignature)
+
+    assert(Buffer.from(leafHash).equals(Buffer.from(receiptLog.leafNode)))
+    assert(receiptLog.reader.equals(provider.publicKey))
+  })
+
+  it("Confirm Message Delivery", async () => {
+    // A message to self needs no consent and the provider can sign as recipient
+    const message = "note to self"
+    const conversation = getConversationAddress(
+      program.programId,
+      provider.publicKey,
+      provider.publicKey
+    )
+    await program.methods
+      .appendMessage(provider.publicKey, message, null, 0, nonce)
+      .accounts({
+        treeConfig: treeConfig,
+        merkleTree: merkleTree.publicKey,
+        treeAuthority: treeAuthority,
+        inbox: getInboxAddress(program.programId, provider.publicKey),
+        blockList: getBlockListAddress(program.programId, provider.publicKey),
+        conversationState: null,
+        conversation: conversation,
+        logWrapper: SPL_NOOP_PROGRAM_ID,
+        compressionProgram: SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
+        globalConfig: globalConfig,
+      })
+      .rpc()
+
+    const leafHash = Array.from(
+      Buffer.from(
+        getMessageHashV2(
+          message,
+          provider.publicKey,
+          provider.publicKey,
+          nonce
+        ),
+        "hex"
+      )
+    )
+    const txSignature = await program.methods
+      .confirmDelivery(leafHash)
+      .accounts({
+        recipient: provider.publicKey,
+        conversation: conversation,
+        logWrapper: SPL_NOOP_PROGRAM_ID,
+        globalConfig: globalConfig,
+      })
+      .rpc()
+
+    const deliveryLog = await getDeliveryLog(connection, txSignature)
+    assert(Buffer.from(leafHash).equals(Buffer.from(deliveryLog.leafNode)))
+    assert(deliveryLog.recipient.equals(provider.publicKey))
+
+    const conversationAccount = await program.account.conversation.fetch(
+      conversation
+    )
+    assert(conversationAccount.deliveredCount.toNumber() === 1)
+  })
+
+  it("React To Note", async () => {
+    const leafHash = Array.from(
+      Buffer.from(
+        getHash(updatedNote, provider.publicKey, NoteStatus.Edited),
+        "hex"
+      )
+    )
+
+    const txSignature = await program.methods
+      .react(leafHash, "👍")
+      .accounts({
+        logWrapper: SPL_NOOP_PROGRAM_ID,
+        globalConfig: globalConfig,
+      })
+      .rpc()
+
+    const reactionLog = await getReactionLog(connection, txSignature)
+
+    assert(Buffer.from(leafHash).equals(Buffer.from(reactionLog.leafNode)))
+    assert(reactionLog.reactor.equals(provider.publicKey))
+    assert(reactionLog.emoji === "👍")
+  })
+
+  it("Quote Append Message", async () => {
+    const quote = await program.methods
+      .quoteAppend(100, {
+        kind: { message: {} },
+        newConversation: true,
+        priority: 0,
+      })
+      .accounts({
+        merkleTree: merkleTree.publicKey,
+        treeConfig: treeConfig,
+        globalConfig: globalConfig,
+      })
+      .view()
+
+    // A new conversation account holds two participants, three counters, two
+    // flag pairs and a bump
+    const conversationRent =
+      await connection.getMinimumBalanceForRentExemption(
+        8 + 32 * 2 + 8 + 8 + 8 + 2 + 2 + 1
+      )
+
+    assert(quote.rent.toNumber() === conversationRent)
+    assert(
+      quote.total.toNumber() ===
+        quote.networkFee.toNumber() +
+          quote.rent.toNumber() +
+          quote.protocolFee.toNumber()
+    )
+  })
+
+  it("Append Message", async () => {
+    const recipient = await openConversation()
+    const message = "hello recipient"
+    const conversation = getConversationAddress(
+      program.programId,
+      provider.publicKey,
+      recipient
+    )
+
+    const txSignature = await program.methods
+      .appendMessage(recipient, message, null, 0, nonce)
+      .accounts({
+        treeConfig: treeConfig,
+        merkleTree: merkleTree.publicKey,
+        treeAuthority: treeAuthority,
+        inbox: getInboxAddress(program.programId, recipient),
+        blockList: getBlockListAddress(program.programId, recipient),
+        conversationState: getConversationState(recipient),
+        conversation: conversation,
+        logWrapper: SPL_NOOP_PROGRAM_ID,
+        compressionProgram: SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
+        globalConfig: globalConfig,
+      })
+      .rpc()
+
+    const messageLog = await getMessageLog(connection, txSignature)
+    const hash = getMessageHashV2(message, provider.publicKey, recipient, nonce)
+
+    assert(hash === Buffer.from(messageLog.leafNode).toString("hex"))
+    assert(messageLog.recipient.equals(recipient))
+    assert(message === messageLog.message)
+    assert(messageLog.parentLeaf === null)
+
+    const conversationAccount = await program.account.conversation.fetch(
+      conversation
+    )
+    assert(conversationAccount.messageCount.toNumber() === 1)
+
+    // Reply to the message, committing to its leaf
+    const reply = "hello sender"
+    const parentLeaf = Buffer.from(messageLog.leafNode)
+    const replySignature = await program.methods
+      .appendMessage(recipient, reply, Array.from(parentLeaf), 0, nonce)
+      .accounts({
+        treeConfig: treeConfig,
+        merkleTree: merkleTree.publicKey,
+        treeAuthority: treeAuthority,
+        inbox: getInboxAddress(program.programId, recipient),
+        blockList: getBlockListAddress(program.programId, recipient),
+        conversationState: getConversationState(recipient),
+        conversation: conversation,
+        logWrapper: SPL_NOOP_PROGRAM_ID,
+        compressionProgram: SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
+        globalConfig: globalConfig,
+      })
+      .rpc()
+
+    const replyLog = await getMessageLog(connection, replySignature)
+    const replyHash = getMessageHashV2(
+      reply,
+      provider.publicKey,
+      recipient,
+      nonce,
+      parentLeaf
+    )
+
+    assert(replyHash === Buffer.from(replyLog.leafNode).toString("hex"))
+    assert(parentLeaf.equals(Buffer.from(replyLog.parentLeaf)))
+
+    // Both messages are unread until the recipient marks them read
+    const inbox = await program.account.inbox.fetch(
+      getInboxAddress(program.programId, recipient)
+    )
+    assert(inbox.unreadCount.toNumber() === 2)
+  })
+
+  it("Repeated Message Gets Distinct Leaves", async () => {
+    const recipient = await openConversation()
+    const message = "ping"
+    const accounts = {
+      treeConfig: treeConfig,
+      merkleTree: merkleTree.publicKey,
+      treeAuthority: treeAuthority,
+      inbox: getInboxAddress(program.programId, recipient),
+      blockList: getBlockListAddress(program.programId, recipient),
+      conversationState: getConversationState(recipient),
+      conversation: getConversationAddress(
+        program.programId,
+        provider.publicKey,
+        recipient
+      ),
+      logWrapper: SPL_NOOP_PROGRAM_ID,
+      compressionProgram: SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
+      globalConfig: globalConfig,
+    }
+
+    const leaves = []
+    for (const messageNonce of [1, 2].map((n) => new anchor.BN(n))) {
+      const txSignature = await program.methods
+        .appendMessage(recipient, message, null, 0, messageNonce)
+        .accounts(accounts)
+        .rpc()
+      const messageLog = await getMessageLog(connection, txSignature)
+      assert(messageLog.nonce.eq(messageNonce))
+      leaves.push(Buffer.from(messageLog.leafNode).toString("hex"))
+    }
+
+    // The same message to the same recipient gets a leaf per nonce
+    assert(leaves[0] !== leaves[1])
+    assert(
+      leaves[1] ===
+        getMessageHashV2(
+          message,
+          provider.publicKey,
+          recipient,
+          new anchor.BN(2)
+        )
+    )
+  })
+
+  it("Set Conversation Flags", async () => {
+    const recipient = await openConversation()
+    const conversation = getConversationAddress(
+      program.programId,
+      provider.publicKey,
+      recipient
+    )
+    await program.methods
+      .appendMessage(recipient, "archive me", null, 0, nonce)
+      .accounts({
+        treeConfig: treeConfig,
+        merkleTree: merkleTree.publicKey,
+        treeAuthority: treeAuthority,
+        inbox: getInboxAddress(program.programId, recipient),
+        blockList: getBlockListAddress(program.programId, recipient),
+        conversationState: getConversationState(recipient),
+        conversation: conversation,
+        logWrapper: SPL_NOOP_PROGRAM_ID,
+        compressionProgram: SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
+        globalConfig: globalConfig,
+      })
+      .rpc()
+
+    await program.methods
+      .setConversationFlags(true, false)
+      .accounts({
+        participant: provider.publicKey,
+        conversation: conversation,
+        globalConfig: globalConfig,
+      })
+      .rpc()
+
+    // Only the signer's flags change, the recipient's stay as they were
+    const conversationAccount = await program.account.conversation.fetch(
+      conversation
+    )
+    const position = conversationAccount.participants.findIndex((key) =>
+      key.equals(provider.publicKey)
+    )
+    assert(conversationAccount.archived[position] === true)
+    assert(conversationAccount.archived[1 - position] === false)
+    assert(conversationAccount.muted.every((muted) => muted === false))
+  })
+
+  it("Urgent Message Counts Toward Urgent Inbox", async () => {
+    const recipient = await openConversation()
+    const message = "the build is on fire"
+    const inbox = getInboxAddress(program.programId, recipient)
+
+    const txSignature = await program.methods
+      .appendMessage(recipient, message, null, 1, nonce)
+      .accounts({
+        treeConfig: treeConfig,
+        merkleTree: merkleTree.publicKey,
+        treeAuthority: treeAuthority,
+        inbox: inbox,
+        blockList: getBlockListAddress(program.programId, recipient),
+        conversationState: getConversationState(recipient),
+        conversation: getConversationAddress(
+          program.programId,
+          provider.publicKey,
+          recipient
+        ),
+        logWrapper: SPL_NOOP_PROGRAM_ID,
+        compressionProgram: SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
+        globalConfig: globalConfig,
+      })
+      .rpc()
+
+    // The priority is committed into the leaf and logged with the message
+    const messageLog = await getMessageLog(connection, txSignature)
+    const hash = getMessageHashV2(
+      message,
+      provider.publicKey,
+      recipient,
+      nonce,
+      undefined,
+      0,
+      1
+    )
+    assert(hash === Buffer.from(messageLog.leafNode).toString("hex"))
+    assert(messageLog.priority === 1)
+
+    const urgentLog = await getUrgentMessageLog(connection, txSignature)
+    assert(hash === Buffer.from(urgentLog.leafNode).toString("hex"))
+    assert(urgentLog.recipient.equals(recipient))
+
+    const inboxAccount = await program.account.inbox.fetch(inbox)
+    assert(inboxAccount.unreadCount.toNumber() === 1)
+    assert(inboxAccount.urgentCount.toNumber() === 1)
+  })
+
+  it("Mirror Message To Sender Outbox", async () => {
+    const recipient = await openConversation()
+    const message = "for my records"
+
+    // The sender's outbox is an ordinary tree created through the program
+    const outboxTree = Keypair.generate()
+    const [outboxAuthority] = PublicKey.findProgramAddressSync(
+      [outboxTree.publicKey.toBuffer()],
+      program.programId
+    )
+    const allocTreeIx = await createAllocTreeIx(
+      connection,
+      outboxTree.publicKey,
+      wallet.publicKey,
+      { maxDepth: 3, maxBufferSize: 8 },
+      0
+    )
+    const ix = await program.methods
+      .createNoteTree(3, 8, 0)
+      .accounts({
+        forest: forest,
+        treasury: treasury,
+        treeConfig: getTreeConfigAddress(
+          program.programId,
+          outboxTree.publicKey
+        ),
+        merkleTree: outboxTree.publicKey,
+        treeAuthority: outboxAuthority,
+        logWrapper: SPL_NOOP_PROGRAM_ID,
+        compressionProgram: SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
+        globalConfig: globalConfig,
+      })
+      .instruction()
+    const tx = new Transaction().add(allocTreeIx, ix)
+    await sendAndConfirmTransaction(connection, tx, [wallet.payer, outboxTree])
+
+    const txSignature = await program.methods
+      .appendMessage(recipient, message, null, 0, nonce)
+      .accounts({
+        treeConfig: treeConfig,
+        merkleTree: merkleTree.publicKey,
+        treeAuthority: treeAuthority,
+        inbox: getInboxAddress(program.programId, recipient),
+        blockList: getBlockListAddress(program.programId, recipient),
+        conversationState: getConversationState(recipient),
+        conversation: getConversationAddress(
+          program.programId,
+          provider.publicKey,
+          recipient
+        ),
+        logWrapper: SPL_NOOP_PROGRAM_ID,
+        compressionProgram: SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
+        globalConfig: globalConfig,
+      })
+      .remainingAccounts([
+        { pubkey: outboxAuthority, isSigner: false, isWritable: false },
+        { pubkey: outboxTree.publicKey, isSigner: false, isWritable: true },
+      ])
+      .rpc()
+
+    // The same leaf lands in the outbox tree, linked to the delivered message
+    const outboxLog = await getOutboxLog(connection, txSignature)
+    const hash = getMessageHashV2(message, provider.publicKey, recipient, nonce)
+    assert(hash === Buffer.from(outboxLog.leafNode).toString("hex"))
+    assert(
+      resolveNoteId(outboxLog.noteId).merkleTree.equals(outboxTree.publicKey)
+    )
+    assert(
+      resolveNoteId(outboxLog.deliveredId).merkleTree.equals(
+        merkleTree.publicKey
+      )
+    )
+  })
+
+
+    const recipient = Keypair.generate().publicKey
+    await program.methods
+      .requestConversation(recipient)
+      .accounts({
+        conversationState: getConversationState(recipient),
+        globalConfig: globalConfig,
+      })
+      .rpc()
+
+    // The request is still pending, so the recipient has not accepted yet
+    try {
+      await program.methods
+        .appendMessage(recipient, "hello stranger", null, 0, nonce)
+        .accounts({
+          treeConfig: treeConfig,
+          merkleTree: merkleTree.publicKey,
+          treeAuthority: treeAuthority,
+          inbox: getInboxAddress(program.programId, recipient),
+          blockList: getBlockListAddress(program.programId, recipient),
+          conversation: getConversationAddress(
+            program.programId,
+            provider.publicKey,
+            recipient
+          ),
+          conversationState: getConversationState(recipient),
+          logWrapper: SPL_NOOP_PROGRAM_ID,
+          compressionProgram: SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
+          globalConfig: globalConfig,
+        })
+        .rpc()
+      assert.fail("the message should need the recipient's consent")
+    } catch (error) {
+      assert(error.error.errorCode.code === "ConversationNotAccepted")
+    }
+  })
+
+  it("Blocked Sender Cannot Message", async () => {
+    const sender = Keypair.generate()
+    const airdropSignature = await connection.requestAirdrop(
+      sender.publicKey,
+      anchor.web3.LAMPORTS_PER_SOL
+    )
+    await connection.confirmTransaction(airdropSignature)
+
+    const blockList = getBlockListAddress(program.programId, provider.publicKey)
+    await program.methods
+      .blockSender(sender.publicKey)
+      .accounts({ blockList: blockList, globalConfig: globalConfig })
+      .rpc()
+
+    try {
+      await program.methods
+        .appendMessage(provider.publicKey, "let me in", null, 0, nonce)
+        .accounts({
+          sender: sender.publicKey,
+          treeConfig: treeConfig,
+          merkleTree: merkleTree.publicKey,
+          treeAuthority: treeAuthority,
+          inbox: getInboxAddress(program.programId, provider.publicKey),
+          blockList: blockList,
+          conversation: getConversationAddress(
+            program.programId,
+            sender.publicKey,
+            provider.publicKey
+          ),
+          logWrapper: SPL_NOOP_PROGRAM_ID,
+          compressionProgram: SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
+          globalConfig: globalConfig,
+        })
+        .signers([sender])
+        .rpc()
+      assert.fail("a blocked sender should not be able to message")
+    } catch (error) {
+      assert(error.error.errorCode.code === "SenderBlocked")
+    }
+
+    await program.methods
+      .unblockSender(sender.publicKey)
+      .accounts({ blockList: blockList, globalConfig: globalConfig })
+      .rpc()
+    const blockListAccount = await program.account.blockList.fetch(blockList)
+    assert(blockListAccount.blocked.length === 0)
+  })
+
+  it("Append Message To Conversation Tree", async () => {
+    const recipient = await openConversation()
+    const conversationTree = getConversationTreeAddress(
+      program.programId,
+      provider.publicKey,
+      recipient
+    )
+    const chatTree = Keypair.generate()
+    const [chatTreeAuthority] = PublicKey.findProgramAddressSync(
+      [chatTree.publicKey.toBuffer()],
+      program.programId
+    )
+
+    // The tree account is allocated by the client in the same transaction
+    const allocTreeIx = await createAllocTreeIx(
+      connection,
+      chatTree.publicKey,
+      wallet.publicKey,
+      { maxDepth: 14, maxBufferSize: 64 },
+      4
+    )
+    // A canopy of 4 levels leaves 10 nodes in each proof instead of 14
+    const ix = await program.methods
+      .createConversationTree(recipient, 14, 64, 4)
+      .accounts({
+        forest: forest,
+        conversationTree: conversationTree,
+        merkleTree: chatTree.publicKey,
+        treeAuthority: chatTreeAuthority,
+        logWrapper: SPL_NOOP_PROGRAM_ID,
+        compressionProgram: SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
+        globalConfig: globalConfig,
+      })
+      .instruction()
+    const tx = new Transaction().add(allocTreeIx, ix)
+    await sendAndConfirmTransaction(connection, tx, [wallet.payer, chatTree])
+
+    const conversationTreeAccount =
+      await program.account.conversationTree.fetch(conversationTree)
+    assert(conversationTreeAccount.merkleTree.equals(chatTree.publicKey))
+
+    const message = "in our own tree"
+    const txSignature = await program.methods
+      .appendMessage(recipient, message, null, 0, nonce)
+      .accounts({
+        treeConfig: getTreeConfigAddress(program.programId, chatTree.publicKey),
+        merkleTree: chatTree.publicKey,
+        treeAuthority: chatTreeAuthority,
+        inbox: getInboxAddress(program.programId, recipient),
+        blockList: getBlockListAddress(program.programId, recipient),
+        conversationState: getConversationState(recipient),
+        conversation: getConversationAddress(
+          program.programId,
+          provider.publicKey,
+          recipient
+        ),
+        logWrapper: SPL_NOOP_PROGRAM_ID,
+        compressionProgram: SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
+        globalConfig: globalConfig,
+      })
+      .rpc()
+
+    const messageLog = await getMessageLog(connection, txSignature)
+    const noteId = resolveNoteId(messageLog.noteId)
+    assert(noteId.merkleTree.equals(chatTree.publicKey))
+    assert(noteId.leafIndex === 0)
+  })
+
+  it("Append Message To Inbox Tree", async () => {
+    const recipient = await openConversation()
+    const inboxTree = getInboxTreeAddress(program.programId, recipient)
+    const [inboxTreeAuthority] = PublicKey.findProgramAddressSync(
+      [inboxTree.toBuffer()],
+      program.programId
+    )
+    const treeAccounts = {
+      merkleTree: inboxTree,
+      treeAuthority: inboxTreeAuthority,
+      logWrapper: SPL_NOOP_PROGRAM_ID,
+      compressionProgram: SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
+      globalConfig: globalConfig,
+    }
+
+    // The tree account grows by 10KiB per call, one more call than needed is a no-op
+    const tx = new Transaction()
+    for (let i = 0; i < 5; i++) {
+      tx.add(
+        await program.methods
+          .getOrCreateInboxTree(recipient)
+          .accounts(treeAccounts)
+          .instruction()
+      )
+    }
+    await sendAndConfirmTransaction(connection, tx, [wallet.payer])
+
+    const inboxTreeAccount =
+      await ConcurrentMerkleTreeAccount.fromAccountAddress(
+        connection,
+        inboxTree
+      )
+    assert(inboxTreeAccount.getMaxDepth() === 14)
+    assert(inboxTreeAccount.getAuthority().equals(inboxTreeAuthority))
+
+    const txSignature = await program.methods
+      .appendMessage(recipient, "delivered to your inbox tree", null, 0, nonce)
+      .accounts({
+        ...treeAccounts,
+        treeConfig: getTreeConfigAddress(program.programId, inboxTree),
+        inbox: getInboxAddress(program.programId, recipient),
+        blockList: getBlockListAddress(program.programId, recipient),
+        conversationState: getConversationState(recipient),
+        conversation: getConversationAddress(
+          program.programId,
+          provider.publicKey,
+          recipient
+        ),
+      })
+      .rpc()
+
+    const messageLog = await getMessageLog(connection, txSignature)
+    assert(resolveNoteId(messageLog.noteId).merkleTree.equals(inboxTree))
+  })
+
+  it("Append Encrypted Message", async () => {
+    const recipient = await openConversation()
+    // The program only commits to the payload, so any bytes will do
+    const ciphertext = Buffer.from("not really encrypted")
+    const nonce = Buffer.alloc(24, 1)
+    const senderEphemeralPubkey = Buffer.alloc(32, 2)
+    const conversation = getConversationAddress(
+      program.programId,
+      provider.publicKey,
+      recipient
+    )
+
+    const txSignature = await program.methods
+      .appendEncryptedMessage(
+        recipient,
+        ciphertext,
+        Array.from(nonce),
+        Array.from(senderEphemeralPubkey)
+      )
+      .accounts({
+        treeConfig: treeConfig,
+        merkleTree: merkleTree.publicKey,
+        treeAuthority: treeAuthority,
+        inbox: getInboxAddress(program.programId, recipient),
+        blockList: getBlockListAddress(program.programId, recipient),
+        conversationState: getConversationState(recipient),
+        conversation: conversation,
+        logWrapper: SPL_NOOP_PROGRAM_ID,
+        compressionProgram: SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
+        globalConfig: globalConfig,
+      })
+      .rpc()
+
+    const messageLog = await getEncryptedMessageLog(connection, txSignature)
+    const hash = getEncryptedMessageHash(
+      ciphertext,
+      provider.publicKey,
+      nonce,
+      senderEphemeralPubkey
+    )
+
+    assert(hash === Buffer.from(messageLog.leafNode).toString("hex"))
+    assert(messageLog.recipient.equals(recipient))
+    assert(ciphertext.equals(Buffer.from(messageLog.ciphertext)))
+    assert(nonce.equals(Buffer.from(messageLog.nonce)))
+  })
+
+  it("Recipient Unlocks Escrowed Message", async () => {
+    const recipient = Keypair.generate()
+    const airdropSignature = await connection.requestAirdrop(
+      recipient.publicKey,
+      anchor.web3.LAMPORTS_PER_SOL
+    )
+    await connection.confirmTransaction(airdropSignature)
+    // The escrow only checks the leaf against its hashed parts, so any bytes will do
+    const ciphertext = Buffer.from("paid advice")
+    const nonce = Buffer.alloc(24, 3)
+    const senderEphemeralPubkey = Buffer.alloc(32, 4)
+    const leafNode = Buffer.from(
+      getEncryptedMessageHash(
+        ciphertext,
+        provider.publicKey,
+        nonce,
+        senderEphemeralPubkey
+      ),
+      "hex"
+    )
+    const [escrow] = PublicKey.findProgramAddressSync(
+      [Buffer.from("escrow"), leafNode],
+      program.programId
+    )
+    const price = anchor.web3.LAMPORTS_PER_SOL / 100
+    const createEscrow = (ciphertextHash: Buffer) =>
+      program.methods
+        .createMessageEscrow(
+          Array.from(leafNode),
+          Array.from(ciphertextHash),
+          Array.from(nonce),
+          Array.from(senderEphemeralPubkey),
+          recipient.publicKey,
+          new anchor.BN(price)
+        )
+        .accounts({
+          escrow: escrow,
+          merkleTree: merkleTree.publicKey,
+          globalConfig: globalConfig,
+        })
+        .rpc()
+
+    // The leaf must hash from the parts given, with the signer as sender
+    try {
+      await createEscrow(Buffer.from(keccak256.arrayBuffer("other advice")))
+      assert.fail("an escrow should only price the signer's message")
+    } catch (error) {
+      assert(error.error.errorCode.code === "InvalidEscrowLeaf")
+    }
+    await createEscrow(Buffer.from(keccak256.arrayBuffer(ciphertext)))
+
+    // The recipient pays the price into the escrow, once
+    const escrowRent = await connection.getBalance(escrow)
+    const unlock = () =>
+      program.methods
+        .unlockMessage()
+        .accounts({
+          recipient: recipient.publicKey,
+          escrow: escrow,
+          globalConfig: globalConfig,
+        })
+        .signers([recipient])
+        .rpc()
+    await unlock()
+    assert((await connection.getBalance(escrow)) === escrowRent + price)
+    try {
+      await unlock()
+      assert.fail("an escrowed message should only be paid for once")
+    } catch (error) {
+      assert(error.error.errorCode.code === "EscrowAlreadyUnlocked")
+    }
+
+    // The recipient cannot take the price back while the sender can still release the key
+    try {
+      await program.methods
+        .refundMessageEscrow()
+        .accounts({
+          recipient: recipient.publicKey,
+          sender: provider.publicKey,
+          escrow: escrow,
+          globalConfig: globalConfig,
+        })
+        .signers([recipient])
+        .rpc()
+      assert.fail("the refund should wait for the release deadline")
+    } catch (error) {
+      assert(error.error.errorCode.code === "KeyReleaseNotOverdue")
+    }
+
+    // The sender logs the key and takes the price, closing the escrow
+    const sealedKey = Buffer.alloc(32, 5)
+    const senderBefore = await connection.getBalance(provider.publicKey)
+    const txSignature = await program.methods
+      .releaseMessageKey(Array.from(sealedKey))
+      .accounts({
+        escrow: escrow,
+        logWrapper: SPL_NOOP_PROGRAM_ID,
+        globalConfig: globalConfig,
+      })
+      .rpc()
+    const keyReleaseLog = await getKeyReleaseLog(connection, txSignature)
+    assert(leafNode.equals(Buffer.from(keyReleaseLog.leafNode)))
+    assert(keyReleaseLog.recipient.equals(recipient.publicKey))
+    assert(keyReleaseLog.price.toNumber() === price)
+    assert(sealedKey.equals(Buffer.from(keyReleaseLog.sealedKey)))
+    assert((await connection.getAccountInfo(escrow)) === null)
+    assert((await connection.getBalance(provider.publicKey)) > senderBefore)
+  })
+
+  it("Forward Message", async () => {
+    const sender = Keypair.generate()
+    const recipient = await openConversation()
+    const message = "pass this on"
+    const accounts = {
+      treeConfig: treeConfig,
+      merkleTree: merkleTree.publicKey,
+      treeAuthority: treeAuthority,
+      logWrapper: SPL_NOOP_PROGRAM_ID,
+      compressionProgram: SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
+      globalConfig: globalConfig,
+    }
+
+    // The original message, sent to the wallet that forwards it
+    const airdropSignature = await connection.requestAirdrop(
+      sender.publicKey,
+      anchor.web3.LAMPORTS_PER_SOL
+    )
+    await connection.confirmTransaction(airdropSignature)
+    // The wallet allowlists the sender instead of accepting a request
+    await program.methods
+      .setAllowlist([sender.publicKey])
+      .accounts({
+        inbox: getInboxAddress(program.programId, provider.publicKey),
+        globalConfig: globalConfig,
+      })
+      .rpc()
+    const originalSignature = await program.methods
+      .appendMessage(provider.publicKey, message, null, 0, nonce)
+      .accounts({
+        ...accounts,
+        sender: sender.publicKey,
+        inbox: getInboxAddress(program.programId, provider.publicKey),
+        blockList: getBlockListAddress(program.programId, provider.publicKey),
+        conversation: getConversationAddress(
+          program.programId,
+          sender.publicKey,
+          provider.publicKey
+        ),
+      })
+      .signers([sender])
+      .rpc()
+    const originalLog = await getMessageLog(connection, originalSignature)
+    const originalLeaf = Buffer.from(originalLog.leafNode)
+
+    const txSignature = await program.methods
+      .forwardMessage(
+        recipient,
+        Array.from(originalLeaf),
+        sender.publicKey,
+        message
+      )
+      .accounts({
+        ...accounts,
+        inbox: getInboxAddress(program.programId, recipient),
+        blockList: getBlockListAddress(program.programId, recipient),
+        conversationState: getConversationState(recipient),
+        conversation: getConversationAddress(
+          program.programId,
+          provider.publicKey,
+          recipient
+        ),
+      })
+      .rpc()
+
+    const forwardedLog = await getForwardedMessageLog(connection, txSignature)
+    const hash = getForwardedMessageHash(
+      message,
+      provider.publicKey,
+      originalLeaf,
+      sender.publicKey
+    )
+
+    assert(hash === Buffer.from(forwardedLog.leafNode).toString("hex"))
+    // The provenance checks out against the original leaf
+    assert(forwardedLog.originalSender.equals(sender.publicKey))
+    assert(
+      getMessageHashV2(
+        message,
+        forwardedLog.originalSender,
+        provider.publicKey,
+        nonce
+      ) ===
+        Buffer.from(forwardedLog.originalLeaf).toString("hex")
+    )
+
+    // Forwarded text is displayed like any other message
+    const cases = [
+      ["bell\u0007", "ControlCharacter"],
+      ["pay\u200Bpal", "InvisibleCharacter"],
+    ]
+    for (const [forwarded, code] of cases) {
+      try {
+        await program.methods
+          .forwardMessage(
+            recipient,
+            Array.from(originalLeaf),
+            sender.publicKey,
+            forwarded
+          )
+          .accounts({
+            ...accounts,
+            inbox: getInboxAddress(program.programId, recipient),
+            blockList: getBlockListAddress(program.programId, recipient),
+            conversationState: getConversationState(recipient),
+            conversation: getConversationAddress(
+              program.programId,
+              provider.publicKey,
+              recipient
+            ),
+          })
+          .rpc()
+        assert.fail(`${code} text should not be forwarded`)
+      } catch (error) {
+        assert(error.error.errorCode.code === code)
+      }
+    }
+  })
+
+  it("Sponsor Pays For A New Conversation", async () => {
+    // The sender holds no lamports, the app backend pays the conversation rent
+    const sender = Keypair.generate()
+    await program.methods
+      .setAllowlist([sender.publicKey])
+      .accounts({
+        inbox: getInboxAddress(program.programId, provider.publicKey),
+        globalConfig: globalConfig,
+      })
+      .rpc()
+    const conversation = getConversationAddress(
+      program.programId,
+      sender.publicKey,
+      provider.publicKey
+    )
+    await program.methods
+      .appendMessage(provider.publicKey, "sponsored", null, 0, nonce)
+      .accounts({
+        sender: sender.publicKey,
+        payer: provider.publicKey,
+        treeConfig: treeConfig,
+        merkleTree: merkleTree.publicKey,
+        treeAuthority: treeAuthority,
+        inbox: getInboxAddress(program.programId, provider.publicKey),
+        blockList: getBlockListAddress(program.programId, provider.publicKey),
+        conversation: conversation,
+        logWrapper: SPL_NOOP_PROGRAM_ID,
+        compressionProgram: SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
+        globalConfig: globalConfig,
+      })
+      .signers([sender])
+      .rpc()
+    const conversationAccount = await program.account.conversation.fetch(
+      conversation
+    )
+    assert(conversationAccount.messageCount.toNumber() === 1)
+    assert((await connection.getBalance(sender.publicKey)) === 0)
+  })
+
+  it("Update Message", async () => {
+    const recipient = await openConversation()
+    const message = "see you at 5"
+    const editedMessage = "see you at 6"
+    const accounts = {
+      treeConfig: treeConfig,
+      merkleTree: merkleTree.publicKey,
+      treeAuthority: treeAuthority,
+      logWrapper: SPL_NOOP_PROGRAM_ID,
+      compressionProgram: SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
+      globalConfig: globalConfig,
+    }
+
+    await program.methods
+      .appendMessage(recipient, message, null, 0, nonce)
+      .accounts({
+        ...accounts,
+        inbox: getInboxAddress(program.programId, recipient),
+        blockList: getBlockListAddress(program.programId, recipient),
+        conversationState: getConversationState(recipient),
+        conversation: getConversationAddress(
+          program.programId,
+          provider.publicKey,
+          recipient
+        ),
+      })
+      .rpc()
+
+    const merkleTreeAccount =
+      await ConcurrentMerkleTreeAccount.fromAccountAddress(
+        connection,
+        merkleTree.publicKey
+      )
+    const root = Array.from(merkleTreeAccount.getCurrentRoot())
+    const index = merkleTreeAccount.tree.rightMostPath.index - 1
+
+    // An edit keeping the message as it is fails instead of silently succeeding
+    try {
+      await program.methods
+        .updateMessageV2(
+          index,
+          root,
+          recipient,
+          message,
+          message,
+          null,
+          0,
+          0,
+          nonce
+        )
+        .accounts(accounts)
+        .rpc()
+      assert.fail("an edit without changes should be refused")
+    } catch (error) {
+      assert(error.error.errorCode.code === "NoChange")
+    }
+
+    const txSignature = await program.methods
+      .updateMessageV2(
+        index,
+        root,
+        recipient,
+        message,
+        editedMessage,
+        null,
+        0,
+        0,
+        nonce
+      )
+      .accounts(accounts)
+      .rpc()
+
+    const editLog = await getEditLog(connection, txSignature)
+    const oldHash = getMessageHashV2(
+      message,
+      provider.publicKey,
+      recipient,
+      nonce
+    )
+    const newHash = getMessageHashV2(
+      editedMessage,
+      provider.publicKey,
+      recipient,
+      nonce,
+      undefined,
+      1
+    )
+
+    assert(oldHash === Buffer.from(editLog.oldLeaf).toString("hex"))
+    assert(newHash === Buffer.from(editLog.newLeaf).toString("hex"))
+    assert(editLog.editor.equals(provider.publicKey))
+    assert(editLog.editCount === 1)
+  })
+
+  it("Acknowledge Message", async () => {
+    const recipient = Keypair.generate()
+    await openConversation(recipient)
+    const accounts = {
+      treeConfig: treeConfig,
+      merkleTree: merkleTree.publicKey,
+      treeAuthority: treeAuthority,
+      logWrapper: SPL_NOOP_PROGRAM_ID,
+      compressionProgram: SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
+      globalConfig: globalConfig,
+    }
+
+    const message = "terms attached, please accept"
+    const messageSignature = await program.methods
+      .appendMessage(recipient.publicKey, message, null, 0, nonce)
+      .accounts({
+        ...accounts,
+        inbox: getInboxAddress(program.programId, recipient.publicKey),
+        blockList: getBlockListAddress(program.programId, recipient.publicKey),
+        conversationState: getConversationState(recipient.publicKey),
+        conversation: getConversationAddress(
+          program.programId,
+          provider.publicKey,
+          recipient.publicKey
+        ),
+      })
+      .rpc()
+    const messageLog = await getMessageLog(connection, messageSignature)
+    const originalLeaf = Buffer.from(messageLog.leafNode)
+
+    const txSignature = await program.methods
+      .acknowledgeMessage(Array.from(originalLeaf))
+      .accounts({ ...accounts, recipient: recipient.publicKey })
+      .signers([recipient])
+      .rpc()
+
+    // The acknowledgement is its own leaf, committing to the original and the recipient
+    const acknowledgementLog = await getAcknowledgementLog(
+      connection,
+      txSignature
+    )
+    const hash = getAcknowledgementHash(originalLeaf, recipient.publicKey)
+    assert(hash === Buffer.from(acknowledgementLog.leafNode).toString("hex"))
+    assert(originalLeaf.equals(Buffer.from(acknowledgementLog.originalLeaf)))
+    assert(acknowledgementLog.recipient.equals(recipient.publicKey))
+  })
+
+  it("Expiring Message Cannot Be Burned Early", async () => {
+    const recipient = await openConversation()
+    const message = "this message will self-destruct"
+    const expiresAt = new anchor.BN(Math.floor(Date.now() / 1000) + 3600)
+
+    const txSignature = await program.methods
+      .appendMessageWithTtl(recipient, message, expiresAt)
+      .accounts({
+        treeConfig: treeConfig,
+        merkleTree: merkleTree.publicKey,
+        treeAuthority: treeAuthority,
+        inbox: getInboxAddress(program.programId, recipient),
+        blockList: getBlockListAddress(program.programId, recipient),
+        conversationState: getConversationState(recipient),
+        conversation: getConversationAddress(
+          program.programId,
+          provider.publicKey,
+          recipient
+        ),
+        logWrapper: SPL_NOOP_PROGRAM_ID,
+        compressionProgram: SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
+        globalConfig: globalConfig,
+      })
+      .rpc()
+
+    const messageLog = await getMessageLog(connection, txSignature)
+    const hash = getTtlMessageHash(message, provider.publicKey, expiresAt)
+
+    assert(hash === Buffer.from(messageLog.leafNode).toString("hex"))
+    assert(messageLog.expiresAt.eq(expiresAt))
+
+    const merkleTreeAccount =
+      await ConcurrentMerkleTreeAccount.fromAccountAddress(
+        connection,
+        merkleTree.publicKey
+      )
+    const root = Array.from(merkleTreeAccount.getCurrentRoot())
+    const index = merkleTreeAccount.tree.rightMostPath.index - 1
+
+    try {
+      await program.methods
+        .burnExpiredMessage(root, message, provider.publicKey, expiresAt, index)
+        .accounts({
+          treeConfig: treeConfig,
+          merkleTree: merkleTree.publicKey,
+          treeAuthority: treeAuthority,
+          logWrapper: SPL_NOOP_PROGRAM_ID,
+          compressionProgram: SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
+          globalConfig: globalConfig,
+        })
+        .rpc()
+      assert.fail("the message should not be burnable before it expires")
+    } catch (error) {
+      assert(error.error.errorCode.code === "MessageNotExpired")
+    }
+  })
+
+  it("Emit Typing Indicator", async () => {
+    const conversation = getConversationAddress(
+      program.programId,
+      provider.publicKey,
+      Keypair.generate().publicKey
+    )
+
+    // Typing is only logged, no account is written
+    const txSignature = await program.methods
+      .emitTyping(conversation)
+      .accounts({
+        logWrapper: SPL_NOOP_PROGRAM_ID,
+        globalConfig: globalConfig,
+      })
+      .rpc()
+
+    const typingLog = await getTypingLog(connection, txSignature)
+    assert(typingLog.user.equals(provider.publicKey))
+    assert(typingLog.conversation.equals(conversation))
+  })
+
+  it("Append Group Message", async () => {
+    const members = [
+      wallet.publicKey,
+      Keypair.generate().publicKey,
+      Keypair.generate().publicKey,
+    ]
+    const groupId = new anchor.BN(0)
+    const [group] = PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("group"),
+        wallet.publicKey.toBuffer(),
+        groupId.toArrayLike(Buffer, "le", 8),
+      ],
+      program.programId
+    )
+
+    await program.methods
+      .createGroup(groupId, members)
+      .accounts({ group: group, globalConfig: globalConfig })
+      .rpc()
+
+    const message = "hello group"
+    const txSignature = await program.methods
+      .appendGroupMessage(message)
+      .accounts({
+        group: group,
+        treeConfig: treeConfig,
+        merkleTree: merkleTree.publicKey,
+        treeAuthority: treeAuthority,
+        logWrapper: SPL_NOOP_PROGRAM_ID,
+        compressionProgram: SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
+        globalConfig: globalConfig,
+      })
+      .rpc()
+
+    const messageLog = await getGroupMessageLog(connection, txSignature)
+    const recipients = members.slice(1)
+    const hash = getGroupMessageHash(message, wallet.publicKey, recipients)
+
+    assert(hash === Buffer.from(messageLog.leafNode).toString("hex"))
+    assert(messageLog.group.equals(group))
+    assert(messageLog.recipients.length === recipients.length)
+    assert(message === messageLog.message)
+  })
+
+  it("Update Conversation Meta", async () => {
+    const members = [wallet.publicKey, Keypair.generate().publicKey]
+    const groupId = new anchor.BN(1)
+    const [group] = PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("group"),
+        wallet.publicKey.toBuffer(),
+        groupId.toArrayLike(Buffer, "le", 8),
+      ],
+      program.programId
+    )
+    const [conversationMeta] = PublicKey.findProgramAddressSync(
+      [Buffer.from("conv_meta"), group.toBuffer()],
+      program.programId
+    )
+
+    await program.methods
+      .createGroup(groupId, members)
+      .accounts({ group: group, globalConfig: globalConfig })
+      .rpc()
+    await program.methods
+      .createConversationMeta("weekend plans", members)
+      .accounts({
+        group: group,
+        conversationMeta: conversationMeta,
+        globalConfig: globalConfig,
+      })
+      .rpc()
+
+    // Rename the chat, mute the second participant and hand over the admin
+    const newAdmin = members[1]
+    await program.methods
+      .updateConversationMeta("weekday plans", members, 0b10, newAdmin)
+      .accounts({
+        conversationMeta: conversationMeta,
+        globalConfig: globalConfig,
+      })
+      .rpc()
+
+    const meta = await program.account.conversationMeta.fetch(conversationMeta)
+    assert(meta.title === "weekday plans")
+    assert(meta.muted === 0b10)
+    assert(meta.admin.equals(newAdmin))
+
+    // The old admin can no longer update the metadata
+    try {
+      await program.methods
+        .updateConversationMeta(null, members, 0, wallet.publicKey)
+        .accounts({
+          conversationMeta: conversationMeta,
+          globalConfig: globalConfig,
+        })
+        .rpc()
+      assert.fail("only the metadata admin should be able to update it")
+    } catch (error) {
+      assert(error.error.errorCode.code === "Unauthorized")
+    }
+  })
+
+  it("Broadcast On Channel", async () => {
+    const channelTree = Keypair.generate()
+    const [channel] = PublicKey.findProgramAddressSync(
+      [Buffer.from("channel"), channelTree.publicKey.toBuffer()],
+      program.programId
+    )
+
+    const allocTreeIx = await createAllocTreeIx(
+      connection,
+      channelTree.publicKey,
+      wallet.publicKey,
+      { maxDepth: 3, maxBufferSize: 8 },
+      0
+    )
+    const ix = await program.methods
+      .createChannelTree(3, 8)
+      .accounts({
+        forest: forest,
+        channel: channel,
+        merkleTree: channelTree.publicKey,
+        logWrapper: SPL_NOOP_PROGRAM_ID,
+        compressionProgram: SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
+        globalConfig: globalConfig,
+      })
+      .instruction()
+    const tx = new Transaction().add(allocTreeIx, ix)
+    await sendAndConfirmTransaction(connection, tx, [wallet.payer, channelTree])
+
+    const [subscription] = PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("subscription"),
+        channel.toBuffer(),
+        wallet.publicKey.toBuffer(),
+      ],
+      program.programId
+    )
+    await program.methods
+      .subscribe()
+      .accounts({
+        channel: channel,
+        subscription: subscription,
+        globalConfig: globalConfig,
+      })
+      .rpc()
+
+    const message = "announcement"
+    const txSignature = await program.methods
+      .broadcast(message)
+      .accounts({
+        channel: channel,
+        merkleTree: channelTree.publicKey,
+        logWrapper: SPL_NOOP_PROGRAM_ID,
+        compressionProgram: SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
+        globalConfig: globalConfig,
+      })
+      .rpc()
+
+    const broadcastLog = await getBroadcastLog(connection, txSignature)
+    assert(broadcastLog.channel.equals(channel))
+    assert(message === broadcastLog.message)
+
+    const channelAccount = await program.account.channel.fetch(channel)
+    assert(channelAccount.subscriberCount.toNumber() === 1)
+    assert(channelAccount.messageCount.toNumber() === 1)
+  })
+
+  it("Auditor Can Audit Leaf", async () => {
+    const [auditorRole] = PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("auditor"),
+        merkleTree.publicKey.toBuffer(),
+        wallet.publicKey.toBuffer(),
+      ],
+      program.programId
+    )
+
+    await program.methods
+      .grantAuditor(wallet.publicKey)
+      .accounts({
+        globalConfig: globalConfig,
+        merkleTree: merkleTree.publicKey,
+        auditorRole: auditorRole,
+      })
+      .rpc()
+
+    const merkleTreeAccount =
+      await ConcurrentMerkleTreeAccount.fromAccountAddress(
+        connection,
+        merkleTree.publicKey
+      )
+    const root = Array.from(merkleTreeAccount.getCurrentRoot())
+    // The first note was locked by an earlier test
+    const leafNode = Array.from(
+      Buffer.from(
+        getHash(updatedNote, provider.publicKey, NoteStatus.Locked),
+        "hex"
+      )
+    )
+
+    await program.methods
+      .auditLeaf(root, leafNode, 0)
+      .accounts({
+        auditorRole: auditorRole,
+        merkleTree: merkleTree.publicKey,
+        logWrapper: SPL_NOOP_PROGRAM_ID,
+        compressionProgram: SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
+        globalConfig: globalConfig,
+      })
+      .rpc()
+
+    await program.methods
+      .revokeAuditor()
+      .accounts({ globalConfig: globalConfig, auditorRole: auditorRole })
+      .rpc()
+    assert((await connection.getAccountInfo(auditorRole)) === null)
+  })
+
+  it("Issue And Revoke Certificate", async () => {
+    const certificateTree = Keypair.generate()
+    const [issuer] = PublicKey.findProgramAddressSync(
+      [Buffer.from("issuer"), certificateTree.publicKey.toBuffer()],
+      program.programId
+    )
+    const accounts = {
+      issuer: issuer,
+      merkleTree: certificateTree.publicKey,
+      logWrapper: SPL_NOOP_PROGRAM_ID,
+      compressionProgram: SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
+      globalConfig: globalConfig,
+    }
+
+    const allocTreeIx = await createAllocTreeIx(
+      connection,
+      certificateTree.publicKey,
+      wallet.publicKey,
+      { maxDepth: 3, maxBufferSize: 8 },
+      0
+    )
+    const ix = await program.methods
+      .createCertificateTree(3, 8)
+      .accounts({ ...accounts, forest: forest })
+      .instruction()
+    const tx = new Transaction().add(allocTreeIx, ix)
+    await sendAndConfirmTransaction(connection, tx, [
+      wallet.payer,
+      certificateTree,
+    ])
+
+    const subject = Keypair.generate().publicKey
+    // The claims themselves stay off-chain, only their hash is committed
+    const claimsHash = Buffer.alloc(32, 7)
+    const expiry = new anchor.BN(Math.floor(Date.now() / 1000) + 3600)
+    const txSignature = await program.methods
+      .issueCertificate(subject, Array.from(claimsHash), expiry)
+      .accounts(accounts)
+      .rpc()
+
+    const certificateLog = await getCertificateLog(connection, txSignature)
+    const hash = getCertificateHash(subject, claimsHash, expiry)
+
+    assert(hash === Buffer.from(certificateLog.leafNode).toString("hex"))
+    assert(certificateLog.issuer.equals(issuer))
+    assert(certificateLog.subject.equals(subject))
+
+    const checkCertificate = async () => {
+      const merkleTreeAccount =
+        await ConcurrentMerkleTreeAccount.fromAccountAddress(
+          connection,
+          certificateTree.publicKey
+        )
+      const root = Array.from(merkleTreeAccount.getCurrentRoot())
+      await program.methods
+        .checkCertificate(root, subject, Array.from(claimsHash), expiry, 0)
+        .accounts(accounts)
+        .rpc()
+      return root
+    }
+
+    // The certificate checks out until it is revoked
+    const root = await checkCertificate()
+    await program.methods
+      .revokeCertificate(root, subject, Array.from(claimsHash), expiry, 0)
+      .accounts(accounts)
+      .rpc()
+    const issuerAccount = await program.account.certificateIssuer.fetch(issuer)
+    assert(issuerAccount.revokedCount.toNumber() === 1)
+
+    let revoked = false
+    try {
+      await checkCertificate()
+    } catch (error) {
+      revoked = true
+    }
+    assert(revoked, "a revoked certificate should not check out")
+  })
+
+  it("Keeper Checkpoints Within Its SLA", async () => {
+    const [keeperBond] = PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("keeper"),
+        merkleTree.publicKey.toBuffer(),
+        wallet.publicKey.toBuffer(),
+      ],
+      program.programId
+    )
+    const bond = new anchor.BN(anchor.web3.LAMPORTS_PER_SOL / 100)
+
+    await program.methods
+      .registerKeeper(bond, new anchor.BN(1000))
+      .accounts({
+        merkleTree: merkleTree.publicKey,
+        keeperBond: keeperBond,
+        globalConfig: globalConfig,
+      })
+      .rpc()
+    await program.methods
+      .keeperCheckpoint()
+      .accounts({
+        merkleTree: merkleTree.publicKey,
+        keeperBond: keeperBond,
+        logWrapper: SPL_NOOP_PROGRAM_ID,
+        globalConfig: globalConfig,
+      })
+      .rpc()
+
+    // The checkpoint records the tree's current root
+    const merkleTreeAccount =
+      await ConcurrentMerkleTreeAccount.fromAccountAddress(
+        connection,
+        merkleTree.publicKey
+      )
+    const keeperBondAccount = await program.account.keeperBond.fetch(
+      keeperBond
+    )
+    assert(
+      Buffer.from(keeperBondAccount.lastRoot).equals(
+        merkleTreeAccount.getCurrentRoot()
+      )
+    )
+
+    // A keeper within its SLA cannot be slashed
+    try {
+      await program.methods
+        .slashKeeper()
+        .accounts({ keeperBond: keeperBond, globalConfig: globalConfig })
+        .rpc()
+      assert.fail("a keeper within its SLA should not be slashed")
+    } catch (error) {
+      assert(error.error.errorCode.code === "SlaNotMissed")
+    }
+
+    await program.methods
+      .deregisterKeeper()
+      .accounts({ keeperBond: keeperBond, globalConfig: globalConfig })
+      .rpc()
+    assert((await connection.getAccountInfo(keeperBond)) === null)
+  })
+
+  it("Sender Nonce Is Only Used Once", async () => {
+    const [senderNonce] = PublicKey.findProgramAddressSync(
+      [Buffer.from("sender_nonce"), provider.publicKey.toBuffer()],
+      program.programId
+    )
+    await program.methods
+      .createSenderNonce()
+      .accounts({ senderNonce: senderNonce, globalConfig: globalConfig })
+      .rpc()
+
+    // A message to self on the private tree, which the provider can write to
+    const accounts = {
+      ...privateTree,
+      inbox: getInboxAddress(program.programId, provider.publicKey),
+      blockList: getBlockListAddress(program.programId, provider.publicKey),
+      conversationState: null,
+      conversation: getConversationAddress(
+        program.programId,
+        provider.publicKey,
+        provider.publicKey
+      ),
+      senderNonce: senderNonce,
+    }
+    const firstNonce = new anchor.BN(0)
+    const txSignature = await program.methods
+      .appendMessage(provider.publicKey, "sent once", null, 0, firstNonce)
+      .accounts(accounts)
+      .rpc()
+    const messageLog = await getMessageLog(connection, txSignature)
+    assert(messageLog.nonce.eq(firstNonce))
+
+    // Submitting the same message again reuses a consumed nonce
+    try {
+      await program.methods
+        .appendMessage(provider.publicKey, "sent once", null, 0, firstNonce)
+        .accounts(accounts)
+        .rpc()
+      assert.fail("a consumed nonce should be refused")
+    } catch (error) {
+      assert(error.error.errorCode.code === "InvalidNonce")
+    }
+    const senderNonceAccount = await program.account.senderNonce.fetch(
+      senderNonce
+    )
+    assert(senderNonceAccount.nextNonce.toNumber() === 1)
+  })
+
+  it("Paused Program Only Allows Reads", async () => {
+    const [keeperBond] = PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("keeper"),
+        merkleTree.publicKey.toBuffer(),
+        wallet.publicKey.toBuffer(),
+      ],
+      program.programId
+    )
+    const keeperAccounts = {
+      merkleTree: merkleTree.publicKey,
+      keeperBond: keeperBond,
+      globalConfig: globalConfig,
+    }
+    await program.methods
+      .registerKeeper(
+        new anchor.BN(anchor.web3.LAMPORTS_PER_SOL / 100),
+        new anchor.BN(1000)
+      )
+      .accounts(keeperAccounts)
+      .rpc()
+
+    await program.methods
+      .pause()
+      .accounts({ globalConfig: globalConfig })
+      .rpc()
+
+    try {
+      await program.methods
+        .markRead(Array(32).fill(0))
+        .accounts({
+          inbox: getInboxAddress(program.programId, provider.publicKey),
+          logWrapper: SPL_NOOP_PROGRAM_ID,
+          globalConfig: globalConfig,
+        })
+        .rpc()
+      assert.fail("mark_read should be paused")
+    } catch (error) {
+      assert(error.error.errorCode.code === "ProgramPaused")
+    }
+
+    // Reading a tree still works while paused
+    const capacity = await program.methods
+      .getTreeCapacity()
+      .accounts({
+        merkleTree: merkleTree.publicKey,
+        globalConfig: globalConfig,
+      })
+      .view()
+    assert(capacity.toNumber() >= 0)
+
+    // Keepers keep checkpointing, a pause must not make them miss their SLA
+    await program.methods
+      .keeperCheckpoint()
+      .accounts({ ...keeperAccounts, logWrapper: SPL_NOOP_PROGRAM_ID })
+      .rpc()
+
+    await program.methods
+      .unpause()
+      .accounts({ globalConfig: globalConfig })
+      .rpc()
+    await program.methods
+      .deregisterKeeper()
+      .accounts({ keeperBond: keeperBond, globalConfig: globalConfig })
+      .rpc()
+  })
+
+  it("Disabled Instruction Fails", async () => {
+    // Every instruction except mark_read (bit 4)
+    const allInstructions = new anchor.BN(1).shln(128).subn(1)
+    const withoutMarkRead = allInstructions.xor(new anchor.BN(1).shln(4))
+
+    await program.methods
+      .setEnabledInstructions(withoutMarkRead)
+      .accounts({ globalConfig: globalConfig })
+      .rpc()
+
+    try {
+      await program.methods
+        .markRead(Array(32).fill(0))
+        .accounts({
+          inbox: getInboxAddress(program.programId, provider.publicKey),
+          logWrapper: SPL_NOOP_PROGRAM_ID,
+          globalConfig: globalConfig,
+        })
+        .rpc()
+      assert.fail("mark_read should be disabled")
+    } catch (error) {
+      assert(error.error.errorCode.code === "InstructionDisabled")
+    }
+
+    await program.methods
+      .setEnabledInstructions(allInstructions)
+      .accounts({ globalConfig: globalConfig })
+      .rpc()
   })
 })
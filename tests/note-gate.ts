@@ -0,0 +1,161 @@
+import * as anchor from "@coral-xyz/anchor"
+import { Program } from "@coral-xyz/anchor"
+import { CompressedNotes } from "../target/types/compressed_notes"
+import { NoteGate } from "../target/types/note_gate"
+import {
+  Keypair,
+  Transaction,
+  PublicKey,
+  sendAndConfirmTransaction,
+  Connection,
+} from "@solana/web3.js"
+import {
+  createAllocTreeIx,
+  SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
+  SPL_NOOP_PROGRAM_ID,
+  ConcurrentMerkleTreeAccount,
+} from "@solana/spl-account-compression"
+import { keccak256 } from "js-sha3"
+import {
+  getHash,
+  getNoteLog,
+  getForestAddress,
+  getTreeConfigAddress,
+  NoteStatus,
+} from "./utils"
+import { assert } from "chai"
+
+// Runs after the compressed-notes suite, which initializes the global config
+describe("note-gate", () => {
+  const provider = anchor.AnchorProvider.env()
+  anchor.setProvider(provider)
+  const connection = new Connection(
+    provider.connection.rpcEndpoint,
+    "confirmed"
+  )
+
+  const wallet = provider.wallet as anchor.Wallet
+  const notes = anchor.workspace.CompressedNotes as Program<CompressedNotes>
+  const program = anchor.workspace.NoteGate as Program<NoteGate>
+
+  const merkleTree = Keypair.generate()
+  const [treeAuthority] = PublicKey.findProgramAddressSync(
+    [merkleTree.publicKey.toBuffer()],
+    notes.programId
+  )
+  const treeConfig = getTreeConfigAddress(notes.programId, merkleTree.publicKey)
+  const forest = getForestAddress(notes.programId, provider.publicKey)
+  const [globalConfig] = PublicKey.findProgramAddressSync(
+    [Buffer.from("global_config")],
+    notes.programId
+  )
+  const [treasury] = PublicKey.findProgramAddressSync(
+    [Buffer.from("treasury")],
+    notes.programId
+  )
+
+  // The pda the example program posts notes as
+  const [poster] = PublicKey.findProgramAddressSync(
+    [Buffer.from("poster")],
+    program.programId
+  )
+  const posterNote = "posted by a program"
+
+  it("Post Note From PDA", async () => {
+    const allocTreeIx = await createAllocTreeIx(
+      connection,
+      merkleTree.publicKey,
+      wallet.publicKey,
+      { maxDepth: 3, maxBufferSize: 8 },
+      0
+    )
+    const ix = await notes.methods
+      .createNoteTree(3, 8, 0)
+      .accounts({
+        forest: forest,
+        treasury: treasury,
+        treeConfig: treeConfig,
+        merkleTree: merkleTree.publicKey,
+        treeAuthority: treeAuthority,
+        logWrapper: SPL_NOOP_PROGRAM_ID,
+        compressionProgram: SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
+        globalConfig: globalConfig,
+      })
+      .instruction()
+    const tx = new Transaction().add(allocTreeIx, ix)
+    await sendAndConfirmTransaction(connection, tx, [wallet.payer, merkleTree])
+
+    const txSignature = await program.methods
+      .postNote(posterNote)
+      .accounts({
+        poster: poster,
+        treeAuthority: treeAuthority,
+        treeConfig: treeConfig,
+        merkleTree: merkleTree.publicKey,
+        logWrapper: SPL_NOOP_PROGRAM_ID,
+        compressionProgram: SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
+        globalConfig: globalConfig,
+        notesProgram: notes.programId,
+      })
+      .rpc()
+
+    // The note is owned by the poster pda, which signed through the cpi
+    const noteLog = await getNoteLog(connection, txSignature)
+    assert(noteLog.owner.equals(poster))
+  })
+
+  it("Claim Pass With Owned Note", async () => {
+    const note = "proof of membership"
+    await notes.methods
+      .appendNote(note)
+      .accounts({
+        treeConfig: treeConfig,
+        merkleTree: merkleTree.publicKey,
+        treeAuthority: treeAuthority,
+        logWrapper: SPL_NOOP_PROGRAM_ID,
+        compressionProgram: SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
+        globalConfig: globalConfig,
+      })
+      .rpc()
+
+    // The note sits at index 1, its proof is the poster's leaf then empty subtrees
+    const leaf = getHash(posterNote, poster, NoteStatus.Published)
+    const emptyLevel1 = keccak256(Buffer.alloc(64))
+    const emptyLevel2 = keccak256(Buffer.from(emptyLevel1.repeat(2), "hex"))
+    const proof = [leaf, emptyLevel1, emptyLevel2].map((node) => ({
+      pubkey: new PublicKey(Buffer.from(node, "hex")),
+      isSigner: false,
+      isWritable: false,
+    }))
+
+    const merkleTreeAccount =
+      await ConcurrentMerkleTreeAccount.fromAccountAddress(
+        connection,
+        merkleTree.publicKey
+      )
+    const root = Array.from(merkleTreeAccount.getCurrentRoot())
+
+    const [pass] = PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("pass"),
+        merkleTree.publicKey.toBuffer(),
+        provider.publicKey.toBuffer(),
+      ],
+      program.programId
+    )
+    await program.methods
+      .claimPass(root, note, { published: {} }, 1)
+      .accounts({
+        pass: pass,
+        treeAuthority: treeAuthority,
+        merkleTree: merkleTree.publicKey,
+        compressionProgram: SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
+      })
+      .remainingAccounts(proof)
+      .rpc()
+
+    const passAccount = await program.account.pass.fetch(pass)
+    assert(passAccount.owner.equals(provider.publicKey))
+    assert(passAccount.index === 1)
+  })
+})
@@ -2,46 +2,1142 @@ import {
   SPL_NOOP_PROGRAM_ID,
   deserializeApplicationDataEvent,
 } from "@solana/spl-account-compression"
-import { Connection, PublicKey } from "@solana/web3.js"
+import {
+  Connection,
+  Keypair,
+  PublicKey,
+  SystemProgram,
+  Transaction,
+  TransactionInstruction,
+  sendAndConfirmTransaction,
+} from "@solana/web3.js"
+import { BN } from "@coral-xyz/anchor"
 import { bs58 } from "@coral-xyz/anchor/dist/cjs/utils/bytes"
 import { deserialize } from "borsh"
 import { keccak256 } from "js-sha3"
 
-class NoteLog {
+// Note statuses in the order of the program's NoteStatus enum
+export const NoteStatus = {
+  Draft: 0,
+  Published: 1,
+  Edited: 2,
+  Locked: 3,
+  Deleted: 4,
+  Redacted: 5,
+}
+
+// Leaf layout kinds of the program's leaf_domain module
+export const LeafDomain = {
+  Certificate: 3,
+  OrgNote: 4,
+  DirectMessage: 5,
+  Acknowledgement: 6,
+  SharedNote: 7,
+  AttributedNote: 8,
+  TtlMessage: 9,
+  EditedMessage: 10,
+  EncryptedMessage: 11,
+  ForwardedMessage: 12,
+  GroupMessage: 13,
+  RevokedCertificate: 14,
+  Takedown: 15,
+}
+
+// The two bytes a leaf of the given layout hashes first
+export function getLeafDomainTag(kind: number) {
+  return Buffer.from([0xff, kind])
+}
+
+class NoteLog {
+  leafNode: Uint8Array
+  noteId: string
+  owner: PublicKey
+  note: string
+  status: number
+
+  constructor(properties: {
+    leafNode: Uint8Array
+    noteId: Uint8Array
+    owner: Uint8Array
+    note: string
+    status: number
+  }) {
+    this.leafNode = properties.leafNode
+    this.noteId = bs58.encode(properties.noteId)
+    this.owner = new PublicKey(properties.owner)
+    this.note = properties.note
+    this.status = properties.status
+  }
+}
+
+// A map that describes the Note structure for Borsh deserialization
+const NoteLogBorshSchema = new Map([
+  [
+    NoteLog,
+    {
+      kind: "struct",
+      fields: [
+        ["leafNode", [32]], // Array of 32 `u8`
+        ["noteId", [38]], // NoteId
+        ["owner", [32]], // Pubkey
+        ["note", "string"],
+        ["status", "u8"], // NoteStatus
+      ],
+    },
+  ],
+])
+
+class MessageLog {
+  leafNode: Uint8Array
+  noteId: string
+  sender: PublicKey
+  recipient: PublicKey
+  message: string
+  parentLeaf: Uint8Array | null
+  expiresAt: BN | null
+  priority: number
+  nonce: BN | null
+
+  constructor(properties: {
+    leafNode: Uint8Array
+    noteId: Uint8Array
+    sender: Uint8Array
+    recipient: Uint8Array
+    message: string
+    parentLeaf: Uint8Array | null
+    expiresAt: BN | null
+    priority: number
+    nonce: BN | null
+  }) {
+    this.leafNode = properties.leafNode
+    this.noteId = bs58.encode(properties.noteId)
+    this.sender = new PublicKey(properties.sender)
+    this.recipient = new PublicKey(properties.recipient)
+    this.message = properties.message
+    this.parentLeaf = properties.parentLeaf ?? null
+    this.expiresAt = properties.expiresAt ?? null
+    this.priority = properties.priority
+    this.nonce = properties.nonce ?? null
+  }
+}
+
+const MessageLogBorshSchema = new Map([
+  [
+    MessageLog,
+    {
+      kind: "struct",
+      fields: [
+        ["leafNode", [32]], // Array of 32 `u8`
+        ["noteId", [38]], // NoteId
+        ["sender", [32]], // Pubkey
+        ["recipient", [32]], // Pubkey
+        ["message", "string"],
+        ["parentLeaf", { kind: "option", type: [32] }], // Option of 32 `u8`
+        ["expiresAt", { kind: "option", type: "u64" }], // Option of `i64`, always positive
+        ["priority", "u8"],
+        ["nonce", { kind: "option", type: "u64" }],
+      ],
+    },
+  ],
+])
+
+class UrgentMessageLog {
+  leafNode: Uint8Array
+  noteId: string
+  sender: PublicKey
+  recipient: PublicKey
+  priority: number
+
+  constructor(properties: {
+    leafNode: Uint8Array
+    noteId: Uint8Array
+    sender: Uint8Array
+    recipient: Uint8Array
+    priority: number
+  }) {
+    this.leafNode = properties.leafNode
+    this.noteId = bs58.encode(properties.noteId)
+    this.sender = new PublicKey(properties.sender)
+    this.recipient = new PublicKey(properties.recipient)
+    this.priority = properties.priority
+  }
+}
+
+const UrgentMessageLogBorshSchema = new Map([
+  [
+    UrgentMessageLog,
+    {
+      kind: "struct",
+      fields: [
+        ["leafNode", [32]], // Array of 32 `u8`
+        ["noteId", [38]], // NoteId
+        ["sender", [32]], // Pubkey
+        ["recipient", [32]], // Pubkey
+        ["priority", "u8"],
+      ],
+    },
+  ],
+])
+
+class OutboxLog {
+  leafNode: Uint8Array
+  noteId: string
+  deliveredId: string
+  sender: PublicKey
+  recipient: PublicKey
+
+  constructor(properties: {
+    leafNode: Uint8Array
+    noteId: Uint8Array
+    deliveredId: Uint8Array
+    sender: Uint8Array
+    recipient: Uint8Array
+  }) {
+    this.leafNode = properties.leafNode
+    this.noteId = bs58.encode(properties.noteId)
+    this.deliveredId = bs58.encode(properties.deliveredId)
+    this.sender = new PublicKey(properties.sender)
+    this.recipient = new PublicKey(properties.recipient)
+  }
+}
+
+const OutboxLogBorshSchema = new Map([
+  [
+    OutboxLog,
+    {
+      kind: "struct",
+      fields: [
+        ["leafNode", [32]], // Array of 32 `u8`
+        ["noteId", [38]], // NoteId
+        ["deliveredId", [38]], // NoteId
+        ["sender", [32]], // Pubkey
+        ["recipient", [32]], // Pubkey
+      ],
+    },
+  ],
+])
+
+class EncryptedMessageLog {
+  leafNode: Uint8Array
+  noteId: string
+  sender: PublicKey
+  recipient: PublicKey
+  ciphertext: Uint8Array
+  nonce: Uint8Array
+  senderEphemeralPubkey: Uint8Array
+
+  constructor(properties: {
+    leafNode: Uint8Array
+    noteId: Uint8Array
+    sender: Uint8Array
+    recipient: Uint8Array
+    ciphertext: Uint8Array
+    nonce: Uint8Array
+    senderEphemeralPubkey: Uint8Array
+  }) {
+    this.leafNode = properties.leafNode
+    this.noteId = bs58.encode(properties.noteId)
+    this.sender = new PublicKey(properties.sender)
+    this.recipient = new PublicKey(properties.recipient)
+    this.ciphertext = properties.ciphertext
+    this.nonce = properties.nonce
+    this.senderEphemeralPubkey = properties.senderEphemeralPubkey
+  }
+}
+
+const EncryptedMessageLogBorshSchema = new Map([
+  [
+    EncryptedMessageLog,
+    {
+      kind: "struct",
+      fields: [
+        ["leafNode", [32]], // Array of 32 `u8`
+        ["noteId", [38]], // NoteId
+        ["sender", [32]], // Pubkey
+        ["recipient", [32]], // Pubkey
+        ["ciphertext", ["u8"]], // Vec of `u8`
+        ["nonce", [24]], // Array of 24 `u8`
+        ["senderEphemeralPubkey", [32]], // Array of 32 `u8`
+      ],
+    },
+  ],
+])
+
+class ForwardedMessageLog {
+  leafNode: Uint8Array
+  noteId: string
+  sender: PublicKey
+  recipient: PublicKey
+  message: string
+  originalLeaf: Uint8Array
+  originalSender: PublicKey
+
+  constructor(properties: {
+    leafNode: Uint8Array
+    noteId: Uint8Array
+    sender: Uint8Array
+    recipient: Uint8Array
+    message: string
+    originalLeaf: Uint8Array
+    originalSender: Uint8Array
+  }) {
+    this.leafNode = properties.leafNode
+    this.noteId = bs58.encode(properties.noteId)
+    this.sender = new PublicKey(properties.sender)
+    this.recipient = new PublicKey(properties.recipient)
+    this.message = properties.message
+    this.originalLeaf = properties.originalLeaf
+    this.originalSender = new PublicKey(properties.originalSender)
+  }
+}
+
+const ForwardedMessageLogBorshSchema = new Map([
+  [
+    ForwardedMessageLog,
+    {
+      kind: "struct",
+      fields: [
+        ["leafNode", [32]], // Array of 32 `u8`
+        ["noteId", [38]], // NoteId
+        ["sender", [32]], // Pubkey
+        ["recipient", [32]], // Pubkey
+        ["message", "string"],
+        ["originalLeaf", [32]], // Array of 32 `u8`
+        ["originalSender", [32]], // Pubkey
+      ],
+    },
+  ],
+])
+
+class GroupMessageLog {
+  leafNode: Uint8Array
+  noteId: string
+  sender: PublicKey
+  group: PublicKey
+  recipients: PublicKey[]
+  message: string
+
+  constructor(properties: {
+    leafNode: Uint8Array
+    noteId: Uint8Array
+    sender: Uint8Array
+    group: Uint8Array
+    recipients: Uint8Array[]
+    message: string
+  }) {
+    this.leafNode = properties.leafNode
+    this.noteId = bs58.encode(properties.noteId)
+    this.sender = new PublicKey(properties.sender)
+    this.group = new PublicKey(properties.group)
+    this.recipients = properties.recipients.map(
+      (recipient) => new PublicKey(recipient)
+    )
+    this.message = properties.message
+  }
+}
+
+const GroupMessageLogBorshSchema = new Map([
+  [
+    GroupMessageLog,
+    {
+      kind: "struct",
+      fields: [
+        ["leafNode", [32]], // Array of 32 `u8`
+        ["noteId", [38]], // NoteId
+        ["sender", [32]], // Pubkey
+        ["group", [32]], // Pubkey
+        ["recipients", [[32]]], // Vec of Pubkeys
+        ["message", "string"],
+      ],
+    },
+  ],
+])
+
+class BroadcastLog {
+  leafNode: Uint8Array
+  noteId: string
+  channel: PublicKey
+  authority: PublicKey
+  message: string
+
+  constructor(properties: {
+    leafNode: Uint8Array
+    noteId: Uint8Array
+    channel: Uint8Array
+    authority: Uint8Array
+    message: string
+  }) {
+    this.leafNode = properties.leafNode
+    this.noteId = bs58.encode(properties.noteId)
+    this.channel = new PublicKey(properties.channel)
+    this.authority = new PublicKey(properties.authority)
+    this.message = properties.message
+  }
+}
+
+const BroadcastLogBorshSchema = new Map([
+  [
+    BroadcastLog,
+    {
+      kind: "struct",
+      fields: [
+        ["leafNode", [32]], // Array of 32 `u8`
+        ["noteId", [38]], // NoteId
+        ["channel", [32]], // Pubkey
+        ["authority", [32]], // Pubkey
+        ["message", "string"],
+      ],
+    },
+  ],
+])
+
+class EditLog {
+  oldLeaf: Uint8Array
+  newLeaf: Uint8Array
+  noteId: string
+  editor: PublicKey
+  editCount: number
+  slot: BN
+
+  constructor(properties: {
+    oldLeaf: Uint8Array
+    newLeaf: Uint8Array
+    noteId: Uint8Array
+    editor: Uint8Array
+    editCount: number
+    slot: BN
+  }) {
+    this.oldLeaf = properties.oldLeaf
+    this.newLeaf = properties.newLeaf
+    this.noteId = bs58.encode(properties.noteId)
+    this.editor = new PublicKey(properties.editor)
+    this.editCount = properties.editCount
+    this.slot = properties.slot
+  }
+}
+
+const EditLogBorshSchema = new Map([
+  [
+    EditLog,
+    {
+      kind: "struct",
+      fields: [
+        ["oldLeaf", [32]], // Array of 32 `u8`
+        ["newLeaf", [32]], // Array of 32 `u8`
+        ["noteId", [38]], // NoteId
+        ["editor", [32]], // Pubkey
+        ["editCount", "u32"],
+        ["slot", "u64"],
+      ],
+    },
+  ],
+])
+
+class CrossPostLog {
+  leafNode: Uint8Array
+  owner: PublicKey
+  noteIds: string[]
+
+  constructor(properties: {
+    leafNode: Uint8Array
+    owner: Uint8Array
+    noteIds: Uint8Array[]
+  }) {
+    this.leafNode = properties.leafNode
+    this.owner = new PublicKey(properties.owner)
+    this.noteIds = properties.noteIds.map((noteId) => bs58.encode(noteId))
+  }
+}
+
+const CrossPostLogBorshSchema = new Map([
+  [
+    CrossPostLog,
+    {
+      kind: "struct",
+      fields: [
+        ["leafNode", [32]], // Array of 32 `u8`
+        ["owner", [32]], // Pubkey
+        ["noteIds", [[38]]], // Vec of NoteIds
+      ],
+    },
+  ],
+])
+
+class CertificateLog {
+  leafNode: Uint8Array
+  noteId: string
+  issuer: PublicKey
+  subject: PublicKey
+  claimsHash: Uint8Array
+  expiry: BN
+
+  constructor(properties: {
+    leafNode: Uint8Array
+    noteId: Uint8Array
+    issuer: Uint8Array
+    subject: Uint8Array
+    claimsHash: Uint8Array
+    expiry: BN
+  }) {
+    this.leafNode = properties.leafNode
+    this.noteId = bs58.encode(properties.noteId)
+    this.issuer = new PublicKey(properties.issuer)
+    this.subject = new PublicKey(properties.subject)
+    this.claimsHash = properties.claimsHash
+    this.expiry = properties.expiry
+  }
+}
+
+const CertificateLogBorshSchema = new Map([
+  [
+    CertificateLog,
+    {
+      kind: "struct",
+      fields: [
+        ["leafNode", [32]], // Array of 32 `u8`
+        ["noteId", [38]], // NoteId
+        ["issuer", [32]], // Pubkey
+        ["subject", [32]], // Pubkey
+        ["claimsHash", [32]], // Array of 32 `u8`
+        ["expiry", "u64"], // `i64`, always positive
+      ],
+    },
+  ],
+])
+
+class OrgNoteLog {
+  leafNode: Uint8Array
+  noteId: string
+  organization: PublicKey
+  member: PublicKey
+  note: string
+  postedAt: BN
+
+  constructor(properties: {
+    leafNode: Uint8Array
+    noteId: Uint8Array
+    organization: Uint8Array
+    member: Uint8Array
+    note: string
+    postedAt: BN
+  }) {
+    this.leafNode = properties.leafNode
+    this.noteId = bs58.encode(properties.noteId)
+    this.organization = new PublicKey(properties.organization)
+    this.member = new PublicKey(properties.member)
+    this.note = properties.note
+    this.postedAt = properties.postedAt
+  }
+}
+
+const OrgNoteLogBorshSchema = new Map([
+  [
+    OrgNoteLog,
+    {
+      kind: "struct",
+      fields: [
+        ["leafNode", [32]], // Array of 32 `u8`
+        ["noteId", [38]], // NoteId
+        ["organization", [32]], // Pubkey
+        ["member", [32]], // Pubkey
+        ["note", "string"],
+        ["postedAt", "u64"], // `i64`, always positive
+      ],
+    },
+  ],
+])
+
+class TypingLog {
+  user: PublicKey
+  conversation: PublicKey
+  slot: BN
+
+  constructor(properties: {
+    user: Uint8Array
+    conversation: Uint8Array
+    slot: BN
+  }) {
+    this.user = new PublicKey(properties.user)
+    this.conversation = new PublicKey(properties.conversation)
+    this.slot = properties.slot
+  }
+}
+
+const TypingLogBorshSchema = new Map([
+  [
+    TypingLog,
+    {
+      kind: "struct",
+      fields: [
+        ["user", [32]], // Pubkey
+        ["conversation", [32]], // Pubkey
+        ["slot", "u64"],
+      ],
+    },
+  ],
+])
+
+class AcknowledgementLog {
+  leafNode: Uint8Array
+  noteId: string
+  originalLeaf: Uint8Array
+  recipient: PublicKey
+  slot: BN
+
+  constructor(properties: {
+    leafNode: Uint8Array
+    noteId: Uint8Array
+    originalLeaf: Uint8Array
+    recipient: Uint8Array
+    slot: BN
+  }) {
+    this.leafNode = properties.leafNode
+    this.noteId = bs58.encode(properties.noteId)
+    this.originalLeaf = properties.originalLeaf
+    this.recipient = new PublicKey(properties.recipient)
+    this.slot = properties.slot
+  }
+}
+
+const AcknowledgementLogBorshSchema = new Map([
+  [
+    AcknowledgementLog,
+    {
+      kind: "struct",
+      fields: [
+        ["leafNode", [32]], // Array of 32 `u8`
+        ["noteId", [38]], // NoteId
+        ["originalLeaf", [32]], // Array of 32 `u8`
+        ["recipient", [32]], // Pubkey
+        ["slot", "u64"],
+      ],
+    },
+  ],
+])
+
+class TreeCloseLog {
+  merkleTree: PublicKey
+  schemaVersion: number
+  authority: PublicKey
+  root: Uint8Array
+  sequenceNumber: BN
+  slot: BN
+
+  constructor(properties: {
+    merkleTree: Uint8Array
+    schemaVersion: number
+    authority: Uint8Array
+    root: Uint8Array
+    sequenceNumber: BN
+    slot: BN
+  }) {
+    this.merkleTree = new PublicKey(properties.merkleTree)
+    this.schemaVersion = properties.schemaVersion
+    this.authority = new PublicKey(properties.authority)
+    this.root = properties.root
+    this.sequenceNumber = properties.sequenceNumber
+    this.slot = properties.slot
+  }
+}
+
+const TreeCloseLogBorshSchema = new Map([
+  [
+    TreeCloseLog,
+    {
+      kind: "struct",
+      fields: [
+        ["merkleTree", [32]], // Pubkey
+        ["schemaVersion", "u16"],
+        ["authority", [32]], // Pubkey
+        ["root", [32]], // Array of 32 `u8`
+        ["sequenceNumber", "u64"],
+        ["slot", "u64"],
+      ],
+    },
+  ],
+])
+
+class TreeCheckpointLog {
+  merkleTree: PublicKey
+  schemaVersion: number
+  root: Uint8Array
+  sequenceNumber: BN
+  leafCount: BN
+  slot: BN
+
+  constructor(properties: {
+    merkleTree: Uint8Array
+    schemaVersion: number
+    root: Uint8Array
+    sequenceNumber: BN
+    leafCount: BN
+    slot: BN
+  }) {
+    this.merkleTree = new PublicKey(properties.merkleTree)
+    this.schemaVersion = properties.schemaVersion
+    this.root = properties.root
+    this.sequenceNumber = properties.sequenceNumber
+    this.leafCount = properties.leafCount
+    this.slot = properties.slot
+  }
+}
+
+const TreeCheckpointLogBorshSchema = new Map([
+  [
+    TreeCheckpointLog,
+    {
+      kind: "struct",
+      fields: [
+        ["merkleTree", [32]], // Pubkey
+        ["schemaVersion", "u16"],
+        ["root", [32]], // Array of 32 `u8`
+        ["sequenceNumber", "u64"],
+        ["leafCount", "u64"],
+        ["slot", "u64"],
+      ],
+    },
+  ],
+])
+
+class TreeAuthorityLog {
+  merkleTree: PublicKey
+  schemaVersion: number
+  oldAuthority: PublicKey
+  newAuthority: PublicKey
+  slot: BN
+
+  constructor(properties: {
+    merkleTree: Uint8Array
+    schemaVersion: number
+    oldAuthority: Uint8Array
+    newAuthority: Uint8Array
+    slot: BN
+  }) {
+    this.merkleTree = new PublicKey(properties.merkleTree)
+    this.schemaVersion = properties.schemaVersion
+    this.oldAuthority = new PublicKey(properties.oldAuthority)
+    this.newAuthority = new PublicKey(properties.newAuthority)
+    this.slot = properties.slot
+  }
+}
+
+const TreeAuthorityLogBorshSchema = new Map([
+  [
+    TreeAuthorityLog,
+    {
+      kind: "struct",
+      fields: [
+        ["merkleTree", [32]], // Pubkey
+        ["schemaVersion", "u16"],
+        ["oldAuthority", [32]], // Pubkey
+        ["newAuthority", [32]], // Pubkey
+        ["slot", "u64"],
+      ],
+    },
+  ],
+])
+
+class TreeFreezeLog {
+  merkleTree: PublicKey
+  schemaVersion: number
+  authority: PublicKey
+  frozen: boolean
+  slot: BN
+
+  constructor(properties: {
+    merkleTree: Uint8Array
+    schemaVersion: number
+    authority: Uint8Array
+    frozen: number
+    slot: BN
+  }) {
+    this.merkleTree = new PublicKey(properties.merkleTree)
+    this.schemaVersion = properties.schemaVersion
+    this.authority = new PublicKey(properties.authority)
+    this.frozen = properties.frozen === 1
+    this.slot = properties.slot
+  }
+}
+
+const TreeFreezeLogBorshSchema = new Map([
+  [
+    TreeFreezeLog,
+    {
+      kind: "struct",
+      fields: [
+        ["merkleTree", [32]], // Pubkey
+        ["schemaVersion", "u16"],
+        ["authority", [32]], // Pubkey
+        ["frozen", "u8"], // bool
+        ["slot", "u64"],
+      ],
+    },
+  ],
+])
+
+class EmergencyFreezeLog {
+  merkleTree: PublicKey
+  schemaVersion: number
+  admin: PublicKey
+  frozen: boolean
+  reasonCode: number
+  slot: BN
+
+  constructor(properties: {
+    merkleTree: Uint8Array
+    schemaVersion: number
+    admin: Uint8Array
+    frozen: number
+    reasonCode: number
+    slot: BN
+  }) {
+    this.merkleTree = new PublicKey(properties.merkleTree)
+    this.schemaVersion = properties.schemaVersion
+    this.admin = new PublicKey(properties.admin)
+    this.frozen = properties.frozen === 1
+    this.reasonCode = properties.reasonCode
+    this.slot = properties.slot
+  }
+}
+
+const EmergencyFreezeLogBorshSchema = new Map([
+  [
+    EmergencyFreezeLog,
+    {
+      kind: "struct",
+      fields: [
+        ["merkleTree", [32]], // Pubkey
+        ["schemaVersion", "u16"],
+        ["admin", [32]], // Pubkey
+        ["frozen", "u8"], // bool
+        ["reasonCode", "u16"],
+        ["slot", "u64"],
+      ],
+    },
+  ],
+])
+
+class TakedownLog {
+  leafNode: Uint8Array
+  noteId: string
+  originalLeaf: Uint8Array
+  moderator: PublicKey
+  reasonCode: number
+  slot: BN
+
+  constructor(properties: {
+    leafNode: Uint8Array
+    noteId: Uint8Array
+    originalLeaf: Uint8Array
+    moderator: Uint8Array
+    reasonCode: number
+    slot: BN
+  }) {
+    this.leafNode = properties.leafNode
+    this.noteId = bs58.encode(properties.noteId)
+    this.originalLeaf = properties.originalLeaf
+    this.moderator = new PublicKey(properties.moderator)
+    this.reasonCode = properties.reasonCode
+    this.slot = properties.slot
+  }
+}
+
+const TakedownLogBorshSchema = new Map([
+  [
+    TakedownLog,
+    {
+      kind: "struct",
+      fields: [
+        ["leafNode", [32]], // Array of 32 `u8`
+        ["noteId", [38]], // NoteId
+        ["originalLeaf", [32]], // Array of 32 `u8`
+        ["moderator", [32]], // Pubkey
+        ["reasonCode", "u16"],
+        ["slot", "u64"],
+      ],
+    },
+  ],
+])
+
+class TipLog {
+  leafNode: Uint8Array
+  noteId: string
+  tipper: PublicKey
+  owner: PublicKey
+  amount: BN
+  mint: PublicKey | null
+
+  constructor(properties: {
+    leafNode: Uint8Array
+    noteId: Uint8Array
+    tipper: Uint8Array
+    owner: Uint8Array
+    amount: BN
+    mint: Uint8Array | null
+  }) {
+    this.leafNode = properties.leafNode
+    this.noteId = bs58.encode(properties.noteId)
+    this.tipper = new PublicKey(properties.tipper)
+    this.owner = new PublicKey(properties.owner)
+    this.amount = properties.amount
+    this.mint = properties.mint ? new PublicKey(properties.mint) : null
+  }
+}
+
+const TipLogBorshSchema = new Map([
+  [
+    TipLog,
+    {
+      kind: "struct",
+      fields: [
+        ["leafNode", [32]], // Array of 32 `u8`
+        ["noteId", [38]], // NoteId
+        ["tipper", [32]], // Pubkey
+        ["owner", [32]], // Pubkey
+        ["amount", "u64"],
+        ["mint", { kind: "option", type: [32] }], // Option of `Pubkey`
+      ],
+    },
+  ],
+])
+
+class KeyReleaseLog {
+  merkleTree: PublicKey
+  leafNode: Uint8Array
+  sender: PublicKey
+  recipient: PublicKey
+  price: BN
+  sealedKey: Uint8Array
+  slot: BN
+
+  constructor(properties: {
+    merkleTree: Uint8Array
+    leafNode: Uint8Array
+    sender: Uint8Array
+    recipient: Uint8Array
+    price: BN
+    sealedKey: Uint8Array
+    slot: BN
+  }) {
+    this.merkleTree = new PublicKey(properties.merkleTree)
+    this.leafNode = properties.leafNode
+    this.sender = new PublicKey(properties.sender)
+    this.recipient = new PublicKey(properties.recipient)
+    this.price = properties.price
+    this.sealedKey = properties.sealedKey
+    this.slot = properties.slot
+  }
+}
+
+const KeyReleaseLogBorshSchema = new Map([
+  [
+    KeyReleaseLog,
+    {
+      kind: "struct",
+      fields: [
+        ["merkleTree", [32]], // Pubkey
+        ["leafNode", [32]], // Array of 32 `u8`
+        ["sender", [32]], // Pubkey
+        ["recipient", [32]], // Pubkey
+        ["price", "u64"],
+        ["sealedKey", [32]], // Array of 32 `u8`
+        ["slot", "u64"],
+      ],
+    },
+  ],
+])
+
+class MigrationLog {
+  leafNode: Uint8Array
+  sourceId: string
+  destId: string
+
+  constructor(properties: {
+    leafNode: Uint8Array
+    sourceId: Uint8Array
+    destId: Uint8Array
+  }) {
+    this.leafNode = properties.leafNode
+    this.sourceId = bs58.encode(properties.sourceId)
+    this.destId = bs58.encode(properties.destId)
+  }
+}
+
+const MigrationLogBorshSchema = new Map([
+  [
+    MigrationLog,
+    {
+      kind: "struct",
+      fields: [
+        ["leafNode", [32]], // Array of 32 `u8`
+        ["sourceId", [38]], // NoteId
+        ["destId", [38]], // NoteId
+      ],
+    },
+  ],
+])
+
+class NoteMoveLog {
+  leafNode: Uint8Array
+  sourceId: string
+  destId: string
+
+  constructor(properties: {
+    leafNode: Uint8Array
+    sourceId: Uint8Array
+    destId: Uint8Array
+  }) {
+    this.leafNode = properties.leafNode
+    this.sourceId = bs58.encode(properties.sourceId)
+    this.destId = bs58.encode(properties.destId)
+  }
+}
+
+const NoteMoveLogBorshSchema = new Map([
+  [
+    NoteMoveLog,
+    {
+      kind: "struct",
+      fields: [
+        ["leafNode", [32]], // Array of 32 `u8`
+        ["sourceId", [38]], // NoteId
+        ["destId", [38]], // NoteId
+      ],
+    },
+  ],
+])
+
+class ReadReceiptLog {
   leafNode: Uint8Array
-  owner: PublicKey
-  note: string
+  reader: PublicKey
+  slot: BN
 
   constructor(properties: {
     leafNode: Uint8Array
-    owner: Uint8Array
-    note: string
+    reader: Uint8Array
+    slot: BN
   }) {
     this.leafNode = properties.leafNode
-    this.owner = new PublicKey(properties.owner)
-    this.note = properties.note
+    this.reader = new PublicKey(properties.reader)
+    this.slot = properties.slot
   }
 }
 
-// A map that describes the Note structure for Borsh deserialization
-const NoteLogBorshSchema = new Map([
+const ReadReceiptLogBorshSchema = new Map([
   [
-    NoteLog,
+    ReadReceiptLog,
     {
       kind: "struct",
       fields: [
         ["leafNode", [32]], // Array of 32 `u8`
-        ["owner", [32]], // Pubkey
-        ["note", "string"],
+        ["reader", [32]], // Pubkey
+        ["slot", "u64"],
+      ],
+    },
+  ],
+])
+
+class DeliveryLog {
+  leafNode: Uint8Array
+  recipient: PublicKey
+  slot: BN
+
+  constructor(properties: {
+    leafNode: Uint8Array
+    recipient: Uint8Array
+    slot: BN
+  }) {
+    this.leafNode = properties.leafNode
+    this.recipient = new PublicKey(properties.recipient)
+    this.slot = properties.slot
+  }
+}
+
+const DeliveryLogBorshSchema = new Map([
+  [
+    DeliveryLog,
+    {
+      kind: "struct",
+      fields: [
+        ["leafNode", [32]], // Array of 32 `u8`
+        ["recipient", [32]], // Pubkey
+        ["slot", "u64"],
+      ],
+    },
+  ],
+])
+
+class ReactionLog {
+  leafNode: Uint8Array
+  reactor: PublicKey
+  emoji: string
+  slot: BN
+
+  constructor(properties: {
+    leafNode: Uint8Array
+    reactor: Uint8Array
+    emoji: string
+    slot: BN
+  }) {
+    this.leafNode = properties.leafNode
+    this.reactor = new PublicKey(properties.reactor)
+    this.emoji = properties.emoji
+    this.slot = properties.slot
+  }
+}
+
+const ReactionLogBorshSchema = new Map([
+  [
+    ReactionLog,
+    {
+      kind: "struct",
+      fields: [
+        ["leafNode", [32]], // Array of 32 `u8`
+        ["reactor", [32]], // Pubkey
+        ["emoji", "string"],
+        ["slot", "u64"],
       ],
     },
   ],
 ])
 
-export function getHash(note: string, owner: PublicKey) {
+// Hashes a leaf, committing to the note status when one is given (note leaves)
+// Encodes a note id: the tree, the leaf index as a le u32 and the leaf schema
+// version as a le u16, in base58
+export function encodeNoteId(
+  merkleTree: PublicKey,
+  leafIndex: number,
+  schemaVersion: number
+) {
+  const bytes = Buffer.alloc(38)
+  merkleTree.toBuffer().copy(bytes, 0)
+  bytes.writeUInt32LE(leafIndex, 32)
+  bytes.writeUInt16LE(schemaVersion, 36)
+  return bs58.encode(bytes)
+}
+
+// Resolves a note id back to the leaf it names
+export function resolveNoteId(noteId: string) {
+  const bytes = Buffer.from(bs58.decode(noteId))
+  if (bytes.length !== 38) {
+    throw new Error("Invalid note id")
+  }
+  return {
+    merkleTree: new PublicKey(bytes.subarray(0, 32)),
+    leafIndex: bytes.readUInt32LE(32),
+    schemaVersion: bytes.readUInt16LE(36),
+  }
+}
+
+export function getHash(note: string, owner: PublicKey, status?: number) {
   const noteBuffer = Buffer.from(note)
   const publicKeyBuffer = Buffer.from(owner.toBytes())
-  const concatenatedBuffer = Buffer.concat([noteBuffer, publicKeyBuffer])
+  const statusBuffer = Buffer.from(status === undefined ? [] : [status])
+  const concatenatedBuffer = Buffer.concat([
+    noteBuffer,
+    publicKeyBuffer,
+    statusBuffer,
+  ])
   const concatenatedUint8Array = new Uint8Array(
     concatenatedBuffer.buffer,
     concatenatedBuffer.byteOffset,
@@ -50,7 +1146,734 @@ export function getHash(note: string, owner: PublicKey) {
   return keccak256(concatenatedUint8Array)
 }
 
+// Hashes a message leaf, committing to the parent leaf when it is a reply
+export function getMessageHash(
+  message: string,
+  sender: PublicKey,
+  parentLeaf?: Buffer,
+  priority = 0
+) {
+  return keccak256(
+    Buffer.concat([
+      Buffer.from(message),
+      sender.toBuffer(),
+      parentLeaf ?? Buffer.alloc(0),
+      // A normal priority message hashes the same as before priorities existed
+      priority === 0 ? Buffer.alloc(0) : Buffer.from([priority]),
+    ])
+  )
+}
+
+// Hashes a V5 direct message leaf, committing to both participants
+export function getMessageHashV2(
+  message: string,
+  sender: PublicKey,
+  recipient: PublicKey,
+  nonce: BN,
+  parentLeaf?: Buffer,
+  editCount = 0,
+  priority = 0
+) {
+  const editCountBuffer = Buffer.alloc(4)
+  editCountBuffer.writeUInt32LE(editCount)
+  return keccak256(
+    Buffer.concat([
+      getLeafDomainTag(LeafDomain.DirectMessage),
+      Buffer.from(keccak256.arrayBuffer(message)),
+      sender.toBuffer(),
+      recipient.toBuffer(),
+      nonce.toArrayLike(Buffer, "le", 8),
+      editCountBuffer,
+      Buffer.from([priority]),
+      parentLeaf ?? Buffer.alloc(0),
+    ])
+  )
+}
+
+// Hashes an acknowledgement leaf, committing to the original leaf and its recipient
+export function getAcknowledgementHash(
+  originalLeaf: Buffer,
+  acknowledger: PublicKey
+) {
+  return keccak256(
+    Buffer.concat([
+      getLeafDomainTag(LeafDomain.Acknowledgement),
+      originalLeaf,
+      acknowledger.toBuffer(),
+    ])
+  )
+}
+
+// Hashes the leaf a moderator takedown leaves in place of the original note leaf
+export function getTakedownHash(originalLeaf: Buffer, owner: PublicKey) {
+  return keccak256(
+    Buffer.concat([
+      getLeafDomainTag(LeafDomain.Takedown),
+      originalLeaf,
+      owner.toBuffer(),
+    ])
+  )
+}
+
+// Hashes a self-destructing message leaf, the message is hashed on its own first
+export function getTtlMessageHash(
+  message: string,
+  sender: PublicKey,
+  expiresAt: BN
+) {
+  return keccak256(
+    Buffer.concat([
+      getLeafDomainTag(LeafDomain.TtlMessage),
+      Buffer.from(keccak256.arrayBuffer(message)),
+      sender.toBuffer(),
+      expiresAt.toArrayLike(Buffer, "le", 8),
+    ])
+  )
+}
+
+// Hashes an edited message leaf, committing to its edit count
+export function getEditedMessageHash(
+  message: string,
+  sender: PublicKey,
+  editCount: number,
+  parentLeaf?: Buffer,
+  priority = 0
+) {
+  const editCountBuffer = Buffer.alloc(4)
+  editCountBuffer.writeUInt32LE(editCount)
+  return keccak256(
+    Buffer.concat([
+      getLeafDomainTag(LeafDomain.EditedMessage),
+      Buffer.from(keccak256.arrayBuffer(message)),
+      sender.toBuffer(),
+      editCountBuffer,
+      parentLeaf ?? Buffer.alloc(0),
+      priority === 0 ? Buffer.alloc(0) : Buffer.from([priority]),
+    ])
+  )
+}
+
+// Hashes an encrypted message leaf, committing to its key exchange metadata
+export function getEncryptedMessageHash(
+  ciphertext: Buffer,
+  sender: PublicKey,
+  nonce: Buffer,
+  senderEphemeralPubkey: Buffer
+) {
+  return keccak256(
+    Buffer.concat([
+      getLeafDomainTag(LeafDomain.EncryptedMessage),
+      Buffer.from(keccak256.arrayBuffer(ciphertext)),
+      sender.toBuffer(),
+      nonce,
+      senderEphemeralPubkey,
+    ])
+  )
+}
+
+// Hashes a forwarded message leaf, committing to the leaf and sender it came from
+export function getForwardedMessageHash(
+  message: string,
+  sender: PublicKey,
+  originalLeaf: Buffer,
+  originalSender: PublicKey
+) {
+  return keccak256(
+    Buffer.concat([
+      getLeafDomainTag(LeafDomain.ForwardedMessage),
+      Buffer.from(keccak256.arrayBuffer(message)),
+      sender.toBuffer(),
+      originalLeaf,
+      originalSender.toBuffer(),
+    ])
+  )
+}
+
+export function getGroupMessageHash(
+  message: string,
+  sender: PublicKey,
+  recipients: PublicKey[]
+) {
+  return keccak256(
+    Buffer.concat([
+      getLeafDomainTag(LeafDomain.GroupMessage),
+      Buffer.from(keccak256.arrayBuffer(message)),
+      sender.toBuffer(),
+      ...recipients.map((recipient) => recipient.toBuffer()),
+    ])
+  )
+}
+
+// Hashes a certificate leaf from its subject, claims hash and expiry
+export function getCertificateHash(
+  subject: PublicKey,
+  claimsHash: Buffer,
+  expiry: BN
+) {
+  return keccak256(
+    Buffer.concat([
+      getLeafDomainTag(LeafDomain.Certificate),
+      subject.toBuffer(),
+      claimsHash,
+      expiry.toArrayLike(Buffer, "le", 8),
+    ])
+  )
+}
+
+export function getOrgNoteHash(
+  note: string,
+  organization: PublicKey,
+  postedAt: BN
+) {
+  return keccak256(
+    Buffer.concat([
+      getLeafDomainTag(LeafDomain.OrgNote),
+      Buffer.from(keccak256.arrayBuffer(note)),
+      organization.toBuffer(),
+      postedAt.toArrayLike(Buffer, "le", 8),
+    ])
+  )
+}
+
+// Hashes the tree, note and expiry into the permit an owner signs for a relayed append
+export function getPermitMessage(
+  programId: PublicKey,
+  merkleTree: PublicKey,
+  note: string,
+  expiry: BN
+) {
+  return Buffer.from(
+    keccak256.arrayBuffer(
+      Buffer.concat([
+        Buffer.from("compressed-notes:append_note_with_permit"),
+        programId.toBuffer(),
+        merkleTree.toBuffer(),
+        Buffer.from(keccak256.arrayBuffer(note)),
+        expiry.toArrayLike(Buffer, "le", 8),
+      ])
+    )
+  )
+}
+
+// Hashes the wallet into the message its ethereum address signs to be linked to it
+export function getLinkMessage(programId: PublicKey, wallet: PublicKey) {
+  return Buffer.from(
+    keccak256.arrayBuffer(
+      Buffer.concat([
+        Buffer.from("compressed-notes:link_eth_identity"),
+        programId.toBuffer(),
+        wallet.toBuffer(),
+      ])
+    )
+  )
+}
+
+// Hashes a note leaf attributed to the owner's linked ethereum address
+export function getAttributedNoteHash(
+  note: string,
+  owner: PublicKey,
+  status: number,
+  ethAddress: Buffer
+) {
+  return keccak256(
+    Buffer.concat([
+      getLeafDomainTag(LeafDomain.AttributedNote),
+      Buffer.from(keccak256.arrayBuffer(note)),
+      owner.toBuffer(),
+      Buffer.from([status]),
+      ethAddress,
+    ])
+  )
+}
+
+// Hashes a shared note leaf, committing to its owners in byte order
+export function getSharedNoteHash(
+  note: string,
+  owners: PublicKey[],
+  status: number
+) {
+  const sorted = owners
+    .map((owner) => owner.toBuffer())
+    .sort((a, b) => Buffer.compare(a, b))
+  return keccak256(
+    Buffer.concat([
+      getLeafDomainTag(LeafDomain.SharedNote),
+      Buffer.from(keccak256.arrayBuffer(note)),
+      Buffer.from([status]),
+      ...sorted,
+    ])
+  )
+}
+
 export async function getNoteLog(connection: Connection, txSignature: string) {
+  return getLog<NoteLog>(connection, txSignature, NoteLogBorshSchema, NoteLog)
+}
+
+export async function getOrgNoteLog(
+  connection: Connection,
+  txSignature: string
+) {
+  return getLog<OrgNoteLog>(
+    connection,
+    txSignature,
+    OrgNoteLogBorshSchema,
+    OrgNoteLog
+  )
+}
+
+export async function getMessageLog(
+  connection: Connection,
+  txSignature: string
+) {
+  return getLog<MessageLog>(
+    connection,
+    txSignature,
+    MessageLogBorshSchema,
+    MessageLog
+  )
+}
+
+export async function getUrgentMessageLog(
+  connection: Connection,
+  txSignature: string
+) {
+  return getLog<UrgentMessageLog>(
+    connection,
+    txSignature,
+    UrgentMessageLogBorshSchema,
+    UrgentMessageLog
+  )
+}
+
+export async function getOutboxLog(
+  connection: Connection,
+  txSignature: string
+) {
+  return getLog<OutboxLog>(
+    connection,
+    txSignature,
+    OutboxLogBorshSchema,
+    OutboxLog
+  )
+}
+
+export async function getTypingLog(
+  connection: Connection,
+  txSignature: string
+) {
+  return getLog<TypingLog>(
+    connection,
+    txSignature,
+    TypingLogBorshSchema,
+    TypingLog
+  )
+}
+
+export async function getEncryptedMessageLog(
+  connection: Connection,
+  txSignature: string
+) {
+  return getLog<EncryptedMessageLog>(
+    connection,
+    txSignature,
+    EncryptedMessageLogBorshSchema,
+    EncryptedMessageLog
+  )
+}
+
+export async function getForwardedMessageLog(
+  connection: Connection,
+  txSignature: string
+) {
+  return getLog<ForwardedMessageLog>(
+    connection,
+    txSignature,
+    ForwardedMessageLogBorshSchema,
+    ForwardedMessageLog
+  )
+}
+
+export async function getGroupMessageLog(
+  connection: Connection,
+  txSignature: string
+) {
+  return getLog<GroupMessageLog>(
+    connection,
+    txSignature,
+    GroupMessageLogBorshSchema,
+    GroupMessageLog
+  )
+}
+
+export async function getBroadcastLog(
+  connection: Connection,
+  txSignature: string
+) {
+  return getLog<BroadcastLog>(
+    connection,
+    txSignature,
+    BroadcastLogBorshSchema,
+    BroadcastLog
+  )
+}
+
+export async function getEditLog(connection: Connection, txSignature: string) {
+  return getLog<EditLog>(connection, txSignature, EditLogBorshSchema, EditLog)
+}
+
+export async function getCrossPostLog(
+  connection: Connection,
+  txSignature: string
+) {
+  return getLog<CrossPostLog>(
+    connection,
+    txSignature,
+    CrossPostLogBorshSchema,
+    CrossPostLog
+  )
+}
+
+// Derives the conversation PDA, with participants ordered like the program seeds
+export function getConversationAddress(
+  programId: PublicKey,
+  a: PublicKey,
+  b: PublicKey
+) {
+  const [first, second] =
+    Buffer.compare(a.toBuffer(), b.toBuffer()) <= 0 ? [a, b] : [b, a]
+  const [conversation] = PublicKey.findProgramAddressSync(
+    [Buffer.from("conv"), first.toBuffer(), second.toBuffer()],
+    programId
+  )
+  return conversation
+}
+
+export function getBlockListAddress(programId: PublicKey, owner: PublicKey) {
+  const [blockList] = PublicKey.findProgramAddressSync(
+    [Buffer.from("block_list"), owner.toBuffer()],
+    programId
+  )
+  return blockList
+}
+
+export function getInboxTreeAddress(
+  programId: PublicKey,
+  recipient: PublicKey
+) {
+  const [inboxTree] = PublicKey.findProgramAddressSync(
+    [Buffer.from("inbox_tree"), recipient.toBuffer()],
+    programId
+  )
+  return inboxTree
+}
+
+export function getTreeConfigAddress(
+  programId: PublicKey,
+  merkleTree: PublicKey
+) {
+  const [treeConfig] = PublicKey.findProgramAddressSync(
+    [Buffer.from("config"), merkleTree.toBuffer()],
+    programId
+  )
+  return treeConfig
+}
+
+// The remaining accounts of one tree of a cross post, in the order of CrossPostTree.
+// Optional accounts left out are passed as the program id, like Anchor does.
+export function getCrossPostTreeAccounts(
+  programId: PublicKey,
+  merkleTree: PublicKey,
+  optional: {
+    role?: PublicKey
+    rateState?: PublicKey
+    recentLeaves?: PublicKey
+    treeTreasury?: PublicKey
+    payerTokenAccount?: PublicKey
+    feeVault?: PublicKey
+    gateTokenAccount?: PublicKey
+    gateMetadata?: PublicKey
+    subscription?: PublicKey
+  } = {}
+) {
+  const [treeAuthority] = PublicKey.findProgramAddressSync(
+    [merkleTree.toBuffer()],
+    programId
+  )
+  return [
+    { pubkey: optional.role ?? programId, isSigner: false, isWritable: false },
+    {
+      pubkey: optional.rateState ?? programId,
+      isSigner: false,
+      isWritable: !!optional.rateState,
+    },
+    {
+      pubkey: optional.recentLeaves ?? programId,
+      isSigner: false,
+      isWritable: !!optional.recentLeaves,
+    },
+    {
+      pubkey: optional.treeTreasury ?? programId,
+      isSigner: false,
+      isWritable: !!optional.treeTreasury,
+    },
+    {
+      pubkey: optional.payerTokenAccount ?? programId,
+      isSigner: false,
+      isWritable: !!optional.payerTokenAccount,
+    },
+    {
+      pubkey: optional.feeVault ?? programId,
+      isSigner: false,
+      isWritable: !!optional.feeVault,
+    },
+    {
+      pubkey: optional.gateTokenAccount ?? programId,
+      isSigner: false,
+      isWritable: false,
+    },
+    {
+      pubkey: optional.gateMetadata ?? programId,
+      isSigner: false,
+      isWritable: false,
+    },
+    {
+      pubkey: optional.subscription ?? programId,
+      isSigner: false,
+      isWritable: false,
+    },
+    {
+      pubkey: getTreeConfigAddress(programId, merkleTree),
+      isSigner: false,
+      isWritable: true,
+    },
+    { pubkey: treeAuthority, isSigner: false, isWritable: false },
+    { pubkey: merkleTree, isSigner: false, isWritable: true },
+  ]
+}
+
+export function getForestAddress(programId: PublicKey, creator: PublicKey) {
+  const [forest] = PublicKey.findProgramAddressSync(
+    [Buffer.from("forest"), creator.toBuffer()],
+    programId
+  )
+  return forest
+}
+
+export function getConversationStateAddress(
+  programId: PublicKey,
+  a: PublicKey,
+  b: PublicKey
+) {
+  const [first, second] =
+    Buffer.compare(a.toBuffer(), b.toBuffer()) <= 0 ? [a, b] : [b, a]
+  const [conversationState] = PublicKey.findProgramAddressSync(
+    [Buffer.from("conv_state"), first.toBuffer(), second.toBuffer()],
+    programId
+  )
+  return conversationState
+}
+
+export function getConversationTreeAddress(
+  programId: PublicKey,
+  a: PublicKey,
+  b: PublicKey
+) {
+  const [first, second] =
+    Buffer.compare(a.toBuffer(), b.toBuffer()) <= 0 ? [a, b] : [b, a]
+  const [conversationTree] = PublicKey.findProgramAddressSync(
+    [Buffer.from("conv_tree"), first.toBuffer(), second.toBuffer()],
+    programId
+  )
+  return conversationTree
+}
+
+export function getInboxAddress(programId: PublicKey, recipient: PublicKey) {
+  const [inbox] = PublicKey.findProgramAddressSync(
+    [Buffer.from("inbox"), recipient.toBuffer()],
+    programId
+  )
+  return inbox
+}
+
+export async function getCertificateLog(
+  connection: Connection,
+  txSignature: string
+) {
+  return getLog<CertificateLog>(
+    connection,
+    txSignature,
+    CertificateLogBorshSchema,
+    CertificateLog
+  )
+}
+
+export async function getAcknowledgementLog(
+  connection: Connection,
+  txSignature: string
+) {
+  return getLog<AcknowledgementLog>(
+    connection,
+    txSignature,
+    AcknowledgementLogBorshSchema,
+    AcknowledgementLog
+  )
+}
+
+export async function getTreeCloseLog(
+  connection: Connection,
+  txSignature: string
+) {
+  return getLog<TreeCloseLog>(
+    connection,
+    txSignature,
+    TreeCloseLogBorshSchema,
+    TreeCloseLog
+  )
+}
+
+export async function getTreeCheckpointLog(
+  connection: Connection,
+  txSignature: string
+) {
+  return getLog<TreeCheckpointLog>(
+    connection,
+    txSignature,
+    TreeCheckpointLogBorshSchema,
+    TreeCheckpointLog
+  )
+}
+
+export async function getTreeAuthorityLog(
+  connection: Connection,
+  txSignature: string
+) {
+  return getLog<TreeAuthorityLog>(
+    connection,
+    txSignature,
+    TreeAuthorityLogBorshSchema,
+    TreeAuthorityLog
+  )
+}
+
+export async function getTreeFreezeLog(
+  connection: Connection,
+  txSignature: string
+) {
+  return getLog<TreeFreezeLog>(
+    connection,
+    txSignature,
+    TreeFreezeLogBorshSchema,
+    TreeFreezeLog
+  )
+}
+
+export async function getEmergencyFreezeLog(
+  connection: Connection,
+  txSignature: string
+) {
+  return getLog<EmergencyFreezeLog>(
+    connection,
+    txSignature,
+    EmergencyFreezeLogBorshSchema,
+    EmergencyFreezeLog
+  )
+}
+
+export async function getTakedownLog(
+  connection: Connection,
+  txSignature: string
+) {
+  return getLog<TakedownLog>(
+    connection,
+    txSignature,
+    TakedownLogBorshSchema,
+    TakedownLog
+  )
+}
+
+export async function getTipLog(connection: Connection, txSignature: string) {
+  return getLog<TipLog>(connection, txSignature, TipLogBorshSchema, TipLog)
+}
+
+export async function getKeyReleaseLog(
+  connection: Connection,
+  txSignature: string
+) {
+  return getLog<KeyReleaseLog>(
+    connection,
+    txSignature,
+    KeyReleaseLogBorshSchema,
+    KeyReleaseLog
+  )
+}
+
+export async function getMigrationLog(
+  connection: Connection,
+  txSignature: string
+) {
+  return getLog<MigrationLog>(
+    connection,
+    txSignature,
+    MigrationLogBorshSchema,
+    MigrationLog
+  )
+}
+
+export async function getNoteMoveLog(
+  connection: Connection,
+  txSignature: string
+) {
+  return getLog<NoteMoveLog>(
+    connection,
+    txSignature,
+    NoteMoveLogBorshSchema,
+    NoteMoveLog
+  )
+}
+
+export async function getReadReceiptLog(
+  connection: Connection,
+  txSignature: string
+) {
+  return getLog<ReadReceiptLog>(
+    connection,
+    txSignature,
+    ReadReceiptLogBorshSchema,
+    ReadReceiptLog
+  )
+}
+
+export async function getDeliveryLog(
+  connection: Connection,
+  txSignature: string
+) {
+  return getLog<DeliveryLog>(
+    connection,
+    txSignature,
+    DeliveryLogBorshSchema,
+    DeliveryLog
+  )
+}
+
+export async function getReactionLog(
+  connection: Connection,
+  txSignature: string
+) {
+  return getLog<ReactionLog>(
+    connection,
+    txSignature,
+    ReactionLogBorshSchema,
+    ReactionLog
+  )
+}
+
+// Finds and deserializes the last noop log in the transaction matching the schema
+export async function getLog<T>(
+  connection: Connection,
+  txSignature: string,
+  schema: Map<Function, any>,
+  classType: { new (args: any): T }
+) {
   // Confirm the transaction, otherwise the getTransaction sometimes returns null
   const latestBlockHash = await connection.getLatestBlockhash()
   await connection.confirmTransaction({
@@ -76,7 +1899,7 @@ export async function getNoteLog(connection: Connection, txSignature: string) {
       ].toBase58() === SPL_NOOP_PROGRAM_ID.toBase58()
   )
 
-  let noteLog: NoteLog
+  let log: T
   for (let i = noopInnerIx.length - 1; i >= 0; i--) {
     try {
       // Try to decode and deserialize the instruction data
@@ -87,18 +1910,103 @@ export async function getNoteLog(connection: Connection, txSignature: string) {
       // Get the application data
       const applicationData = applicationDataEvent.fields[0].applicationData
 
-      // Deserialize the application data into NoteLog instance
-      noteLog = deserialize(
-        NoteLogBorshSchema,
-        NoteLog,
-        Buffer.from(applicationData)
-      )
+      // Deserialize the application data into an instance of the log class
+      log = deserialize(schema, classType, Buffer.from(applicationData))
 
-      if (noteLog !== undefined) {
+      if (log !== undefined) {
         break
       }
     } catch (__) {}
   }
 
-  return noteLog
+  return log
+}
+
+// The spl token program, driven directly so the tests need no token client
+export const TOKEN_PROGRAM_ID = new PublicKey(
+  "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA"
+)
+
+// Creates an spl token account of `space` bytes initialized by `data`
+async function createTokenProgramAccount(
+  connection: Connection,
+  payer: Keypair,
+  space: number,
+  data: Buffer,
+  extraKeys: PublicKey[] = []
+) {
+  const account = Keypair.generate()
+  const lamports = await connection.getMinimumBalanceForRentExemption(space)
+  const tx = new Transaction().add(
+    SystemProgram.createAccount({
+      fromPubkey: payer.publicKey,
+      newAccountPubkey: account.publicKey,
+      lamports,
+      space,
+      programId: TOKEN_PROGRAM_ID,
+    }),
+    new TransactionInstruction({
+      programId: TOKEN_PROGRAM_ID,
+      keys: [account.publicKey, ...extraKeys].map((pubkey) => ({
+        pubkey,
+        isSigner: false,
+        isWritable: pubkey.equals(account.publicKey),
+      })),
+      data,
+    })
+  )
+  await sendAndConfirmTransaction(connection, tx, [payer, account])
+  return account.publicKey
+}
+
+// Creates a mint with `payer` as its mint authority
+export async function createTokenMint(
+  connection: Connection,
+  payer: Keypair,
+  decimals = 0
+) {
+  // InitializeMint2: decimals, mint authority and no freeze authority
+  const data = Buffer.concat([
+    Buffer.from([20, decimals]),
+    payer.publicKey.toBuffer(),
+    Buffer.from([0]),
+  ])
+  return createTokenProgramAccount(connection, payer, 82, data)
+}
+
+// Creates a token account of `mint` held by `owner`
+export async function createTokenAccount(
+  connection: Connection,
+  payer: Keypair,
+  mint: PublicKey,
+  owner: PublicKey
+) {
+  // InitializeAccount3: the owner, with the mint as the second account
+  const data = Buffer.concat([Buffer.from([18]), owner.toBuffer()])
+  return createTokenProgramAccount(connection, payer, 165, data, [mint])
+}
+
+// Mints `amount` tokens of `mint` to `destination`, signed by the mint authority `payer`
+export async function mintTokens(
+  connection: Connection,
+  payer: Keypair,
+  mint: PublicKey,
+  destination: PublicKey,
+  amount: number
+) {
+  const data = Buffer.alloc(9)
+  data.writeUInt8(7, 0) // MintTo
+  data.writeBigUInt64LE(BigInt(amount), 1)
+  const tx = new Transaction().add(
+    new TransactionInstruction({
+      programId: TOKEN_PROGRAM_ID,
+      keys: [
+        { pubkey: mint, isSigner: false, isWritable: true },
+        { pubkey: destination, isSigner: false, isWritable: true },
+        { pubkey: payer.publicKey, isSigner: true, isWritable: false },
+      ],
+      data,
+    })
+  )
+  await sendAndConfirmTransaction(connection, tx, [payer])
 }